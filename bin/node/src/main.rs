@@ -1,3 +1,114 @@
-fn main() {
-    println!("Hello, world!");
+use common::config::{load_config, NodeConfig};
+use common::error::{DatabaseError, Result};
+use common::hlc::HybridClock;
+use common::types::{Membership, NodeId};
+use common::util::init_logger;
+use log::{error, info, warn};
+use raft_node::{GrpcCoordinatorTransport, GrpcRaftTransport, LivenessReporter, MultiRaft, Node};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use storage::{Storage, StorageOptions};
+use tokio::signal;
+
+#[tokio::main]
+async fn main() -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Load the configuration named on the command line, if any
+    let config: NodeConfig = match std::env::args().nth(1) {
+        Some(path) => load_config(path)?,
+        None => NodeConfig::default(),
+    };
+    init_logger(&format!("node {}", config.node_id));
+    info!("Starting node {}...", config.node_id.0);
+    config.validate()?;
+
+    let options = StorageOptions { gc_window_ms: config.mvcc_gc_window_ms };
+    let storage = Arc::new(Storage::open_with_options(&config.data_dir, options)?);
+    let clock = Arc::new(HybridClock::new(config.max_clock_offset_ms));
+    let node = Arc::new(Node::open(&config, clock, storage.clone())?);
+
+    // The nodes listed as Raft peers host the meta group from the start
+    if !config.raft_peers.is_empty() {
+        node.bootstrap_meta_group(&config, meta_membership(&config)?).await?;
+    }
+
+    // Drive the hosted groups: ticks, heartbeats and replication
+    tokio::spawn(MultiRaft::run(node.host(), Arc::new(GrpcRaftTransport::new(config.node_id.clone()))));
+
+    // Drop versions that fell out of the GC window
+    let gc_storage = storage.clone();
+    let gc_interval = Duration::from_millis(config.mvcc_gc_interval_ms);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(gc_interval);
+        loop {
+            ticker.tick().await;
+            let storage = gc_storage.clone();
+            match tokio::task::spawn_blocking(move || storage.collect_garbage()).await {
+                Ok(Ok(removed)) if removed > 0 => info!("Garbage collected {} old versions", removed),
+                Ok(Ok(_)) => {},
+                Ok(Err(e)) => warn!("Garbage collection failed: {}", e),
+                Err(e) => error!("Garbage collection panicked: {}", e),
+            }
+        }
+    });
+
+    // Report liveness and load to the coordinator, and learn its metadata
+    // version
+    if let Some(coordinator_addr) = &config.coordinator_addr {
+        let reporter = LivenessReporter::new(&config, Arc::new(GrpcCoordinatorTransport::new(coordinator_addr.clone())))?;
+        let interval = Duration::from_millis(config.coordinator_heartbeat_interval_ms);
+        let node = node.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let report = match node.load().await {
+                    Ok(load) => reporter.report_once(load).await,
+                    Err(e) => Err(e),
+                };
+                match report {
+                    Ok(metadata_version) => node.observe_metadata_version(metadata_version),
+                    Err(e) => warn!("Failed to heartbeat the coordinator: {}", e),
+                }
+            }
+        });
+    }
+
+    // Start the gRPC server
+    let server_addr = config.listen_addr.clone();
+    let server_node = node.clone();
+    let server_handle = tokio::spawn(async move { raft_node::start_grpc_server(&server_addr, server_node).await });
+
+    info!("Node running on {}. Press Ctrl+C to exit.", config.listen_addr);
+
+    // Wait for Ctrl+C
+    match signal::ctrl_c().await {
+        Ok(()) => {
+            info!("Shutting down node...");
+        }
+        Err(err) => {
+            error!("Unable to listen for shutdown signal: {}", err);
+        }
+    }
+
+    server_handle.abort();
+    Ok(())
+}
+
+/// The meta group's replicas, from `raft_peers` entries of the form
+/// `node_id=address`. This node is a replica whether listed or not.
+fn meta_membership(config: &NodeConfig) -> Result<Membership> {
+    let mut addresses = HashMap::from([(config.node_id.clone(), config.listen_addr.clone())]);
+    let mut voters = vec![config.node_id.clone()];
+    for peer in &config.raft_peers {
+        let (id, address) = peer.split_once('=').ok_or_else(|| {
+            DatabaseError::Config(format!("Raft peer {} is not of the form node_id=address", peer))
+        })?;
+        let id = NodeId(id.to_string());
+        if !voters.contains(&id) {
+            voters.push(id.clone());
+        }
+        addresses.insert(id, address.to_string());
+    }
+    Ok(Membership { configs: vec![voters], learners: Vec::new(), addresses })
 }
@@ -3,20 +3,55 @@
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 use crate::error::{DatabaseError, Result};
-use crate::types::NodeId;
+use crate::hlc::DEFAULT_MAX_CLOCK_OFFSET_MS;
+use crate::types::{Locality, NodeId};
 
-/// Configuration for a database node.
+/// Configuration for a database node. Settings missing from a config file
+/// take their default values, so files written before a setting was added
+/// still load.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct NodeConfig {
     pub node_id: NodeId,
     pub listen_addr: String,
+    /// Where the node runs, for spreading replicas over failure domains
+    pub locality: Locality,
     pub data_dir: String,
+    /// Peers the meta Raft group is bootstrapped with, as `node_id=address`;
+    /// later membership changes go through the coordinator's admin service
     pub raft_peers: Vec<String>,
     pub coordinator_addr: Option<String>,
+    /// How often the node reports its liveness and load to the coordinator
+    pub coordinator_heartbeat_interval_ms: u64,
     pub heartbeat_interval_ms: u64,
     pub election_timeout_min_ms: u64,
     pub election_timeout_max_ms: u64,
     pub snapshot_threshold: u64,
+    /// How long old MVCC versions are kept for "as of" reads
+    pub mvcc_gc_window_ms: u64,
+    /// How often versions older than the GC window are removed
+    pub mvcc_gc_interval_ms: u64,
+    /// Largest clock offset tolerated between this node and its peers
+    pub max_clock_offset_ms: u64,
+    /// How long a transaction waits in a lock queue before giving up
+    pub lock_wait_timeout_ms: u64,
+    /// How the leader confirms its leadership before a linearizable read
+    pub linearizable_reads: LinearizableReadMode,
+    /// Length of the leader lease; must be shorter than the minimum election timeout
+    pub leader_lease_ms: u64,
+}
+
+/// How a Raft leader makes sure it is still the leader before serving a
+/// linearizable read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinearizableReadMode {
+    /// Confirm leadership with a round of heartbeats for every read.
+    #[default]
+    ReadIndex,
+    /// Skip the heartbeat round while the leader lease, extended by every
+    /// heartbeat acknowledged by a quorum, has not expired. Relies on clock
+    /// rates being roughly equal across nodes.
+    Lease,
 }
 
 impl Default for NodeConfig {
@@ -24,32 +59,161 @@ impl Default for NodeConfig {
         Self {
             node_id: NodeId("node1".to_string()),
             listen_addr: "127.0.0.1:9090".to_string(),
+            locality: Locality::default(),
             data_dir: "data".to_string(),
             raft_peers: vec![],
             coordinator_addr: None,
+            coordinator_heartbeat_interval_ms: 1000,
             heartbeat_interval_ms: 100,
             election_timeout_min_ms: 150,
             election_timeout_max_ms: 300,
             snapshot_threshold: 1000,
+            mvcc_gc_window_ms: 4 * 60 * 60 * 1000,
+            mvcc_gc_interval_ms: 10 * 60 * 1000,
+            max_clock_offset_ms: DEFAULT_MAX_CLOCK_OFFSET_MS,
+            lock_wait_timeout_ms: 10_000,
+            linearizable_reads: LinearizableReadMode::default(),
+            leader_lease_ms: 100,
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Check settings that depend on each other.
+    pub fn validate(&self) -> Result<()> {
+        if self.election_timeout_min_ms > self.election_timeout_max_ms {
+            return Err(DatabaseError::Config(
+                "election_timeout_min_ms must not exceed election_timeout_max_ms".to_string(),
+            ));
+        }
+        // A follower may start an election once the minimum election timeout
+        // passes without heartbeats; the old leader's lease must be over by then
+        if self.linearizable_reads == LinearizableReadMode::Lease
+            && self.leader_lease_ms >= self.election_timeout_min_ms
+        {
+            return Err(DatabaseError::Config(
+                "leader_lease_ms must be shorter than election_timeout_min_ms".to_string(),
+            ));
         }
+        Ok(())
     }
 }
 
-/// Configuration for a coordinator node.
+/// Configuration for a coordinator node. Settings missing from a config file
+/// take their default values, as for `NodeConfig`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CoordinatorConfig {
     pub listen_addr: String,
     pub initial_nodes: Vec<String>,
+    /// Addresses of the nodes hosting the meta Raft group, which stores the
+    /// cluster metadata so any coordinator can serve; without any, the
+    /// metadata lives only in this coordinator's memory
+    pub meta_nodes: Vec<String>,
+    /// How often the metadata is refreshed from the meta group; changes are
+    /// saved as they are made
     pub metadata_refresh_interval_ms: u64,
+    /// How long the coordinator running the background jobs holds on to
+    /// them without renewing its lease
+    pub coordinator_lease_ms: u64,
+    /// Largest clock offset tolerated between the coordinator and nodes
+    pub max_clock_offset_ms: u64,
+    /// How long a pending transaction may go untouched before others may abort it
+    pub transaction_abandon_timeout_ms: u64,
+    /// How often lock wait queues are checked for deadlocks
+    pub deadlock_detection_interval_ms: u64,
+    /// How often partition sizes and loads are checked for splits and merges
+    pub range_check_interval_ms: u64,
+    /// Partitions larger than this are split
+    pub split_size_bytes: u64,
+    /// Partitions serving more requests per second than this are split
+    pub split_qps: f64,
+    /// Adjacent partitions are merged if together they stay below this size;
+    /// must be below `split_size_bytes`, and well below so merged partitions do not split again
+    pub merge_size_bytes: u64,
+    /// Adjacent partitions are merged if together they serve fewer requests per second than this;
+    /// must be below `split_qps`
+    pub merge_qps: f64,
+    /// How often replicas are rebalanced over the nodes
+    pub rebalance_interval_ms: u64,
+    /// Most replica moves started per rebalancing round
+    pub rebalance_max_moves: usize,
+    /// Smallest improvement in the balance of node load scores worth a replica move
+    pub rebalance_min_gain: f64,
+    /// Only log the replica moves the rebalancer would make
+    pub rebalance_dry_run: bool,
+    /// Number of voting replicas every partition should have
+    pub replication_factor: usize,
+    /// How often node heartbeats are checked
+    pub failure_check_interval_ms: u64,
+    /// A node silent for this long is suspect: it keeps its replicas but
+    /// takes no new ones and leads no partitions
+    pub node_suspect_timeout_ms: u64,
+    /// A node silent for this long is dead and its replicas are replaced
+    pub node_dead_timeout_ms: u64,
+    /// How often decommissioning nodes move replicas off
+    pub decommission_interval_ms: u64,
+    /// Most replicas moved off each decommissioning node per round
+    pub decommission_max_moves: usize,
 }
 
 impl Default for CoordinatorConfig {
     fn default() -> Self {
         Self {
-            listen_addr: "127.0.0.1:8080".to_string(),
+            listen_addr: "127.0.0.1:50051".to_string(),
             initial_nodes: vec![],
+            meta_nodes: vec![],
             metadata_refresh_interval_ms: 1000,
+            coordinator_lease_ms: 5000,
+            max_clock_offset_ms: DEFAULT_MAX_CLOCK_OFFSET_MS,
+            transaction_abandon_timeout_ms: 5000,
+            deadlock_detection_interval_ms: 1000,
+            range_check_interval_ms: 10_000,
+            split_size_bytes: 64 * 1024 * 1024,
+            split_qps: 2500.0,
+            merge_size_bytes: 16 * 1024 * 1024,
+            merge_qps: 250.0,
+            rebalance_interval_ms: 60_000,
+            rebalance_max_moves: 2,
+            rebalance_min_gain: 0.1,
+            rebalance_dry_run: false,
+            replication_factor: 3,
+            failure_check_interval_ms: 1000,
+            node_suspect_timeout_ms: 5000,
+            node_dead_timeout_ms: 60_000,
+            decommission_interval_ms: 1000,
+            decommission_max_moves: 4,
+        }
+    }
+}
+
+impl CoordinatorConfig {
+    /// Check settings that depend on each other.
+    pub fn validate(&self) -> Result<()> {
+        if self.replication_factor == 0 {
+            return Err(DatabaseError::Config("replication_factor must be at least 1".to_string()));
+        }
+        // The lease is renewed every refresh and must outlive a few of them
+        if self.coordinator_lease_ms <= self.metadata_refresh_interval_ms {
+            return Err(DatabaseError::Config(
+                "coordinator_lease_ms must exceed metadata_refresh_interval_ms".to_string(),
+            ));
+        }
+        if self.node_suspect_timeout_ms > self.node_dead_timeout_ms {
+            return Err(DatabaseError::Config(
+                "node_suspect_timeout_ms must not exceed node_dead_timeout_ms".to_string(),
+            ));
+        }
+        // A merged partition must not qualify for a split right away
+        if self.merge_size_bytes >= self.split_size_bytes {
+            return Err(DatabaseError::Config(
+                "merge_size_bytes must be below split_size_bytes".to_string(),
+            ));
+        }
+        if self.merge_qps >= self.split_qps {
+            return Err(DatabaseError::Config("merge_qps must be below split_qps".to_string()));
         }
+        Ok(())
     }
 }
 
@@ -73,4 +237,34 @@ pub fn save_config<T: Serialize>(config: &T, path: impl AsRef<Path>) -> Result<(
         .map_err(|e| DatabaseError::Config(format!("Failed to write config file: {}", e)))?;
     
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_files_without_newer_settings_load() {
+        let node: NodeConfig = serde_json::from_str(r#"{
+            "node_id": "node2",
+            "listen_addr": "127.0.0.1:9091",
+            "data_dir": "data2",
+            "raft_peers": [],
+            "coordinator_addr": null,
+            "heartbeat_interval_ms": 100,
+            "election_timeout_min_ms": 150,
+            "election_timeout_max_ms": 300,
+            "snapshot_threshold": 1000
+        }"#).unwrap();
+        assert_eq!(node.node_id, NodeId::from("node2"));
+        assert_eq!(node.lock_wait_timeout_ms, NodeConfig::default().lock_wait_timeout_ms);
+        node.validate().unwrap();
+
+        let coordinator: CoordinatorConfig = serde_json::from_str(r#"{
+            "listen_addr": "127.0.0.1:50052",
+            "initial_nodes": [],
+            "metadata_refresh_interval_ms": 1000
+        }"#).unwrap();
+        assert_eq!(coordinator.listen_addr, "127.0.0.1:50052");
+        coordinator.validate().unwrap();
+    }
+}
@@ -1,5 +1,11 @@
 //! Error types for the distributed database system.
+//!
+//! Each variant names one kind of failure, so callers in any crate can
+//! branch on it: clients decide whether to retry with `is_retryable`, the
+//! gRPC layer picks a status code per variant, and variants that concern a
+//! partition, node or key carry it.
 
+use crate::types::NodeId;
 use thiserror::Error;
 
 /// The main error type for the database system.
@@ -14,48 +20,218 @@ pub enum DatabaseError {
     #[error("Deserialization error: {0}")]
     Deserialization(#[from] serde_json::Error),
     
+    /// A call to a peer failed in transit, e.g. the connection dropped or
+    /// the status that came back was not one of ours
     #[error("RPC error: {0}")]
     Rpc(String),
     
-    #[error("Storage error: {0}")]
-    Storage(String),
+    /// A peer could not be reached, or cannot serve requests for now
+    #[error("Unavailable: {0}")]
+    Unavailable(String),
     
-    #[error("Raft error: {0}")]
-    Raft(String),
+    /// The storage engine failed; the key it failed on, if it concerns one
+    #[error("Storage error: {message}")]
+    Storage { key: Option<String>, message: String },
     
-    #[error("SQL parsing error: {0}")]
-    SqlParsing(String),
+    /// Replication failed; the partition and replica concerned, if known
+    #[error("Raft error: {message}")]
+    Raft {
+        partition: Option<u64>,
+        node: Option<NodeId>,
+        message: String,
+    },
+    
+    /// The replica does not lead the partition; the leader and its address,
+    /// if known
+    #[error("Not the leader of {}{}", describe_partition(.partition), describe_leader(.leader))]
+    NotLeader {
+        partition: Option<u64>,
+        leader: Option<(NodeId, String)>,
+    },
     
     #[error("SQL parsing error: {0}")]
     SqlParse(String),
     
+    /// A statement does not fit the schema of its table
+    #[error("Schema error: {0}")]
+    Schema(String),
+    
     #[error("Configuration error: {0}")]
     Config(String),
     
     #[error("Node not found: {0}")]
-    NodeNotFound(String),
+    NodeNotFound(NodeId),
     
-    #[error("Partition error: {0}")]
-    Partition(String),
+    #[error("Key not found: {key}")]
+    KeyNotFound { key: String },
+    
+    /// A partition cannot serve the operation; the partition, if it concerns
+    /// one
+    #[error("Partition error: {message}")]
+    Partition { partition: Option<u64>, message: String },
     
     #[error("Timeout error: {0}")]
     Timeout(String),
     
+    /// A limit was hit, e.g. on queued requests or disk space
+    #[error("Resource exhausted: {0}")]
+    ResourceExhausted(String),
+    
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    
+    #[error("Clock offset error: {0}")]
+    ClockOffset(String),
+    
+    #[error("Transaction error: {0}")]
+    Transaction(String),
+    
+    /// The transaction conflicted with a concurrent one and was aborted;
+    /// running it again from the start may succeed. The key it conflicted
+    /// on, if known.
+    #[error("Conflict: {message}")]
+    Conflict { key: Option<String>, message: String },
+    
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
 
-/// A specialized Result type for database operations.
-pub type Result<T> = std::result::Result<T, DatabaseError>;
+impl DatabaseError {
+    /// Whether retrying the failed operation from the start, after a
+    /// backoff, may succeed: the cluster was briefly unable to serve it, or
+    /// it conflicted with a concurrent one. RPC and Raft errors count, as
+    /// they mostly come from a peer restarting or a leader stepping down.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            DatabaseError::Rpc(_)
+                | DatabaseError::Unavailable(_)
+                | DatabaseError::Timeout(_)
+                | DatabaseError::ResourceExhausted(_)
+                | DatabaseError::Raft { .. }
+                | DatabaseError::NotLeader { .. }
+                | DatabaseError::Conflict { .. }
+        )
+    }
 
-impl From<&str> for DatabaseError {
-    fn from(s: &str) -> Self {
-        DatabaseError::Unknown(s.to_string())
+    /// Whether the operation was aborted on a conflict with a concurrent one.
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, DatabaseError::Conflict { .. })
+    }
+
+    /// Whether the key or node the operation named does not exist.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, DatabaseError::KeyNotFound { .. } | DatabaseError::NodeNotFound(_))
+    }
+
+    /// The leader to send the operation to instead, and its address.
+    pub fn leader_hint(&self) -> Option<&(NodeId, String)> {
+        match self {
+            DatabaseError::NotLeader { leader, .. } => leader.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The partition the error concerns, if known.
+    pub fn partition(&self) -> Option<u64> {
+        match self {
+            DatabaseError::Raft { partition, .. }
+            | DatabaseError::NotLeader { partition, .. }
+            | DatabaseError::Partition { partition, .. } => *partition,
+            _ => None,
+        }
+    }
+
+    /// The node the error concerns, if known.
+    pub fn node(&self) -> Option<&NodeId> {
+        match self {
+            DatabaseError::Raft { node, .. } => node.as_ref(),
+            DatabaseError::NodeNotFound(node) => Some(node),
+            _ => None,
+        }
+    }
+
+    /// The key the error concerns, if known.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            DatabaseError::Storage { key, .. } | DatabaseError::Conflict { key, .. } => key.as_deref(),
+            DatabaseError::KeyNotFound { key } => Some(key),
+            _ => None,
+        }
+    }
+
+    /// The message of the error, without the prefix naming its kind. For
+    /// variants with context it is the key or node concerned, or the whole
+    /// description; `NotLeader` has no message apart from its context, so
+    /// it gets its whole description.
+    pub fn message(&self) -> String {
+        match self {
+            DatabaseError::Io(e) => e.to_string(),
+            DatabaseError::Deserialization(e) => e.to_string(),
+            DatabaseError::NotLeader { .. } => self.to_string(),
+            DatabaseError::NodeNotFound(node) => node.to_string(),
+            DatabaseError::KeyNotFound { key } => key.clone(),
+            DatabaseError::Storage { message, .. }
+            | DatabaseError::Raft { message, .. }
+            | DatabaseError::Partition { message, .. }
+            | DatabaseError::Conflict { message, .. } => message.clone(),
+            DatabaseError::Serialization(message)
+            | DatabaseError::Rpc(message)
+            | DatabaseError::Unavailable(message)
+            | DatabaseError::Timeout(message)
+            | DatabaseError::ResourceExhausted(message)
+            | DatabaseError::SqlParse(message)
+            | DatabaseError::Schema(message)
+            | DatabaseError::Config(message)
+            | DatabaseError::InvalidArgument(message)
+            | DatabaseError::ClockOffset(message)
+            | DatabaseError::Transaction(message)
+            | DatabaseError::Unknown(message) => message.clone(),
+        }
+    }
+}
+
+fn describe_partition(partition: &Option<u64>) -> String {
+    match partition {
+        Some(id) => format!("partition {}", id),
+        None => "the partition".to_string(),
+    }
+}
+
+fn describe_leader(leader: &Option<(NodeId, String)>) -> String {
+    match leader {
+        Some((id, address)) => format!("; the leader is {} at {}", id, address),
+        None => String::new(),
     }
 }
 
-impl From<String> for DatabaseError {
-    fn from(s: String) -> Self {
-        DatabaseError::Unknown(s)
+/// A specialized Result type for database operations.
+pub type Result<T> = std::result::Result<T, DatabaseError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification() {
+        let not_leader = DatabaseError::NotLeader {
+            partition: Some(3),
+            leader: Some((NodeId::from("node2"), "node2:9090".to_string())),
+        };
+        assert!(not_leader.is_retryable());
+        assert_eq!(not_leader.leader_hint().map(|(id, _)| id.0.as_str()), Some("node2"));
+        assert_eq!(not_leader.to_string(), "Not the leader of partition 3; the leader is node2 at node2:9090");
+
+        let conflict = DatabaseError::Conflict { key: Some("user:1".to_string()), message: "write conflict".to_string() };
+        assert!(conflict.is_conflict() && conflict.is_retryable());
+        assert_eq!(conflict.key(), Some("user:1"));
+        assert_eq!(conflict.to_string(), "Conflict: write conflict");
+        assert!(DatabaseError::Unavailable("connection refused".to_string()).is_retryable());
+        assert!(!DatabaseError::InvalidArgument("empty key".to_string()).is_retryable());
+
+        let missing = DatabaseError::KeyNotFound { key: "user:1".to_string() };
+        assert!(missing.is_not_found() && !missing.is_retryable());
+        assert_eq!(missing.message(), "user:1");
+        assert_eq!(not_leader.message(), not_leader.to_string(), "NotLeader is all context");
     }
-}
\ No newline at end of file
+}
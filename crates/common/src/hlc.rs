@@ -0,0 +1,180 @@
+//! Hybrid logical clock.
+//!
+//! An HLC timestamp is a wall-clock reading in milliseconds plus a logical
+//! counter. Every node stamps outgoing messages with its clock and advances
+//! its clock past every timestamp it receives, so timestamps respect
+//! causality even when physical clocks disagree, while staying close to
+//! wall-clock time.
+
+use crate::error::{DatabaseError, Result};
+use crate::util::timestamp_ms;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+
+/// Default bound on how far a remote clock may run ahead of ours.
+pub const DEFAULT_MAX_CLOCK_OFFSET_MS: u64 = 500;
+
+/// A point in hybrid logical time. Ordered by wall time, then logical counter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct HlcTimestamp {
+    /// Physical component, in milliseconds since the epoch.
+    pub wall_ms: u64,
+    /// Orders events that share the same wall time.
+    pub logical: u32,
+}
+
+impl HlcTimestamp {
+    pub const MIN: HlcTimestamp = HlcTimestamp { wall_ms: 0, logical: 0 };
+    pub const MAX: HlcTimestamp = HlcTimestamp { wall_ms: u64::MAX, logical: u32::MAX };
+
+    pub fn new(wall_ms: u64, logical: u32) -> Self {
+        Self { wall_ms, logical }
+    }
+
+    /// The latest timestamp within wall time `wall_ms`, so that reading at it
+    /// sees every event that happened during that millisecond.
+    pub fn latest_at(wall_ms: u64) -> Self {
+        Self { wall_ms, logical: u32::MAX }
+    }
+}
+
+impl fmt::Display for HlcTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.wall_ms, self.logical)
+    }
+}
+
+/// A hybrid logical clock shared by everything running on one process.
+pub struct HybridClock {
+    max_offset_ms: u64,
+    last: Mutex<HlcTimestamp>,
+}
+
+impl HybridClock {
+    /// Create a clock that rejects remote timestamps more than
+    /// `max_offset_ms` ahead of the local wall clock.
+    pub fn new(max_offset_ms: u64) -> Self {
+        Self {
+            max_offset_ms,
+            last: Mutex::new(HlcTimestamp::MIN),
+        }
+    }
+
+    /// Maximum tolerated clock offset in milliseconds.
+    pub fn max_offset_ms(&self) -> u64 {
+        self.max_offset_ms
+    }
+
+    /// Read the clock. Every call returns a timestamp greater than all
+    /// timestamps previously returned or observed.
+    pub fn now(&self) -> HlcTimestamp {
+        self.now_at(timestamp_ms())
+    }
+
+    /// Advance the clock past a timestamp received from another node and
+    /// return the new reading.
+    ///
+    /// Fails without touching the clock when `remote` is further ahead of the
+    /// local wall clock than the maximum offset, since accepting it would drag
+    /// every later timestamp on this node into the future.
+    pub fn update(&self, remote: HlcTimestamp) -> Result<HlcTimestamp> {
+        self.update_at(remote, timestamp_ms())
+    }
+
+    fn now_at(&self, physical_ms: u64) -> HlcTimestamp {
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        *last = if physical_ms > last.wall_ms {
+            HlcTimestamp::new(physical_ms, 0)
+        } else {
+            Self::tick(*last)
+        };
+        *last
+    }
+
+    fn update_at(&self, remote: HlcTimestamp, physical_ms: u64) -> Result<HlcTimestamp> {
+        if remote.wall_ms > physical_ms.saturating_add(self.max_offset_ms) {
+            return Err(DatabaseError::ClockOffset(format!(
+                "Remote timestamp {} is more than {} ms ahead of local time {}",
+                remote, self.max_offset_ms, physical_ms
+            )));
+        }
+
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let latest = (*last).max(remote);
+        *last = if physical_ms > latest.wall_ms {
+            HlcTimestamp::new(physical_ms, 0)
+        } else {
+            Self::tick(latest)
+        };
+        Ok(*last)
+    }
+
+    // The logical counter only overflows after 2^32 events within one
+    // millisecond; borrow the next millisecond rather than wrap
+    fn tick(ts: HlcTimestamp) -> HlcTimestamp {
+        match ts.logical.checked_add(1) {
+            Some(logical) => HlcTimestamp::new(ts.wall_ms, logical),
+            None => HlcTimestamp::new(ts.wall_ms + 1, 0),
+        }
+    }
+}
+
+impl Default for HybridClock {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CLOCK_OFFSET_MS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_now_is_monotonic() {
+        let clock = HybridClock::new(500);
+
+        let a = clock.now_at(100);
+        let b = clock.now_at(100);
+        let c = clock.now_at(90);
+        let d = clock.now_at(101);
+
+        assert_eq!(a, HlcTimestamp::new(100, 0));
+        assert!(a < b && b < c && c < d, "{} {} {} {}", a, b, c, d);
+        assert_eq!(d, HlcTimestamp::new(101, 0));
+    }
+
+    #[test]
+    fn test_update_moves_past_remote() {
+        let clock = HybridClock::new(500);
+        clock.now_at(100);
+
+        // A remote clock slightly ahead pulls ours forward
+        let remote = HlcTimestamp::new(300, 7);
+        let updated = clock.update_at(remote, 100).unwrap();
+        assert!(updated > remote);
+        assert!(clock.now_at(100) > updated);
+
+        // A remote clock behind ours leaves it ahead
+        let updated = clock.update_at(HlcTimestamp::new(50, 0), 100).unwrap();
+        assert!(updated > remote);
+    }
+
+    #[test]
+    fn test_update_rejects_excessive_offset() {
+        let clock = HybridClock::new(500);
+        let before = clock.now_at(1_000);
+
+        assert!(clock.update_at(HlcTimestamp::new(1_501, 0), 1_000).is_err());
+        assert!(clock.update_at(HlcTimestamp::new(1_500, 0), 1_000).is_ok());
+        assert!(before < clock.now_at(1_000));
+    }
+
+    #[test]
+    fn test_logical_overflow() {
+        let clock = HybridClock::new(500);
+        clock.update_at(HlcTimestamp::new(100, u32::MAX - 1), 100).unwrap();
+
+        assert_eq!(clock.now_at(100), HlcTimestamp::new(101, 0));
+    }
+}
@@ -4,8 +4,12 @@ pub mod error;
 pub mod types;
 pub mod config;
 pub mod util;
+pub mod hlc;
 
 // Re-export commonly used items
 pub use error::{DatabaseError, Result};
-pub use types::{KeyRange, PartitionInfo, NodeId, LogEntry};
+pub use types::{KeyRange, PartitionInfo, NodeId, LogEntry, ScanToken, Command, CommandResponse, BatchOperation, ClusterMetadata, VersionedValue};
+pub use types::{TransactionId, TransactionMeta, TransactionRecord, TransactionStatus, Intent, IsolationLevel, WaitForEdge};
+pub use types::ReadConsistency;
+pub use hlc::{HlcTimestamp, HybridClock};
 pub use config::{NodeConfig, CoordinatorConfig, load_config};
@@ -2,8 +2,10 @@
 
 use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use chrono::{DateTime, Utc};
+use crate::error::{DatabaseError, Result};
+use crate::hlc::HlcTimestamp;
 
 /// A unique identifier for a node in the cluster.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -27,8 +29,18 @@ impl From<&str> for NodeId {
     }
 }
 
+/// Raft group of the cluster metadata. Partition ids start at 1, so no
+/// partition shares it.
+pub const META_GROUP_ID: u64 = 0;
+
+/// Key of the cluster metadata in the meta group.
+pub const CLUSTER_METADATA_KEY: &str = "\0meta/cluster";
+
+/// Key of the lease that lets one coordinator run the background jobs.
+pub const COORDINATOR_LEASE_KEY: &str = "\0meta/lease";
+
 /// Represents a range of keys.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct KeyRange {
     pub start: String,
     pub end: String,
@@ -46,6 +58,22 @@ impl KeyRange {
     pub fn contains(&self, key: &str) -> bool {
         key >= self.start.as_str() && key < self.end.as_str() //needs to be properly derefernced otherwise crates errors
     }
+
+    /// Check if every key of `other` is within this range.
+    pub fn covers(&self, other: &KeyRange) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    /// Keys served by the meta group. User keys must not start with a NUL
+    /// byte, so every partition range lies above it.
+    pub fn meta() -> Self {
+        Self::new("\0", "\u{1}")
+    }
+
+    /// Keys of the rows of SQL table `table`: everything behind `{table}/`
+    pub fn table(table: &str) -> Self {
+        Self::new(format!("{}/", table), format!("{}0", table))
+    }
 }
 
 /// Information about a partition in the database.
@@ -54,7 +82,114 @@ pub struct PartitionInfo {
     pub id: u64,
     pub range: KeyRange,
     pub leader: NodeId,
+    /// Voting replicas other than the leader.
     pub followers: Vec<NodeId>,
+    /// Replicas that receive the log without voting, e.g. while catching up.
+    #[serde(default)]
+    pub learners: Vec<NodeId>,
+}
+
+impl PartitionInfo {
+    /// The voting replicas, leader first.
+    pub fn voters(&self) -> Vec<NodeId> {
+        std::iter::once(self.leader.clone()).chain(self.followers.iter().cloned()).collect()
+    }
+
+    /// Whether `node` holds a replica of this partition, voting or not.
+    pub fn has_replica(&self, node: &NodeId) -> bool {
+        self.leader == *node || self.followers.contains(node) || self.learners.contains(node)
+    }
+}
+
+/// The replicas of a Raft group and the addresses of their nodes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Membership {
+    /// Voter sets that must each reach a majority: one normally, the old
+    /// and the new set during a joint consensus change.
+    pub configs: Vec<Vec<NodeId>>,
+    /// Replicas that receive the log without voting.
+    pub learners: Vec<NodeId>,
+    pub addresses: HashMap<NodeId, String>,
+}
+
+impl Membership {
+    /// The replicas of `partition`, whose nodes listen at `addresses`.
+    pub fn of_partition(partition: &PartitionInfo, addresses: HashMap<NodeId, String>) -> Self {
+        Self {
+            configs: vec![partition.voters()],
+            learners: partition.learners.clone(),
+            addresses,
+        }
+    }
+
+    /// Whether `node` votes in any of the voter sets.
+    pub fn is_voter(&self, node: &NodeId) -> bool {
+        self.configs.iter().any(|voters| voters.contains(node))
+    }
+
+    /// Every replica, voting or not, each once.
+    pub fn replicas(&self) -> Vec<NodeId> {
+        let mut replicas: Vec<NodeId> = Vec::new();
+        for node in self.configs.iter().flatten().chain(&self.learners) {
+            if !replicas.contains(node) {
+                replicas.push(node.clone());
+            }
+        }
+        replicas
+    }
+
+    /// Whether the nodes for which `agrees` holds form a majority of every voter set.
+    pub fn is_quorum(&self, agrees: impl Fn(&NodeId) -> bool) -> bool {
+        !self.configs.is_empty()
+            && self.configs.iter().all(|voters| voters.iter().filter(|node| agrees(node)).count() * 2 > voters.len())
+    }
+}
+
+/// Continuation state for a paginated scan.
+///
+/// The token records only the last key returned and the timestamp the first
+/// page was read at. It names no partition or node, so a scan can resume after
+/// the range has been split or its leader has moved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanToken {
+    pub last_key: String,
+    pub read_timestamp: HlcTimestamp,
+}
+
+impl ScanToken {
+    pub fn new(last_key: impl Into<String>, read_timestamp: HlcTimestamp) -> Self {
+        Self {
+            last_key: last_key.into(),
+            read_timestamp,
+        }
+    }
+
+    /// The smallest key strictly greater than `last_key`, where the next page starts.
+    pub fn resume_key(&self) -> String {
+        format!("{}\0", self.last_key)
+    }
+
+    /// Encode the token as an opaque string for clients.
+    pub fn encode(&self) -> String {
+        let bytes = serde_json::to_vec(self).unwrap_or_default();
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Decode a token previously produced by `encode`.
+    pub fn decode(token: &str) -> Result<Self> {
+        let invalid = || DatabaseError::InvalidArgument(format!("Invalid continuation token: {}", token));
+
+        if !token.len().is_multiple_of(2) || !token.is_ascii() {
+            return Err(invalid());
+        }
+        let bytes = (0..token.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&token[i..i + 2], 16))
+            .collect::<std::result::Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+
+        serde_json::from_slice(&bytes).map_err(|_| invalid())
+    }
 }
 
 /// A log entry in the Raft consensus algorithm.
@@ -63,25 +198,463 @@ pub struct LogEntry {
     pub term: u64,
     pub index: u64,
     pub command: Command,
-    pub timestamp: DateTime<Utc>,
+    /// Assigned by the leader's hybrid logical clock when the entry is proposed.
+    pub timestamp: HlcTimestamp,
 }
 
 /// Commands that can be executed on the database.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
-    Write { key: String, value: Vec<u8> },
+    /// Appended by a new leader to commit an entry of its own term.
+    Noop,
+    /// Store a value; `expires_at` is an absolute expiry in ms since the epoch.
+    Write { key: String, value: Vec<u8>, expires_at: Option<u64> },
     Delete { key: String },
+    /// Puts and deletes applied atomically as a single log entry.
+    Batch { operations: Vec<BatchOperation> },
+    /// Write only if the key does not exist.
+    PutIfAbsent { key: String, value: Vec<u8> },
+    /// Write only if the key's current version equals `expected_version` (0 = absent).
+    PutIfVersion { key: String, value: Vec<u8>, expected_version: u64 },
+    /// Delete only if the key currently holds `expected_value`.
+    DeleteIfValue { key: String, expected_value: Vec<u8> },
+    /// Add `delta` to the integer stored under the key, treating a missing key as 0.
+    Increment { key: String, delta: i64 },
+    /// Lay down write intents for the keys of a transaction that this
+    /// partition owns. When the operations include the primary key, the
+    /// transaction record is created too, listing `txn_keys`, every key the
+    /// transaction writes, so the transaction can be recovered.
+    Prewrite { txn: TransactionMeta, operations: Vec<BatchOperation>, txn_keys: Vec<String> },
+    /// Finalize the transaction record: commit at `commit_ts`, or abort when `None`.
+    EndTransaction { txn: TransactionMeta, commit_ts: Option<HlcTimestamp> },
+    /// Turn the transaction's intents on `keys` into committed versions at
+    /// `commit_ts`, or discard them when `None`.
+    ResolveIntents { txn_id: TransactionId, keys: Vec<String>, commit_ts: Option<HlcTimestamp> },
+    /// Abort the transaction if its record was last touched before
+    /// `abandoned_before_ms`, and report the record's status either way.
+    RecoverTransaction { txn: TransactionMeta, abandoned_before_ms: u64 },
+    /// Check that nothing wrote `keys`, or any key in `ranges`, since the
+    /// transaction started: no version committed after `start_ts` and no
+    /// intent of another transaction. Used to validate the reads and scans
+    /// of serializable transactions; checking whole ranges catches rows
+    /// inserted into a range the transaction scanned.
+    ValidateReads {
+        txn: TransactionMeta,
+        keys: Vec<String>,
+        #[serde(default)]
+        ranges: Vec<KeyRange>,
+    },
     CreatePartition { partition: PartitionInfo },
     UpdatePartition { partition: PartitionInfo },
     DeletePartition { partition_id: u64 },
+    /// Split a partition in two at `right.range.start`: the group keeps
+    /// `left`, and a new group with the same replicas takes over `right`.
+    /// Applied in the log of the partition being split, so every command is
+    /// ordered either before the split or after it.
+    SplitPartition { left: PartitionInfo, right: PartitionInfo },
+    /// First step of a merge, in the log of the right partition: stop
+    /// applying commands, which are answered with the range of `merged` from
+    /// then on.
+    FreezeForMerge { merged: PartitionInfo },
+    /// Second step of a merge, in the log of the left partition: take over
+    /// the range of the frozen right partition `right_id`. A replica applies
+    /// it only once its copy of the right partition applied the freeze, so
+    /// it holds all of the right partition's data.
+    MergePartitions { merged: PartitionInfo, right_id: u64 },
+    /// Move the Raft group to `membership`. Takes effect as soon as it is
+    /// in a replica's log, without waiting for the commit; a joint
+    /// configuration is followed by the new voter set alone once committed.
+    ChangeMembership { membership: Membership },
+}
+
+impl Command {
+    /// Keys a plain, non-transactional write touches; empty for other commands.
+    pub fn write_keys(&self) -> Vec<&str> {
+        match self {
+            Command::Write { key, .. }
+            | Command::Delete { key }
+            | Command::PutIfAbsent { key, .. }
+            | Command::PutIfVersion { key, .. }
+            | Command::DeleteIfValue { key, .. }
+            | Command::Increment { key, .. } => vec![key],
+            Command::Batch { operations } => operations.iter().map(|op| op.key()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Keys the command reads or writes, which the partition applying it must own.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Prewrite { operations, .. } => operations.iter().map(|op| op.key()).collect(),
+            Command::EndTransaction { txn, .. } | Command::RecoverTransaction { txn, .. } => vec![&txn.primary_key],
+            Command::ResolveIntents { keys, .. } | Command::ValidateReads { keys, .. } => {
+                keys.iter().map(String::as_str).collect()
+            },
+            command => command.write_keys(),
+        }
+    }
+}
+
+/// How up to date a read must be.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReadConsistency {
+    /// Served by the Raft leader; sees every write acknowledged before the
+    /// read started.
+    #[default]
+    Linearizable,
+    /// May be served by a follower, as long as it is at most
+    /// `max_staleness_ms` behind the leader.
+    Stale { max_staleness_ms: u64 },
+}
+
+/// A value together with the version of the write that produced it.
+///
+/// The version is the Raft log index of the last write to the key, so it
+/// grows with every update. Version 0 means the key does not exist.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionedValue {
+    pub value: Vec<u8>,
+    pub version: u64,
+}
+
+/// Outcome of applying a command to the state machine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandResponse {
+    /// Whether the command took effect; false when a condition did not hold.
+    pub succeeded: bool,
+    /// The key's value after the command, or the value that failed the condition.
+    pub current: Option<VersionedValue>,
+    /// The transaction whose intent blocked the command, if any.
+    pub conflict: Option<TransactionMeta>,
+    /// The key a transaction command conflicted on, if it did.
+    #[serde(default)]
+    pub conflict_key: Option<String>,
+    /// The transaction record, for transaction commands.
+    pub transaction: Option<TransactionRecord>,
+    /// The partition's range, when the command has keys outside it because
+    /// it was routed before a split.
+    #[serde(default)]
+    pub out_of_range: Option<KeyRange>,
+}
+
+impl CommandResponse {
+    /// The command was applied.
+    pub fn applied(current: Option<VersionedValue>) -> Self {
+        Self { succeeded: true, current, conflict: None, conflict_key: None, transaction: None, out_of_range: None }
+    }
+
+    /// The command's condition did not hold; nothing was written.
+    pub fn rejected(current: Option<VersionedValue>) -> Self {
+        Self { succeeded: false, current, conflict: None, conflict_key: None, transaction: None, out_of_range: None }
+    }
+
+    /// A key is locked by another transaction's intent; nothing was written.
+    pub fn conflict(txn: TransactionMeta) -> Self {
+        Self { succeeded: false, current: None, conflict: Some(txn), conflict_key: None, transaction: None, out_of_range: None }
+    }
+
+    /// Name the key the command conflicted on.
+    pub fn on_key(mut self, key: &str) -> Self {
+        self.conflict_key = Some(key.to_string());
+        self
+    }
+
+    /// The command has keys outside the partition's `range`; nothing was written.
+    pub fn out_of_range(range: KeyRange) -> Self {
+        Self { succeeded: false, current: None, conflict: None, conflict_key: None, transaction: None, out_of_range: Some(range) }
+    }
+
+    /// Outcome of a transaction command, carrying the transaction record.
+    pub fn transaction(succeeded: bool, record: Option<TransactionRecord>) -> Self {
+        Self { succeeded, current: None, conflict: None, conflict_key: None, transaction: record, out_of_range: None }
+    }
+}
+
+/// A unique identifier for a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TransactionId(pub String);
+
+impl TransactionId {
+    pub fn generate() -> Self {
+        TransactionId(uuid::Uuid::new_v4().to_string())
+    }
+}
+
+impl fmt::Display for TransactionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Identity of a transaction, carried by each of its intents.
+///
+/// The primary key is one of the keys the transaction writes; its partition
+/// holds the transaction record, which is the single source of truth for
+/// whether the transaction committed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionMeta {
+    pub id: TransactionId,
+    pub primary_key: String,
+    /// Timestamp the transaction reads at.
+    pub start_ts: HlcTimestamp,
+}
+
+/// Isolation level of a transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IsolationLevel {
+    /// Reads see a snapshot as of the start timestamp and concurrent writes
+    /// to the same key conflict. Allows write skew.
+    #[default]
+    Snapshot,
+    /// Snapshot isolation plus validation of the read set at commit, so that
+    /// transactions behave as if they ran one at a time.
+    Serializable,
+}
+
+/// Lifecycle of a transaction record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionStatus {
+    Pending,
+    Committed,
+    Aborted,
+}
+
+/// Durable state of a transaction, stored next to its primary key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    pub meta: TransactionMeta,
+    pub status: TransactionStatus,
+    pub commit_ts: Option<HlcTimestamp>,
+    /// Every key the transaction writes, across all partitions.
+    pub keys: Vec<String>,
+    /// Wall time (ms) of the last write to the record.
+    pub last_active_ms: u64,
+}
+
+/// A provisional write of a transaction that has not been resolved yet.
+/// A `None` value is a provisional delete.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Intent {
+    pub txn: TransactionMeta,
+    pub value: Option<Vec<u8>>,
+}
+
+/// What a read found: the data, or the pending transaction whose intent on
+/// `key` hides it and must be settled before the key can be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadOutcome<T> {
+    Read(T),
+    Blocked { key: String, txn: TransactionMeta },
+}
+
+/// A transaction waiting for a lock held by another transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WaitForEdge {
+    pub waiter: TransactionId,
+    /// Start timestamp of the waiter; the youngest transaction of a deadlock is aborted.
+    pub waiter_start_ts: HlcTimestamp,
+    pub holder: TransactionId,
+    pub key: String,
+}
+
+/// Size and load of a partition, reported by its leader for split decisions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionStats {
+    pub partition_id: u64,
+    /// Approximate size of the live keys and values
+    pub size_bytes: u64,
+    pub key_count: u64,
+    /// Requests per second, averaged over the recent past
+    pub qps: f64,
+    /// Key that divides the partition's data in two halves of about equal
+    /// size; `None` if the partition holds fewer than two keys
+    pub split_key: Option<String>,
+}
+
+/// Heartbeat from the leader of one Raft group, sent in a batch with the
+/// heartbeats of the other groups the same node leads.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupHeartbeat {
+    pub group_id: u64,
+    pub term: u64,
+    pub leader: NodeId,
+    pub leader_commit: u64,
+    /// Leader time (ms) when the heartbeat was sent, for stale reads
+    pub leader_ms: u64,
+}
+
+/// A follower's answer to a `GroupHeartbeat`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupHeartbeatAck {
+    pub group_id: u64,
+    pub term: u64,
+    /// False if the follower knows a newer term than the heartbeat's, or
+    /// has yet to receive entries the leader committed
+    pub success: bool,
+}
+
+/// A single write within a `Command::Batch`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BatchOperation {
+    Put { key: String, value: Vec<u8> },
+    Delete { key: String },
+}
+
+impl BatchOperation {
+    /// The key this operation writes.
+    pub fn key(&self) -> &str {
+        match self {
+            BatchOperation::Put { key, .. } => key,
+            BatchOperation::Delete { key } => key,
+        }
+    }
 }
 
 /// Metadata about the cluster.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ClusterMetadata {
     pub nodes: HashMap<NodeId, NodeInfo>,
     pub partitions: HashMap<u64, PartitionInfo>,
+    /// Bumped on every change. Published with the partition map, so clients
+    /// routing on a copy can tell whether it is older than the coordinator's.
     pub version: u64,
+    #[serde(default)]
+    pub constraints: Vec<PlacementConstraints>,
+    /// Lowest partition id never handed out. Ids are not reused, since
+    /// replicas of a merged-away partition may still be around under its id.
+    #[serde(default)]
+    pub next_partition_id: u64,
+    /// Nodes whose leaderships are moved away for maintenance
+    #[serde(default)]
+    pub draining: HashSet<NodeId>,
+    /// SQL tables by name
+    #[serde(default)]
+    pub tables: HashMap<String, TableSchema>,
+}
+
+/// The columns of a SQL table, in the order they were defined.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableSchema {
+    pub columns: Vec<ColumnSchema>,
+}
+
+impl TableSchema {
+    /// The column whose value identifies a row.
+    pub fn primary_key(&self) -> Option<&ColumnSchema> {
+        self.columns.iter().find(|column| column.primary_key)
+    }
+
+    pub fn column(&self, name: &str) -> Option<&ColumnSchema> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub data_type: ColumnType,
+    pub nullable: bool,
+    pub primary_key: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColumnType {
+    Int,
+    Float,
+    String,
+    Bool,
+    Blob,
+    Timestamp,
+}
+
+/// Where requests for the keys of a partition go.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionRoute {
+    pub partition_id: u64,
+    pub range: KeyRange,
+    pub leader: NodeId,
+    /// Address of the leader; empty if it is unknown
+    pub leader_address: String,
+}
+
+impl ClusterMetadata {
+    /// Hand out a partition id no partition has had before.
+    pub fn allocate_partition_id(&mut self) -> u64 {
+        let highest = self.partitions.keys().max().map_or(0, |id| id + 1);
+        let id = self.next_partition_id.max(highest).max(1);
+        self.next_partition_id = id + 1;
+        id
+    }
+
+    /// Find the partition whose range contains `key`.
+    pub fn partition_for_key(&self, key: &str) -> Option<&PartitionInfo> {
+        self.partitions.values().find(|partition| partition.range.contains(key))
+    }
+
+    /// The route of a partition, to its leader.
+    pub fn route(&self, partition: &PartitionInfo) -> PartitionRoute {
+        PartitionRoute {
+            partition_id: partition.id,
+            range: partition.range.clone(),
+            leader: partition.leader.clone(),
+            leader_address: self.nodes.get(&partition.leader).map(|info| info.address.clone()).unwrap_or_default(),
+        }
+    }
+
+    /// The placement constraints of a partition: those of the narrowest
+    /// constrained range covering it.
+    pub fn constraints_for(&self, partition: &PartitionInfo) -> Option<&PlacementConstraints> {
+        self.constraints.iter()
+            .filter(|constraints| constraints.range.covers(&partition.range))
+            .min_by(|a, b| b.range.start.cmp(&a.range.start).then(a.range.end.cmp(&b.range.end)))
+    }
+
+    /// Locality of a node; empty for unknown nodes.
+    pub fn locality(&self, node: &NodeId) -> Locality {
+        self.nodes.get(node).map(|info| info.locality.clone()).unwrap_or_default()
+    }
+}
+
+/// Where the replicas of a key range may live.
+///
+/// For example, three replicas across three zones with the leader in
+/// zone-a is `num_replicas: Some(3)`, `spread_over: Some("zone")` and
+/// `leader_locality: zone=zone-a`. Constraints of a table cover the key
+/// range of its rows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlacementConstraints {
+    pub range: KeyRange,
+    /// SQL table the constraints were set for, whose rows `range` holds
+    #[serde(default)]
+    pub table: Option<String>,
+    /// Number of voting replicas; `None` uses the cluster's replication factor
+    pub num_replicas: Option<usize>,
+    /// Locality tier whose values no two voters may share, e.g. "zone"
+    pub spread_over: Option<String>,
+    /// Tiers every replica's node must match
+    pub required: Locality,
+    /// Tiers the leader's node should match
+    pub leader_locality: Locality,
+}
+
+impl PlacementConstraints {
+    /// Constraints of `range` that leave everything to the defaults.
+    pub fn new(range: KeyRange) -> Self {
+        Self {
+            range,
+            table: None,
+            num_replicas: None,
+            spread_over: None,
+            required: Locality::default(),
+            leader_locality: Locality::default(),
+        }
+    }
+
+    /// Constraints of the rows of SQL table `table` that leave everything
+    /// to the defaults.
+    pub fn for_table(table: &str) -> Self {
+        Self { table: Some(table.to_string()), ..Self::new(KeyRange::table(table)) }
+    }
 }
 
 /// Information about a node in the cluster.
@@ -91,13 +664,203 @@ pub struct NodeInfo {
     pub address: String,
     pub status: NodeStatus,
     pub last_heartbeat: Option<DateTime<Utc>>,
+    /// Disk usage and request rate last reported by the node
+    #[serde(default)]
+    pub load: NodeLoad,
+    #[serde(default)]
+    pub locality: Locality,
+}
+
+/// Where a node runs, as tiers from the outermost in, e.g.
+/// `region=us-east,zone=us-east-1a,rack=r12`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Locality {
+    pub tiers: Vec<LocalityTier>,
+}
+
+/// One tier of a locality, such as `zone=us-east-1a`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalityTier {
+    pub key: String,
+    pub value: String,
+}
+
+impl Locality {
+    /// Parse tiers written as `key=value` pairs separated by commas.
+    pub fn parse(s: &str) -> Result<Self> {
+        let tiers = s.split(',')
+            .map(str::trim)
+            .filter(|tier| !tier.is_empty())
+            .map(|tier| match tier.split_once('=') {
+                Some((key, value)) if !key.is_empty() && !value.is_empty() => {
+                    Ok(LocalityTier { key: key.to_string(), value: value.to_string() })
+                },
+                _ => Err(DatabaseError::InvalidArgument(format!("Invalid locality tier {}", tier))),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { tiers })
+    }
+
+    /// Value of the tier `key`, if the locality has it.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.tiers.iter().find(|tier| tier.key == key).map(|tier| tier.value.as_str())
+    }
+
+    /// Whether this locality has every tier of `other`.
+    pub fn matches(&self, other: &Locality) -> bool {
+        other.tiers.iter().all(|tier| self.get(&tier.key) == Some(tier.value.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiers.is_empty()
+    }
+}
+
+impl fmt::Display for Locality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tiers: Vec<String> = self.tiers.iter().map(|tier| format!("{}={}", tier.key, tier.value)).collect();
+        write!(f, "{}", tiers.join(","))
+    }
+}
+
+/// Disk usage and request rate of a node, for replica placement.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeLoad {
+    pub disk_used_bytes: u64,
+    /// Zero if unknown
+    pub disk_capacity_bytes: u64,
+    /// Requests per second over all replicas on the node
+    pub qps: f64,
+}
+
+impl NodeLoad {
+    /// Fraction of the disk in use; zero if the capacity is unknown.
+    pub fn disk_usage(&self) -> f64 {
+        if self.disk_capacity_bytes == 0 {
+            return 0.0;
+        }
+        self.disk_used_bytes as f64 / self.disk_capacity_bytes as f64
+    }
 }
 
 /// The status of a node in the cluster.
+///
+/// A node joins, and is active once it heartbeats the coordinator. A silent
+/// node, joining or active, turns suspect and then dead, and is active again
+/// once it reports. A decommissioned node is leaving until its replicas have
+/// moved off, and then removed for good; aborting the decommissioning makes
+/// it active again. Operators can take an active node out of service as
+/// inactive. Inactive and leaving nodes that go silent are dead as well, so
+/// their replicas are repaired like those of any other dead node. A node
+/// that died for good is decommissioned like any other, or removed at once
+/// if it no longer holds replicas.
+///
+/// `can_become` lists the exact transitions allowed.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NodeStatus {
+    /// Serving and taking new replicas
     Active,
+    /// Taken out of service by an operator; keeps its replicas but takes no
+    /// new ones
     Inactive,
+    /// Registered but not heard from yet
     Joining,
+    /// Being decommissioned; its replicas are moved off
     Leaving,
-}
\ No newline at end of file
+    /// Missed heartbeats for longer than the suspect timeout
+    Suspect,
+    /// Missed heartbeats for longer than the dead timeout; its replicas are
+    /// replaced on other nodes
+    Dead,
+    /// Decommissioned: holds no replicas and takes none
+    Removed,
+}
+
+impl NodeStatus {
+    /// Whether a node may go from this status to `next`
+    pub fn can_become(&self, next: &NodeStatus) -> bool {
+        use NodeStatus::*;
+        matches!(
+            (self, next),
+            (Joining, Active | Suspect | Dead)
+                | (Active, Inactive | Suspect | Dead | Leaving)
+                | (Inactive, Active | Dead | Leaving)
+                | (Suspect, Active | Dead | Leaving)
+                | (Dead, Active | Leaving | Removed)
+                | (Leaving, Active | Dead | Removed)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_token_roundtrip() {
+        let token = ScanToken::new("user:42", HlcTimestamp::new(1_700_000_000_000, 3));
+        let decoded = ScanToken::decode(&token.encode()).unwrap();
+
+        assert_eq!(decoded, token);
+        assert!(decoded.resume_key().as_str() > "user:42");
+        assert!(decoded.resume_key().as_str() < "user:420");
+    }
+
+    #[test]
+    fn test_scan_token_rejects_garbage() {
+        assert!(ScanToken::decode("not-a-token").is_err());
+        assert!(ScanToken::decode("abc").is_err());
+        assert!(ScanToken::decode("7b7d").is_err());
+    }
+
+    #[test]
+    fn test_locality() {
+        let locality = Locality::parse("region=us-east, zone=us-east-1a,rack=r12").unwrap();
+        assert_eq!(locality.get("zone"), Some("us-east-1a"));
+        assert_eq!(locality.to_string(), "region=us-east,zone=us-east-1a,rack=r12");
+        assert!(locality.matches(&Locality::parse("zone=us-east-1a").unwrap()));
+        assert!(!locality.matches(&Locality::parse("zone=us-east-1b").unwrap()));
+        assert!(locality.matches(&Locality::default()));
+        assert!(Locality::parse("zone").is_err());
+        assert!(Locality::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_narrowest_constraints_apply() {
+        let partition = PartitionInfo {
+            id: 1,
+            range: KeyRange::new("users/a", "users/b"),
+            leader: NodeId::from("node1"),
+            followers: vec![],
+            learners: vec![],
+        };
+        let mut metadata = ClusterMetadata::default();
+        assert!(metadata.constraints_for(&partition).is_none());
+
+        metadata.constraints.push(PlacementConstraints::new(KeyRange::new("a", "z")));
+        metadata.constraints.push(PlacementConstraints::new(KeyRange::new("users/", "users0")));
+        metadata.constraints.push(PlacementConstraints::new(KeyRange::new("users/b", "users/c")));
+        assert_eq!(metadata.constraints_for(&partition).unwrap().range, KeyRange::new("users/", "users0"));
+    }
+
+    #[test]
+    fn test_node_status_transitions() {
+        assert!(NodeStatus::Joining.can_become(&NodeStatus::Active));
+        assert!(NodeStatus::Suspect.can_become(&NodeStatus::Dead));
+        assert!(NodeStatus::Dead.can_become(&NodeStatus::Active));
+        assert!(NodeStatus::Leaving.can_become(&NodeStatus::Removed));
+        assert!(NodeStatus::Inactive.can_become(&NodeStatus::Dead));
+        assert!(NodeStatus::Leaving.can_become(&NodeStatus::Dead));
+        assert!(!NodeStatus::Inactive.can_become(&NodeStatus::Suspect));
+        assert!(NodeStatus::Joining.can_become(&NodeStatus::Suspect));
+        assert!(NodeStatus::Joining.can_become(&NodeStatus::Dead));
+        assert!(NodeStatus::Dead.can_become(&NodeStatus::Leaving), "Dead nodes can be decommissioned");
+        assert!(NodeStatus::Dead.can_become(&NodeStatus::Removed), "Dead nodes without replicas can be removed");
+        assert!(!NodeStatus::Dead.can_become(&NodeStatus::Suspect));
+        assert!(!NodeStatus::Active.can_become(&NodeStatus::Removed), "Nodes are removed by decommissioning them");
+        assert!(!NodeStatus::Active.can_become(&NodeStatus::Active));
+        for status in [NodeStatus::Active, NodeStatus::Joining, NodeStatus::Leaving, NodeStatus::Dead] {
+            assert!(!NodeStatus::Removed.can_become(&status), "Removed nodes stay removed");
+        }
+    }
+}
@@ -4,7 +4,6 @@
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::path::PathBuf;
 use crate::error::Result;
-use crate::types::NodeId;
 use log::info;
 
 /// Generate a timestamp in milliseconds.
@@ -26,14 +25,14 @@ pub fn ensure_dir_exists(path: impl Into<PathBuf>) -> Result<PathBuf> {
 }
 
 /// Initialize the logger.
-pub fn init_logger(node_id: &NodeId) {
+pub fn init_logger(name: &str) {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
         .format_timestamp_millis()
         .format_module_path(true)
         .format_target(false)
         .init();
     
-    info!("Logger initialized for node: {}", node_id);
+    info!("Logger initialized for {}", name);
 }
 
 /// Generate a random delay within a range.
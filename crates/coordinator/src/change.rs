@@ -0,0 +1,278 @@
+//! Partition changes run step by step.
+//!
+//! Splits, merges and replica moves take several requests to partition
+//! leaders, some of which wait for a learner to catch up or a new leader to
+//! win its election. Each change is carried out as a series of steps with
+//! one request each. A step is planned from the partition map as it is, its
+//! request is sent, and once the leader reports it done the step's outcome
+//! is recorded in the partition map, from which the next step is planned.
+//!
+//! The background jobs run these steps holding the coordinator lock only to
+//! plan and to record them, so client requests are served while a learner
+//! catches up. A partition with a step in flight is not changed otherwise
+//! until the step is recorded.
+//!
+//! With a meta group, each step is planned from the stored metadata, so a
+//! coordinator whose copy is stale fails before sending any request, and
+//! its outcome is saved as soon as it is recorded. A step done by the data
+//! nodes is never undone: should another coordinator have saved meanwhile,
+//! the step is recorded again on top of the stored copy.
+
+use crate::rebalance::ReplicaMove;
+use crate::transport::{propose_on_leader, NodeTransport};
+use crate::Coordinator;
+use common::error::{DatabaseError, Result};
+use common::types::{Command, NodeId, PartitionInfo};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Times a step done by the data nodes is recorded again on top of metadata
+/// other coordinators saved meanwhile, before giving up on saving it
+const MAX_SAVE_ATTEMPTS: usize = 3;
+
+/// A change of the partition map that needs requests to partition leaders
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartitionChange {
+    /// Split a partition at a key, which becomes the first key of the right half
+    Split { partition_id: u64, split_key: String },
+    /// Merge a partition into its left neighbour
+    Merge { left_id: u64, right_id: u64 },
+    /// Move a voting replica to another node
+    MoveReplica(ReplicaMove),
+    /// Move the leadership of a partition to one of its voters
+    MoveLeader { partition_id: u64, target: NodeId },
+}
+
+/// The request of one step
+#[derive(Debug, Clone)]
+pub(crate) enum StepRequest {
+    AddLearner { node: NodeId, address: String },
+    ChangeMembership(PartitionInfo),
+    TransferLeader(NodeId),
+    /// Commands proposed in order, each to the given partition
+    Propose(Vec<(u64, Command)>),
+}
+
+/// One step of a partition change: a request to the leader of a partition,
+/// and the partitions to record once it is done.
+pub struct Step {
+    /// The transport and the leader's address; `None` without a transport
+    leader: Option<(Arc<dyn NodeTransport>, String)>,
+    partition_id: u64,
+    request: StepRequest,
+    updated: Vec<PartitionInfo>,
+    removed: Option<u64>,
+}
+
+impl Step {
+    /// Also remove partition `partition_id` from the partition map
+    pub(crate) fn removing(mut self, partition_id: u64) -> Self {
+        self.removed = Some(partition_id);
+        self
+    }
+
+    /// Ids of the partitions the step changes
+    fn partitions(&self) -> Vec<u64> {
+        std::iter::once(self.partition_id)
+            .chain(self.updated.iter().map(|partition| partition.id))
+            .chain(self.removed)
+            .collect()
+    }
+
+    /// Send the step's request. Without a transport there is nothing to send.
+    pub async fn run(&self) -> Result<()> {
+        let Some((transport, leader)) = &self.leader else {
+            return Ok(());
+        };
+        match &self.request {
+            StepRequest::AddLearner { node, address } => {
+                transport.add_learner(leader, self.partition_id, node, address).await
+            },
+            StepRequest::ChangeMembership(target) => transport.change_membership(leader, target).await,
+            StepRequest::TransferLeader(target) => transport.transfer_leader(leader, self.partition_id, target).await,
+            StepRequest::Propose(commands) => {
+                for (partition_id, command) in commands {
+                    propose_on_leader(transport.as_ref(), leader.clone(), *partition_id, command).await?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Coordinator {
+    /// Carry out `change`, holding the coordinator throughout. Returns the
+    /// partitions recorded by its last step.
+    pub async fn run_change(&mut self, change: &PartitionChange) -> Result<Vec<PartitionInfo>> {
+        self.sync_metadata().await?;
+        self.check_change(change)?;
+        let mut changed = Vec::new();
+        while let Some(step) = self.next_step(change)? {
+            changed = self.run_step(step).await?;
+        }
+        Ok(changed)
+    }
+
+    /// Reject a change that cannot be made to the partition map as it is
+    pub fn check_change(&self, change: &PartitionChange) -> Result<()> {
+        match change {
+            PartitionChange::Split { partition_id, split_key } => {
+                self.check_split(&self.partition(*partition_id)?, split_key)
+            },
+            PartitionChange::Merge { left_id, right_id } => {
+                self.check_merge(&self.partition(*left_id)?, &self.partition(*right_id)?)
+            },
+            PartitionChange::MoveReplica(planned) => {
+                let partition = self.partition(planned.partition_id)?;
+                if !partition.voters().contains(&planned.from) {
+                    return Err(DatabaseError::Partition {
+                        partition: Some(planned.partition_id),
+                        message: format!("Node {} is not a voter of partition {}", planned.from, planned.partition_id),
+                    });
+                }
+                if partition.has_replica(&planned.to) {
+                    return Err(DatabaseError::Partition {
+                        partition: Some(planned.partition_id),
+                        message: format!("Node {} already holds a replica of partition {}", planned.to, planned.partition_id),
+                    });
+                }
+                Ok(())
+            },
+            PartitionChange::MoveLeader { partition_id, target } => {
+                if !self.partition(*partition_id)?.voters().contains(target) {
+                    return Err(DatabaseError::Partition {
+                        partition: Some(*partition_id),
+                        message: format!("Node {} is not a voter of partition {}", target, partition_id),
+                    });
+                }
+                Ok(())
+            },
+        }
+    }
+
+    /// The next step of `change`, planned from the partition map as it is,
+    /// or `None` once the change is made
+    pub fn next_step(&mut self, change: &PartitionChange) -> Result<Option<Step>> {
+        match change {
+            PartitionChange::Split { partition_id, split_key } => {
+                if self.partition(*partition_id)?.range.end == *split_key {
+                    return Ok(None);
+                }
+                self.split_step(*partition_id, split_key.clone()).map(Some)
+            },
+            PartitionChange::Merge { left_id, right_id } => self.merge_next_step(*left_id, *right_id),
+            PartitionChange::MoveReplica(planned) => {
+                let partition = self.partition(planned.partition_id)?;
+                if !partition.has_replica(&planned.to) {
+                    let address = self.address(&planned.to)?;
+                    return self.add_learner_step(partition.id, planned.to.clone(), address).map(Some);
+                }
+                if partition.learners.contains(&planned.to) {
+                    return self.promote_step(partition.id, planned.to.clone()).map(Some);
+                }
+                // Leadership moves away before the old replica goes
+                if partition.leader == planned.from {
+                    return self.leader_step(partition.id, None);
+                }
+                if partition.has_replica(&planned.from) {
+                    return self.removal_step(partition.id, planned.from.clone()).map(Some);
+                }
+                Ok(None)
+            },
+            PartitionChange::MoveLeader { partition_id, target } => self.leader_step(*partition_id, Some(target.clone())),
+        }
+    }
+
+    /// Record a step whose request is done. Returns the partitions it changed.
+    pub fn finish_step(&mut self, step: &Step) -> Vec<PartitionInfo> {
+        if let Some(removed) = step.removed {
+            self.metadata.partitions.remove(&removed);
+        }
+        for partition in &step.updated {
+            self.metadata.partitions.insert(partition.id, partition.clone());
+        }
+        self.metadata.version += 1;
+        step.updated.clone()
+    }
+
+    /// Record a step whose request is done and save it right away, recording
+    /// it again on top of the stored copy while other coordinators saved
+    /// first. Returns the partitions it changed.
+    pub(crate) async fn save_step(&mut self, step: &Step) -> Result<Vec<PartitionInfo>> {
+        let mut attempts = 0;
+        loop {
+            let changed = self.finish_step(step);
+            match self.sync_metadata().await {
+                Err(DatabaseError::Conflict { .. }) if attempts < MAX_SAVE_ATTEMPTS => attempts += 1,
+                result => return result.map(|_| changed),
+            }
+        }
+    }
+
+    /// Run a step planned right after a sync, holding the coordinator
+    pub(crate) async fn run_step(&mut self, step: Step) -> Result<Vec<PartitionInfo>> {
+        step.run().await?;
+        self.save_step(&step).await
+    }
+
+    /// Fail if partition `partition_id` has a step in flight
+    pub(crate) fn check_idle(&self, partition_id: u64) -> Result<()> {
+        if self.changing.contains(&partition_id) {
+            return Err(DatabaseError::Partition {
+                partition: Some(partition_id),
+                message: format!("Partition {} is being changed", partition_id),
+            });
+        }
+        Ok(())
+    }
+
+    /// A step sending `request` to the leader of `partition`, which records
+    /// `updated` once done
+    pub(crate) fn step(&self, partition: &PartitionInfo, request: StepRequest, updated: Vec<PartitionInfo>) -> Result<Step> {
+        for changed in std::iter::once(partition).chain(&updated) {
+            self.check_idle(changed.id)?;
+        }
+        let leader = match &self.transport {
+            Some(transport) => Some((transport.clone(), self.address(&partition.leader)?)),
+            None => None,
+        };
+        Ok(Step {
+            leader,
+            partition_id: partition.id,
+            request,
+            updated,
+            removed: None,
+        })
+    }
+}
+
+/// Carry out `change` on the coordinator behind `coordinator`, holding its
+/// lock only to plan and to record each step. Returns the partitions
+/// recorded by the last step.
+pub async fn run_change_unlocked(coordinator: &Mutex<Coordinator>, change: &PartitionChange) -> Result<Vec<PartitionInfo>> {
+    {
+        let mut coordinator = coordinator.lock().await;
+        coordinator.sync_metadata().await?;
+        coordinator.check_change(change)?;
+    }
+    let mut changed = Vec::new();
+    loop {
+        let step = {
+            let mut coordinator = coordinator.lock().await;
+            coordinator.sync_metadata().await?;
+            let Some(step) = coordinator.next_step(change)? else {
+                return Ok(changed);
+            };
+            coordinator.changing.extend(step.partitions());
+            step
+        };
+
+        let outcome = step.run().await;
+        let mut coordinator = coordinator.lock().await;
+        for partition_id in step.partitions() {
+            coordinator.changing.remove(&partition_id);
+        }
+        outcome?;
+        changed = coordinator.save_step(&step).await?;
+    }
+}
@@ -0,0 +1,174 @@
+//! Distributed deadlock detection.
+//!
+//! Each data node only sees the lock waits of its own partitions, so a cycle
+//! of transactions waiting for each other across partitions is invisible to
+//! every single node. The detector periodically collects the wait-for edges
+//! of all nodes into one graph, picks a victim in every cycle and aborts the
+//! victim's lock waits. The victim's coordinator then rolls it back, which
+//! releases its locks and lets the rest of the cycle proceed.
+
+use crate::Coordinator;
+use common::config::CoordinatorConfig;
+use common::error::Result;
+use common::hlc::{HlcTimestamp, HybridClock};
+use common::types::{NodeStatus, TransactionId, WaitForEdge};
+use log::{error, warn};
+use rpc::client::NodeClient;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Periodically breaks deadlocks between the lock tables of the nodes in the
+/// cluster metadata.
+pub struct DeadlockDetector {
+    coordinator: Arc<Mutex<Coordinator>>,
+    interval: Duration,
+}
+
+impl DeadlockDetector {
+    pub fn new(config: &CoordinatorConfig, coordinator: Arc<Mutex<Coordinator>>) -> Self {
+        Self {
+            coordinator,
+            interval: Duration::from_millis(config.deadlock_detection_interval_ms),
+        }
+    }
+
+    /// Run detection rounds forever.
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.detect_once().await {
+                error!("Deadlock detection failed: {}", e);
+            }
+        }
+    }
+
+    /// Run one detection round and return the transactions aborted. Only the
+    /// coordinator holding the lease detects.
+    ///
+    /// The nodes are asked one after another, so the edges of one pass come
+    /// from different moments and may form a cycle that never existed. A
+    /// victim is only aborted if its cycle is still there when all nodes are
+    /// asked again. Unreachable nodes are skipped; missing their edges can
+    /// only hide a deadlock until a later round, never invent one.
+    pub async fn detect_once(&self) -> Result<Vec<TransactionId>> {
+        let (addresses, clock) = {
+            let coordinator = self.coordinator.lock().await;
+            if !coordinator.holds_lease() {
+                return Ok(Vec::new());
+            }
+            let addresses: Vec<String> = coordinator.nodes().into_iter()
+                .filter(|node| !matches!(node.status, NodeStatus::Dead | NodeStatus::Removed))
+                .map(|node| node.address)
+                .collect();
+            (addresses, coordinator.clock())
+        };
+
+        let (_, first) = wait_for_graph(&addresses, &clock).await;
+        if find_deadlock_victims(&first).is_empty() {
+            return Ok(Vec::new());
+        }
+        let (mut clients, second) = wait_for_graph(&addresses, &clock).await;
+        let confirmed: Vec<WaitForEdge> = second.into_iter().filter(|edge| first.contains(edge)).collect();
+
+        let victims = find_deadlock_victims(&confirmed);
+        for victim in &victims {
+            for client in clients.iter_mut() {
+                let reason = format!("Transaction {} aborted to break a deadlock", victim);
+                if let Err(e) = client.abort_lock_waits(victim, reason).await {
+                    warn!("Failed to abort lock waits of transaction {}: {}", victim, e);
+                }
+            }
+        }
+        Ok(victims)
+    }
+}
+
+/// Collect the wait-for edges of every reachable node, along with clients
+/// of those nodes.
+async fn wait_for_graph(addresses: &[String], clock: &Arc<HybridClock>) -> (Vec<NodeClient>, Vec<WaitForEdge>) {
+    let mut clients = Vec::new();
+    let mut edges = Vec::new();
+    for addr in addresses {
+        let fetched = match NodeClient::connect(addr, clock.clone()).await {
+            Ok(mut client) => client.wait_for_graph().await.map(|node_edges| (client, node_edges)),
+            Err(e) => Err(e),
+        };
+        match fetched {
+            Ok((client, node_edges)) => {
+                edges.extend(node_edges);
+                clients.push(client);
+            },
+            Err(e) => warn!("Skipping node {} in deadlock detection: {}", addr, e),
+        }
+    }
+    (clients, edges)
+}
+
+/// Pick the transactions to abort so that the wait-for graph has no cycles.
+///
+/// The youngest transaction of each cycle is chosen, so that the oldest
+/// transactions, which have waited the longest, make progress.
+pub fn find_deadlock_victims(edges: &[WaitForEdge]) -> Vec<TransactionId> {
+    let mut graph: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    let mut start_ts: HashMap<&str, HlcTimestamp> = HashMap::new();
+    for edge in edges {
+        graph.entry(&edge.waiter.0).or_default().insert(&edge.holder.0);
+        start_ts.insert(&edge.waiter.0, edge.waiter_start_ts);
+    }
+
+    let mut victims = Vec::new();
+    while let Some(cycle) = find_cycle(&graph) {
+        // Every member of a cycle waits, so its start timestamp is known
+        let victim = cycle.into_iter()
+            .max_by_key(|id| (start_ts.get(id).copied().unwrap_or_default(), *id))
+            .expect("cycles are not empty");
+        graph.remove(victim);
+        for holders in graph.values_mut() {
+            holders.remove(victim);
+        }
+        victims.push(TransactionId(victim.to_string()));
+    }
+    victims
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    OnPath,
+    Done,
+}
+
+fn find_cycle<'a>(graph: &BTreeMap<&'a str, BTreeSet<&'a str>>) -> Option<Vec<&'a str>> {
+    let mut visits = HashMap::new();
+    let mut path = Vec::new();
+    graph.keys().find_map(|node| visit(node, graph, &mut visits, &mut path))
+}
+
+fn visit<'a>(
+    node: &'a str,
+    graph: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+    visits: &mut HashMap<&'a str, Visit>,
+    path: &mut Vec<&'a str>,
+) -> Option<Vec<&'a str>> {
+    match visits.get(node) {
+        Some(Visit::Done) => return None,
+        Some(Visit::OnPath) => {
+            let start = path.iter().position(|n| *n == node)?;
+            return Some(path[start..].to_vec());
+        },
+        None => {},
+    }
+
+    visits.insert(node, Visit::OnPath);
+    path.push(node);
+    for next in graph.get(node).into_iter().flatten() {
+        if let Some(cycle) = visit(next, graph, visits, path) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    visits.insert(node, Visit::Done);
+    None
+}
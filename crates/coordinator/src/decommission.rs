@@ -0,0 +1,230 @@
+//! Node decommissioning.
+//!
+//! A decommissioned node leaves the cluster without ever leaving a
+//! partition short of replicas. The node is marked leaving, which keeps new
+//! replicas and leaderships away, and then moved off in rounds: its
+//! leaderships go to other voters, and each of its voting replicas is
+//! replaced by a new voter, placed like any other new replica, before the
+//! old one is removed. Once no partition has a replica on the node it is
+//! marked removed.
+//!
+//! Decommissioning can be aborted at any point. The node becomes active
+//! again, and the replicas already moved off stay where they are until the
+//! rebalancer moves some back.
+//!
+//! A dead node is decommissioned the same way, so it can leave the cluster
+//! for good; once the failure detector has replaced all its replicas, it is
+//! removed at once.
+
+use crate::failure::replica_counts;
+use crate::locality::choose_target;
+use crate::Coordinator;
+use common::config::CoordinatorConfig;
+use common::error::{DatabaseError, Result};
+use common::types::{NodeId, NodeStatus, PartitionInfo};
+use common::util::timestamp_ms;
+use log::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// A decommissioning in progress
+#[derive(Debug, Clone)]
+pub(crate) struct Decommission {
+    started_ms: u64,
+    /// Replicas on the node when decommissioning started
+    replicas_total: usize,
+}
+
+/// How far decommissioning a node has come
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecommissionProgress {
+    pub node: NodeId,
+    pub replicas_total: usize,
+    pub replicas_left: usize,
+    /// Estimated time until the node holds no replicas, from the pace so far;
+    /// `None` until the first replica moved
+    pub eta: Option<Duration>,
+    /// Whether the node was removed
+    pub done: bool,
+}
+
+impl From<DecommissionProgress> for rpc::proto::admin::DecommissionProgress {
+    fn from(progress: DecommissionProgress) -> Self {
+        Self {
+            node_id: progress.node.0,
+            replicas_total: progress.replicas_total as u64,
+            replicas_left: progress.replicas_left as u64,
+            eta_ms: progress.eta.map_or(0, |eta| eta.as_millis() as u64),
+            done: progress.done,
+        }
+    }
+}
+
+/// Periodically moves replicas off the nodes being decommissioned.
+pub struct Decommissioner {
+    interval: Duration,
+    max_moves: usize,
+    coordinator: Arc<Mutex<Coordinator>>,
+}
+
+impl Decommissioner {
+    pub fn new(config: &CoordinatorConfig, coordinator: Arc<Mutex<Coordinator>>) -> Self {
+        Self {
+            interval: Duration::from_millis(config.decommission_interval_ms),
+            max_moves: config.decommission_max_moves,
+            coordinator,
+        }
+    }
+
+    /// Decommission nodes forever.
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            for progress in self.check_once().await {
+                if progress.done {
+                    info!("Node {} is decommissioned", progress.node);
+                } else {
+                    info!(
+                        "Decommissioning node {}: {} of {} replicas left, ETA {}",
+                        progress.node,
+                        progress.replicas_left,
+                        progress.replicas_total,
+                        progress.eta.map_or("unknown".to_string(), |eta| format!("{}s", eta.as_secs())),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Run one round for every node being decommissioned and return their
+    /// progress. Only the coordinator holding the lease moves replicas.
+    pub async fn check_once(&self) -> Vec<DecommissionProgress> {
+        let mut coordinator = self.coordinator.lock().await;
+        if !coordinator.holds_lease() {
+            return Vec::new();
+        }
+        let mut nodes: Vec<NodeId> = coordinator.decommissions.keys().cloned().collect();
+        nodes.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut progress = Vec::new();
+        for node in nodes {
+            match coordinator.decommission_step(&node, self.max_moves).await {
+                Ok(step) => progress.push(step),
+                Err(e) => warn!("Decommissioning node {} failed: {}", node, e),
+            }
+        }
+        progress
+    }
+}
+
+impl Coordinator {
+    /// Start decommissioning a node. The node takes no new replicas or
+    /// leaderships from now on; its replicas are moved off in the background.
+    /// A dead node without replicas is removed right away.
+    pub fn decommission_node(&mut self, node: &NodeId) -> Result<DecommissionProgress> {
+        if self.decommissions.contains_key(node) {
+            return self.decommission_progress(node);
+        }
+        let dead = self.node(node)?.status == NodeStatus::Dead;
+        if dead && self.node_partitions(node).is_empty() {
+            self.set_node_status(node, NodeStatus::Removed)?;
+            return self.decommission_progress(node);
+        }
+        self.set_node_status(node, NodeStatus::Leaving)?;
+        self.decommission_progress(node)
+    }
+
+    /// Whether a leaving node was already dead when its decommissioning
+    /// started, or when this coordinator took it over
+    pub(crate) fn decommissioned_dead(&self, node: &NodeId, last_heartbeat_ms: u64) -> bool {
+        self.decommissions.get(node)
+            .is_some_and(|decommission| decommission.started_ms.saturating_sub(last_heartbeat_ms) > self.node_dead_timeout_ms)
+    }
+
+    /// Stop decommissioning a node, which becomes active again
+    pub fn abort_decommission(&mut self, node: &NodeId) -> Result<DecommissionProgress> {
+        let progress = self.decommission_progress(node)?;
+        self.set_node_status(node, NodeStatus::Active)?;
+        Ok(progress)
+    }
+
+    /// Progress of decommissioning a node
+    pub fn decommission_progress(&self, node: &NodeId) -> Result<DecommissionProgress> {
+        if self.metadata.nodes.get(node).is_some_and(|info| info.status == NodeStatus::Removed) {
+            return Ok(DecommissionProgress { node: node.clone(), replicas_total: 0, replicas_left: 0, eta: None, done: true });
+        }
+        let decommission = self.decommissions.get(node)
+            .ok_or_else(|| DatabaseError::InvalidArgument(format!("Node {} is not being decommissioned", node)))?;
+
+        let replicas_left = replica_counts(&self.metadata).get(node).copied().unwrap_or(0);
+        let moved = decommission.replicas_total.saturating_sub(replicas_left);
+        let elapsed_ms = timestamp_ms().saturating_sub(decommission.started_ms);
+        let eta = (moved > 0).then(|| Duration::from_millis(elapsed_ms * replicas_left as u64 / moved as u64));
+        Ok(DecommissionProgress {
+            node: node.clone(),
+            replicas_total: decommission.replicas_total.max(replicas_left),
+            replicas_left,
+            eta,
+            done: false,
+        })
+    }
+
+    /// Move the leaderships and up to `max_moves` replicas off a node being
+    /// decommissioned, and mark it removed once it holds no replicas.
+    pub async fn decommission_step(&mut self, node: &NodeId, max_moves: usize) -> Result<DecommissionProgress> {
+        self.sync_metadata().await?;
+        self.decommission_progress(node)?;
+
+        let mut held: Vec<PartitionInfo> = self.metadata.partitions.values()
+            .filter(|partition| partition.has_replica(node))
+            .cloned()
+            .collect();
+        held.sort_by_key(|partition| partition.id);
+
+        for partition in held.iter().filter(|partition| partition.leader == *node) {
+            self.transfer_leader(partition.id, None).await?;
+        }
+        for partition in held.iter().take(max_moves) {
+            if partition.voters().contains(node) {
+                let target = self.replacement(partition, node).ok_or_else(|| DatabaseError::Partition {
+                    partition: Some(partition.id),
+                    message: format!("No node can take over the replica of partition {}", partition.id),
+                })?;
+                let address = self.address(&target)?;
+                self.add_voter(partition.id, target, address).await?;
+            }
+            self.remove_replica(partition.id, node.clone()).await?;
+        }
+
+        if held.len() > max_moves {
+            return self.decommission_progress(node);
+        }
+        self.set_node_status(node, NodeStatus::Removed)?;
+        self.sync_metadata().await?;
+        self.decommission_progress(node)
+    }
+
+    /// Track the decommissioning of every leaving node, after a change of
+    /// status here or by another coordinator. Progress of a decommissioning
+    /// tracked anew starts from the replicas the node holds now.
+    pub(crate) fn resume_decommissions(&mut self) {
+        let leaving: Vec<NodeId> = self.metadata.nodes.values()
+            .filter(|info| info.status == NodeStatus::Leaving)
+            .map(|info| info.id.clone())
+            .collect();
+        self.decommissions.retain(|node, _| leaving.contains(node));
+        let counts = replica_counts(&self.metadata);
+        for node in leaving {
+            let replicas_total = counts.get(&node).copied().unwrap_or(0);
+            self.decommissions.entry(node).or_insert_with(|| Decommission { started_ms: timestamp_ms(), replicas_total });
+        }
+    }
+
+    /// Where the replica of `partition` on `node` goes
+    fn replacement(&self, partition: &PartitionInfo, node: &NodeId) -> Option<NodeId> {
+        let others: Vec<NodeId> = partition.voters().into_iter().filter(|voter| voter != node).collect();
+        choose_target(&self.metadata, &self.metadata.draining, partition, &others, &replica_counts(&self.metadata))
+    }
+}
@@ -0,0 +1,224 @@
+//! Node failure detection and re-replication.
+//!
+//! Nodes heartbeat the coordinator, and a node that stays silent for longer
+//! than the suspect timeout becomes suspect. A suspect node keeps its
+//! replicas, since most silences are restarts or network blips that end
+//! before re-replicating would, but it takes no new replicas and leads no
+//! partitions. A node silent for longer than the dead timeout is dead: each
+//! of its replicas is replaced by a new voter on a healthy node, added
+//! through the usual learner catch-up before the dead replica is removed.
+//!
+//! The same repair tops up any partition with fewer voters than the
+//! replication factor, e.g. after the factor was raised.
+
+use crate::locality::{choose_target, num_replicas};
+use crate::Coordinator;
+use chrono::{DateTime, Utc};
+use common::config::CoordinatorConfig;
+use common::error::Result;
+use common::types::{ClusterMetadata, Locality, NodeId, NodeInfo, NodeLoad, NodeStatus, PartitionInfo};
+use log::{error, warn};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Replicas to add to and remove from a partition to repair it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repair {
+    pub partition_id: u64,
+    pub add: Vec<NodeId>,
+    pub remove: Vec<NodeId>,
+}
+
+/// Periodically checks node heartbeats and re-replicates the partitions of
+/// dead nodes.
+pub struct FailureDetector {
+    interval: Duration,
+    coordinator: Arc<Mutex<Coordinator>>,
+}
+
+impl FailureDetector {
+    pub fn new(config: &CoordinatorConfig, coordinator: Arc<Mutex<Coordinator>>) -> Self {
+        Self {
+            interval: Duration::from_millis(config.failure_check_interval_ms),
+            coordinator,
+        }
+    }
+
+    /// Check for failures forever.
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.check_once().await {
+                error!("Failure check failed: {}", e);
+            }
+        }
+    }
+
+    /// Run one failure check and return the partitions repaired. Only the
+    /// coordinator holding the lease checks.
+    pub async fn check_once(&self) -> Result<Vec<PartitionInfo>> {
+        let mut coordinator = self.coordinator.lock().await;
+        if !coordinator.holds_lease() {
+            return Ok(Vec::new());
+        }
+        for (node, status) in coordinator.detect_failures(Utc::now()) {
+            warn!("Node {} is now {:?}", node, status);
+        }
+        coordinator.sync_metadata().await?;
+        coordinator.re_replicate().await
+    }
+}
+
+impl Coordinator {
+    /// Record a heartbeat from a node. An unknown or joining node becomes
+    /// active, and so does a suspect or dead node that reports again; the
+    /// statuses operators set are left alone. Returns the node's status.
+    pub fn record_heartbeat(
+        &mut self,
+        node: NodeId,
+        address: String,
+        locality: Locality,
+        load: NodeLoad,
+        now: DateTime<Utc>,
+    ) -> NodeStatus {
+        let info = self.metadata.nodes.entry(node.clone()).or_insert_with(|| NodeInfo {
+            id: node,
+            address: String::new(),
+            status: NodeStatus::Joining,
+            last_heartbeat: None,
+            load: NodeLoad::default(),
+            locality: Locality::default(),
+        });
+        let mut changed = info.address != address || info.locality != locality;
+        let silenced = matches!(info.status, NodeStatus::Joining | NodeStatus::Suspect | NodeStatus::Dead);
+        if silenced && info.status.can_become(&NodeStatus::Active) {
+            info.status = NodeStatus::Active;
+            changed = true;
+        }
+        info.address = address;
+        info.locality = locality;
+        info.last_heartbeat = Some(now);
+        info.load = load;
+
+        let status = info.status.clone();
+        if changed {
+            self.metadata.version += 1;
+        }
+        self.heartbeat_received();
+        status
+    }
+
+    /// Mark active nodes silent for longer than the suspect timeout suspect,
+    /// and nodes silent for longer than the dead timeout dead. Nodes that
+    /// never sent a heartbeat are left alone, and a leaving node that dies
+    /// is no longer decommissioned, unless it was dead already when its
+    /// decommissioning started. Returns the nodes whose status changed.
+    pub fn detect_failures(&mut self, now: DateTime<Utc>) -> Vec<(NodeId, NodeStatus)> {
+        let mut changes = Vec::new();
+        for info in self.metadata.nodes.values() {
+            let Some(last_heartbeat) = info.last_heartbeat else { continue };
+            let silent_ms = (now - last_heartbeat).num_milliseconds().max(0) as u64;
+            let status = if silent_ms > self.node_dead_timeout_ms {
+                NodeStatus::Dead
+            } else if silent_ms > self.node_suspect_timeout_ms {
+                NodeStatus::Suspect
+            } else {
+                continue;
+            };
+            let last_heartbeat_ms = last_heartbeat.timestamp_millis().max(0) as u64;
+            if !info.status.can_become(&status) || self.decommissioned_dead(&info.id, last_heartbeat_ms) {
+                continue;
+            }
+            changes.push((info.id.clone(), status));
+        }
+        for (node, status) in &changes {
+            if let Some(info) = self.metadata.nodes.get_mut(node) {
+                info.status = status.clone();
+            }
+        }
+
+        if !changes.is_empty() {
+            self.metadata.version += 1;
+            self.resume_decommissions();
+        }
+        changes.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        changes
+    }
+
+    /// Replace the replicas on dead nodes and top up partitions with fewer
+    /// voters than the replication factor. Returns the partitions repaired;
+    /// partitions that cannot be repaired right now are skipped.
+    pub async fn re_replicate(&mut self) -> Result<Vec<PartitionInfo>> {
+        let mut repaired = Vec::new();
+        for repair in plan_repairs(&self.metadata, &self.metadata.draining, self.replication_factor) {
+            match self.repair(&repair).await {
+                Ok(partition) => repaired.push(partition),
+                Err(e) => warn!("Not repairing partition {}: {}", repair.partition_id, e),
+            }
+        }
+        Ok(repaired)
+    }
+
+    async fn repair(&mut self, repair: &Repair) -> Result<PartitionInfo> {
+        for node in &repair.add {
+            let address = self.address(node)?;
+            self.add_voter(repair.partition_id, node.clone(), address).await?;
+        }
+        for node in &repair.remove {
+            self.remove_replica(repair.partition_id, node.clone()).await?;
+        }
+        self.partition(repair.partition_id)
+    }
+}
+
+/// Plan the repairs of partitions with replicas on dead nodes or fewer
+/// healthy voters than `replication_factor`.
+///
+/// Replicas on dead nodes are removed, and new voters go to the active,
+/// undrained nodes that the partition's placement constraints allow,
+/// preferring those that spread it over more localities and then those
+/// holding the fewest replicas. Voters on suspect nodes still count as
+/// healthy, and the constraints may override the replication factor.
+pub fn plan_repairs(metadata: &ClusterMetadata, draining: &HashSet<NodeId>, replication_factor: usize) -> Vec<Repair> {
+    let is_dead = |node: &NodeId| metadata.nodes.get(node).is_some_and(|info| info.status == NodeStatus::Dead);
+    let mut partitions: Vec<&PartitionInfo> = metadata.partitions.values().collect();
+    partitions.sort_by_key(|partition| partition.id);
+
+    let mut counts = replica_counts(metadata);
+
+    let mut repairs = Vec::new();
+    for partition in partitions {
+        let remove: Vec<NodeId> = partition.voters().into_iter()
+            .chain(partition.learners.iter().cloned())
+            .filter(|node| is_dead(node))
+            .collect();
+        let mut healthy: Vec<NodeId> = partition.voters().into_iter().filter(|node| !is_dead(node)).collect();
+
+        let mut add = Vec::new();
+        for _ in healthy.len()..num_replicas(metadata, partition, replication_factor) {
+            let Some(target) = choose_target(metadata, draining, partition, &healthy, &counts) else { break };
+            *counts.entry(target.clone()).or_insert(0) += 1;
+            healthy.push(target.clone());
+            add.push(target);
+        }
+
+        if !add.is_empty() || !remove.is_empty() {
+            repairs.push(Repair { partition_id: partition.id, add, remove });
+        }
+    }
+    repairs
+}
+
+/// Number of replicas, voters and learners, on each node
+pub(crate) fn replica_counts(metadata: &ClusterMetadata) -> HashMap<NodeId, usize> {
+    let mut counts = HashMap::new();
+    for partition in metadata.partitions.values() {
+        for node in partition.voters().into_iter().chain(partition.learners.iter().cloned()) {
+            *counts.entry(node).or_insert(0) += 1;
+        }
+    }
+    counts
+}
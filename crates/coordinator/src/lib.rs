@@ -1,171 +1,552 @@
+use common::config::CoordinatorConfig;
 use common::error::{DatabaseError, Result};
+use common::hlc::{HlcTimestamp, HybridClock};
+use common::types::{
+    BatchOperation, ClusterMetadata, Command, CommandResponse, IsolationLevel, NodeId, PartitionInfo, PartitionRoute,
+    ReadConsistency, ReadOutcome, ScanToken, TransactionId, TransactionMeta, TransactionStatus, VersionedValue,
+};
+use rpc::routing::Routed;
+use common::util::timestamp_ms;
 use sql_parser::{parse_sql, SqlStatement};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::vec::Vec;
 
+mod change;
+mod deadlock;
+mod decommission;
+mod failure;
+mod locality;
+mod membership;
+mod merge;
+mod meta;
+mod nodes;
+mod placement;
+mod rebalance;
 mod server;
+mod split;
+mod sql;
+mod transaction;
+mod transport;
+
+pub use change::{run_change_unlocked, PartitionChange, Step};
+pub use deadlock::{find_deadlock_victims, DeadlockDetector};
+pub use decommission::{DecommissionProgress, Decommissioner};
+pub use failure::{plan_repairs, FailureDetector, Repair};
+pub use merge::plan_merges;
+pub use meta::{MemoryMetaStore, MetaStore, MetaSync, RaftMetaStore};
+pub use placement::plan_leader_transfers;
+pub use rebalance::{plan_replica_moves, Rebalancer, ReplicaMove};
+pub use split::{RangeChanges, RangeScheduler};
+use transaction::Transaction;
+pub use transaction::{commit_transaction_unlocked, transaction_get_for_update_unlocked, TransactionReaper};
+pub use transport::{GrpcNodeTransport, NodeTransport};
+
+/// How often a read settles intents in its way before giving up
+const MAX_READ_ATTEMPTS: usize = 3;
+/// How often a proposal follows a replica's pointer to the current leader
+const MAX_LEADER_REDIRECTS: usize = 3;
 
 /// Coordinator manages the distributed system components
 pub struct Coordinator {
-    // Add fields for node tracking and state management
-    nodes: HashMap<String, NodeStatus>,
+    metadata: ClusterMetadata,
+    clock: Arc<HybridClock>,
+    /// Open transactions, which only this coordinator knows of
+    transactions: HashMap<TransactionId, Transaction>,
+    transaction_abandon_timeout_ms: u64,
+    /// Spreads stale reads over the followers of a partition
+    read_rotation: usize,
+    /// Nodes whose replicas are being moved away for good
+    decommissions: HashMap<NodeId, decommission::Decommission>,
+    split_size_bytes: u64,
+    split_qps: f64,
+    merge_size_bytes: u64,
+    merge_qps: f64,
+    replication_factor: usize,
+    node_suspect_timeout_ms: u64,
+    node_dead_timeout_ms: u64,
+    /// Where the metadata is stored; `None` keeps it in memory only
+    meta: Option<meta::MetaState>,
+    /// How partition leaders are reached; `None` records partition changes in
+    /// the metadata only and fails reads and writes
+    transport: Option<Arc<dyn NodeTransport>>,
+    /// Partitions with a change step in flight outside the coordinator lock
+    changing: HashSet<u64>,
 }
 
-/// Status of a node in the cluster
-#[derive(Debug, Clone, PartialEq)]
-pub enum NodeStatus {
-    Active,
-    Inactive,
-    Failed,
+/// One page of a range scan
+#[derive(Debug, Clone, Default)]
+pub struct ScanPage {
+    pub items: Vec<(String, Vec<u8>)>,
+    pub continuation_token: Option<String>,
+    pub has_more: bool,
 }
 
 impl Coordinator {
     pub fn new() -> Self {
+        Self::with_config(&CoordinatorConfig::default())
+    }
+
+    pub fn with_config(config: &CoordinatorConfig) -> Self {
         Self {
-            nodes: HashMap::new(),
+            metadata: ClusterMetadata::default(),
+            clock: Arc::new(HybridClock::new(config.max_clock_offset_ms)),
+            transactions: HashMap::new(),
+            transaction_abandon_timeout_ms: config.transaction_abandon_timeout_ms,
+            read_rotation: 0,
+            decommissions: HashMap::new(),
+            split_size_bytes: config.split_size_bytes,
+            split_qps: config.split_qps,
+            merge_size_bytes: config.merge_size_bytes,
+            merge_qps: config.merge_qps,
+            replication_factor: config.replication_factor,
+            node_suspect_timeout_ms: config.node_suspect_timeout_ms,
+            node_dead_timeout_ms: config.node_dead_timeout_ms,
+            meta: None,
+            transport: None,
+            changing: HashSet::new(),
         }
     }
 
+    /// Carry out partition changes on the partition leaders through `transport`
+    pub fn set_transport(&mut self, transport: Arc<dyn NodeTransport>) {
+        self.transport = Some(transport);
+    }
+
+    /// The coordinator's hybrid logical clock, shared with its RPC clients and services
+    pub fn clock(&self) -> Arc<HybridClock> {
+        self.clock.clone()
+    }
+
     /// Execute a SQL query by parsing it and routing to appropriate handler
     pub async fn execute_query(&mut self, query: String, parameters: HashMap<String, String>) -> Result<Vec<HashMap<String, String>>> {
+        self.execute_query_in_transaction(query, parameters, None).await
+    }
+
+    /// Execute a SQL query, inside `transaction` when given
+    ///
+    /// `BEGIN` returns a single row whose `transaction_id` column identifies
+    /// the new transaction; pass it back for the following statements.
+    pub async fn execute_query_in_transaction(
+        &mut self,
+        query: String,
+        parameters: HashMap<String, String>,
+        transaction: Option<TransactionId>,
+    ) -> Result<Vec<HashMap<String, String>>> {
         // Parse the SQL query
         let sql_stmt = parse_sql(&query)
             .map_err(|e| DatabaseError::SqlParse(e))?;
 
         // Handle the parsed statement
         match sql_stmt {
-            SqlStatement::Select { columns, table, where_clause, limit } => {
-                self.handle_select(columns, table, where_clause, limit, &parameters).await
+            SqlStatement::Select { columns, table, where_clause, limit, as_of, for_update } => {
+                if for_update && transaction.is_none() {
+                    return Err(DatabaseError::Transaction("SELECT FOR UPDATE requires a transaction".to_string()));
+                }
+                self.handle_select(columns, table, where_clause, limit, as_of, for_update, transaction.as_ref(), &parameters).await
             },
             SqlStatement::Insert { table, columns, values } => {
-                self.handle_insert(table, columns, values, &parameters).await
+                self.handle_insert(table, columns, values, transaction.as_ref(), &parameters).await
             },
             SqlStatement::Update { table, assignments, where_clause } => {
-                self.handle_update(table, assignments, where_clause, &parameters).await
+                self.handle_update(table, assignments, where_clause, transaction.as_ref(), &parameters).await
             },
             SqlStatement::Delete { table, where_clause } => {
-                self.handle_delete(table, where_clause, &parameters).await
+                self.handle_delete(table, where_clause, transaction.as_ref(), &parameters).await
             },
             SqlStatement::CreateTable { name, columns } => {
-                self.handle_create_table(name, columns, &parameters).await
+                if transaction.is_some() {
+                    return Err(DatabaseError::Transaction("CREATE TABLE cannot be used inside a transaction".to_string()));
+                }
+                self.handle_create_table(name, columns).await
+            },
+            SqlStatement::Begin => {
+                if let Some(txn_id) = transaction {
+                    return Err(DatabaseError::Transaction(format!("Transaction {} is already open", txn_id)));
+                }
+                let txn_id = self.begin_transaction(IsolationLevel::default());
+                Ok(vec![HashMap::from([("transaction_id".to_string(), txn_id.0)])])
+            },
+            SqlStatement::Commit => {
+                let txn_id = transaction.ok_or_else(|| DatabaseError::Transaction("No open transaction".to_string()))?;
+                self.commit_transaction(&txn_id).await?;
+                Ok(Vec::new())
+            },
+            SqlStatement::Rollback => {
+                let txn_id = transaction.ok_or_else(|| DatabaseError::Transaction("No open transaction".to_string()))?;
+                self.rollback_transaction(&txn_id).await?;
+                Ok(Vec::new())
+            },
+            SqlStatement::SetTransaction { isolation } => {
+                let txn_id = transaction.ok_or_else(|| DatabaseError::Transaction("No open transaction".to_string()))?;
+                let isolation = match isolation {
+                    sql_parser::IsolationLevel::Snapshot => IsolationLevel::Snapshot,
+                    sql_parser::IsolationLevel::Serializable => IsolationLevel::Serializable,
+                };
+                self.set_transaction_isolation(&txn_id, isolation)?;
+                Ok(Vec::new())
             },
         }
     }
 
-    /// Handle SELECT queries
-    async fn handle_select(
-        &mut self, 
-        columns: Vec<String>, 
-        table: String, 
-        where_clause: Option<sql_parser::WhereClause>,
-        limit: Option<usize>,
-        parameters: &HashMap<String, String>
-    ) -> Result<Vec<HashMap<String, String>>> {
-        // Implement distributed SELECT logic
-        // For now, return empty result set
-        Ok(Vec::new())
+    /// Get a value and its version by key (for key-value access)
+    ///
+    /// With a `read_timestamp` (ms since the epoch) the value the key had at
+    /// that time is returned instead of the latest one. `consistency` decides
+    /// whether a follower may serve the read.
+    pub async fn get(&mut self, key: String, read_timestamp: Option<u64>, consistency: ReadConsistency) -> Result<VersionedValue> {
+        let read_timestamp = match read_timestamp {
+            Some(read_timestamp) => self.as_of_timestamp(read_timestamp)?,
+            None => self.clock.now(),
+        };
+        self.read_key(&key, read_timestamp, consistency).await?
+            .ok_or(DatabaseError::KeyNotFound { key })
     }
 
-    /// Handle INSERT queries
-    async fn handle_insert(
-        &mut self,
-        table: String,
-        columns: Vec<String>,
-        values: Vec<sql_parser::SqlValue>,
-        parameters: &HashMap<String, String>
-    ) -> Result<Vec<HashMap<String, String>>> {
-        // Implement distributed INSERT logic
-        // For now, return empty result set
-        Ok(Vec::new())
+    /// Read the newest version of `key` visible at `read_timestamp`
+    ///
+    /// An intent of a transaction that may commit below the read timestamp
+    /// hides the value; the transaction is recovered and the key read again.
+    async fn read_key(&mut self, key: &str, read_timestamp: HlcTimestamp, consistency: ReadConsistency) -> Result<Option<VersionedValue>> {
+        let partition = self.partition_for_key(key)?;
+        let transport = self.data_transport()?;
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let replica = self.read_replica(&partition, consistency);
+            let address = self.address(&replica)?;
+            match transport.read(&address, key, read_timestamp, consistency).await? {
+                Routed::Served(ReadOutcome::Read(value)) => return Ok(value),
+                Routed::Served(ReadOutcome::Blocked { key, txn }) => self.settle_blocking_intent(&txn, &key).await?,
+                Routed::Misrouted(error) => return Err(error.into()),
+            }
+        }
+        Err(Self::still_blocked(key))
     }
 
-    /// Handle UPDATE queries
-    async fn handle_update(
-        &mut self,
-        table: String,
-        assignments: Vec<(String, sql_parser::SqlValue)>,
-        where_clause: Option<sql_parser::WhereClause>,
-        parameters: &HashMap<String, String>
-    ) -> Result<Vec<HashMap<String, String>>> {
-        // Implement distributed UPDATE logic
-        // For now, return empty result set
-        Ok(Vec::new())
+    /// Store a key-value pair (for key-value access)
+    ///
+    /// `expires_at` is an absolute expiry in milliseconds since the epoch;
+    /// the key becomes invisible to reads once it passes.
+    pub async fn put(&mut self, key: String, value: Vec<u8>, expires_at: Option<u64>) -> Result<()> {
+        let partition = self.partition_for_key(&key)?;
+        self.propose_write(&partition, Command::Write { key, value, expires_at }).await?;
+        Ok(())
+    }
+
+    /// Delete a key (for key-value access)
+    pub async fn delete(&mut self, key: String) -> Result<()> {
+        let partition = self.partition_for_key(&key)?;
+        self.propose_write(&partition, Command::Delete { key }).await?;
+        Ok(())
+    }
+
+    /// Apply a batch of puts and deletes atomically (for key-value access)
+    ///
+    /// Every key must belong to the same partition; the batch is then
+    /// replicated as a single Raft entry so it is applied all-or-nothing.
+    pub async fn batch(&mut self, operations: Vec<BatchOperation>) -> Result<()> {
+        let Some(first) = operations.first() else {
+            return Ok(());
+        };
+
+        let partition = self.partition_for_key(first.key())?;
+        if let Some(op) = operations.iter().find(|op| !partition.range.contains(op.key())) {
+            return Err(DatabaseError::Partition {
+                partition: Some(partition.id),
+                message: format!(
+                    "Batch spans multiple partitions: key {} is outside partition {}",
+                    op.key(),
+                    partition.id
+                ),
+            });
+        }
+
+        self.propose_write(&partition, Command::Batch { operations }).await?;
+        Ok(())
+    }
+
+    /// Store a value only if the key does not exist yet
+    pub async fn put_if_absent(&mut self, key: String, value: Vec<u8>) -> Result<CommandResponse> {
+        let partition = self.partition_for_key(&key)?;
+        self.propose_write(&partition, Command::PutIfAbsent { key, value }).await
+    }
+
+    /// Store a value only if the key is still at `expected_version` (0 = absent)
+    pub async fn compare_and_swap(&mut self, key: String, value: Vec<u8>, expected_version: u64) -> Result<CommandResponse> {
+        let partition = self.partition_for_key(&key)?;
+        self.propose_write(&partition, Command::PutIfVersion { key, value, expected_version }).await
+    }
+
+    /// Delete a key only if it currently holds `expected_value`
+    pub async fn delete_if_value(&mut self, key: String, expected_value: Vec<u8>) -> Result<CommandResponse> {
+        let partition = self.partition_for_key(&key)?;
+        self.propose_write(&partition, Command::DeleteIfValue { key, expected_value }).await
+    }
+
+    /// Atomically add `delta` to an integer value and return the new value
+    pub async fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        let partition = self.partition_for_key(&key)?;
+        let response = self.propose_write(&partition, Command::Increment { key: key.clone(), delta }).await?;
+        if !response.succeeded {
+            return Err(DatabaseError::InvalidArgument(format!(
+                "Value of key {} is not an integer or the increment overflows", key
+            )));
+        }
+
+        response.current
+            .and_then(|current| String::from_utf8(current.value).ok())
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| DatabaseError::Raft {
+                partition: Some(partition.id),
+                node: None,
+                message: format!("Increment of key {} returned no value", key),
+            })
     }
 
-    /// Handle DELETE queries
-    async fn handle_delete(
+    /// Scan a range of keys (for key-value access)
+    ///
+    /// Returns at most `limit` items (all items if `limit <= 0`). When more
+    /// items remain, the page carries a continuation token that resumes the
+    /// scan after the last returned key at the same read timestamp.
+    ///
+    /// The first page reads at `read_timestamp` (ms since the epoch), or at
+    /// the current time when it is `None`; resumed pages use the token's.
+    pub async fn scan(
         &mut self,
-        table: String,
-        where_clause: Option<sql_parser::WhereClause>,
-        parameters: &HashMap<String, String>
-    ) -> Result<Vec<HashMap<String, String>>> {
-        // Implement distributed DELETE logic
-        // For now, return empty result set
-        Ok(Vec::new())
+        start_key: String,
+        end_key: String,
+        limit: i32,
+        continuation_token: Option<String>,
+        read_timestamp: Option<u64>,
+        consistency: ReadConsistency,
+    ) -> Result<ScanPage> {
+        let (resume_key, read_timestamp) = match continuation_token {
+            Some(token) => {
+                let token = ScanToken::decode(&token)?;
+                if token.last_key < start_key || (!end_key.is_empty() && token.last_key >= end_key) {
+                    return Err(DatabaseError::InvalidArgument(
+                        "Continuation token does not belong to the requested range".to_string(),
+                    ));
+                }
+                (token.resume_key(), token.read_timestamp)
+            },
+            None => match read_timestamp {
+                Some(read_timestamp) => (start_key, self.as_of_timestamp(read_timestamp)?),
+                None => (start_key, self.clock.now()),
+            },
+        };
+
+        // Fetch one extra item to learn whether another page exists
+        let fetch_limit = if limit > 0 { limit.saturating_add(1) } else { 0 };
+        let mut items = self.scan_range(&resume_key, &end_key, fetch_limit, read_timestamp, consistency).await?;
+
+        let has_more = limit > 0 && items.len() > limit as usize;
+        if has_more {
+            items.truncate(limit as usize);
+        }
+        let continuation_token = if has_more {
+            items.last().map(|(key, _)| ScanToken::new(key.clone(), read_timestamp).encode())
+        } else {
+            None
+        };
+
+        Ok(ScanPage { items, continuation_token, has_more })
     }
 
-    /// Handle CREATE TABLE queries
-    async fn handle_create_table(
+    /// Read up to `limit` items of `[start_key, end_key)` as of `read_timestamp`
+    ///
+    /// The partitions overlapping the range are read in key order, each
+    /// from its `read_replica`; intents in the way are settled as for
+    /// `read_key`.
+    async fn scan_range(
         &mut self,
-        name: String,
-        columns: Vec<sql_parser::ColumnDef>,
-        parameters: &HashMap<String, String>
-    ) -> Result<Vec<HashMap<String, String>>> {
-        // Implement distributed CREATE TABLE logic
-        // For now, return empty result set
-        Ok(Vec::new())
+        start_key: &str,
+        end_key: &str,
+        limit: i32,
+        read_timestamp: HlcTimestamp,
+        consistency: ReadConsistency,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        let transport = self.data_transport()?;
+        let limit = usize::try_from(limit).unwrap_or(0);
+        let mut items: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut next = start_key.to_string();
+        let mut blocked = 0;
+        loop {
+            let partition = self.partition_for_key(&next)?;
+            let replica = self.read_replica(&partition, consistency);
+            let address = self.address(&replica)?;
+            let remaining = if limit > 0 { limit - items.len() } else { 0 };
+            let (page, has_more) = match transport.scan(&address, &next, end_key, remaining, read_timestamp, consistency).await? {
+                Routed::Served(ReadOutcome::Read(page)) => page,
+                Routed::Served(ReadOutcome::Blocked { key, txn }) => {
+                    blocked += 1;
+                    if blocked == MAX_READ_ATTEMPTS {
+                        return Err(Self::still_blocked(&key));
+                    }
+                    self.settle_blocking_intent(&txn, &key).await?;
+                    continue;
+                },
+                Routed::Misrouted(error) => return Err(error.into()),
+            };
+            items.extend(page);
+
+            // The replica stops at the end of its range; go on with the next
+            // unless the range ends the keyspace
+            let done = !has_more
+                || (limit > 0 && items.len() >= limit)
+                || (!end_key.is_empty() && partition.range.end.as_str() >= end_key)
+                || self.is_last_partition(&partition);
+            if done {
+                return Ok(items);
+            }
+            next = partition.range.end.clone();
+        }
     }
 
-    /// Get a value by key (for key-value access)
-    pub async fn get(&mut self, key: String) -> Result<Vec<u8>> {
-        // Implement distributed GET logic
-        // For now, return empty vector
-        Ok(Vec::new())
+    /// Whether no partition holds keys past the end of `partition`
+    fn is_last_partition(&self, partition: &PartitionInfo) -> bool {
+        partition.range.end.is_empty()
+            || !self.metadata.partitions.values().any(|other| other.range.start >= partition.range.end)
     }
 
-    /// Store a key-value pair (for key-value access)
-    pub async fn put(&mut self, key: String, value: Vec<u8>) -> Result<()> {
-        // Implement distributed PUT logic
+    /// Settle the transaction whose intent on `key` blocks a read; fails with
+    /// a retryable conflict while the transaction is still running
+    async fn settle_blocking_intent(&mut self, txn: &TransactionMeta, key: &str) -> Result<()> {
+        if self.recover_transaction(txn, &[key.to_string()]).await? == TransactionStatus::Pending {
+            return Err(DatabaseError::Conflict {
+                key: Some(key.to_string()),
+                message: format!("Read blocked by pending transaction {}", txn.id),
+            });
+        }
         Ok(())
     }
 
-    /// Delete a key (for key-value access)
-    pub async fn delete(&mut self, key: String) -> Result<()> {
-        // Implement distributed DELETE logic
-        Ok(())
+    fn still_blocked(key: &str) -> DatabaseError {
+        DatabaseError::Conflict {
+            key: Some(key.to_string()),
+            message: format!("Reads of {} kept running into intents", key),
+        }
     }
 
-    /// Scan a range of keys (for key-value access)
-    pub async fn scan(&mut self, start_key: String, end_key: String, limit: i32) -> Result<Vec<(String, Vec<u8>)>> {
-        // Implement distributed SCAN logic
-        Ok(Vec::new())
+    /// Turn a client-supplied read timestamp (ms) into an HLC read timestamp
+    fn as_of_timestamp(&self, read_timestamp: u64) -> Result<HlcTimestamp> {
+        check_read_timestamp(read_timestamp).map(HlcTimestamp::latest_at)
+    }
+
+    /// The replica of `partition` that serves a read at `consistency`
+    fn read_replica(&mut self, partition: &PartitionInfo, consistency: ReadConsistency) -> NodeId {
+        match consistency {
+            ReadConsistency::Linearizable => partition.leader.clone(),
+            ReadConsistency::Stale { .. } if !partition.followers.is_empty() => {
+                self.read_rotation = self.read_rotation.wrapping_add(1);
+                partition.followers[self.read_rotation % partition.followers.len()].clone()
+            },
+            ReadConsistency::Stale { .. } => partition.leader.clone(),
+        }
     }
 
-    /// Add a node to the cluster
-    pub fn add_node(&mut self, node_id: String, status: NodeStatus) {
-        self.nodes.insert(node_id, status);
+    /// Find the partition that owns `key`
+    fn partition_for_key(&self, key: &str) -> Result<PartitionInfo> {
+        self.metadata.partition_for_key(key)
+            .cloned()
+            .ok_or_else(|| DatabaseError::Partition { partition: None, message: format!("No partition owns key {}", key) })
     }
 
-    /// Remove a node from the cluster
-    pub fn remove_node(&mut self, node_id: &str) -> Option<NodeStatus> {
-        self.nodes.remove(node_id)
+    /// Replicate a command through the Raft group of `partition`
+    ///
+    /// The command goes to the leader the metadata names, or to the leader
+    /// a replica points at if leadership moved since.
+    async fn propose(&mut self, partition: &PartitionInfo, command: Command) -> Result<CommandResponse> {
+        let transport = self.data_transport()?;
+        let leader = self.address(&partition.leader)?;
+        transport::propose_on_leader(transport.as_ref(), leader, partition.id, &command).await
     }
 
-    /// Get a node's status
-    pub fn get_node_status(&self, node_id: &str) -> Option<&NodeStatus> {
-        self.nodes.get(node_id)
+    /// The transport reads, writes and locks go through; only partition
+    /// changes can be recorded without one
+    fn data_transport(&self) -> Result<Arc<dyn NodeTransport>> {
+        self.transport.clone()
+            .ok_or_else(|| DatabaseError::Config("The coordinator has no transport to the data nodes".to_string()))
     }
 
-    /// Update a node's status
-    pub fn update_node_status(&mut self, node_id: &str, status: NodeStatus) -> Option<NodeStatus> {
-        if let Some(node_status) = self.nodes.get_mut(node_id) {
-            let old_status = node_status.clone();
-            *node_status = status;
-            Some(old_status)
-        } else {
-            None
-        }
+    /// Register a partition with the coordinator
+    pub fn add_partition(&mut self, partition: PartitionInfo) {
+        self.metadata.partitions.insert(partition.id, partition);
+        self.metadata.version += 1;
+    }
+
+    /// Version of the cluster metadata
+    pub fn metadata_version(&self) -> u64 {
+        self.metadata.version
+    }
+
+    /// Routes of every partition by range start, or only of the partition
+    /// owning `key`, with the metadata version they come from
+    pub fn partition_map(&self, key: Option<&str>) -> Result<(u64, Vec<PartitionRoute>)> {
+        let mut partitions: Vec<&PartitionInfo> = match key {
+            Some(key) => vec![self.metadata.partition_for_key(key)
+                .ok_or_else(|| DatabaseError::Partition {
+                    partition: None,
+                    message: format!("No partition owns key {}", key),
+                })?],
+            None => self.metadata.partitions.values().collect(),
+        };
+        partitions.sort_by(|a, b| a.range.start.cmp(&b.range.start));
+        let routes = partitions.into_iter().map(|partition| self.metadata.route(partition)).collect();
+        Ok((self.metadata.version, routes))
+    }
+}
+
+/// Reject read timestamps in the future; data there may still change
+fn check_read_timestamp(read_timestamp: u64) -> Result<u64> {
+    if read_timestamp > timestamp_ms() {
+        return Err(DatabaseError::InvalidArgument(format!(
+            "Read timestamp {} is in the future", read_timestamp
+        )));
     }
+    Ok(read_timestamp)
+}
+
+/// Resolve an `AS OF SYSTEM TIME` value to a read timestamp in ms.
+///
+/// Accepts an absolute timestamp in ms since the epoch, or a negative
+/// interval relative to now such as `'-10s'`, `'-500ms'`, `'-2m'` or `'-1h'`.
+pub fn resolve_as_of(value: &sql_parser::SqlValue, parameters: &HashMap<String, String>) -> Result<u64> {
+    use sql_parser::SqlValue;
+
+    let invalid = || DatabaseError::InvalidArgument(format!("Invalid AS OF SYSTEM TIME value: {:?}", value));
+
+    let read_timestamp = match value {
+        SqlValue::Integer(ms) if *ms > 0 => *ms as u64,
+        SqlValue::String(s) => parse_as_of(s).ok_or_else(invalid)?,
+        SqlValue::Parameter(name) => {
+            let param = parameters.get(name).ok_or_else(|| {
+                DatabaseError::InvalidArgument(format!("Missing parameter: {}", name))
+            })?;
+            parse_as_of(param).ok_or_else(invalid)?
+        },
+        _ => return Err(invalid()),
+    };
+
+    check_read_timestamp(read_timestamp)
+}
+
+fn parse_as_of(value: &str) -> Option<u64> {
+    let Some(interval) = value.trim().strip_prefix('-') else {
+        return value.trim().parse().ok().filter(|ms| *ms > 0);
+    };
+
+    let split = interval.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = interval.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    let unit_ms = match unit {
+        "ms" => 1,
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        _ => return None,
+    };
+
+    timestamp_ms().checked_sub(amount.checked_mul(unit_ms)?)
 }
 
 // Re-export the server module's start_grpc_server function
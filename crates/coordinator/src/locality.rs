@@ -0,0 +1,204 @@
+//! Locality-aware replica placement.
+//!
+//! Nodes declare where they run as locality tiers, from the outermost in,
+//! such as region, zone and rack. New replicas go where they spread a
+//! partition over the most distinct values of the outer tiers, so losing a
+//! rack or a zone takes out as few replicas of any partition as it can: with
+//! three replicas on three racks, a failed rack still leaves a quorum.
+//!
+//! Placement constraints of a key range go further. They can set the number
+//! of replicas, require every voter to sit in a different value of a tier
+//! ("3 replicas across 3 zones"), restrict replicas to nodes of a locality
+//! and prefer leaders in a locality ("leader in zone-a"). Constraints set
+//! for a SQL table cover the key range of its rows. The narrowest
+//! constrained range covering a partition applies to it, and partitions are
+//! split where a constrained range begins or ends, so that it covers whole
+//! partitions.
+
+use crate::failure::replica_counts;
+use crate::placement::is_eligible;
+use crate::rebalance::ReplicaMove;
+use crate::Coordinator;
+use common::error::{DatabaseError, Result};
+use common::types::{ClusterMetadata, KeyRange, NodeId, PartitionInfo, PlacementConstraints};
+use std::collections::{HashMap, HashSet};
+
+impl Coordinator {
+    /// Set the placement constraints of a key range or table, replacing
+    /// those set for the same range before
+    pub fn set_placement_constraints(&mut self, mut constraints: PlacementConstraints) -> Result<()> {
+        if let Some(table) = &constraints.table {
+            self.table(table)?;
+            constraints.range = KeyRange::table(table);
+        }
+        if constraints.range.start >= constraints.range.end {
+            return Err(DatabaseError::InvalidArgument(format!(
+                "Empty key range [{}, {})", constraints.range.start, constraints.range.end
+            )));
+        }
+        if constraints.num_replicas == Some(0) {
+            return Err(DatabaseError::InvalidArgument("Constraints must keep at least one replica".to_string()));
+        }
+
+        self.metadata.constraints.retain(|existing| existing.range != constraints.range);
+        self.metadata.constraints.push(constraints);
+        self.metadata.constraints.sort_by(|a, b| a.range.start.cmp(&b.range.start).then(a.range.end.cmp(&b.range.end)));
+        self.metadata.version += 1;
+        Ok(())
+    }
+
+    /// Placement constraints of every constrained key range, by range start
+    pub fn placement_constraints(&self) -> Vec<PlacementConstraints> {
+        self.metadata.constraints.clone()
+    }
+}
+
+/// Where `partition` splits so that constrained ranges cover whole
+/// partitions: the first key inside it where one begins or ends
+pub(crate) fn constraint_split_key(metadata: &ClusterMetadata, partition: &PartitionInfo) -> Option<String> {
+    metadata.constraints.iter()
+        .flat_map(|constraints| [&constraints.range.start, &constraints.range.end])
+        .filter(|key| **key != partition.range.start && partition.range.contains(key))
+        .min()
+        .cloned()
+}
+
+/// Whether a constrained range begins or ends at `key`
+pub(crate) fn is_constraint_boundary(metadata: &ClusterMetadata, key: &str) -> bool {
+    metadata.constraints.iter().any(|constraints| constraints.range.start == key || constraints.range.end == key)
+}
+
+/// Whether the constraints of a partition allow a voter on `node` next to
+/// the voters on `others`
+pub(crate) fn allows(
+    metadata: &ClusterMetadata,
+    constraints: Option<&PlacementConstraints>,
+    others: &[NodeId],
+    node: &NodeId,
+) -> bool {
+    let Some(constraints) = constraints else { return true };
+    let locality = metadata.locality(node);
+    if !locality.matches(&constraints.required) {
+        return false;
+    }
+    match &constraints.spread_over {
+        Some(key) => match locality.get(key) {
+            Some(value) => others.iter().all(|other| metadata.locality(other).get(key) != Some(value)),
+            None => false,
+        },
+        None => true,
+    }
+}
+
+/// Whether the constraints of `partition` prefer `node` as its leader
+pub(crate) fn prefers_leader(metadata: &ClusterMetadata, partition: &PartitionInfo, node: &NodeId) -> bool {
+    metadata.constraints_for(partition)
+        .is_none_or(|constraints| metadata.locality(node).matches(&constraints.leader_locality))
+}
+
+/// Number of voting replicas `partition` should have
+pub(crate) fn num_replicas(metadata: &ClusterMetadata, partition: &PartitionInfo, replication_factor: usize) -> usize {
+    metadata.constraints_for(partition)
+        .and_then(|constraints| constraints.num_replicas)
+        .unwrap_or(replication_factor)
+}
+
+/// Number of distinct values of each locality tier among `nodes`, outermost
+/// tier first, so spreads compare by their outer tiers before their inner
+/// ones. Nodes without a tier count as one more value of it.
+pub(crate) fn diversity(metadata: &ClusterMetadata, nodes: &[NodeId]) -> Vec<usize> {
+    tier_keys(metadata).iter()
+        .map(|key| {
+            nodes.iter()
+                .map(|node| metadata.locality(node).get(key).map(str::to_string))
+                .collect::<HashSet<_>>()
+                .len()
+        })
+        .collect()
+}
+
+/// Where to add a voter of `partition` next to the voters on `others`: the
+/// eligible node without a replica that the constraints allow, spreading
+/// the voters the most and, among those, holding the fewest replicas.
+pub(crate) fn choose_target(
+    metadata: &ClusterMetadata,
+    draining: &HashSet<NodeId>,
+    partition: &PartitionInfo,
+    others: &[NodeId],
+    counts: &HashMap<NodeId, usize>,
+) -> Option<NodeId> {
+    let constraints = metadata.constraints_for(partition);
+    let mut candidates: Vec<&NodeId> = metadata.nodes.keys()
+        .filter(|node| is_eligible(metadata, draining, node))
+        .filter(|node| !partition.has_replica(node) && !others.contains(node))
+        .filter(|node| allows(metadata, constraints, others, node))
+        .collect();
+    candidates.sort_by(|a, b| a.0.cmp(&b.0));
+
+    candidates.into_iter()
+        .map(|node| {
+            let mut voters = others.to_vec();
+            voters.push(node.clone());
+            (diversity(metadata, &voters), node)
+        })
+        .min_by(|(a_spread, a), (b_spread, b)| {
+            b_spread.cmp(a_spread).then_with(|| counts.get(*a).copied().unwrap_or(0).cmp(&counts.get(*b).copied().unwrap_or(0)))
+        })
+        .map(|(_, node)| node.clone())
+}
+
+/// Plan moves that fix the placement of partitions, at most one per
+/// partition: voters the constraints do not allow move first, then voters
+/// whose move spreads the partition over more localities.
+pub(crate) fn plan_locality_moves(metadata: &ClusterMetadata, draining: &HashSet<NodeId>) -> Vec<ReplicaMove> {
+    let mut partitions: Vec<&PartitionInfo> = metadata.partitions.values().collect();
+    partitions.sort_by_key(|partition| partition.id);
+    let mut counts = replica_counts(metadata);
+
+    let mut moves = Vec::new();
+    for partition in partitions {
+        let constraints = metadata.constraints_for(partition);
+        let voters = partition.voters();
+        let spread = diversity(metadata, &voters);
+
+        let mut best: Option<((bool, Vec<usize>), ReplicaMove)> = None;
+        for from in &voters {
+            let others: Vec<NodeId> = voters.iter().filter(|voter| *voter != from).cloned().collect();
+            let misplaced = !allows(metadata, constraints, &others, from);
+            let Some(to) = choose_target(metadata, draining, partition, &others, &counts) else { continue };
+            let mut after = others;
+            after.push(to.clone());
+            let key = (misplaced, diversity(metadata, &after));
+            if !misplaced && key.1 <= spread {
+                continue;
+            }
+            if best.as_ref().is_none_or(|(best_key, _)| key > *best_key) {
+                best = Some((key, ReplicaMove { partition_id: partition.id, from: from.clone(), to }));
+            }
+        }
+
+        if let Some((_, planned)) = best {
+            *counts.entry(planned.to.clone()).or_insert(0) += 1;
+            if let Some(count) = counts.get_mut(&planned.from) {
+                *count -= 1;
+            }
+            moves.push(planned);
+        }
+    }
+    moves
+}
+
+/// Tier keys of the cluster, outermost first, in the order the nodes list them
+fn tier_keys(metadata: &ClusterMetadata) -> Vec<String> {
+    let mut nodes: Vec<_> = metadata.nodes.values().collect();
+    nodes.sort_by(|a, b| a.id.0.cmp(&b.id.0));
+    let mut keys: Vec<String> = Vec::new();
+    for info in nodes {
+        for tier in &info.locality.tiers {
+            if !keys.contains(&tier.key) {
+                keys.push(tier.key.clone());
+            }
+        }
+    }
+    keys
+}
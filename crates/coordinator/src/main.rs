@@ -1,4 +1,10 @@
-use coordinator_lib::Coordinator;
+use common::config::{load_config, CoordinatorConfig};
+use common::util::init_logger;
+use coordinator_lib::{
+    commit_transaction_unlocked, transaction_get_for_update_unlocked, Coordinator, DeadlockDetector, Decommissioner,
+    FailureDetector, GrpcNodeTransport, MetaSync, RaftMetaStore, RangeScheduler, Rebalancer, TransactionReaper,
+};
+use log::{error, info};
 use tokio::signal;
 use tokio::sync::Mutex;
 use std::sync::Arc;
@@ -7,34 +13,64 @@ mod server;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    println!("Starting coordinator...");
-    
+    // Load the configuration named on the command line, if any
+    let config: CoordinatorConfig = match std::env::args().nth(1) {
+        Some(path) => load_config(path)?,
+        None => CoordinatorConfig::default(),
+    };
+    init_logger(&format!("coordinator at {}", config.listen_addr));
+    info!("Starting coordinator...");
+    config.validate()?;
+
     // Create a new coordinator instance
-    let coordinator = Arc::new(Mutex::new(Coordinator::new()));
+    let coordinator = Arc::new(Mutex::new(Coordinator::with_config(&config)));
+    let clock = coordinator.lock().await.clock();
+    coordinator.lock().await.set_transport(Arc::new(GrpcNodeTransport::new(clock.clone())));
     
-    // Initialize the coordinator (if needed)
-    {
-        let coord = coordinator.lock().await;
-        // You might want to load configuration here
-        // coord.initialize("localhost:50051", "localhost:50052").await?;
+    // Load the cluster metadata from the meta group, if there is one
+    if !config.meta_nodes.is_empty() {
+        let mut coord = coordinator.lock().await;
+        coord.set_meta_store(&config, Arc::new(RaftMetaStore::new(&config, clock.clone())));
+        coord.sync_metadata().await?;
     }
     
+    // Keep the metadata in sync with the other coordinators, and run the
+    // background jobs below only while holding the coordinator lease
+    tokio::spawn(MetaSync::new(&config, coordinator.clone()).run());
+    
+    // Roll back transactions their clients abandoned
+    tokio::spawn(TransactionReaper::new(&config, coordinator.clone()).run());
+    
+    // Break deadlocks between lock waits on the data nodes
+    tokio::spawn(DeadlockDetector::new(&config, coordinator.clone()).run());
+    
+    // Split partitions that grow too large or too busy, merge small idle ones
+    tokio::spawn(RangeScheduler::new(&config, coordinator.clone(), clock.clone()).run());
+    
+    // Replace the replicas of nodes that stopped heartbeating
+    tokio::spawn(FailureDetector::new(&config, coordinator.clone()).run());
+    
+    // Move replicas off nodes being decommissioned
+    tokio::spawn(Decommissioner::new(&config, coordinator.clone()).run());
+    
+    // Move replicas off overloaded nodes
+    tokio::spawn(Rebalancer::new(&config, coordinator.clone(), clock).run());
+    
     // Start the gRPC server
-    let server_addr = "127.0.0.1:50051";
-    let server_handle = tokio::spawn(server::start_grpc_server(
-        server_addr,
-        coordinator.clone(),
-    ));
+    let server_addr = config.listen_addr.clone();
+    let server_handle = tokio::spawn(async move {
+        server::start_grpc_server(&server_addr, coordinator.clone()).await
+    });
     
-    println!("Coordinator running on {}. Press Ctrl+C to exit.", server_addr);
+    info!("Coordinator running on {}. Press Ctrl+C to exit.", config.listen_addr);
     
     // Wait for Ctrl+C
     match signal::ctrl_c().await {
         Ok(()) => {
-            println!("Shutting down coordinator...");
+            info!("Shutting down coordinator...");
         }
         Err(err) => {
-            eprintln!("Unable to listen for shutdown signal: {}", err);
+            error!("Unable to listen for shutdown signal: {}", err);
         }
     }
     
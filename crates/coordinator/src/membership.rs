@@ -0,0 +1,126 @@
+//! Raft membership changes.
+//!
+//! A replica is added in two steps. The new node first joins the Raft group
+//! of the partition as a learner: it receives the log but does not vote, so
+//! a slow catch-up cannot hold up commits. The leader brings it up to date,
+//! with a snapshot when the entries it lacks were already compacted, and
+//! only then is it promoted to a voter.
+//!
+//! Promotions and removals change the voter set through joint consensus. The
+//! leader first commits a joint configuration, in which elections and commits
+//! need a majority of both the old and the new voters, and then the new
+//! configuration alone. No two disjoint majorities can form at any point, so
+//! the partition stays available throughout.
+//!
+//! Removing the leader outright would leave the group without a leader
+//! until an election, so leadership moves to another voter first.
+
+use crate::change::{Step, StepRequest};
+use crate::Coordinator;
+use common::error::{DatabaseError, Result};
+use common::types::{NodeId, PartitionInfo};
+
+impl Coordinator {
+    /// The current replicas of a partition
+    pub fn partition(&self, partition_id: u64) -> Result<PartitionInfo> {
+        self.metadata.partitions.get(&partition_id)
+            .cloned()
+            .ok_or_else(|| DatabaseError::Partition {
+                partition: Some(partition_id),
+                message: format!("Unknown partition {}", partition_id),
+            })
+    }
+
+    /// Add `node` to a partition as a learner. Returns once the learner has
+    /// caught up with the leader's log.
+    pub async fn add_learner(&mut self, partition_id: u64, node: NodeId, address: String) -> Result<PartitionInfo> {
+        self.sync_metadata().await?;
+        let step = self.add_learner_step(partition_id, node, address)?;
+        Ok(self.run_step(step).await?.remove(0))
+    }
+
+    /// Promote a learner of a partition to a voter
+    pub async fn promote_learner(&mut self, partition_id: u64, node: NodeId) -> Result<PartitionInfo> {
+        self.sync_metadata().await?;
+        let step = self.promote_step(partition_id, node)?;
+        Ok(self.run_step(step).await?.remove(0))
+    }
+
+    /// Add `node` to a partition as a voter: join as a learner, catch up,
+    /// then get promoted
+    pub async fn add_voter(&mut self, partition_id: u64, node: NodeId, address: String) -> Result<PartitionInfo> {
+        self.add_learner(partition_id, node.clone(), address).await?;
+        self.promote_learner(partition_id, node).await
+    }
+
+    /// Remove a voter or learner from a partition
+    pub async fn remove_replica(&mut self, partition_id: u64, node: NodeId) -> Result<PartitionInfo> {
+        self.sync_metadata().await?;
+        if self.partition(partition_id)?.leader == node {
+            self.transfer_leader(partition_id, None).await?;
+        }
+        let step = self.removal_step(partition_id, node)?;
+        Ok(self.run_step(step).await?.remove(0))
+    }
+
+    /// Have the partition leader replicate to `node` as a learner, which
+    /// it answers once the learner has caught up
+    pub(crate) fn add_learner_step(&self, partition_id: u64, node: NodeId, address: String) -> Result<Step> {
+        let partition = self.partition(partition_id)?;
+        if partition.has_replica(&node) {
+            return Err(DatabaseError::Partition {
+                partition: Some(partition_id),
+                message: format!("Node {} already holds a replica of partition {}", node, partition_id),
+            });
+        }
+
+        let mut target = partition.clone();
+        target.learners.push(node.clone());
+        self.step(&partition, StepRequest::AddLearner { node, address }, vec![target])
+    }
+
+    pub(crate) fn promote_step(&self, partition_id: u64, node: NodeId) -> Result<Step> {
+        let partition = self.partition(partition_id)?;
+        if !partition.learners.contains(&node) {
+            return Err(DatabaseError::Partition {
+                partition: Some(partition_id),
+                message: format!("Node {} is not a learner of partition {}", node, partition_id),
+            });
+        }
+
+        let mut target = partition.clone();
+        target.learners.retain(|learner| *learner != node);
+        target.followers.push(node);
+        self.membership_step(&partition, target)
+    }
+
+    /// Remove a replica other than the leader
+    pub(crate) fn removal_step(&self, partition_id: u64, node: NodeId) -> Result<Step> {
+        let partition = self.partition(partition_id)?;
+        if !partition.has_replica(&node) {
+            return Err(DatabaseError::Partition {
+                partition: Some(partition_id),
+                message: format!("Node {} holds no replica of partition {}", node, partition_id),
+            });
+        }
+
+        let mut target = partition.clone();
+        target.followers.retain(|follower| *follower != node);
+        target.learners.retain(|learner| *learner != node);
+        self.membership_step(&partition, target)
+    }
+
+    /// Address of a registered node
+    pub(crate) fn address(&self, node: &NodeId) -> Result<String> {
+        self.metadata.nodes.get(node)
+            .map(|info| info.address.clone())
+            .ok_or_else(|| DatabaseError::NodeNotFound(node.clone()))
+    }
+
+    /// Move the Raft group of a partition to the voters and learners of
+    /// `target`. The leader commits a joint configuration and then the
+    /// target configuration.
+    fn membership_step(&self, partition: &PartitionInfo, target: PartitionInfo) -> Result<Step> {
+        self.step(partition, StepRequest::ChangeMembership(target.clone()), vec![target])
+    }
+}
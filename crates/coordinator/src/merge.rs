@@ -0,0 +1,142 @@
+//! Range merges.
+//!
+//! Splits only ever add partitions, so after load moves elsewhere or data is
+//! deleted the cluster is left with many small, idle partitions, each paying
+//! for its own Raft group. Adjacent partitions that together stay below the
+//! merge thresholds are merged back into one.
+//!
+//! A merge needs the replicas of both partitions on the same nodes, so the
+//! right partition first gets the voters of the left one, through the usual
+//! learner catch-up, and its leadership moves to the left leader before its
+//! other replicas are removed. Then two log entries do the merge. The right
+//! partition freezes: commands after the freeze are answered with the merged
+//! range and routed again. The left partition then takes over the right's
+//! range; every replica applies this only once its frozen copy of the right
+//! partition holds all of the right's data, so again no data moves.
+
+use crate::change::{PartitionChange, Step, StepRequest};
+use crate::locality::is_constraint_boundary;
+use crate::Coordinator;
+use common::error::{DatabaseError, Result};
+use common::types::{ClusterMetadata, Command, KeyRange, PartitionInfo, PartitionStats};
+use log::warn;
+use std::collections::{HashMap, HashSet};
+
+impl Coordinator {
+    /// Merges of adjacent partitions whose stats together stay below the
+    /// merge thresholds
+    pub fn plan_merges(&self, stats: &[PartitionStats]) -> Vec<PartitionChange> {
+        plan_merges(&self.metadata, stats, self.merge_size_bytes, self.merge_qps)
+            .into_iter()
+            .map(|(left_id, right_id)| PartitionChange::Merge { left_id, right_id })
+            .collect()
+    }
+
+    /// Merge adjacent partitions whose stats together stay below the merge
+    /// thresholds. Returns the merged partitions.
+    pub async fn merge_partitions(&mut self, stats: &[PartitionStats]) -> Result<Vec<PartitionInfo>> {
+        let mut merges = Vec::new();
+        for change in self.plan_merges(stats) {
+            match self.run_change(&change).await {
+                Ok(mut merged) => merges.push(merged.remove(0)),
+                Err(e) => warn!("Not making {:?}: {}", change, e),
+            }
+        }
+        Ok(merges)
+    }
+
+    /// Merge partition `right_id` into its left neighbour `left_id`. Returns
+    /// the merged partition, which keeps the id and replicas of the left one.
+    pub async fn merge_partition(&mut self, left_id: u64, right_id: u64) -> Result<PartitionInfo> {
+        self.run_change(&PartitionChange::Merge { left_id, right_id }).await?;
+        self.partition(left_id)
+    }
+
+    pub(crate) fn check_merge(&self, left: &PartitionInfo, right: &PartitionInfo) -> Result<()> {
+        if left.range.end != right.range.start {
+            return Err(DatabaseError::InvalidArgument(format!(
+                "Partitions {} and {} are not adjacent", left.id, right.id
+            )));
+        }
+        if is_constraint_boundary(&self.metadata, &right.range.start) {
+            return Err(DatabaseError::InvalidArgument(format!(
+                "Partitions {} and {} are split where placement constraints begin or end", left.id, right.id
+            )));
+        }
+        Ok(())
+    }
+
+    /// The next step of merging `right_id` into `left_id`: first move the
+    /// replicas of the right partition to the voters of the left one, with
+    /// the same leader, then merge. `None` once the right one is gone.
+    pub(crate) fn merge_next_step(&self, left_id: u64, right_id: u64) -> Result<Option<Step>> {
+        let Ok(right) = self.partition(right_id) else {
+            return Ok(None);
+        };
+        let left = self.partition(left_id)?;
+        self.check_merge(&left, &right)?;
+
+        let target_voters = left.voters();
+        for node in &target_voters {
+            if right.learners.contains(node) {
+                return self.promote_step(right_id, node.clone()).map(Some);
+            }
+            if !right.has_replica(node) {
+                let address = self.address(node)?;
+                return self.add_learner_step(right_id, node.clone(), address).map(Some);
+            }
+        }
+        if right.leader != left.leader {
+            return self.leader_step(right_id, Some(left.leader.clone()));
+        }
+        let extra = right.voters().into_iter()
+            .chain(right.learners.iter().cloned())
+            .find(|node| !target_voters.contains(node));
+        if let Some(node) = extra {
+            return self.removal_step(right_id, node).map(Some);
+        }
+
+        let merged = PartitionInfo {
+            range: KeyRange::new(left.range.start.clone(), right.range.end.clone()),
+            ..left.clone()
+        };
+        let commands = vec![
+            (right_id, Command::FreezeForMerge { merged: merged.clone() }),
+            (left_id, Command::MergePartitions { merged: merged.clone(), right_id }),
+        ];
+        // Both partitions have the same leader by now
+        self.check_idle(right_id)?;
+        Ok(Some(self.step(&left, StepRequest::Propose(commands), vec![merged])?.removing(right_id)))
+    }
+}
+
+/// Plan merges of adjacent partitions whose combined size and request rate
+/// stay below the given limits. Partitions without stats are left alone, as
+/// are partitions already in another merge of the plan. Returns
+/// `(left_id, right_id)` pairs.
+pub fn plan_merges(
+    metadata: &ClusterMetadata,
+    stats: &[PartitionStats],
+    max_size_bytes: u64,
+    max_qps: f64,
+) -> Vec<(u64, u64)> {
+    let stats: HashMap<u64, &PartitionStats> = stats.iter().map(|stats| (stats.partition_id, stats)).collect();
+    let mut partitions: Vec<&PartitionInfo> = metadata.partitions.values().collect();
+    partitions.sort_by(|a, b| a.range.start.cmp(&b.range.start));
+
+    let mut merging = HashSet::new();
+    let mut merges = Vec::new();
+    for pair in partitions.windows(2) {
+        let (left, right) = (pair[0], pair[1]);
+        if left.range.end != right.range.start || merging.contains(&left.id) {
+            continue;
+        }
+        let (Some(left_stats), Some(right_stats)) = (stats.get(&left.id), stats.get(&right.id)) else { continue };
+        if left_stats.size_bytes + right_stats.size_bytes <= max_size_bytes && left_stats.qps + right_stats.qps <= max_qps {
+            merging.insert(left.id);
+            merging.insert(right.id);
+            merges.push((left.id, right.id));
+        }
+    }
+    merges
+}
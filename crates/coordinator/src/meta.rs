@@ -0,0 +1,288 @@
+//! Cluster metadata in the meta Raft group.
+//!
+//! A coordinator holds no state of its own that the cluster cannot do
+//! without. The cluster metadata lives in the meta group, a Raft group
+//! hosted by a few data nodes that serves the reserved keys below any user
+//! key, so it survives coordinator restarts and any number of coordinators
+//! can serve clients side by side.
+//!
+//! Each coordinator works on an in-memory copy. Changes are saved with a
+//! compare-and-swap on the version of the stored copy, so a coordinator
+//! whose copy went stale cannot overwrite a newer one: its save fails, it
+//! reloads, and the request that made the change can be retried. Unchanged
+//! copies are refreshed from the meta group periodically.
+//!
+//! Node heartbeats are soft state. A coordinator keeps the newest heartbeat
+//! of each node from its own copy and the stored one, and saves after
+//! hearing from nodes, so failure detection sees heartbeats sent to any
+//! coordinator.
+//!
+//! Background jobs such as failure detection and rebalancing must not run
+//! on two coordinators at once. They only run on the coordinator holding
+//! the coordinator lease, which it renews on every refresh; when it stops
+//! renewing, another coordinator takes over once the lease runs out.
+
+use crate::Coordinator;
+use common::config::CoordinatorConfig;
+use common::error::{DatabaseError, Result};
+use common::hlc::HybridClock;
+use common::types::{ClusterMetadata, CLUSTER_METADATA_KEY, COORDINATOR_LEASE_KEY};
+use common::util::timestamp_ms;
+use log::{error, info};
+use rpc::client::NodeClient;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Versioned keys the cluster metadata is stored under.
+#[tonic::async_trait]
+pub trait MetaStore: Send + Sync + 'static {
+    /// Value and version of a key; `None` if the key does not exist.
+    async fn get(&self, key: &str) -> Result<Option<(Vec<u8>, u64)>>;
+
+    /// Store a value only if the key is still at `expected_version` (0 =
+    /// absent). Returns the new version, or `None` if the key moved on.
+    async fn put_if_version(&self, key: &str, value: Vec<u8>, expected_version: u64) -> Result<Option<u64>>;
+}
+
+/// Meta store backed by the meta Raft group. Requests go to the first of
+/// the group's nodes that answers, which forwards them to the group leader.
+pub struct RaftMetaStore {
+    nodes: Vec<String>,
+    clock: Arc<HybridClock>,
+}
+
+impl RaftMetaStore {
+    pub fn new(config: &CoordinatorConfig, clock: Arc<HybridClock>) -> Self {
+        Self { nodes: config.meta_nodes.clone(), clock }
+    }
+
+    async fn client(&self) -> Result<NodeClient> {
+        let mut last_error = DatabaseError::Config("No meta group nodes configured".to_string());
+        for addr in &self.nodes {
+            match NodeClient::connect(addr, self.clock.clone()).await {
+                Ok(client) => return Ok(client),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+#[tonic::async_trait]
+impl MetaStore for RaftMetaStore {
+    async fn get(&self, key: &str) -> Result<Option<(Vec<u8>, u64)>> {
+        self.client().await?.read(key.to_string()).await
+    }
+
+    async fn put_if_version(&self, key: &str, value: Vec<u8>, expected_version: u64) -> Result<Option<u64>> {
+        let response = self.client().await?.put_if_version(key.to_string(), value, expected_version).await?;
+        Ok(response.applied.then_some(response.version))
+    }
+}
+
+/// Meta store in memory, for a single coordinator or tests.
+#[derive(Default)]
+pub struct MemoryMetaStore {
+    entries: std::sync::Mutex<HashMap<String, (Vec<u8>, u64)>>,
+}
+
+#[tonic::async_trait]
+impl MetaStore for MemoryMetaStore {
+    async fn get(&self, key: &str) -> Result<Option<(Vec<u8>, u64)>> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+
+    async fn put_if_version(&self, key: &str, value: Vec<u8>, expected_version: u64) -> Result<Option<u64>> {
+        let mut entries = self.entries.lock().unwrap();
+        let version = entries.get(key).map_or(0, |(_, version)| *version);
+        if version != expected_version {
+            return Ok(None);
+        }
+        entries.insert(key.to_string(), (value, version + 1));
+        Ok(Some(version + 1))
+    }
+}
+
+/// The coordinator lease, as stored in the meta group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Lease {
+    holder: String,
+    expires_ms: u64,
+}
+
+/// Coordinator state tied to the meta group
+pub(crate) struct MetaState {
+    store: Arc<dyn MetaStore>,
+    /// Identifies the coordinator as a lease holder
+    id: String,
+    lease_ms: u64,
+    lease_expires_ms: u64,
+    /// Version of the stored metadata the in-memory copy derives from
+    stored_version: u64,
+    /// `ClusterMetadata::version` when last saved or loaded
+    saved_version: u64,
+    /// Whether heartbeats arrived since the last save
+    unsaved_heartbeats: bool,
+}
+
+/// Periodically refreshes the cluster metadata from the meta group, saving
+/// changes made while the meta group was unreachable, and renews the
+/// coordinator lease.
+pub struct MetaSync {
+    interval: Duration,
+    coordinator: Arc<Mutex<Coordinator>>,
+}
+
+impl MetaSync {
+    pub fn new(config: &CoordinatorConfig, coordinator: Arc<Mutex<Coordinator>>) -> Self {
+        Self {
+            interval: Duration::from_millis(config.metadata_refresh_interval_ms),
+            coordinator,
+        }
+    }
+
+    /// Sync forever.
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        let mut leading = false;
+        loop {
+            ticker.tick().await;
+            match self.check_once().await {
+                Ok(holds_lease) if holds_lease != leading => {
+                    leading = holds_lease;
+                    info!("{} the coordinator lease", if leading { "Acquired" } else { "Lost" });
+                },
+                Ok(_) => {},
+                Err(e) => error!("Metadata sync failed: {}", e),
+            }
+        }
+    }
+
+    /// Sync the metadata and renew the lease once. Returns whether this
+    /// coordinator holds the lease.
+    pub async fn check_once(&self) -> Result<bool> {
+        let mut coordinator = self.coordinator.lock().await;
+        if let Err(e) = coordinator.sync_metadata().await {
+            error!("Metadata sync failed: {}", e);
+        }
+        coordinator.renew_lease(timestamp_ms()).await
+    }
+}
+
+impl Coordinator {
+    /// Keep the cluster metadata in `store`. Call `sync_metadata` to load
+    /// it before serving.
+    pub fn set_meta_store(&mut self, config: &CoordinatorConfig, store: Arc<dyn MetaStore>) {
+        self.meta = Some(MetaState {
+            store,
+            id: config.listen_addr.clone(),
+            lease_ms: config.coordinator_lease_ms,
+            lease_expires_ms: 0,
+            stored_version: 0,
+            saved_version: 0,
+            unsaved_heartbeats: false,
+        });
+    }
+
+    /// Save the metadata if it changed since the last sync, or else load
+    /// the stored copy if another coordinator changed it.
+    ///
+    /// Every change of the metadata is saved right after it is made, and
+    /// before any request to a data node acts on it. A save fails with a
+    /// conflict if another coordinator saved first: the stored copy is
+    /// loaded then, and the change made here is undone and must be retried
+    /// by whoever made it. Heartbeats alone never conflict, as loading keeps
+    /// the newer heartbeat of every node.
+    pub async fn sync_metadata(&mut self) -> Result<()> {
+        let Some(meta) = self.meta.as_ref() else { return Ok(()) };
+        let store = meta.store.clone();
+        if self.metadata.version == meta.saved_version && !meta.unsaved_heartbeats {
+            return self.load_metadata(store.as_ref()).await;
+        }
+
+        loop {
+            let Some(meta) = self.meta.as_ref() else { return Ok(()) };
+            let only_heartbeats = self.metadata.version == meta.saved_version;
+            let value = serde_json::to_vec(&self.metadata).map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+            if let Some(stored_version) = store.put_if_version(CLUSTER_METADATA_KEY, value, meta.stored_version).await? {
+                let version = self.metadata.version;
+                if let Some(meta) = self.meta.as_mut() {
+                    meta.stored_version = stored_version;
+                    meta.saved_version = version;
+                    meta.unsaved_heartbeats = false;
+                }
+                return Ok(());
+            }
+
+            self.load_metadata(store.as_ref()).await?;
+            if !only_heartbeats {
+                return Err(DatabaseError::Conflict {
+                    key: None,
+                    message: "Cluster metadata was changed by another coordinator".to_string(),
+                });
+            }
+        }
+    }
+
+    /// Renew the coordinator lease, or take it over if it is free or ran
+    /// out. Returns whether this coordinator holds it.
+    pub async fn renew_lease(&mut self, now_ms: u64) -> Result<bool> {
+        let Some(meta) = self.meta.as_mut() else { return Ok(true) };
+        let (current, version) = match meta.store.get(COORDINATOR_LEASE_KEY).await? {
+            Some((value, version)) => (Some(serde_json::from_slice::<Lease>(&value)?), version),
+            None => (None, 0),
+        };
+        if current.is_some_and(|lease| lease.holder != meta.id && lease.expires_ms > now_ms) {
+            meta.lease_expires_ms = 0;
+            return Ok(false);
+        }
+
+        let lease = Lease { holder: meta.id.clone(), expires_ms: now_ms + meta.lease_ms };
+        let value = serde_json::to_vec(&lease).map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+        let renewed = meta.store.put_if_version(COORDINATOR_LEASE_KEY, value, version).await?.is_some();
+        meta.lease_expires_ms = if renewed { lease.expires_ms } else { 0 };
+        Ok(renewed)
+    }
+
+    /// Whether this coordinator may run the background jobs: it holds the
+    /// coordinator lease, or it keeps the metadata to itself.
+    pub fn holds_lease(&self) -> bool {
+        self.meta.as_ref().is_none_or(|meta| timestamp_ms() < meta.lease_expires_ms)
+    }
+
+    /// Note that a node heartbeat arrived, so the next sync saves it.
+    pub(crate) fn heartbeat_received(&mut self) {
+        if let Some(meta) = self.meta.as_mut() {
+            meta.unsaved_heartbeats = true;
+        }
+    }
+
+    /// Load the stored metadata if it differs from the copy here, keeping the
+    /// newer heartbeat of every node.
+    async fn load_metadata(&mut self, store: &dyn MetaStore) -> Result<()> {
+        let Some((value, stored_version)) = store.get(CLUSTER_METADATA_KEY).await? else { return Ok(()) };
+        if self.meta.as_ref().is_some_and(|meta| meta.stored_version == stored_version) {
+            return Ok(());
+        }
+
+        let mut metadata: ClusterMetadata = serde_json::from_slice(&value)?;
+        for info in metadata.nodes.values_mut() {
+            let Some(local) = self.metadata.nodes.get(&info.id) else { continue };
+            if local.last_heartbeat > info.last_heartbeat {
+                info.last_heartbeat = local.last_heartbeat;
+                info.load = local.load.clone();
+            }
+        }
+        let version = metadata.version;
+        self.metadata = metadata;
+        if let Some(meta) = self.meta.as_mut() {
+            meta.stored_version = stored_version;
+            meta.saved_version = version;
+        }
+        self.resume_decommissions();
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+//! Cluster membership.
+//!
+//! The nodes of the cluster live in the cluster metadata next to the
+//! partitions, so every coordinator sees the same nodes and a change of
+//! status is saved with the rest of the metadata. Statuses only change along
+//! the transitions `NodeStatus::can_become` allows: heartbeats make nodes
+//! active, the failure detector marks silent ones suspect and dead, and
+//! decommissioning moves nodes through leaving to removed.
+
+use crate::Coordinator;
+use common::error::{DatabaseError, Result};
+use common::types::{NodeId, NodeInfo, NodeStatus, PartitionInfo};
+
+impl Coordinator {
+    /// Register a node that may hold replicas and lead partitions
+    pub fn register_node(&mut self, info: NodeInfo) {
+        self.metadata.nodes.insert(info.id.clone(), info);
+        self.metadata.version += 1;
+    }
+
+    /// Every node of the cluster, by id
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        let mut nodes: Vec<NodeInfo> = self.metadata.nodes.values().cloned().collect();
+        nodes.sort_by(|a, b| a.id.0.cmp(&b.id.0));
+        nodes
+    }
+
+    /// A node of the cluster
+    pub fn node(&self, node: &NodeId) -> Result<NodeInfo> {
+        self.metadata.nodes.get(node)
+            .cloned()
+            .ok_or_else(|| DatabaseError::NodeNotFound(node.clone()))
+    }
+
+    /// The partitions with a replica on `node`, by id
+    pub fn node_partitions(&self, node: &NodeId) -> Vec<PartitionInfo> {
+        let mut partitions: Vec<PartitionInfo> = self.metadata.partitions.values()
+            .filter(|partition| partition.has_replica(node))
+            .cloned()
+            .collect();
+        partitions.sort_by_key(|partition| partition.id);
+        partitions
+    }
+
+    /// Change the status of a node and return the one it had. Fails if the
+    /// node cannot go from its status to `status`.
+    pub fn set_node_status(&mut self, node: &NodeId, status: NodeStatus) -> Result<NodeStatus> {
+        let info = self.metadata.nodes.get_mut(node)
+            .ok_or_else(|| DatabaseError::NodeNotFound(node.clone()))?;
+        if !info.status.can_become(&status) {
+            return Err(DatabaseError::InvalidArgument(format!(
+                "Node {} is {:?} and cannot become {:?}", node, info.status, status
+            )));
+        }
+
+        let previous = std::mem::replace(&mut info.status, status);
+        self.metadata.version += 1;
+        self.resume_decommissions();
+        Ok(previous)
+    }
+}
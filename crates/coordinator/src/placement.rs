@@ -0,0 +1,232 @@
+//! Leader placement.
+//!
+//! Leadership of a partition moves with a leader transfer: the leader stops
+//! taking new proposals, brings the target voter up to date and tells it to
+//! start an election right away, which it wins since its log is complete.
+//!
+//! A node going down for maintenance is drained first. Draining moves all its
+//! leaderships to other voters and keeps new ones away until the node is
+//! undrained. Outside of maintenance, the placement policy spreads leaders
+//! evenly over the active nodes, since the leader does most of the work of a
+//! partition, but keeps them in the locality the placement constraints of
+//! the partition prefer whenever a voter there can lead.
+
+use crate::change::{PartitionChange, Step, StepRequest};
+use crate::locality::prefers_leader;
+use crate::Coordinator;
+use common::error::{DatabaseError, Result};
+use common::types::{ClusterMetadata, NodeId, NodeStatus, PartitionInfo};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+impl Coordinator {
+    /// Move leadership of a partition to `target`, or to the least loaded
+    /// eligible voter when no target is given
+    pub async fn transfer_leader(&mut self, partition_id: u64, target: Option<NodeId>) -> Result<PartitionInfo> {
+        self.sync_metadata().await?;
+        match self.leader_step(partition_id, target)? {
+            Some(step) => Ok(self.run_step(step).await?.remove(0)),
+            None => self.partition(partition_id),
+        }
+    }
+
+    /// Move every leadership off `node` and keep new ones away until it is
+    /// undrained. Fails if some partition has no other voter to take over.
+    pub async fn drain_node(&mut self, node: NodeId) -> Result<Vec<PartitionInfo>> {
+        if self.metadata.draining.insert(node.clone()) {
+            self.metadata.version += 1;
+        }
+        self.sync_metadata().await?;
+
+        let mut led: Vec<PartitionInfo> = self.metadata.partitions.values()
+            .filter(|partition| partition.leader == node)
+            .cloned()
+            .collect();
+        led.sort_by_key(|partition| partition.id);
+
+        let mut moved = Vec::new();
+        let mut stuck = Vec::new();
+        for partition in led {
+            let id = partition.id;
+            let result = match self.leader_candidate(&partition) {
+                Some(target) => self.move_leader(partition, target).await,
+                None => Err(DatabaseError::Partition { partition: Some(id), message: "no other voter".to_string() }),
+            };
+            match result {
+                Ok(partition) => moved.push(partition),
+                Err(e) => stuck.push(format!("{} ({})", id, e)),
+            }
+        }
+
+        if !stuck.is_empty() {
+            return Err(DatabaseError::Partition {
+                partition: None,
+                message: format!("Node {} still leads partitions {}", node, stuck.join(", ")),
+            });
+        }
+        Ok(moved)
+    }
+
+    /// Let a drained node lead partitions again. Its share of leaders comes
+    /// back with the next rebalancing.
+    pub fn undrain_node(&mut self, node: &NodeId) -> bool {
+        let undrained = self.metadata.draining.remove(node);
+        if undrained {
+            self.metadata.version += 1;
+        }
+        undrained
+    }
+
+    /// Whether `node` is drained
+    pub fn is_draining(&self, node: &NodeId) -> bool {
+        self.metadata.draining.contains(node)
+    }
+
+    /// Spread leaders evenly over the active nodes. Returns the partitions
+    /// whose leader moved.
+    pub async fn balance_leaders(&mut self) -> Result<Vec<PartitionInfo>> {
+        let mut moved = Vec::new();
+        for change in self.plan_leader_moves() {
+            moved.extend(self.run_change(&change).await?);
+        }
+        Ok(moved)
+    }
+
+    /// Leader moves that spread leaders evenly over the active nodes
+    pub fn plan_leader_moves(&self) -> Vec<PartitionChange> {
+        plan_leader_transfers(&self.metadata, &self.metadata.draining)
+            .into_iter()
+            .map(|(partition_id, target)| PartitionChange::MoveLeader { partition_id, target })
+            .collect()
+    }
+
+    /// The eligible voter of `partition` that leads the fewest partitions,
+    /// preferring voters in the partition's preferred leader locality
+    fn leader_candidate(&self, partition: &PartitionInfo) -> Option<NodeId> {
+        let counts = leader_counts(&self.metadata);
+        partition.followers.iter()
+            .filter(|node| is_eligible(&self.metadata, &self.metadata.draining, node))
+            .min_by_key(|node| {
+                (!prefers_leader(&self.metadata, partition, node), counts.get(*node).copied().unwrap_or(0), node.0.as_str())
+            })
+            .cloned()
+    }
+
+    /// Move leadership of a partition to `target`, or to the least loaded
+    /// eligible voter when no target is given; `None` if `target` leads
+    /// already
+    pub(crate) fn leader_step(&self, partition_id: u64, target: Option<NodeId>) -> Result<Option<Step>> {
+        let partition = self.partition(partition_id)?;
+        let target = match target {
+            Some(target) if target == partition.leader => return Ok(None),
+            Some(target) if partition.followers.contains(&target) => target,
+            Some(target) => {
+                return Err(DatabaseError::Partition {
+                    partition: Some(partition_id),
+                    message: format!("Node {} is not a voter of partition {}", target, partition_id),
+                });
+            },
+            None => self.leader_candidate(&partition).ok_or_else(|| DatabaseError::Partition {
+                partition: Some(partition_id),
+                message: format!("Partition {} has no other voter to lead it", partition_id),
+            })?,
+        };
+        self.move_leader_step(partition, target).map(Some)
+    }
+
+    async fn move_leader(&mut self, partition: PartitionInfo, target: NodeId) -> Result<PartitionInfo> {
+        let step = self.move_leader_step(partition, target)?;
+        Ok(self.run_step(step).await?.remove(0))
+    }
+
+    /// Have the partition leader hand over to `target`, which it answers
+    /// once the target has won the election
+    fn move_leader_step(&self, partition: PartitionInfo, target: NodeId) -> Result<Step> {
+        let mut moved = partition.clone();
+        moved.followers.retain(|follower| *follower != target);
+        let previous = std::mem::replace(&mut moved.leader, target.clone());
+        moved.followers.insert(0, previous);
+        self.step(&partition, StepRequest::TransferLeader(target), vec![moved])
+    }
+}
+
+/// Plan leader transfers that spread the leaders of all partitions evenly
+/// over the active, undrained nodes.
+///
+/// Leaders on ineligible nodes, or outside the leader locality the
+/// partition's constraints prefer while an eligible voter inside it exists,
+/// move first. Then leaderships move from the most to the least loaded
+/// voters of each partition while that narrows the gap, never leaving the
+/// preferred locality, so no node ends up leading two partitions more than
+/// another node that could have led one of them. Returns
+/// `(partition_id, new_leader)`.
+pub fn plan_leader_transfers(metadata: &ClusterMetadata, draining: &HashSet<NodeId>) -> Vec<(u64, NodeId)> {
+    let eligible = |node: &NodeId| is_eligible(metadata, draining, node);
+    let preferred = |partition: &PartitionInfo, node: &NodeId| prefers_leader(metadata, partition, node);
+    let mut partitions: Vec<&PartitionInfo> = metadata.partitions.values().collect();
+    partitions.sort_by_key(|partition| partition.id);
+
+    let mut counts: HashMap<NodeId, usize> = leader_counts(metadata);
+    let mut leaders: BTreeMap<u64, NodeId> = partitions.iter()
+        .map(|partition| (partition.id, partition.leader.clone()))
+        .collect();
+    let least_loaded = |partition: &PartitionInfo, leader: &NodeId, counts: &HashMap<NodeId, usize>| {
+        partition.voters().into_iter()
+            .filter(|node| node != leader && eligible(node))
+            .filter(|node| preferred(partition, node) || !preferred(partition, leader))
+            .min_by_key(|node| (!preferred(partition, node), counts.get(node).copied().unwrap_or(0), node.0.clone()))
+    };
+
+    for partition in &partitions {
+        if eligible(&partition.leader) && preferred(partition, &partition.leader) {
+            continue;
+        }
+        let target = least_loaded(partition, &partition.leader, &counts)
+            .filter(|target| !eligible(&partition.leader) || preferred(partition, target));
+        if let Some(target) = target {
+            move_count(&mut counts, &partition.leader, &target);
+            leaders.insert(partition.id, target);
+        }
+    }
+
+    // Every move lowers the sum of squared counts, so this terminates
+    loop {
+        let best = partitions.iter()
+            .filter_map(|partition| {
+                let leader = leaders[&partition.id].clone();
+                if !eligible(&leader) {
+                    return None;
+                }
+                let target = least_loaded(partition, &leader, &counts)?;
+                let gap = counts[&leader].saturating_sub(counts.get(&target).copied().unwrap_or(0));
+                (gap >= 2).then_some((gap, partition.id, leader, target))
+            })
+            .max_by_key(|(gap, id, _, _)| (*gap, std::cmp::Reverse(*id)));
+        let Some((_, id, from, to)) = best else { break };
+        move_count(&mut counts, &from, &to);
+        leaders.insert(id, to);
+    }
+
+    partitions.iter()
+        .filter(|partition| leaders[&partition.id] != partition.leader)
+        .map(|partition| (partition.id, leaders[&partition.id].clone()))
+        .collect()
+}
+
+pub(crate) fn is_eligible(metadata: &ClusterMetadata, draining: &HashSet<NodeId>, node: &NodeId) -> bool {
+    !draining.contains(node) && metadata.nodes.get(node).is_some_and(|info| info.status == NodeStatus::Active)
+}
+
+fn move_count(counts: &mut HashMap<NodeId, usize>, from: &NodeId, to: &NodeId) {
+    if let Some(count) = counts.get_mut(from) {
+        *count -= 1;
+    }
+    *counts.entry(to.clone()).or_insert(0) += 1;
+}
+
+fn leader_counts(metadata: &ClusterMetadata) -> HashMap<NodeId, usize> {
+    let mut counts = HashMap::new();
+    for partition in metadata.partitions.values() {
+        *counts.entry(partition.leader.clone()).or_insert(0) += 1;
+    }
+    counts
+}
@@ -0,0 +1,313 @@
+//! Replica rebalancing.
+//!
+//! Nodes report their disk usage and request rate, and the rebalancer moves
+//! replicas from the most to the least loaded nodes until no move improves
+//! the balance by much. A node's load score adds up its replica count, disk
+//! usage and request rate, each relative to the cluster mean, so a node twice
+//! as busy as the average counts as much as one holding twice the average
+//! number of replicas.
+//!
+//! A replica moves like any membership change: the target joins as a
+//! learner, catches up and is promoted, and only then is the old replica
+//! removed, so the partition never runs with fewer replicas than it should.
+//! Catching up copies the partition's data, so only a few moves are started
+//! per round.
+//!
+//! Placement constraints come before balance: replicas in the wrong place,
+//! or that could spread their partition over more localities, move first,
+//! and no move for balance breaks a constraint or narrows a partition's
+//! spread. The rebalancer also moves leaders to where the constraints
+//! prefer them.
+
+use crate::change::{run_change_unlocked, PartitionChange};
+use crate::locality::{allows, diversity, plan_locality_moves};
+use crate::placement::{is_eligible, plan_leader_transfers};
+use crate::Coordinator;
+use common::config::CoordinatorConfig;
+use common::error::Result;
+use common::hlc::HybridClock;
+use common::types::{ClusterMetadata, NodeId, NodeLoad, PartitionInfo};
+use log::{error, info, warn};
+use rpc::client::NodeClient;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// A replica of a partition to move from one node to another
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicaMove {
+    pub partition_id: u64,
+    pub from: NodeId,
+    pub to: NodeId,
+}
+
+impl fmt::Display for ReplicaMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "partition {}: {} -> {}", self.partition_id, self.from, self.to)
+    }
+}
+
+/// Periodically collects the load of the nodes listed in the coordinator
+/// configuration and moves replicas to even it out. In dry-run mode the
+/// moves are only logged.
+pub struct Rebalancer {
+    nodes: Vec<String>,
+    clock: Arc<HybridClock>,
+    interval: Duration,
+    max_moves: usize,
+    min_gain: f64,
+    dry_run: bool,
+    coordinator: Arc<Mutex<Coordinator>>,
+}
+
+impl Rebalancer {
+    pub fn new(config: &CoordinatorConfig, coordinator: Arc<Mutex<Coordinator>>, clock: Arc<HybridClock>) -> Self {
+        Self {
+            nodes: config.initial_nodes.clone(),
+            clock,
+            interval: Duration::from_millis(config.rebalance_interval_ms),
+            max_moves: config.rebalance_max_moves,
+            min_gain: config.rebalance_min_gain,
+            dry_run: config.rebalance_dry_run,
+            coordinator,
+        }
+    }
+
+    /// Rebalance forever.
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.check_once().await {
+                error!("Rebalancing failed: {}", e);
+            }
+        }
+    }
+
+    /// Run one rebalancing round. Returns the moves made, or in dry-run mode
+    /// the moves that would have been made. Only the coordinator holding the
+    /// lease rebalances.
+    pub async fn check_once(&self) -> Result<Vec<ReplicaMove>> {
+        if !self.coordinator.lock().await.holds_lease() {
+            return Ok(Vec::new());
+        }
+        let mut loads = Vec::new();
+        for addr in &self.nodes {
+            let fetched = match NodeClient::connect(addr, self.clock.clone()).await {
+                Ok(mut client) => client.node_load().await,
+                Err(e) => Err(e),
+            };
+            match fetched {
+                Ok(load) => loads.push(load),
+                Err(e) => warn!("Skipping node {} in rebalancing: {}", addr, e),
+            }
+        }
+
+        let planned = {
+            let mut coordinator = self.coordinator.lock().await;
+            for (node, load) in loads {
+                coordinator.record_node_load(&node, load);
+            }
+            coordinator.plan_replica_moves(self.max_moves, self.min_gain)
+        };
+        if self.dry_run {
+            for planned in &planned {
+                info!("Rebalancer dry run: would move replica of {}", planned);
+            }
+            let coordinator = self.coordinator.lock().await;
+            for (partition_id, target) in plan_leader_transfers(&coordinator.metadata, &coordinator.metadata.draining) {
+                info!("Rebalancer dry run: would move leader of partition {} to {}", partition_id, target);
+            }
+            return Ok(planned);
+        }
+
+        // The moves run without the coordinator lock, which is only taken
+        // to plan and record each step
+        let mut moved = Vec::new();
+        for planned in planned {
+            match run_change_unlocked(&self.coordinator, &PartitionChange::MoveReplica(planned.clone())).await {
+                Ok(_) => moved.push(planned),
+                Err(e) => warn!("Not moving replica of {}: {}", planned, e),
+            }
+        }
+        let leader_moves = self.coordinator.lock().await.plan_leader_moves();
+        for change in leader_moves {
+            run_change_unlocked(&self.coordinator, &change).await?;
+        }
+        Ok(moved)
+    }
+}
+
+impl Coordinator {
+    /// Record the load last reported by a node. Returns false for unknown nodes.
+    pub fn record_node_load(&mut self, node: &NodeId, load: NodeLoad) -> bool {
+        match self.metadata.nodes.get_mut(node) {
+            Some(info) => {
+                info.load = load;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Plan up to `max_moves` replica moves, each improving the balance by at
+    /// least `min_gain`
+    pub fn plan_replica_moves(&self, max_moves: usize, min_gain: f64) -> Vec<ReplicaMove> {
+        plan_replica_moves(&self.metadata, &self.metadata.draining, max_moves, min_gain)
+    }
+
+    /// Move a voting replica of a partition to another node: add the target
+    /// as a learner, promote it once caught up, then remove the old replica.
+    /// Leadership moves away first if the old replica leads.
+    pub async fn move_replica(&mut self, planned: &ReplicaMove) -> Result<PartitionInfo> {
+        self.run_change(&PartitionChange::MoveReplica(planned.clone())).await?;
+        self.partition(planned.partition_id)
+    }
+}
+
+/// Replica count and load of a node while planning
+#[derive(Debug, Clone, Default)]
+struct Usage {
+    replicas: f64,
+    disk_used_bytes: f64,
+    disk_capacity_bytes: f64,
+    qps: f64,
+}
+
+impl Usage {
+    fn disk_usage(&self) -> f64 {
+        if self.disk_capacity_bytes == 0.0 {
+            return 0.0;
+        }
+        self.disk_used_bytes / self.disk_capacity_bytes
+    }
+
+    /// Usage after gaining (`sign` 1) or losing (`sign` -1) one replica with
+    /// the given disk usage and request rate
+    fn shifted(&self, sign: f64, disk_bytes: f64, qps: f64) -> Self {
+        Self {
+            replicas: self.replicas + sign,
+            disk_used_bytes: self.disk_used_bytes + sign * disk_bytes,
+            qps: self.qps + sign * qps,
+            ..self.clone()
+        }
+    }
+}
+
+/// Cluster means the load scores are relative to
+struct Means {
+    replicas: f64,
+    disk_usage: f64,
+    qps: f64,
+}
+
+impl Means {
+    fn score(&self, usage: &Usage) -> f64 {
+        let relative = |value: f64, mean: f64| if mean > 0.0 { value / mean } else { 0.0 };
+        relative(usage.replicas, self.replicas) + relative(usage.disk_usage(), self.disk_usage) + relative(usage.qps, self.qps)
+    }
+}
+
+/// Plan replica moves that fix the placement of partitions under their
+/// constraints and even out the load of the nodes.
+///
+/// Moves fixing placement come first and are not held to `min_gain`.
+/// Each step picks the move of a voting replica that lowers the sum of the
+/// squared load scores of its two nodes the most, which favours moves off
+/// the most loaded nodes, and stops when the best move lowers it by less
+/// than `min_gain` or after `max_moves` moves. Targets must be active,
+/// undrained and allowed by the constraints without narrowing the spread of
+/// the partition, and a partition moves at most one replica per plan. A
+/// replica's share of its node's disk usage and request rate is taken to be
+/// an even share of the node's load.
+pub fn plan_replica_moves(
+    metadata: &ClusterMetadata,
+    draining: &HashSet<NodeId>,
+    max_moves: usize,
+    min_gain: f64,
+) -> Vec<ReplicaMove> {
+    let mut usage: HashMap<NodeId, Usage> = HashMap::new();
+    for (id, info) in &metadata.nodes {
+        usage.insert(id.clone(), Usage {
+            disk_used_bytes: info.load.disk_used_bytes as f64,
+            disk_capacity_bytes: info.load.disk_capacity_bytes as f64,
+            qps: info.load.qps,
+            ..Usage::default()
+        });
+    }
+    let mut partitions: Vec<&PartitionInfo> = metadata.partitions.values().collect();
+    partitions.sort_by_key(|partition| partition.id);
+    for partition in &partitions {
+        for node in partition.voters().into_iter().chain(partition.learners.iter().cloned()) {
+            usage.entry(node).or_default().replicas += 1.0;
+        }
+    }
+
+    let count = usage.len().max(1) as f64;
+    let means = Means {
+        replicas: usage.values().map(|usage| usage.replicas).sum::<f64>() / count,
+        disk_usage: usage.values().map(Usage::disk_usage).sum::<f64>() / count,
+        qps: usage.values().map(|usage| usage.qps).sum::<f64>() / count,
+    };
+    let mut targets: Vec<NodeId> = usage.keys()
+        .filter(|node| is_eligible(metadata, draining, node))
+        .cloned()
+        .collect();
+    targets.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut moved = HashSet::new();
+    let mut moves = Vec::new();
+    for planned in plan_locality_moves(metadata, draining).into_iter().take(max_moves) {
+        let source = &usage[&planned.from];
+        let (disk_bytes, qps) = (source.disk_used_bytes / source.replicas, source.qps / source.replicas);
+        let after_source = source.shifted(-1.0, disk_bytes, qps);
+        let after_target = usage[&planned.to].shifted(1.0, disk_bytes, qps);
+        usage.insert(planned.from.clone(), after_source);
+        usage.insert(planned.to.clone(), after_target);
+        moved.insert(planned.partition_id);
+        moves.push(planned);
+    }
+
+    while moves.len() < max_moves {
+        let mut best: Option<(f64, ReplicaMove, Usage, Usage)> = None;
+        for partition in partitions.iter().filter(|partition| !moved.contains(&partition.id)) {
+            let constraints = metadata.constraints_for(partition);
+            let voters = partition.voters();
+            let spread = diversity(metadata, &voters);
+            for from in &voters {
+                let others: Vec<NodeId> = voters.iter().filter(|voter| *voter != from).cloned().collect();
+                let keeps_placement = |to: &NodeId| {
+                    let mut after = others.clone();
+                    after.push(to.clone());
+                    allows(metadata, constraints, &others, to) && diversity(metadata, &after) >= spread
+                };
+                let source = &usage[from];
+                let before_source = means.score(source).powi(2);
+                let disk_bytes = source.disk_used_bytes / source.replicas;
+                let qps = source.qps / source.replicas;
+                let after_source = source.shifted(-1.0, disk_bytes, qps);
+                for to in targets.iter().filter(|node| !partition.has_replica(node) && keeps_placement(node)) {
+                    let after_target = usage[to].shifted(1.0, disk_bytes, qps);
+                    let before = before_source + means.score(&usage[to]).powi(2);
+                    let gain = before - means.score(&after_source).powi(2) - means.score(&after_target).powi(2);
+                    if best.as_ref().is_none_or(|(best_gain, ..)| gain > *best_gain) {
+                        let planned = ReplicaMove { partition_id: partition.id, from: from.clone(), to: to.clone() };
+                        best = Some((gain, planned, after_source.clone(), after_target));
+                    }
+                }
+            }
+        }
+
+        let Some((gain, planned, after_source, after_target)) = best else { break };
+        if gain < min_gain {
+            break;
+        }
+        usage.insert(planned.from.clone(), after_source);
+        usage.insert(planned.to.clone(), after_target);
+        moved.insert(planned.partition_id);
+        moves.push(planned);
+    }
+    moves
+}
@@ -1,8 +1,28 @@
-use crate::Coordinator;
+use crate::{commit_transaction_unlocked, transaction_get_for_update_unlocked, Coordinator};
+use common::error::DatabaseError;
+use common::hlc::HybridClock;
+use common::types::{
+    BatchOperation, IsolationLevel, Locality, NodeId, NodeLoad, PartitionInfo, ReadConsistency, TransactionId,
+};
+use common::util::timestamp_ms;
+use log::info;
+use rpc::proto::admin::admin_service_server::{AdminService, AdminServiceServer};
+use rpc::proto::admin::{
+    AbortDecommissionRequest, AddReplicaRequest, BalanceLeadersRequest, DecommissionNodeRequest, DecommissionResponse,
+    DecommissionStatusRequest, DescribeNodeRequest, DescribeNodeResponse, DrainNodeRequest, GetMembershipRequest,
+    LeaderTransfersResponse, ListNodesRequest, ListNodesResponse, ListPlacementConstraintsRequest, MembershipResponse, PlacementConstraintsResponse, PromoteLearnerRequest,
+    RemoveReplicaRequest, SetPlacementConstraintsRequest, TransferLeaderRequest, UndrainNodeRequest, UndrainNodeResponse,
+};
+use rpc::proto::cluster::cluster_service_server::{ClusterService, ClusterServiceServer};
+use rpc::proto::cluster::{NodeHeartbeatRequest, NodeHeartbeatResponse};
 use rpc::proto::database::database_service_server::{DatabaseService, DatabaseServiceServer};
 use rpc::proto::database::{
-    DeleteRequest, DeleteResponse, GetRequest, GetResponse, PutRequest, PutResponse,
-    QueryRequest, QueryResponse, Row, ScanRequest, ScanResponse, Value,
+    BatchRequest, BatchResponse, BeginTransactionRequest, BeginTransactionResponse,
+    CommitTransactionRequest, CommitTransactionResponse, ConditionalWriteRequest, ConditionalWriteResponse, DeleteRequest,
+    DeleteResponse, GetPartitionMapRequest, GetPartitionMapResponse, GetRequest, GetResponse, IncrementRequest,
+    IncrementResponse, PutRequest,
+    PutResponse, QueryRequest, QueryResponse, RollbackTransactionRequest, RollbackTransactionResponse, Row,
+    ScanRequest, ScanResponse, Value,
 };
 use rpc::proto::node::node_service_server::{NodeService, NodeServiceServer};
 use rpc::proto::node::{
@@ -11,8 +31,10 @@ use rpc::proto::node::{
 use rpc::proto::raft::raft_service_server::{RaftService, RaftServiceServer};
 use rpc::proto::raft::{
     AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse,
-    RequestVoteRequest, RequestVoteResponse,
+    HeartbeatBatchRequest, HeartbeatBatchResponse, RequestVoteRequest, RequestVoteResponse, TimeoutNowRequest,
+    TimeoutNowResponse,
 };
+use rpc::status::{to_status, to_status_at_version};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tonic::Request;
@@ -40,6 +62,65 @@ impl DatabaseServiceImpl {
 
         Row { values }
     }
+
+    // Helper to turn the TTL or absolute expiry of a put into an absolute expiry.
+    // TTLs are resolved here, once, so every replica stores the same expiry.
+    #[allow(clippy::result_large_err)]
+    fn resolve_expiry(ttl_ms: u64, expires_at_ms: u64) -> Result<Option<u64>, Status> {
+        match (ttl_ms, expires_at_ms) {
+            (0, 0) => Ok(None),
+            (ttl_ms, 0) => Ok(Some(timestamp_ms().saturating_add(ttl_ms))),
+            (0, expires_at_ms) => Ok(Some(expires_at_ms)),
+            _ => Err(Status::invalid_argument("Set at most one of ttl_ms and expires_at_ms")),
+        }
+    }
+
+    // Helper to convert the read consistency of a request
+    #[allow(clippy::result_large_err)]
+    fn read_consistency(consistency: i32, max_staleness_ms: u64) -> Result<ReadConsistency, Status> {
+        match rpc::proto::database::ReadConsistency::try_from(consistency) {
+            Ok(rpc::proto::database::ReadConsistency::Linearizable) => Ok(ReadConsistency::Linearizable),
+            Ok(rpc::proto::database::ReadConsistency::Stale) if max_staleness_ms > 0 => {
+                Ok(ReadConsistency::Stale { max_staleness_ms })
+            },
+            Ok(rpc::proto::database::ReadConsistency::Stale) => {
+                Err(Status::invalid_argument("Stale reads need a positive max_staleness_ms"))
+            },
+            Err(_) => Err(Status::invalid_argument(format!("Unknown read consistency {}", consistency))),
+        }
+    }
+
+    // Helper to fail a request with the error it ran into, along with the
+    // metadata version the coordinator is at
+    fn status(coordinator: &Coordinator, error: DatabaseError) -> Status {
+        to_status_at_version(error, coordinator.metadata_version())
+    }
+
+    // Helper to read the optional transaction id of a request
+    fn transaction_id(id: String) -> Option<TransactionId> {
+        Some(id).filter(|id| !id.is_empty()).map(TransactionId)
+    }
+
+    // Helper to convert protobuf batch operations into storage commands
+    #[allow(clippy::result_large_err)]
+    fn convert_from_proto_batch(
+        operations: Vec<rpc::proto::database::BatchOperation>,
+    ) -> Result<Vec<BatchOperation>, Status> {
+        use rpc::proto::database::batch_operation::Operation;
+
+        operations.into_iter().map(|op| match op.operation {
+            Some(Operation::Put(put)) if put.ttl_ms != 0 || put.expires_at_ms != 0 => {
+                Err(Status::invalid_argument("TTLs are not supported on batched puts"))
+            },
+            Some(Operation::Put(PutRequest { transaction_id, .. }))
+            | Some(Operation::Delete(DeleteRequest { transaction_id, .. })) if !transaction_id.is_empty() => {
+                Err(Status::invalid_argument("Batched operations cannot be part of a transaction"))
+            },
+            Some(Operation::Put(put)) => Ok(BatchOperation::Put { key: put.key, value: put.value }),
+            Some(Operation::Delete(delete)) => Ok(BatchOperation::Delete { key: delete.key }),
+            None => Err(Status::invalid_argument("Batch operation must be a put or a delete")),
+        }).collect()
+    }
 }
 
 #[tonic::async_trait]
@@ -51,43 +132,49 @@ impl DatabaseService for DatabaseServiceImpl {
         let req = request.into_inner();
         let mut coordinator = self.coordinator.lock().await;
 
-        match coordinator.execute_query(req.query, req.parameters).await {
-            Ok(result_rows) => {
-                // Convert the result rows to protobuf format
-                let rows = result_rows.iter()
-                    .map(|row| Self::convert_to_proto_row(row))
-                    .collect();
+        let transaction = Self::transaction_id(req.transaction_id);
 
-                Ok(Response::new(QueryResponse {
-                    success: true,
-                    error: "".to_string(),
-                    rows,
-                    affected_rows: result_rows.len() as u64,
-                }))
-            },
-            Err(e) => Ok(Response::new(QueryResponse {
-                success: false,
-                error: e.to_string(),
-                rows: vec![],
-                affected_rows: 0,
-            })),
-        }
+        let result_rows = coordinator.execute_query_in_transaction(req.query, req.parameters, transaction)
+            .await
+            .map_err(|e| Self::status(&coordinator, e))?;
+        // Convert the result rows to protobuf format
+        let rows = result_rows.iter()
+            .map(Self::convert_to_proto_row)
+            .collect();
+
+        Ok(Response::new(QueryResponse {
+            rows,
+            affected_rows: result_rows.len() as u64,
+        }))
     }
 
     async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
         let req = request.into_inner();
-        let mut coordinator = self.coordinator.lock().await;
-        match coordinator.get(req.key).await {
+        let read_timestamp = Some(req.read_timestamp).filter(|ts| *ts > 0);
+        let consistency = Self::read_consistency(req.consistency, req.max_staleness_ms)?;
+        let result = match Self::transaction_id(req.transaction_id) {
+            Some(_) if read_timestamp.is_some() => {
+                return Err(Status::invalid_argument("Reads inside a transaction cannot set read_timestamp"));
+            },
+            Some(_) if consistency != ReadConsistency::Linearizable => {
+                return Err(Status::invalid_argument("Reads inside a transaction must be linearizable"));
+            },
+            // The row lock is waited for without holding up the coordinator
+            Some(txn_id) if req.for_update => transaction_get_for_update_unlocked(&self.coordinator, &txn_id, req.key).await,
+            Some(txn_id) => self.coordinator.lock().await.transaction_get(&txn_id, req.key).await,
+            None if req.for_update => {
+                return Err(Status::invalid_argument("for_update requires a transaction"));
+            },
+            None => self.coordinator.lock().await.get(req.key, read_timestamp, consistency).await,
+        };
+        match result {
             Ok(value) => Ok(Response::new(GetResponse {
                 found: true,
-                value,
-                error: "".to_string(),
-            })),
-            Err(e) => Ok(Response::new(GetResponse {
-                found: false,
-                value: vec![],
-                error: e.to_string(),
+                value: value.value,
+                version: value.version,
             })),
+            Err(DatabaseError::KeyNotFound { .. }) => Ok(Response::new(GetResponse::default())),
+            Err(e) => Err(Self::status(&*self.coordinator.lock().await, e)),
         }
     }
 
@@ -96,18 +183,18 @@ impl DatabaseService for DatabaseServiceImpl {
         request: Request<PutRequest>,
     ) -> Result<Response<PutResponse>, Status> {
         let req = request.into_inner();
+        let expires_at = Self::resolve_expiry(req.ttl_ms, req.expires_at_ms)?;
         let mut coordinator = self.coordinator.lock().await;
         
-        match coordinator.put(req.key, req.value).await {
-            Ok(_) => Ok(Response::new(PutResponse {
-                success: true,
-                error: "".to_string(),
-            })),
-            Err(e) => Ok(Response::new(PutResponse {
-                success: false,
-                error: e.to_string(),
-            })),
-        }
+        let result = match Self::transaction_id(req.transaction_id) {
+            Some(_) if expires_at.is_some() => {
+                return Err(Status::invalid_argument("TTLs are not supported inside transactions"));
+            },
+            Some(txn_id) => coordinator.transaction_put(&txn_id, req.key, req.value),
+            None => coordinator.put(req.key, req.value, expires_at).await,
+        };
+        result.map_err(|e| Self::status(&coordinator, e))?;
+        Ok(Response::new(PutResponse {}))
     }
 
     async fn delete(
@@ -117,16 +204,12 @@ impl DatabaseService for DatabaseServiceImpl {
         let req = request.into_inner();
         let mut coordinator = self.coordinator.lock().await;
         
-        match coordinator.delete(req.key).await {
-            Ok(_) => Ok(Response::new(DeleteResponse {
-                success: true,
-                error: "".to_string(),
-            })),
-            Err(e) => Ok(Response::new(DeleteResponse {
-                success: false,
-                error: e.to_string(),
-            })),
-        }
+        let result = match Self::transaction_id(req.transaction_id) {
+            Some(txn_id) => coordinator.transaction_delete(&txn_id, req.key),
+            None => coordinator.delete(req.key).await,
+        };
+        result.map_err(|e| Self::status(&coordinator, e))?;
+        Ok(Response::new(DeleteResponse {}))
     }
 
     async fn scan(
@@ -136,75 +219,375 @@ impl DatabaseService for DatabaseServiceImpl {
         let req = request.into_inner();
         let mut coordinator = self.coordinator.lock().await;
         
-        match coordinator.scan(req.start_key, req.end_key, req.limit).await {
-            Ok(items) => {
-                let proto_items = items.into_iter()
-                    .map(|(key, value)| rpc::proto::database::KeyValue { key, value })
-                    .collect();
-                
-                Ok(Response::new(ScanResponse {
-                    items: proto_items,
-                    error: "".to_string(),
-                }))
+        let continuation_token = Some(req.continuation_token).filter(|token| !token.is_empty());
+        let read_timestamp = Some(req.read_timestamp).filter(|ts| *ts > 0);
+        let consistency = Self::read_consistency(req.consistency, req.max_staleness_ms)?;
+        
+        let page = coordinator.scan(req.start_key, req.end_key, req.limit, continuation_token, read_timestamp, consistency)
+            .await
+            .map_err(|e| Self::status(&coordinator, e))?;
+        let proto_items = page.items.into_iter()
+            .map(|(key, value)| rpc::proto::database::KeyValue { key, value })
+            .collect();
+        
+        Ok(Response::new(ScanResponse {
+            items: proto_items,
+            continuation_token: page.continuation_token.unwrap_or_default(),
+            has_more: page.has_more,
+        }))
+    }
+
+    async fn batch(
+        &self,
+        request: Request<BatchRequest>,
+    ) -> Result<Response<BatchResponse>, Status> {
+        let req = request.into_inner();
+        let operations = Self::convert_from_proto_batch(req.operations)?;
+        let mut coordinator = self.coordinator.lock().await;
+        
+        coordinator.batch(operations).await.map_err(|e| Self::status(&coordinator, e))?;
+        Ok(Response::new(BatchResponse {}))
+    }
+
+    async fn conditional_write(
+        &self,
+        request: Request<ConditionalWriteRequest>,
+    ) -> Result<Response<ConditionalWriteResponse>, Status> {
+        use rpc::proto::database::conditional_write_request::Condition;
+
+        let req = request.into_inner();
+        let mut coordinator = self.coordinator.lock().await;
+        
+        let result = match req.condition {
+            Some(Condition::PutIfAbsent(value)) => coordinator.put_if_absent(req.key, value).await,
+            Some(Condition::PutIfVersion(cas)) => {
+                coordinator.compare_and_swap(req.key, cas.value, cas.expected_version).await
             },
-            Err(e) => Ok(Response::new(ScanResponse {
-                items: vec![],
-                error: e.to_string(),
-            })),
-        }
+            Some(Condition::DeleteIfValue(expected)) => coordinator.delete_if_value(req.key, expected).await,
+            None => return Err(Status::invalid_argument("Conditional write requires a condition")),
+        };
+
+        let response = result.map_err(|e| Self::status(&coordinator, e))?;
+        let found = response.current.is_some();
+        let current = response.current.unwrap_or_default();
+        Ok(Response::new(ConditionalWriteResponse {
+            applied: response.succeeded,
+            found,
+            value: current.value,
+            version: current.version,
+        }))
+    }
+
+    async fn increment(
+        &self,
+        request: Request<IncrementRequest>,
+    ) -> Result<Response<IncrementResponse>, Status> {
+        let req = request.into_inner();
+        let mut coordinator = self.coordinator.lock().await;
+        
+        let value = coordinator.increment(req.key, req.delta).await.map_err(|e| Self::status(&coordinator, e))?;
+        Ok(Response::new(IncrementResponse { value }))
+    }
+
+    async fn begin_transaction(
+        &self,
+        request: Request<BeginTransactionRequest>,
+    ) -> Result<Response<BeginTransactionResponse>, Status> {
+        let req = request.into_inner();
+        let isolation = match req.isolation_level() {
+            rpc::proto::database::IsolationLevel::Snapshot => IsolationLevel::Snapshot,
+            rpc::proto::database::IsolationLevel::Serializable => IsolationLevel::Serializable,
+        };
+        let mut coordinator = self.coordinator.lock().await;
+        let txn_id = coordinator.begin_transaction(isolation);
+        
+        Ok(Response::new(BeginTransactionResponse {
+            transaction_id: txn_id.0,
+        }))
+    }
+
+    async fn commit_transaction(
+        &self,
+        request: Request<CommitTransactionRequest>,
+    ) -> Result<Response<CommitTransactionResponse>, Status> {
+        let req = request.into_inner();
+        
+        // Lock waits and releases happen without holding up the coordinator
+        let commit_ts = match commit_transaction_unlocked(&self.coordinator, &TransactionId(req.transaction_id)).await {
+            Ok(commit_ts) => commit_ts,
+            Err(e) => return Err(Self::status(&*self.coordinator.lock().await, e)),
+        };
+        Ok(Response::new(CommitTransactionResponse {
+            commit_timestamp: commit_ts.wall_ms,
+        }))
+    }
+
+    async fn rollback_transaction(
+        &self,
+        request: Request<RollbackTransactionRequest>,
+    ) -> Result<Response<RollbackTransactionResponse>, Status> {
+        let req = request.into_inner();
+        let mut coordinator = self.coordinator.lock().await;
+        
+        coordinator.rollback_transaction(&TransactionId(req.transaction_id))
+            .await
+            .map_err(|e| Self::status(&coordinator, e))?;
+        Ok(Response::new(RollbackTransactionResponse {}))
+    }
+
+    async fn get_partition_map(
+        &self,
+        request: Request<GetPartitionMapRequest>,
+    ) -> Result<Response<GetPartitionMapResponse>, Status> {
+        let req = request.into_inner();
+        let coordinator = self.coordinator.lock().await;
+
+        let key = (!req.key.is_empty()).then_some(req.key.as_str());
+        let (metadata_version, routes) = coordinator.partition_map(key).map_err(|e| Self::status(&coordinator, e))?;
+        Ok(Response::new(GetPartitionMapResponse {
+            metadata_version,
+            routes: routes.into_iter().map(Into::into).collect(),
+        }))
     }
 }
 
 //Node service implementation
 pub struct NodeServiceImpl {
-    coordinator: Arc<Mutex<Coordinator>>,
+    clock: Arc<HybridClock>,
 }
+
 impl NodeServiceImpl {
-    pub fn new(coordinator: Arc<Mutex<Coordinator>>) -> Self {
-        Self { coordinator }
+    pub fn new(clock: Arc<HybridClock>) -> Self {
+        Self { clock }
+    }
+
+    // Helper to advance the local clock past the caller's clock reading.
+    // Callers whose clock runs too far ahead are rejected.
+    #[allow(clippy::result_large_err)]
+    fn observe_clock(&self, hlc: Option<rpc::proto::node::HlcTimestamp>) -> Result<(), Status> {
+        if let Some(hlc) = hlc {
+            self.clock.update(hlc.into()).map_err(to_status)?;
+        }
+        Ok(())
+    }
+
+    // The coordinator hosts no partitions; nodes serve these calls.
+    fn unimplemented(method: &str) -> Status {
+        Status::unimplemented(format!("{} is served by the data nodes, not the coordinator", method))
     }
 }
+
 #[tonic::async_trait]
 impl NodeService for NodeServiceImpl {
     async fn write(
         &self,
         request: Request<WriteRequest>,
     ) -> Result<Response<WriteResponse>, Status> {
-        let req=request.into_inner();
-        todo!("Implement write")
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("write"))
     }
 
     async fn read(&self, request: Request<ReadRequest>) -> Result<Response<ReadResponse>, Status> {
-        let req=request.into_inner();
-        todo!("Implement read")
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("read"))
     }
 
     async fn scan(
         &self,
         request: Request<rpc::proto::node::ScanRequest>,
     ) -> Result<Response<rpc::proto::node::ScanResponse>, Status> {
-        let req=request.into_inner();
-        todo!("Implement scan")
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("scan"))
+    }
+
+    async fn batch(
+        &self,
+        request: Request<rpc::proto::node::BatchRequest>,
+    ) -> Result<Response<rpc::proto::node::BatchResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("batch"))
+    }
+
+    async fn conditional_write(
+        &self,
+        request: Request<rpc::proto::node::ConditionalWriteRequest>,
+    ) -> Result<Response<rpc::proto::node::ConditionalWriteResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("conditional_write"))
+    }
+
+    async fn increment(
+        &self,
+        request: Request<rpc::proto::node::IncrementRequest>,
+    ) -> Result<Response<rpc::proto::node::IncrementResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("increment"))
+    }
+
+    async fn prewrite(
+        &self,
+        request: Request<rpc::proto::node::PrewriteRequest>,
+    ) -> Result<Response<rpc::proto::node::PrewriteResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("prewrite"))
+    }
+
+    async fn end_transaction(
+        &self,
+        request: Request<rpc::proto::node::EndTransactionRequest>,
+    ) -> Result<Response<rpc::proto::node::EndTransactionResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("end_transaction"))
+    }
+
+    async fn resolve_intents(
+        &self,
+        request: Request<rpc::proto::node::ResolveIntentsRequest>,
+    ) -> Result<Response<rpc::proto::node::ResolveIntentsResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("resolve_intents"))
+    }
+
+    async fn recover_transaction(
+        &self,
+        request: Request<rpc::proto::node::RecoverTransactionRequest>,
+    ) -> Result<Response<rpc::proto::node::RecoverTransactionResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("recover_transaction"))
+    }
+
+    async fn validate_reads(
+        &self,
+        request: Request<rpc::proto::node::ValidateReadsRequest>,
+    ) -> Result<Response<rpc::proto::node::ValidateReadsResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("validate_reads"))
+    }
+
+    async fn acquire_locks(
+        &self,
+        request: Request<rpc::proto::node::AcquireLocksRequest>,
+    ) -> Result<Response<rpc::proto::node::AcquireLocksResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("acquire_locks"))
+    }
+
+    async fn propose(
+        &self,
+        request: Request<rpc::proto::node::ProposeRequest>,
+    ) -> Result<Response<rpc::proto::node::ProposeResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("propose"))
+    }
+
+    async fn release_locks(
+        &self,
+        request: Request<rpc::proto::node::ReleaseLocksRequest>,
+    ) -> Result<Response<rpc::proto::node::ReleaseLocksResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("release_locks"))
+    }
+
+    async fn get_wait_for_graph(
+        &self,
+        request: Request<rpc::proto::node::WaitForGraphRequest>,
+    ) -> Result<Response<rpc::proto::node::WaitForGraphResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("get_wait_for_graph"))
+    }
+
+    async fn abort_lock_waits(
+        &self,
+        request: Request<rpc::proto::node::AbortLockWaitsRequest>,
+    ) -> Result<Response<rpc::proto::node::AbortLockWaitsResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("abort_lock_waits"))
+    }
+
+    async fn add_learner(
+        &self,
+        request: Request<rpc::proto::node::AddLearnerRequest>,
+    ) -> Result<Response<rpc::proto::node::AddLearnerResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("add_learner"))
+    }
+
+    async fn change_membership(
+        &self,
+        request: Request<rpc::proto::node::ChangeMembershipRequest>,
+    ) -> Result<Response<rpc::proto::node::ChangeMembershipResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("change_membership"))
+    }
+
+    async fn transfer_leader(
+        &self,
+        request: Request<rpc::proto::node::TransferLeaderRequest>,
+    ) -> Result<Response<rpc::proto::node::TransferLeaderResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("transfer_leader"))
+    }
+
+    async fn get_partition_stats(
+        &self,
+        request: Request<rpc::proto::node::PartitionStatsRequest>,
+    ) -> Result<Response<rpc::proto::node::PartitionStatsResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("get_partition_stats"))
+    }
+
+    async fn get_node_load(
+        &self,
+        request: Request<rpc::proto::node::NodeLoadRequest>,
+    ) -> Result<Response<rpc::proto::node::NodeLoadResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("get_node_load"))
     }
 
     async fn get_status(
         &self,
         request: Request<StatusRequest>,
     ) -> Result<Response<StatusResponse>, Status> {
-        let req=request.into_inner();
-        todo!("Implement get_status")
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        Err(Self::unimplemented("get_status"))
     }
 }
 
 // Raft service implementation
-pub struct RaftServiceImpl {
-    coordinator: Arc<Mutex<Coordinator>>,
-}
+#[derive(Default)]
+pub struct RaftServiceImpl;
 
 impl RaftServiceImpl {
     //constructor like used while initializing it
-    pub fn new(coordinator: Arc<Mutex<Coordinator>>) -> Self {
-        Self { coordinator }
+    pub fn new() -> Self {
+        Self
+    }
+
+    // The coordinator is not a member of any Raft group.
+    fn unimplemented(method: &str) -> Status {
+        Status::unimplemented(format!("{} is served by the data nodes, not the coordinator", method))
     }
 }
 
@@ -212,26 +595,304 @@ impl RaftServiceImpl {
 impl RaftService for RaftServiceImpl {
     async fn append_entries(
         &self,
-        request: Request<AppendEntriesRequest>,
+        _request: Request<AppendEntriesRequest>,
     ) -> Result<Response<AppendEntriesResponse>, Status> {
-        let req=request.into_inner();
-        todo!("Implement append_entries")
+        Err(Self::unimplemented("append_entries"))
     }
 
     async fn request_vote(
         &self,
-        request: Request<RequestVoteRequest>,
+        _request: Request<RequestVoteRequest>,
     ) -> Result<Response<RequestVoteResponse>, Status> {
-        let req=request.into_inner();
-        todo!("Implement request_vote")
+        Err(Self::unimplemented("request_vote"))
     }
 
     async fn install_snapshot(
         &self,
-        request: Request<InstallSnapshotRequest>,
+        _request: Request<InstallSnapshotRequest>,
     ) -> Result<Response<InstallSnapshotResponse>, Status> {
-        let req=request.into_inner();
-        todo!("Implement install_snapshot")
+        Err(Self::unimplemented("install_snapshot"))
+    }
+
+    async fn timeout_now(
+        &self,
+        _request: Request<TimeoutNowRequest>,
+    ) -> Result<Response<TimeoutNowResponse>, Status> {
+        Err(Self::unimplemented("timeout_now"))
+    }
+
+    async fn heartbeat(
+        &self,
+        _request: Request<HeartbeatBatchRequest>,
+    ) -> Result<Response<HeartbeatBatchResponse>, Status> {
+        Err(Self::unimplemented("heartbeat"))
+    }
+}
+
+// Admin service implementation
+pub struct AdminServiceImpl {
+    coordinator: Arc<Mutex<Coordinator>>,
+}
+
+impl AdminServiceImpl {
+    pub fn new(coordinator: Arc<Mutex<Coordinator>>) -> Self {
+        Self { coordinator }
+    }
+
+    // Helper to turn the outcome of a membership operation into a response
+    #[allow(clippy::result_large_err)]
+    fn membership_response(result: common::error::Result<PartitionInfo>) -> Result<Response<MembershipResponse>, Status> {
+        let partition = result.map_err(to_status)?;
+        Ok(Response::new(MembershipResponse { membership: Some((&partition).into()) }))
+    }
+
+    // Helper to turn the outcome of a batch of leader transfers into a response
+    #[allow(clippy::result_large_err)]
+    fn transfers_response(result: common::error::Result<Vec<PartitionInfo>>) -> Result<Response<LeaderTransfersResponse>, Status> {
+        let moved = result.map_err(to_status)?;
+        Ok(Response::new(LeaderTransfersResponse { moved: moved.iter().map(Into::into).collect() }))
+    }
+
+    // Helper to save the metadata a request changed before answering it, so
+    // an answered change is never lost with this coordinator
+    async fn saved<T>(coordinator: &mut Coordinator, result: common::error::Result<T>) -> common::error::Result<T> {
+        let value = result?;
+        coordinator.sync_metadata().await?;
+        Ok(value)
+    }
+
+    // Helper to turn the progress of a decommissioning into a response
+    #[allow(clippy::result_large_err)]
+    fn decommission_response<P: Into<rpc::proto::admin::DecommissionProgress>>(
+        result: common::error::Result<P>,
+    ) -> Result<Response<DecommissionResponse>, Status> {
+        let progress = result.map_err(to_status)?;
+        Ok(Response::new(DecommissionResponse { progress: Some(progress.into()) }))
+    }
+}
+
+#[tonic::async_trait]
+impl AdminService for AdminServiceImpl {
+    async fn add_replica(
+        &self,
+        request: Request<AddReplicaRequest>,
+    ) -> Result<Response<MembershipResponse>, Status> {
+        let req = request.into_inner();
+        let node = NodeId(req.node_id);
+        let mut coordinator = self.coordinator.lock().await;
+
+        let result = if req.as_learner {
+            coordinator.add_learner(req.partition_id, node, req.address).await
+        } else {
+            coordinator.add_voter(req.partition_id, node, req.address).await
+        };
+        Self::membership_response(Self::saved(&mut coordinator, result).await)
+    }
+
+    async fn promote_learner(
+        &self,
+        request: Request<PromoteLearnerRequest>,
+    ) -> Result<Response<MembershipResponse>, Status> {
+        let req = request.into_inner();
+        let mut coordinator = self.coordinator.lock().await;
+
+        let result = coordinator.promote_learner(req.partition_id, NodeId(req.node_id)).await;
+        Self::membership_response(Self::saved(&mut coordinator, result).await)
+    }
+
+    async fn remove_replica(
+        &self,
+        request: Request<RemoveReplicaRequest>,
+    ) -> Result<Response<MembershipResponse>, Status> {
+        let req = request.into_inner();
+        let mut coordinator = self.coordinator.lock().await;
+
+        let result = coordinator.remove_replica(req.partition_id, NodeId(req.node_id)).await;
+        Self::membership_response(Self::saved(&mut coordinator, result).await)
+    }
+
+    async fn get_membership(
+        &self,
+        request: Request<GetMembershipRequest>,
+    ) -> Result<Response<MembershipResponse>, Status> {
+        let req = request.into_inner();
+        let coordinator = self.coordinator.lock().await;
+
+        Self::membership_response(coordinator.partition(req.partition_id))
+    }
+
+    async fn transfer_leader(
+        &self,
+        request: Request<TransferLeaderRequest>,
+    ) -> Result<Response<MembershipResponse>, Status> {
+        let req = request.into_inner();
+        let target = Some(req.target).filter(|target| !target.is_empty()).map(NodeId);
+        let mut coordinator = self.coordinator.lock().await;
+
+        let result = coordinator.transfer_leader(req.partition_id, target).await;
+        Self::membership_response(Self::saved(&mut coordinator, result).await)
+    }
+
+    async fn drain_node(
+        &self,
+        request: Request<DrainNodeRequest>,
+    ) -> Result<Response<LeaderTransfersResponse>, Status> {
+        let req = request.into_inner();
+        let mut coordinator = self.coordinator.lock().await;
+
+        let result = coordinator.drain_node(NodeId(req.node_id)).await;
+        Self::transfers_response(Self::saved(&mut coordinator, result).await)
+    }
+
+    async fn undrain_node(
+        &self,
+        request: Request<UndrainNodeRequest>,
+    ) -> Result<Response<UndrainNodeResponse>, Status> {
+        let req = request.into_inner();
+        let mut coordinator = self.coordinator.lock().await;
+
+        let node = NodeId(req.node_id);
+        if !coordinator.undrain_node(&node) {
+            return Err(Status::failed_precondition(format!("Node {} is not drained", node)));
+        }
+        coordinator.sync_metadata().await.map_err(to_status)?;
+        Ok(Response::new(UndrainNodeResponse {}))
+    }
+
+    async fn balance_leaders(
+        &self,
+        _request: Request<BalanceLeadersRequest>,
+    ) -> Result<Response<LeaderTransfersResponse>, Status> {
+        let mut coordinator = self.coordinator.lock().await;
+
+        let result = coordinator.balance_leaders().await;
+        Self::transfers_response(Self::saved(&mut coordinator, result).await)
+    }
+
+    async fn decommission_node(
+        &self,
+        request: Request<DecommissionNodeRequest>,
+    ) -> Result<Response<DecommissionResponse>, Status> {
+        let req = request.into_inner();
+        let mut coordinator = self.coordinator.lock().await;
+
+        let result = coordinator.decommission_node(&NodeId(req.node_id));
+        Self::decommission_response(Self::saved(&mut coordinator, result).await)
+    }
+
+    async fn get_decommission_status(
+        &self,
+        request: Request<DecommissionStatusRequest>,
+    ) -> Result<Response<DecommissionResponse>, Status> {
+        let req = request.into_inner();
+        let coordinator = self.coordinator.lock().await;
+
+        Self::decommission_response(coordinator.decommission_progress(&NodeId(req.node_id)))
+    }
+
+    async fn abort_decommission(
+        &self,
+        request: Request<AbortDecommissionRequest>,
+    ) -> Result<Response<DecommissionResponse>, Status> {
+        let req = request.into_inner();
+        let mut coordinator = self.coordinator.lock().await;
+
+        let result = coordinator.abort_decommission(&NodeId(req.node_id));
+        Self::decommission_response(Self::saved(&mut coordinator, result).await)
+    }
+
+    async fn set_placement_constraints(
+        &self,
+        request: Request<SetPlacementConstraintsRequest>,
+    ) -> Result<Response<PlacementConstraintsResponse>, Status> {
+        let req = request.into_inner();
+        let constraints = req.constraints
+            .ok_or_else(|| Status::invalid_argument("No placement constraints given"))?
+            .try_into()
+            .map_err(to_status)?;
+        let mut coordinator = self.coordinator.lock().await;
+
+        let result = coordinator.set_placement_constraints(constraints);
+        Self::saved(&mut coordinator, result).await.map_err(to_status)?;
+        Ok(Response::new(PlacementConstraintsResponse {
+            constraints: coordinator.placement_constraints().into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn list_placement_constraints(
+        &self,
+        _request: Request<ListPlacementConstraintsRequest>,
+    ) -> Result<Response<PlacementConstraintsResponse>, Status> {
+        let coordinator = self.coordinator.lock().await;
+
+        Ok(Response::new(PlacementConstraintsResponse {
+            constraints: coordinator.placement_constraints().into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn list_nodes(
+        &self,
+        _request: Request<ListNodesRequest>,
+    ) -> Result<Response<ListNodesResponse>, Status> {
+        let coordinator = self.coordinator.lock().await;
+
+        Ok(Response::new(ListNodesResponse {
+            nodes: coordinator.nodes().into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn describe_node(
+        &self,
+        request: Request<DescribeNodeRequest>,
+    ) -> Result<Response<DescribeNodeResponse>, Status> {
+        let req = request.into_inner();
+        let node = NodeId(req.node_id);
+        let coordinator = self.coordinator.lock().await;
+
+        let info = coordinator.node(&node).map_err(to_status)?;
+        Ok(Response::new(DescribeNodeResponse {
+            node: Some(info.into()),
+            partitions: coordinator.node_partitions(&node).iter().map(Into::into).collect(),
+            draining: coordinator.is_draining(&node),
+        }))
+    }
+}
+
+// cluster service implementation, for the data nodes
+pub struct ClusterServiceImpl {
+    coordinator: Arc<Mutex<Coordinator>>,
+}
+
+impl ClusterServiceImpl {
+    pub fn new(coordinator: Arc<Mutex<Coordinator>>) -> Self {
+        Self { coordinator }
+    }
+}
+
+#[tonic::async_trait]
+impl ClusterService for ClusterServiceImpl {
+    async fn heartbeat(
+        &self,
+        request: Request<NodeHeartbeatRequest>,
+    ) -> Result<Response<NodeHeartbeatResponse>, Status> {
+        let req = request.into_inner();
+        if req.node_id.is_empty() {
+            return Err(Status::invalid_argument("Heartbeat without a node id"));
+        }
+        let locality = Locality::parse(&req.locality).map_err(to_status)?;
+        let load = NodeLoad {
+            disk_used_bytes: req.disk_used_bytes,
+            disk_capacity_bytes: req.disk_capacity_bytes,
+            qps: req.qps,
+        };
+        let mut coordinator = self.coordinator.lock().await;
+
+        let status = coordinator.record_heartbeat(NodeId(req.node_id), req.address, locality, load, chrono::Utc::now());
+        coordinator.sync_metadata().await.map_err(to_status)?;
+        Ok(Response::new(NodeHeartbeatResponse {
+            status: format!("{:?}", status),
+            metadata_version: coordinator.metadata_version(),
+        }))
     }
 }
 
@@ -240,14 +901,19 @@ pub async fn start_grpc_server(
     coordinator: Arc<Mutex<Coordinator>>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let db_service = DatabaseServiceImpl::new(coordinator.clone());
-    let node_service = NodeServiceImpl::new(coordinator.clone());
-    let raft_service = RaftServiceImpl::new(coordinator.clone());
+    let clock = coordinator.lock().await.clock();
+    let node_service = NodeServiceImpl::new(clock);
+    let raft_service = RaftServiceImpl::new();
+    let admin_service = AdminServiceImpl::new(coordinator.clone());
+    let cluster_service = ClusterServiceImpl::new(coordinator.clone());
 
-    println!("Starting gRPC server on {}...", addr);
+    info!("Starting gRPC server on {}...", addr);
     Server::builder()
         .add_service(DatabaseServiceServer::new(db_service))
         .add_service(NodeServiceServer::new(node_service))
         .add_service(RaftServiceServer::new(raft_service))
+        .add_service(AdminServiceServer::new(admin_service))
+        .add_service(ClusterServiceServer::new(cluster_service))
         .serve(addr.parse()?)
         .await?;
 
@@ -0,0 +1,211 @@
+//! Automatic range splits.
+//!
+//! Leaders report the size and request rate of their partitions. A partition
+//! over the size or load threshold is split at the key that halves its data.
+//!
+//! The split is a single entry in the partition's Raft log. Commands before
+//! it apply to the whole range and commands after it only to the left half,
+//! while a new Raft group with the same replicas and the same leader takes
+//! over the right half from the data those replicas already hold. No data
+//! moves and no election is needed, so writes pause only for the one log
+//! entry. Commands routed with the old partition map are answered with the
+//! new range and routed again.
+//!
+//! Partitions are also split where the key range of some placement
+//! constraints begins or ends, so that the constraints cover whole
+//! partitions; see the `locality` module.
+//!
+//! The same periodic check merges small, idle neighbours back together; see
+//! the `merge` module.
+
+use crate::change::{run_change_unlocked, PartitionChange, Step, StepRequest};
+use crate::locality::constraint_split_key;
+use crate::Coordinator;
+use common::config::CoordinatorConfig;
+use common::error::{DatabaseError, Result};
+use common::hlc::HybridClock;
+use common::types::{Command, KeyRange, PartitionInfo, PartitionStats};
+use log::{error, warn};
+use rpc::client::NodeClient;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Partitions split and merged by one range check.
+#[derive(Debug, Default)]
+pub struct RangeChanges {
+    /// Left and right half of each split partition
+    pub splits: Vec<(PartitionInfo, PartitionInfo)>,
+    pub merges: Vec<PartitionInfo>,
+}
+
+/// Periodically collects partition stats from the nodes listed in the
+/// coordinator configuration, splits the partitions that grew too large or
+/// too busy and merges neighbours that are small and idle.
+pub struct RangeScheduler {
+    nodes: Vec<String>,
+    clock: Arc<HybridClock>,
+    interval: Duration,
+    coordinator: Arc<Mutex<Coordinator>>,
+}
+
+impl RangeScheduler {
+    pub fn new(config: &CoordinatorConfig, coordinator: Arc<Mutex<Coordinator>>, clock: Arc<HybridClock>) -> Self {
+        Self {
+            nodes: config.initial_nodes.clone(),
+            clock,
+            interval: Duration::from_millis(config.range_check_interval_ms),
+            coordinator,
+        }
+    }
+
+    /// Run range checks forever.
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.check_once().await {
+                error!("Range check failed: {}", e);
+            }
+        }
+    }
+
+    /// Run one range check. Splits come first; partitions split in this
+    /// check are not merged, since their stats are stale. Only the
+    /// coordinator holding the lease checks. The changes are planned under
+    /// the coordinator lock and carried out without it.
+    pub async fn check_once(&self) -> Result<RangeChanges> {
+        if !self.coordinator.lock().await.holds_lease() {
+            return Ok(RangeChanges::default());
+        }
+        let mut stats = Vec::new();
+        for addr in &self.nodes {
+            let fetched = match NodeClient::connect(addr, self.clock.clone()).await {
+                Ok(mut client) => client.partition_stats().await,
+                Err(e) => Err(e),
+            };
+            match fetched {
+                Ok(node_stats) => stats.extend(node_stats),
+                Err(e) => warn!("Skipping node {} in range check: {}", addr, e),
+            }
+        }
+
+        let planned = self.coordinator.lock().await.plan_splits(&stats);
+        let mut splits = Vec::new();
+        for change in planned {
+            match run_change_unlocked(&self.coordinator, &change).await {
+                Ok(halves) => splits.push(halves_of(halves)),
+                Err(e) => warn!("Not making {:?}: {}", change, e),
+            }
+        }
+
+        let split: HashSet<u64> = splits.iter().map(|(left, _)| left.id).collect();
+        stats.retain(|stats| !split.contains(&stats.partition_id));
+        let planned = self.coordinator.lock().await.plan_merges(&stats);
+        let mut merges = Vec::new();
+        for change in planned {
+            match run_change_unlocked(&self.coordinator, &change).await {
+                Ok(mut merged) => merges.push(merged.remove(0)),
+                Err(e) => warn!("Not making {:?}: {}", change, e),
+            }
+        }
+        Ok(RangeChanges { splits, merges })
+    }
+}
+
+impl Coordinator {
+    /// Whether a partition has grown past the size or load threshold
+    pub fn needs_split(&self, stats: &PartitionStats) -> bool {
+        stats.size_bytes > self.split_size_bytes || stats.qps > self.split_qps
+    }
+
+    /// Splits of the partitions that straddle the boundary of a constrained
+    /// key range, then of those whose stats cross a threshold, at most one
+    /// per partition. Stats that no longer match the partition map, e.g. of
+    /// a partition split since, are skipped.
+    pub fn plan_splits(&self, stats: &[PartitionStats]) -> Vec<PartitionChange> {
+        let mut partitions: Vec<&PartitionInfo> = self.metadata.partitions.values().collect();
+        partitions.sort_by_key(|partition| partition.id);
+        let mut changes = Vec::new();
+        let mut planned = HashSet::new();
+        for partition in partitions {
+            if let Some(split_key) = constraint_split_key(&self.metadata, partition) {
+                planned.insert(partition.id);
+                changes.push(PartitionChange::Split { partition_id: partition.id, split_key });
+            }
+        }
+
+        changes.extend(stats.iter()
+            .filter(|stats| !planned.contains(&stats.partition_id) && self.needs_split(stats))
+            .filter_map(|stats| {
+                let split_key = stats.split_key.clone()?;
+                let change = PartitionChange::Split { partition_id: stats.partition_id, split_key };
+                match self.check_change(&change) {
+                    Ok(()) => Some(change),
+                    Err(e) => {
+                        warn!("Not splitting partition {}: {}", stats.partition_id, e);
+                        None
+                    },
+                }
+            }));
+        changes
+    }
+
+    /// Split every partition that straddles a constrained range or whose
+    /// stats cross a threshold; see `plan_splits`
+    pub async fn split_partitions(&mut self, stats: &[PartitionStats]) -> Result<Vec<(PartitionInfo, PartitionInfo)>> {
+        let mut splits = Vec::new();
+        for change in self.plan_splits(stats) {
+            match self.run_change(&change).await {
+                Ok(halves) => splits.push(halves_of(halves)),
+                Err(e) => warn!("Not making {:?}: {}", change, e),
+            }
+        }
+        Ok(splits)
+    }
+
+    /// Split a partition at `split_key`, which becomes the first key of the
+    /// new right half. Returns the left and right half.
+    pub async fn split_partition(&mut self, partition_id: u64, split_key: String) -> Result<(PartitionInfo, PartitionInfo)> {
+        self.sync_metadata().await?;
+        let step = self.split_step(partition_id, split_key)?;
+        Ok(halves_of(self.run_step(step).await?))
+    }
+
+    pub(crate) fn check_split(&self, partition: &PartitionInfo, split_key: &str) -> Result<()> {
+        if split_key == partition.range.start || !partition.range.contains(split_key) {
+            return Err(DatabaseError::InvalidArgument(format!(
+                "Split key {} is not inside partition {}", split_key, partition.id
+            )));
+        }
+        Ok(())
+    }
+
+    /// The split is one command in the partition's log; the right half gets
+    /// a new id
+    pub(crate) fn split_step(&mut self, partition_id: u64, split_key: String) -> Result<Step> {
+        let partition = self.partition(partition_id)?;
+        self.check_split(&partition, &split_key)?;
+
+        let right_id = self.metadata.allocate_partition_id();
+        let left = PartitionInfo {
+            range: KeyRange::new(partition.range.start.clone(), split_key.clone()),
+            ..partition.clone()
+        };
+        let right = PartitionInfo {
+            id: right_id,
+            range: KeyRange::new(split_key, partition.range.end.clone()),
+            ..partition.clone()
+        };
+        let command = Command::SplitPartition { left: left.clone(), right: right.clone() };
+        self.step(&partition, StepRequest::Propose(vec![(partition.id, command)]), vec![left, right])
+    }
+}
+
+// The left and right half recorded by a split
+fn halves_of(mut halves: Vec<PartitionInfo>) -> (PartitionInfo, PartitionInfo) {
+    let right = halves.pop().expect("a split records both halves");
+    let left = halves.pop().expect("a split records both halves");
+    (left, right)
+}
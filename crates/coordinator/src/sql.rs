@@ -0,0 +1,476 @@
+//! SQL tables on top of the key-value store.
+//!
+//! Table schemas live in the cluster metadata. A row is stored under
+//! `{table}/{primary key}` as a JSON object from column names to values,
+//! all as strings; NULL columns are left out. SELECT, UPDATE and DELETE scan
+//! the rows of the table and filter them with the WHERE clause, comparing
+//! numeric columns as numbers and the others as strings.
+//!
+//! Reads and writes go through a transaction: the one the statement runs in,
+//! or else an implicit one that commits when the statement succeeds. Writes
+//! therefore lay down intents like any other transactional write, and a
+//! statement changing several rows applies all or none of them.
+
+use crate::Coordinator;
+use common::error::{DatabaseError, Result};
+use common::hlc::HlcTimestamp;
+use common::types::{ColumnSchema, ColumnType, IsolationLevel, KeyRange, ReadConsistency, TableSchema, TransactionId};
+use log::warn;
+use sql_parser::{ColumnDef, ComparisonOp, Condition, DataType, SqlValue, WhereClause};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+/// A row: column name to value; NULL columns are absent
+type Row = HashMap<String, String>;
+
+impl Coordinator {
+    /// Handle SELECT queries
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn handle_select(
+        &mut self,
+        columns: Vec<String>,
+        table: String,
+        where_clause: Option<WhereClause>,
+        limit: Option<usize>,
+        as_of: Option<SqlValue>,
+        for_update: bool,
+        transaction: Option<&TransactionId>,
+        parameters: &HashMap<String, String>,
+    ) -> Result<Vec<HashMap<String, String>>> {
+        // Every partition is read at the same timestamp so the result is a
+        // consistent snapshot even when the table spans several partitions
+        let read_timestamp = match (as_of, transaction) {
+            (Some(_), Some(_)) => {
+                return Err(DatabaseError::Transaction(
+                    "AS OF SYSTEM TIME cannot be used inside a transaction".to_string(),
+                ));
+            },
+            (Some(value), None) => HlcTimestamp::latest_at(crate::resolve_as_of(&value, parameters)?),
+            (None, Some(txn_id)) => self.transaction_start(txn_id)?,
+            (None, None) => self.clock.now(),
+        };
+
+        // Nothing was ever written to a table that does not exist
+        let Some(schema) = self.metadata.tables.get(&table).cloned() else {
+            return Ok(Vec::new());
+        };
+        let columns = select_columns(&table, &schema, columns)?;
+        let rows = match transaction {
+            Some(txn_id) => self.transaction_rows(txn_id, &table).await?,
+            None => self.rows_at(&table, read_timestamp).await?,
+        };
+
+        let mut selected = Vec::new();
+        for (key, row) in rows {
+            if selected.len() == limit.unwrap_or(usize::MAX) {
+                break;
+            }
+            if !matches(&table, &schema, &row, where_clause.as_ref(), parameters)? {
+                continue;
+            }
+            let row = match transaction.filter(|_| for_update) {
+                Some(txn_id) => decode_row(&key, &self.transaction_get_for_update(txn_id, key.clone()).await?.value)?,
+                None => row,
+            };
+            selected.push(columns.iter()
+                .filter_map(|column| row.get(column).map(|value| (column.clone(), value.clone())))
+                .collect());
+        }
+        Ok(selected)
+    }
+
+    /// Handle INSERT queries
+    pub(crate) async fn handle_insert(
+        &mut self,
+        table: String,
+        columns: Vec<String>,
+        values: Vec<SqlValue>,
+        transaction: Option<&TransactionId>,
+        parameters: &HashMap<String, String>,
+    ) -> Result<Vec<HashMap<String, String>>> {
+        let schema = self.table(&table)?;
+        if columns.len() != values.len() {
+            return Err(DatabaseError::Schema(format!(
+                "INSERT into {} names {} columns but gives {} values", table, columns.len(), values.len()
+            )));
+        }
+
+        let mut row = Row::new();
+        for (column, value) in columns.into_iter().zip(&values) {
+            if row.contains_key(&column) {
+                return Err(DatabaseError::Schema(format!("Column {} of table {} is given twice", column, table)));
+            }
+            let column = schema_column(&table, &schema, &column)?;
+            if let Some(value) = check_value(&table, column, resolve(value, parameters)?)? {
+                row.insert(column.name.clone(), value);
+            }
+        }
+        for column in &schema.columns {
+            if !row.contains_key(&column.name) {
+                check_value(&table, column, None)?;
+            }
+        }
+        let key = row_key(&table, &schema, &row)?;
+
+        let (txn_id, implicit) = self.statement_transaction(transaction);
+        let outcome = self.insert_row(&txn_id, key, &row).await;
+        self.finish_statement(&txn_id, implicit, outcome).await
+    }
+
+    /// Handle UPDATE queries
+    pub(crate) async fn handle_update(
+        &mut self,
+        table: String,
+        assignments: Vec<(String, SqlValue)>,
+        where_clause: Option<WhereClause>,
+        transaction: Option<&TransactionId>,
+        parameters: &HashMap<String, String>,
+    ) -> Result<Vec<HashMap<String, String>>> {
+        let schema = self.table(&table)?;
+        let mut changes = Vec::new();
+        for (column, value) in &assignments {
+            let column = schema_column(&table, &schema, column)?;
+            if column.primary_key {
+                return Err(DatabaseError::Schema(format!(
+                    "Primary key column {} of table {} cannot be updated", column.name, table
+                )));
+            }
+            changes.push((column.name.clone(), check_value(&table, column, resolve(value, parameters)?)?));
+        }
+
+        let (txn_id, implicit) = self.statement_transaction(transaction);
+        let outcome = self.update_rows(&txn_id, &table, &schema, &changes, where_clause.as_ref(), parameters).await;
+        self.finish_statement(&txn_id, implicit, outcome).await
+    }
+
+    /// Handle DELETE queries
+    pub(crate) async fn handle_delete(
+        &mut self,
+        table: String,
+        where_clause: Option<WhereClause>,
+        transaction: Option<&TransactionId>,
+        parameters: &HashMap<String, String>,
+    ) -> Result<Vec<HashMap<String, String>>> {
+        let schema = self.table(&table)?;
+        let (txn_id, implicit) = self.statement_transaction(transaction);
+        let outcome = self.delete_rows(&txn_id, &table, &schema, where_clause.as_ref(), parameters).await;
+        self.finish_statement(&txn_id, implicit, outcome).await
+    }
+
+    /// Handle CREATE TABLE queries
+    pub(crate) async fn handle_create_table(
+        &mut self,
+        name: String,
+        columns: Vec<ColumnDef>,
+    ) -> Result<Vec<HashMap<String, String>>> {
+        validate_columns(&name, &columns)?;
+        self.sync_metadata().await?;
+        if self.metadata.tables.contains_key(&name) {
+            return Err(DatabaseError::Schema(format!("Table {} already exists", name)));
+        }
+
+        let columns = columns.into_iter()
+            .map(|column| ColumnSchema {
+                name: column.name,
+                data_type: column_type(&column.data_type),
+                // The primary key identifies the row, so it is never NULL
+                nullable: column.nullable && !column.primary_key,
+                primary_key: column.primary_key,
+            })
+            .collect();
+        self.metadata.tables.insert(name, TableSchema { columns });
+        self.metadata.version += 1;
+        self.sync_metadata().await?;
+        Ok(Vec::new())
+    }
+
+    async fn insert_row(&mut self, txn_id: &TransactionId, key: String, row: &Row) -> Result<usize> {
+        let existing = self.transaction_scan(txn_id, &key, &format!("{}\0", key)).await?;
+        if !existing.is_empty() {
+            return Err(DatabaseError::Schema(format!("A row with key {} already exists", key)));
+        }
+        self.transaction_put(txn_id, key, encode_row(row)?)?;
+        Ok(1)
+    }
+
+    async fn update_rows(
+        &mut self,
+        txn_id: &TransactionId,
+        table: &str,
+        schema: &TableSchema,
+        changes: &[(String, Option<String>)],
+        where_clause: Option<&WhereClause>,
+        parameters: &HashMap<String, String>,
+    ) -> Result<usize> {
+        let mut updated = 0;
+        for (key, mut row) in self.transaction_rows(txn_id, table).await? {
+            if !matches(table, schema, &row, where_clause, parameters)? {
+                continue;
+            }
+            for (column, value) in changes {
+                match value {
+                    Some(value) => row.insert(column.clone(), value.clone()),
+                    None => row.remove(column),
+                };
+            }
+            self.transaction_put(txn_id, key, encode_row(&row)?)?;
+            updated += 1;
+        }
+        Ok(updated)
+    }
+
+    async fn delete_rows(
+        &mut self,
+        txn_id: &TransactionId,
+        table: &str,
+        schema: &TableSchema,
+        where_clause: Option<&WhereClause>,
+        parameters: &HashMap<String, String>,
+    ) -> Result<usize> {
+        let mut deleted = 0;
+        for (key, row) in self.transaction_rows(txn_id, table).await? {
+            if matches(table, schema, &row, where_clause, parameters)? {
+                self.transaction_delete(txn_id, key)?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// The rows of `table` as the transaction sees them, in key order
+    async fn transaction_rows(&mut self, txn_id: &TransactionId, table: &str) -> Result<Vec<(String, Row)>> {
+        let range = KeyRange::table(table);
+        self.transaction_scan(txn_id, &range.start, &range.end).await?
+            .into_iter()
+            .map(|(key, value)| decode_row(&key, &value).map(|row| (key, row)))
+            .collect()
+    }
+
+    /// The rows of `table` as of `read_timestamp`, in key order
+    async fn rows_at(&mut self, table: &str, read_timestamp: HlcTimestamp) -> Result<Vec<(String, Row)>> {
+        let range = KeyRange::table(table);
+        self.scan_range(&range.start, &range.end, 0, read_timestamp, ReadConsistency::Linearizable).await?
+            .into_iter()
+            .map(|(key, value)| decode_row(&key, &value).map(|row| (key, row)))
+            .collect()
+    }
+
+    pub(crate) fn table(&self, table: &str) -> Result<TableSchema> {
+        self.metadata.tables.get(table)
+            .cloned()
+            .ok_or_else(|| DatabaseError::Schema(format!("Table {} does not exist", table)))
+    }
+
+    // The transaction a statement runs in, and whether it was started for
+    // the statement alone
+    fn statement_transaction(&mut self, transaction: Option<&TransactionId>) -> (TransactionId, bool) {
+        match transaction {
+            Some(txn_id) => (txn_id.clone(), false),
+            None => (self.begin_transaction(IsolationLevel::default()), true),
+        }
+    }
+
+    // Commit or roll back the implicit transaction of a statement; returns
+    // the number of rows the statement changed
+    async fn finish_statement(
+        &mut self,
+        txn_id: &TransactionId,
+        implicit: bool,
+        outcome: Result<usize>,
+    ) -> Result<Vec<HashMap<String, String>>> {
+        let rows = match outcome {
+            Ok(rows) => rows,
+            Err(e) => {
+                if implicit {
+                    if let Err(rollback) = self.rollback_transaction(txn_id).await {
+                        warn!("Failed to roll back transaction {}: {}", txn_id, rollback);
+                    }
+                }
+                return Err(e);
+            },
+        };
+        if implicit {
+            self.commit_transaction(txn_id).await?;
+        }
+        Ok(vec![HashMap::from([("rows_affected".to_string(), rows.to_string())])])
+    }
+}
+
+/// Reject a table definition without columns, with a column defined twice,
+/// or without exactly one primary key column
+pub(crate) fn validate_columns(table: &str, columns: &[ColumnDef]) -> Result<()> {
+    if columns.is_empty() {
+        return Err(DatabaseError::Schema(format!("Table {} has no columns", table)));
+    }
+    let mut names = HashSet::new();
+    if let Some(column) = columns.iter().find(|column| !names.insert(column.name.as_str())) {
+        return Err(DatabaseError::Schema(format!("Column {} of table {} is defined twice", column.name, table)));
+    }
+    if columns.iter().filter(|column| column.primary_key).count() != 1 {
+        return Err(DatabaseError::Schema(format!("Table {} needs exactly one primary key column", table)));
+    }
+    Ok(())
+}
+
+fn column_type(data_type: &DataType) -> ColumnType {
+    match data_type {
+        DataType::Int => ColumnType::Int,
+        DataType::Float => ColumnType::Float,
+        DataType::String => ColumnType::String,
+        DataType::Bool => ColumnType::Bool,
+        DataType::Blob => ColumnType::Blob,
+        DataType::Timestamp => ColumnType::Timestamp,
+    }
+}
+
+fn row_key(table: &str, schema: &TableSchema, row: &Row) -> Result<String> {
+    let primary_key = schema.primary_key()
+        .and_then(|column| row.get(&column.name))
+        .ok_or_else(|| DatabaseError::Schema(format!("Row of table {} has no primary key", table)))?;
+    Ok(format!("{}/{}", table, primary_key))
+}
+
+fn encode_row(row: &Row) -> Result<Vec<u8>> {
+    serde_json::to_vec(row).map_err(|e| DatabaseError::Serialization(e.to_string()))
+}
+
+fn decode_row(key: &str, value: &[u8]) -> Result<Row> {
+    serde_json::from_slice(value)
+        .map_err(|e| DatabaseError::Serialization(format!("Row {} is not valid: {}", key, e)))
+}
+
+fn select_columns(table: &str, schema: &TableSchema, columns: Vec<String>) -> Result<Vec<String>> {
+    if columns.iter().any(|column| column == "*") {
+        return Ok(schema.columns.iter().map(|column| column.name.clone()).collect());
+    }
+    for column in &columns {
+        schema_column(table, schema, column)?;
+    }
+    Ok(columns)
+}
+
+fn schema_column<'a>(table: &str, schema: &'a TableSchema, column: &str) -> Result<&'a ColumnSchema> {
+    schema.column(column)
+        .ok_or_else(|| DatabaseError::Schema(format!("Table {} has no column {}", table, column)))
+}
+
+/// The value of a literal or parameter as stored; `None` is NULL
+fn resolve(value: &SqlValue, parameters: &HashMap<String, String>) -> Result<Option<String>> {
+    Ok(match value {
+        SqlValue::String(s) => Some(s.clone()),
+        SqlValue::Integer(i) => Some(i.to_string()),
+        SqlValue::Float(f) => Some(f.to_string()),
+        SqlValue::Boolean(b) => Some(b.to_string()),
+        SqlValue::Null => None,
+        SqlValue::Parameter(name) => Some(parameters.get(name).cloned().ok_or_else(|| {
+            DatabaseError::InvalidArgument(format!("Missing parameter: {}", name))
+        })?),
+    })
+}
+
+// Reject NULL in a NOT NULL column and values that do not fit the column's type
+fn check_value(table: &str, column: &ColumnSchema, value: Option<String>) -> Result<Option<String>> {
+    let Some(value) = value else {
+        if !column.nullable {
+            return Err(DatabaseError::Schema(format!("Column {} of table {} cannot be NULL", column.name, table)));
+        }
+        return Ok(None);
+    };
+
+    let valid = match column.data_type {
+        ColumnType::Int | ColumnType::Timestamp => value.parse::<i64>().is_ok(),
+        ColumnType::Float => value.parse::<f64>().is_ok(),
+        ColumnType::Bool => value == "true" || value == "false",
+        ColumnType::String | ColumnType::Blob => true,
+    };
+    if !valid {
+        return Err(DatabaseError::Schema(format!(
+            "Value {} does not fit column {} of table {} ({:?})", value, column.name, table, column.data_type
+        )));
+    }
+    Ok(Some(value))
+}
+
+fn matches(
+    table: &str,
+    schema: &TableSchema,
+    row: &Row,
+    where_clause: Option<&WhereClause>,
+    parameters: &HashMap<String, String>,
+) -> Result<bool> {
+    match where_clause {
+        Some(where_clause) => evaluate(table, schema, row, &where_clause.condition, parameters),
+        None => Ok(true),
+    }
+}
+
+// Comparisons with NULL are false, as in SQL
+fn evaluate(
+    table: &str,
+    schema: &TableSchema,
+    row: &Row,
+    condition: &Condition,
+    parameters: &HashMap<String, String>,
+) -> Result<bool> {
+    match condition {
+        Condition::And(left, right) => {
+            Ok(evaluate(table, schema, row, left, parameters)? && evaluate(table, schema, row, right, parameters)?)
+        },
+        Condition::Or(left, right) => {
+            Ok(evaluate(table, schema, row, left, parameters)? || evaluate(table, schema, row, right, parameters)?)
+        },
+        Condition::Comparison { left, op, right } => {
+            let column = schema_column(table, schema, left)?;
+            let (Some(value), Some(operand)) = (row.get(left), resolve(right, parameters)?) else {
+                return Ok(false);
+            };
+            if *op == ComparisonOp::Like {
+                return Ok(like(value, &operand));
+            }
+            let ordering = match column.data_type {
+                ColumnType::Int | ColumnType::Float | ColumnType::Timestamp => {
+                    match (value.parse::<f64>(), operand.parse::<f64>()) {
+                        (Ok(value), Ok(operand)) => value.partial_cmp(&operand),
+                        _ => None,
+                    }
+                },
+                _ => Some(value.as_str().cmp(operand.as_str())),
+            };
+            let Some(ordering) = ordering else { return Ok(false) };
+            Ok(match op {
+                ComparisonOp::Eq => ordering == Ordering::Equal,
+                ComparisonOp::NotEq => ordering != Ordering::Equal,
+                ComparisonOp::Lt => ordering == Ordering::Less,
+                ComparisonOp::LtEq => ordering != Ordering::Greater,
+                ComparisonOp::Gt => ordering == Ordering::Greater,
+                ComparisonOp::GtEq => ordering != Ordering::Less,
+                ComparisonOp::Like => unreachable!("LIKE is matched above"),
+            })
+        },
+    }
+}
+
+/// Match `value` against a LIKE pattern, where `%` stands for any run of
+/// characters and `_` for a single one
+fn like(value: &str, pattern: &str) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    // matched[j]: whether the value so far matches the first j pattern characters
+    let mut matched = vec![false; pattern.len() + 1];
+    matched[0] = true;
+    for j in 0..pattern.len() {
+        matched[j + 1] = matched[j] && pattern[j] == '%';
+    }
+    for c in value {
+        let mut next = vec![false; pattern.len() + 1];
+        for j in 0..pattern.len() {
+            next[j + 1] = match pattern[j] {
+                '%' => next[j] || matched[j + 1],
+                '_' => matched[j],
+                p => matched[j] && p == c,
+            };
+        }
+        matched = next;
+    }
+    matched[pattern.len()]
+}
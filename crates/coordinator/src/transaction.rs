@@ -0,0 +1,679 @@
+//! Distributed transactions.
+//!
+//! Transactions follow a Percolator-style protocol. Writes are buffered in
+//! the coordinator until commit. Commit then:
+//!
+//! 1. Prewrites every key as an intent, starting with the partition of the
+//!    primary key, which also creates the transaction record.
+//! 2. Commits the transaction record at a fresh commit timestamp. This single
+//!    Raft write is the commit point.
+//! 3. Resolves the intents into committed versions on every partition.
+//!
+//! Transactions run under snapshot isolation unless they ask for
+//! serializable. Under snapshot isolation two transactions conflict only
+//! when they write the same key, which allows write skew. Serializable
+//! transactions also remember the keys they read and the ranges they
+//! scanned and, after prewriting, check that none of them was written by a
+//! concurrent transaction, which includes rows inserted into a scanned range;
+//! since
+//! every transaction lays down its intents before that check, of two
+//! transactions that read what the other writes at least one sees the other
+//! and aborts. Conflicts fail the commit with the retryable
+//! `DatabaseError::Conflict`.
+//!
+//! `SELECT ... FOR UPDATE` takes pessimistic locks in the lock tables of the
+//! data nodes, and commit locks the keys it writes before prewriting them, so
+//! writers queue behind rows locked for update instead of failing. Locks are
+//! held until the transaction ends. A transaction whose lock wait is aborted
+//! by the deadlock detector is rolled back.
+//!
+//! If the coordinator crashes part way, the intents it left behind block
+//! other writers. Whoever runs into one recovers the transaction through its
+//! record: once the record has been inactive for the abandon timeout it is
+//! aborted, and the intents are resolved according to the record's outcome.
+//! A client that goes away leaves its open transaction behind the same way;
+//! the `TransactionReaper` drops transactions that were not touched for the
+//! abandon timeout and releases their locks.
+//!
+//! Open transactions, with their buffered writes, live only in the memory of
+//! the coordinator that began them; other coordinators do not know them. A
+//! client must send every request of a transaction to that coordinator, as
+//! `SmartClient::begin_transaction` does, and run the transaction again if
+//! the coordinator goes away.
+
+use crate::{Coordinator, NodeTransport};
+use common::config::CoordinatorConfig;
+use common::error::{DatabaseError, Result};
+use common::hlc::HlcTimestamp;
+use common::types::{
+    BatchOperation, Command, CommandResponse, IsolationLevel, KeyRange, PartitionInfo, ReadConsistency,
+    TransactionId, TransactionMeta, TransactionStatus, VersionedValue,
+};
+use common::util::timestamp_ms;
+use log::{info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// An open transaction.
+pub(crate) struct Transaction {
+    start_ts: HlcTimestamp,
+    isolation: IsolationLevel,
+    /// Keys read from storage; only tracked for serializable transactions.
+    reads: BTreeSet<String>,
+    /// Key ranges scanned; only tracked for serializable transactions.
+    scans: Vec<KeyRange>,
+    /// Keys locked with `SELECT ... FOR UPDATE`.
+    locked: BTreeSet<String>,
+    /// Buffered writes; `None` is a delete.
+    writes: BTreeMap<String, Option<Vec<u8>>>,
+    /// When the transaction was last used, in ms since the epoch
+    last_active_ms: u64,
+}
+
+/// Periodically drops transactions their clients abandoned.
+pub struct TransactionReaper {
+    interval: Duration,
+    coordinator: Arc<Mutex<Coordinator>>,
+}
+
+impl TransactionReaper {
+    pub fn new(config: &CoordinatorConfig, coordinator: Arc<Mutex<Coordinator>>) -> Self {
+        Self {
+            interval: Duration::from_millis(config.transaction_abandon_timeout_ms),
+            coordinator,
+        }
+    }
+
+    /// Reap forever.
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            let expired = self.coordinator.lock().await.expire_transactions(timestamp_ms()).await;
+            if expired > 0 {
+                info!("Rolled back {} abandoned transactions", expired);
+            }
+        }
+    }
+}
+
+/// Row locks of a transaction in the lock tables of partition leaders
+///
+/// They are planned while holding the coordinator, but taken and released
+/// without it, so a transaction waiting for a lock does not hold up the
+/// one it waits for.
+pub(crate) struct RowLocks {
+    transport: Arc<dyn NodeTransport>,
+    txn_id: TransactionId,
+    /// Leader addresses with the keys to lock on each
+    leaders: Vec<(String, Vec<String>)>,
+}
+
+impl RowLocks {
+    /// Wait for exclusive locks on the keys, one leader at a time
+    ///
+    /// Each leader queues the request until every lock is granted or the
+    /// wait times out.
+    async fn acquire(&self, start_ts: HlcTimestamp) -> Result<()> {
+        for (leader, keys) in &self.leaders {
+            self.transport.acquire_locks(leader, &self.txn_id, start_ts, keys.clone()).await?;
+        }
+        Ok(())
+    }
+
+    // Best effort: locks die with their leader anyway, and a lost release
+    // only delays waiters until their lock wait timeout
+    async fn release(&self) {
+        for (leader, _) in &self.leaders {
+            // The leader grants the locks to the next waiters
+            if let Err(e) = self.transport.release_locks(leader, &self.txn_id).await {
+                warn!("Failed to release locks of transaction {}: {}", self.txn_id, e);
+            }
+        }
+    }
+}
+
+/// [`Coordinator::transaction_get_for_update`] on the coordinator behind
+/// `coordinator`, holding its lock only to plan the row lock, and to
+/// record it and read the key once granted
+pub async fn transaction_get_for_update_unlocked(
+    coordinator: &Mutex<Coordinator>,
+    txn_id: &TransactionId,
+    key: String,
+) -> Result<VersionedValue> {
+    let (locks, start_ts) = {
+        let mut coordinator = coordinator.lock().await;
+        let start_ts = coordinator.transaction_mut(txn_id)?.start_ts;
+        (coordinator.row_locks(txn_id, std::slice::from_ref(&key))?, start_ts)
+    };
+    let locked = match &locks {
+        Some(locks) => locks.acquire(start_ts).await,
+        None => Ok(()),
+    };
+    coordinator.lock().await.locked_for_update(txn_id, key, locked).await
+}
+
+/// [`Coordinator::commit_transaction`] on the coordinator behind
+/// `coordinator`, which is not held while waiting for the row locks of the
+/// keys the transaction writes, nor while releasing its locks
+pub async fn commit_transaction_unlocked(coordinator: &Mutex<Coordinator>, txn_id: &TransactionId) -> Result<HlcTimestamp> {
+    let (transaction, writes, locked) = {
+        let mut coordinator = coordinator.lock().await;
+        let transaction = coordinator.transactions.remove(txn_id).ok_or_else(|| Coordinator::unknown_transaction(txn_id))?;
+        let writes: Vec<String> = transaction.writes.keys().cloned().collect();
+        let locked = Coordinator::locked_at_commit(&transaction);
+        match (coordinator.row_locks(txn_id, &writes), coordinator.row_locks(txn_id, &locked)) {
+            (Ok(writes), Ok(locked)) => (transaction, writes, locked),
+            (Err(e), _) | (_, Err(e)) => {
+                coordinator.release_locks(txn_id, &locked).await;
+                return Err(e);
+            },
+        }
+    };
+
+    let outcome = match &writes {
+        Some(writes) => writes.acquire(transaction.start_ts).await,
+        None => Ok(()),
+    };
+    let outcome = match outcome {
+        Ok(()) => coordinator.lock().await.commit(txn_id, transaction).await,
+        Err(e) => Err(e),
+    };
+    if let Some(locked) = locked {
+        locked.release().await;
+    }
+    outcome
+}
+
+impl Coordinator {
+    /// Start a transaction; its reads see a snapshot as of now
+    pub fn begin_transaction(&mut self, isolation: IsolationLevel) -> TransactionId {
+        let id = TransactionId::generate();
+        let transaction = Transaction {
+            start_ts: self.clock.now(),
+            isolation,
+            reads: BTreeSet::new(),
+            scans: Vec::new(),
+            locked: BTreeSet::new(),
+            writes: BTreeMap::new(),
+            last_active_ms: timestamp_ms(),
+        };
+        self.transactions.insert(id.clone(), transaction);
+        id
+    }
+
+    /// Change the isolation level of a transaction that has not read or
+    /// written anything yet
+    pub fn set_transaction_isolation(&mut self, txn_id: &TransactionId, isolation: IsolationLevel) -> Result<()> {
+        let transaction = self.transaction_mut(txn_id)?;
+        let used = !transaction.reads.is_empty() || !transaction.scans.is_empty();
+        if used || !transaction.locked.is_empty() || !transaction.writes.is_empty() {
+            return Err(DatabaseError::Transaction(
+                "The isolation level must be set before the first read or write".to_string()
+            ));
+        }
+        transaction.isolation = isolation;
+        Ok(())
+    }
+
+    /// Read a key inside a transaction, seeing the transaction's own writes.
+    /// A key the transaction deleted fails with `KeyNotFound`.
+    pub async fn transaction_get(&mut self, txn_id: &TransactionId, key: String) -> Result<VersionedValue> {
+        let transaction = self.transaction_mut(txn_id)?;
+        match transaction.writes.get(&key) {
+            // Not committed yet, so there is no version to report
+            Some(Some(value)) => return Ok(VersionedValue { value: value.clone(), version: 0 }),
+            Some(None) => return Err(DatabaseError::KeyNotFound { key }),
+            None => {},
+        }
+
+        let start_ts = transaction.start_ts;
+        let value = self.read_key(&key, start_ts, ReadConsistency::Linearizable).await?;
+
+        // Reading that a key is absent is a read too
+        let transaction = self.transaction_mut(txn_id)?;
+        if transaction.isolation == IsolationLevel::Serializable {
+            transaction.reads.insert(key.clone());
+        }
+        value.ok_or(DatabaseError::KeyNotFound { key })
+    }
+
+    /// Read the items of `[start_key, end_key)` inside a transaction, seeing
+    /// the transaction's own writes
+    pub async fn transaction_scan(
+        &mut self,
+        txn_id: &TransactionId,
+        start_key: &str,
+        end_key: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        let start_ts = self.transaction_mut(txn_id)?.start_ts;
+        let items = self.scan_range(start_key, end_key, 0, start_ts, ReadConsistency::Linearizable).await?;
+
+        let transaction = self.transaction_mut(txn_id)?;
+        if transaction.isolation == IsolationLevel::Serializable {
+            transaction.scans.push(KeyRange::new(start_key, end_key));
+        }
+        let mut items: BTreeMap<String, Vec<u8>> = items.into_iter().collect();
+        let end = if end_key.is_empty() { std::ops::Bound::Unbounded } else { std::ops::Bound::Excluded(end_key) };
+        for (key, value) in transaction.writes.range::<str, _>((std::ops::Bound::Included(start_key), end)) {
+            match value {
+                Some(value) => items.insert(key.clone(), value.clone()),
+                None => items.remove(key),
+            };
+        }
+        Ok(items.into_iter().collect())
+    }
+
+    /// Lock a key until the transaction ends, then read it
+    ///
+    /// Waits while another transaction holds the lock. If the wait is aborted
+    /// to break a deadlock the transaction is rolled back and the retryable
+    /// error is returned. The read still sees the transaction's snapshot; if
+    /// the key changed since, the commit fails with a conflict.
+    pub async fn transaction_get_for_update(&mut self, txn_id: &TransactionId, key: String) -> Result<VersionedValue> {
+        let start_ts = self.transaction_mut(txn_id)?.start_ts;
+        let locked = match self.row_locks(txn_id, std::slice::from_ref(&key)) {
+            Ok(Some(locks)) => locks.acquire(start_ts).await,
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        self.locked_for_update(txn_id, key, locked).await
+    }
+
+    // Record the outcome of locking `key` for update, then read it
+    async fn locked_for_update(&mut self, txn_id: &TransactionId, key: String, locked: Result<()>) -> Result<VersionedValue> {
+        if let Err(e) = locked {
+            if e.is_conflict() && self.transactions.contains_key(txn_id) {
+                self.rollback_transaction(txn_id).await?;
+            }
+            return Err(e);
+        }
+
+        // Rolled back while waiting; the lock goes with it
+        let Ok(transaction) = self.transaction_mut(txn_id) else {
+            self.release_locks(txn_id, &[key]).await;
+            return Err(Self::unknown_transaction(txn_id));
+        };
+        transaction.locked.insert(key.clone());
+        self.transaction_get(txn_id, key).await
+    }
+
+    /// Buffer a write inside a transaction
+    pub fn transaction_put(&mut self, txn_id: &TransactionId, key: String, value: Vec<u8>) -> Result<()> {
+        self.partition_for_key(&key)?;
+        self.transaction_mut(txn_id)?.writes.insert(key, Some(value));
+        Ok(())
+    }
+
+    /// Buffer a delete inside a transaction
+    pub fn transaction_delete(&mut self, txn_id: &TransactionId, key: String) -> Result<()> {
+        self.partition_for_key(&key)?;
+        self.transaction_mut(txn_id)?.writes.insert(key, None);
+        Ok(())
+    }
+
+    /// Roll back the transactions that were not used for the abandon timeout
+    /// as of `now_ms`. Returns how many there were.
+    pub async fn expire_transactions(&mut self, now_ms: u64) -> usize {
+        let abandoned_before_ms = now_ms.saturating_sub(self.transaction_abandon_timeout_ms);
+        let expired: Vec<TransactionId> = self.transactions.iter()
+            .filter(|(_, transaction)| transaction.last_active_ms < abandoned_before_ms)
+            .map(|(txn_id, _)| txn_id.clone())
+            .collect();
+        for txn_id in &expired {
+            if let Err(e) = self.rollback_transaction(txn_id).await {
+                warn!("Failed to roll back abandoned transaction {}: {}", txn_id, e);
+            }
+        }
+        expired.len()
+    }
+
+    /// Discard a transaction and its buffered writes, releasing its locks
+    pub async fn rollback_transaction(&mut self, txn_id: &TransactionId) -> Result<()> {
+        let transaction = self.transactions.remove(txn_id).ok_or_else(|| Self::unknown_transaction(txn_id))?;
+        let locked: Vec<String> = transaction.locked.into_iter().collect();
+        self.release_locks(txn_id, &locked).await;
+        Ok(())
+    }
+
+    /// Commit a transaction atomically across partitions
+    ///
+    /// Returns the commit timestamp. On error nothing the transaction wrote
+    /// becomes visible. Read-only transactions commit at their start
+    /// timestamp, where their snapshot is already consistent.
+    pub async fn commit_transaction(&mut self, txn_id: &TransactionId) -> Result<HlcTimestamp> {
+        let transaction = self.transactions.remove(txn_id).ok_or_else(|| Self::unknown_transaction(txn_id))?;
+        let locked = Self::locked_at_commit(&transaction);
+
+        let writes: Vec<String> = transaction.writes.keys().cloned().collect();
+        let outcome = match self.row_locks(txn_id, &writes) {
+            Ok(Some(locks)) => locks.acquire(transaction.start_ts).await,
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        let outcome = match outcome {
+            Ok(()) => self.commit(txn_id, transaction).await,
+            Err(e) => Err(e),
+        };
+        self.release_locks(txn_id, &locked).await;
+        outcome
+    }
+
+    // Commit locks the keys it writes, on top of those locked for update
+    fn locked_at_commit(transaction: &Transaction) -> Vec<String> {
+        let mut locked: Vec<String> = transaction.locked.iter()
+            .chain(transaction.writes.keys())
+            .cloned()
+            .collect();
+        locked.sort();
+        locked.dedup();
+        locked
+    }
+
+    async fn commit(&mut self, txn_id: &TransactionId, transaction: Transaction) -> Result<HlcTimestamp> {
+        let Some(primary_key) = transaction.writes.keys().next().cloned() else {
+            // Read-only transactions have nothing to commit
+            return Ok(transaction.start_ts);
+        };
+
+        let txn = TransactionMeta {
+            id: txn_id.clone(),
+            primary_key,
+            start_ts: transaction.start_ts,
+        };
+        let txn_keys: Vec<String> = transaction.writes.keys().cloned().collect();
+        let reads: Vec<String> = transaction.reads.into_iter()
+            .filter(|key| !transaction.writes.contains_key(key))
+            .collect();
+        let scans = transaction.scans;
+        let groups = self.group_writes(&txn, transaction.writes)?;
+
+        for (partition, operations) in &groups {
+            let holds_primary = operations.iter().any(|op| op.key() == txn.primary_key);
+            let command = Command::Prewrite {
+                txn: txn.clone(),
+                operations: operations.clone(),
+                txn_keys: if holds_primary { txn_keys.clone() } else { Vec::new() },
+            };
+            match self.propose(partition, command).await {
+                Ok(response) if response.succeeded => {},
+                outcome => {
+                    self.abort(&txn, &txn_keys).await;
+                    return Err(Self::prewrite_error(&txn, outcome));
+                },
+            }
+        }
+
+        // Taken before validating reads, so a transaction that writes one of
+        // them after the check gets a later commit timestamp
+        let commit_ts = self.clock.now();
+        if let Err(e) = self.validate_reads(&txn, reads, scans).await {
+            self.abort(&txn, &txn_keys).await;
+            return Err(e);
+        }
+
+        // Committing the record is the commit point
+        let primary = &groups[0].0;
+        let command = Command::EndTransaction { txn: txn.clone(), commit_ts: Some(commit_ts) };
+        let committed = match self.propose(primary, command).await {
+            Ok(response) => response.succeeded,
+            Err(e) => self.commit_outcome(primary, &txn, e).await?,
+        };
+        if !committed {
+            // Recovered and aborted by someone else while we were prewriting
+            self.resolve_intents(&txn.id, &txn_keys, None).await?;
+            return Err(DatabaseError::Conflict { key: None, message: format!("Transaction {} was aborted", txn.id) });
+        }
+
+        // Committed; intents left behind are resolved lazily through recovery
+        if let Err(e) = self.resolve_intents(&txn.id, &txn_keys, Some(commit_ts)).await {
+            warn!("Failed to resolve intents of committed transaction {}: {}", txn.id, e);
+        }
+        Ok(commit_ts)
+    }
+
+    /// Learn from its record whether a commit whose answer was lost took
+    /// effect
+    ///
+    /// Fails with a non-retryable error when the record cannot tell, e.g. it
+    /// is still pending while the commit may yet be applied; retrying could
+    /// then commit the writes twice.
+    async fn commit_outcome(&mut self, partition: &PartitionInfo, txn: &TransactionMeta, error: DatabaseError) -> Result<bool> {
+        // Nothing is abandoned before the epoch, so this only reads the record
+        let command = Command::RecoverTransaction { txn: txn.clone(), abandoned_before_ms: 0 };
+        let status = match self.propose(partition, command).await {
+            Ok(response) => response.transaction.map(|record| record.status),
+            Err(_) => None,
+        };
+        match status {
+            Some(TransactionStatus::Committed) => Ok(true),
+            Some(TransactionStatus::Aborted) => Ok(false),
+            _ => Err(DatabaseError::Transaction(format!(
+                "Outcome of committing transaction {} is unknown: {}",
+                txn.id, error
+            ))),
+        }
+    }
+
+    /// Settle a transaction whose intent blocks another operation
+    ///
+    /// A transaction whose record has been inactive for longer than the
+    /// abandon timeout is aborted. Once the transaction is committed or
+    /// aborted, its intents, and the intents on `blocked_keys`, are resolved.
+    /// Returns the transaction's status; `Pending` means it is still running.
+    pub async fn recover_transaction(&mut self, txn: &TransactionMeta, blocked_keys: &[String]) -> Result<TransactionStatus> {
+        let partition = self.partition_for_key(&txn.primary_key)?;
+        let abandoned_before_ms = self.clock.now().wall_ms.saturating_sub(self.transaction_abandon_timeout_ms);
+        let command = Command::RecoverTransaction { txn: txn.clone(), abandoned_before_ms };
+        let record = self.propose(&partition, command).await?.transaction
+            .ok_or_else(|| DatabaseError::Transaction(format!("No record for transaction {}", txn.id)))?;
+
+        if record.status != TransactionStatus::Pending {
+            let mut keys = record.keys.clone();
+            keys.extend(blocked_keys.iter().filter(|key| !record.keys.contains(key)).cloned());
+            let commit_ts = record.commit_ts.filter(|_| record.status == TransactionStatus::Committed);
+            self.resolve_intents(&txn.id, &keys, commit_ts).await?;
+        }
+        Ok(record.status)
+    }
+
+    /// Propose a plain write, first recovering an abandoned transaction whose
+    /// intent is in the way
+    pub(crate) async fn propose_write(&mut self, partition: &PartitionInfo, command: Command) -> Result<CommandResponse> {
+        let response = self.propose(partition, command.clone()).await?;
+        let Some(txn) = response.conflict else {
+            return Ok(response);
+        };
+
+        let keys: Vec<String> = command.write_keys().into_iter().map(String::from).collect();
+        if self.recover_transaction(&txn, &keys).await? == TransactionStatus::Pending {
+            return Err(Self::write_blocked(&txn, &keys));
+        }
+
+        let response = self.propose(partition, command).await?;
+        match response.conflict {
+            Some(txn) => Err(Self::write_blocked(&txn, &keys)),
+            None => Ok(response),
+        }
+    }
+
+    // The retryable conflict of a write held up by a pending transaction
+    fn write_blocked(txn: &TransactionMeta, keys: &[String]) -> DatabaseError {
+        DatabaseError::Conflict {
+            key: if let [key] = keys { Some(key.clone()) } else { None },
+            message: format!("Write blocked by pending transaction {}", txn.id),
+        }
+    }
+
+    /// The read timestamp of an open transaction
+    pub(crate) fn transaction_start(&self, txn_id: &TransactionId) -> Result<HlcTimestamp> {
+        Ok(self.transaction(txn_id)?.start_ts)
+    }
+
+    fn transaction(&self, txn_id: &TransactionId) -> Result<&Transaction> {
+        self.transactions.get(txn_id).ok_or_else(|| Self::unknown_transaction(txn_id))
+    }
+
+    // Every use of a transaction goes through here, which keeps it alive
+    fn transaction_mut(&mut self, txn_id: &TransactionId) -> Result<&mut Transaction> {
+        let transaction = self.transactions.get_mut(txn_id).ok_or_else(|| Self::unknown_transaction(txn_id))?;
+        transaction.last_active_ms = timestamp_ms();
+        Ok(transaction)
+    }
+
+    // Transactions stay on the coordinator that began them
+    fn unknown_transaction(txn_id: &TransactionId) -> DatabaseError {
+        DatabaseError::Transaction(format!("Unknown transaction {}; it is not open on this coordinator", txn_id))
+    }
+
+    // Group buffered writes by partition, with the primary key's partition first
+    fn group_writes(
+        &self,
+        txn: &TransactionMeta,
+        writes: BTreeMap<String, Option<Vec<u8>>>,
+    ) -> Result<Vec<(PartitionInfo, Vec<BatchOperation>)>> {
+        let mut groups: Vec<(PartitionInfo, Vec<BatchOperation>)> = Vec::new();
+        for (key, value) in writes {
+            let partition = self.partition_for_key(&key)?;
+            let operation = match value {
+                Some(value) => BatchOperation::Put { key, value },
+                None => BatchOperation::Delete { key },
+            };
+            match groups.iter_mut().find(|(p, _)| p.id == partition.id) {
+                Some((_, operations)) => operations.push(operation),
+                None => groups.push((partition, vec![operation])),
+            }
+        }
+
+        let primary = groups.iter()
+            .position(|(_, ops)| ops.iter().any(|op| op.key() == txn.primary_key))
+            .unwrap_or(0);
+        groups.swap(0, primary);
+        Ok(groups)
+    }
+
+    // Best-effort abort after a failed prewrite; recovery cleans up the rest
+    async fn abort(&mut self, txn: &TransactionMeta, keys: &[String]) {
+        let outcome = match self.partition_for_key(&txn.primary_key) {
+            Ok(primary) => self.propose(&primary, Command::EndTransaction { txn: txn.clone(), commit_ts: None }).await,
+            Err(e) => Err(e),
+        };
+        let outcome = match outcome {
+            Ok(_) => self.resolve_intents(&txn.id, keys, None).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = outcome {
+            warn!("Failed to abort transaction {}: {}", txn.id, e);
+        }
+    }
+
+    /// The row locks on `keys` of a transaction, in the lock tables of the
+    /// leaders of their partitions; `None` without keys
+    fn row_locks(&self, txn_id: &TransactionId, keys: &[String]) -> Result<Option<RowLocks>> {
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        let leaders = self.group_keys(keys)?
+            .into_iter()
+            .map(|(partition, keys)| Ok((self.address(&partition.leader)?, keys)))
+            .collect::<Result<_>>()?;
+        Ok(Some(RowLocks { transport: self.data_transport()?, txn_id: txn_id.clone(), leaders }))
+    }
+
+    async fn release_locks(&self, txn_id: &TransactionId, keys: &[String]) {
+        match self.row_locks(txn_id, keys) {
+            Ok(Some(locks)) => locks.release().await,
+            Ok(None) => {},
+            Err(e) => warn!("Failed to release locks of transaction {}: {}", txn_id, e),
+        }
+    }
+
+    // Check that no concurrent transaction wrote the keys a serializable
+    // transaction read or the ranges it scanned
+    async fn validate_reads(&mut self, txn: &TransactionMeta, reads: Vec<String>, scans: Vec<KeyRange>) -> Result<()> {
+        let mut checks: Vec<(PartitionInfo, Vec<String>, Vec<KeyRange>)> = self.group_keys(&reads)?
+            .into_iter()
+            .map(|(partition, keys)| (partition, keys, Vec::new()))
+            .collect();
+        for (partition, range) in self.split_ranges(&scans) {
+            match checks.iter_mut().find(|(p, _, _)| p.id == partition.id) {
+                Some((_, _, ranges)) => ranges.push(range),
+                None => checks.push((partition, Vec::new(), vec![range])),
+            }
+        }
+
+        for (partition, keys, ranges) in checks {
+            let response = self.propose(&partition, Command::ValidateReads { txn: txn.clone(), keys, ranges }).await?;
+            if response.succeeded {
+                continue;
+            }
+            let message = match response.conflict {
+                Some(other) => format!(
+                    "Transaction {} read a key written by concurrent transaction {}", txn.id, other.id
+                ),
+                None => format!("Transaction {} read a key written after it started", txn.id),
+            };
+            return Err(DatabaseError::Conflict { key: response.conflict_key, message });
+        }
+        Ok(())
+    }
+
+    // Cut key ranges at partition boundaries; an empty end is unbounded
+    fn split_ranges(&self, ranges: &[KeyRange]) -> Vec<(PartitionInfo, KeyRange)> {
+        let mut partitions: Vec<&PartitionInfo> = self.metadata.partitions.values().collect();
+        partitions.sort_by(|a, b| a.range.start.cmp(&b.range.start));
+
+        let mut pieces = Vec::new();
+        for range in ranges {
+            for partition in &partitions {
+                let start = range.start.as_str().max(partition.range.start.as_str());
+                let end = if range.end.is_empty() {
+                    partition.range.end.as_str()
+                } else {
+                    range.end.as_str().min(partition.range.end.as_str())
+                };
+                if start < end {
+                    pieces.push(((*partition).clone(), KeyRange::new(start, end)));
+                }
+            }
+        }
+        pieces
+    }
+
+    async fn resolve_intents(&mut self, txn_id: &TransactionId, keys: &[String], commit_ts: Option<HlcTimestamp>) -> Result<()> {
+        for (partition, keys) in self.group_keys(keys)? {
+            let command = Command::ResolveIntents { txn_id: txn_id.clone(), keys, commit_ts };
+            self.propose(&partition, command).await?;
+        }
+        Ok(())
+    }
+
+    fn group_keys(&self, keys: &[String]) -> Result<Vec<(PartitionInfo, Vec<String>)>> {
+        let mut groups: Vec<(PartitionInfo, Vec<String>)> = Vec::new();
+        for key in keys {
+            let partition = self.partition_for_key(key)?;
+            match groups.iter_mut().find(|(p, _)| p.id == partition.id) {
+                Some((_, keys)) => keys.push(key.clone()),
+                None => groups.push((partition, vec![key.clone()])),
+            }
+        }
+        Ok(groups)
+    }
+
+    fn prewrite_error(txn: &TransactionMeta, outcome: Result<CommandResponse>) -> DatabaseError {
+        match outcome {
+            Err(e) => e,
+            Ok(response) => DatabaseError::Conflict {
+                key: response.conflict_key,
+                message: match response.conflict {
+                    Some(other) => format!(
+                        "Transaction {} conflicts with pending transaction {}", txn.id, other.id
+                    ),
+                    None => format!("Transaction {} conflicts with a write committed after it started", txn.id),
+                },
+            },
+        }
+    }
+}
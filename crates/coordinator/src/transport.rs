@@ -0,0 +1,174 @@
+//! Requests the coordinator sends to the data nodes.
+//!
+//! Changes to a partition's replicas and leadership are carried out by the
+//! leader of its Raft group. The coordinator asks the leader, and records
+//! the change in the cluster metadata only once the leader reports it done,
+//! so the metadata never names a replica the group does not have. Without
+//! a transport, as in tests, changes are recorded in the metadata alone.
+//!
+//! Commands of the transaction protocol and of range changes are proposed
+//! to the partition leader the same way, and reads go to the replica that
+//! serves them at the requested consistency. Pessimistic locks live in the
+//! lock tables of the partition leaders.
+
+use crate::MAX_LEADER_REDIRECTS;
+use common::error::{DatabaseError, Result};
+use common::hlc::{HlcTimestamp, HybridClock};
+use common::types::{
+    Command, CommandResponse, NodeId, PartitionInfo, ReadConsistency, ReadOutcome, TransactionId, VersionedValue,
+};
+use rpc::client::{NodeClient, Timeouts};
+use rpc::routing::{RouteError, Routed};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a membership change may take; adding a learner waits for it
+/// to catch up, possibly from a snapshot.
+const MEMBERSHIP_CHANGE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a lock request may take; longer than the lock wait timeout of
+/// the nodes, which end the wait themselves.
+const LOCK_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Requests to the leaders of partitions.
+#[tonic::async_trait]
+pub trait NodeTransport: Send + Sync + 'static {
+    /// Add `node`, listening at `address`, as a learner of partition
+    /// `partition_id`, whose leader listens at `leader`. Returns once the
+    /// learner has caught up.
+    async fn add_learner(&self, leader: &str, partition_id: u64, node: &NodeId, address: &str) -> Result<()>;
+
+    /// Move the Raft group of `target.id`, whose leader listens at `leader`,
+    /// to the voters and learners of `target`. Returns once committed.
+    async fn change_membership(&self, leader: &str, target: &PartitionInfo) -> Result<()>;
+
+    /// Hand leadership of partition `partition_id`, whose leader listens at
+    /// `leader`, to the voter `target`. Returns once the target has won the
+    /// election.
+    async fn transfer_leader(&self, leader: &str, partition_id: u64, target: &NodeId) -> Result<()>;
+
+    /// Replicate `command` through partition `partition_id`, whose leader
+    /// listens at `leader`, and return the command's response once applied.
+    async fn propose(&self, leader: &str, partition_id: u64, command: &Command) -> Result<Routed<CommandResponse>>;
+
+    /// Read `key` as of `read_ts` from the replica listening at `replica`.
+    async fn read(
+        &self,
+        replica: &str,
+        key: &str,
+        read_ts: HlcTimestamp,
+        consistency: ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<Option<VersionedValue>>>>;
+
+    /// Read up to `limit` pairs (all if 0) from `start_key` up to `end_key`
+    /// as of `read_ts` from the replica listening at `replica`, and whether
+    /// the replica stopped short of `end_key`, e.g. at the end of its range.
+    async fn scan(
+        &self,
+        replica: &str,
+        start_key: &str,
+        end_key: &str,
+        limit: usize,
+        read_ts: HlcTimestamp,
+        consistency: ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<(Vec<(String, Vec<u8>)>, bool)>>>;
+
+    /// Lock `keys` for `txn_id` on the leader listening at `leader`,
+    /// returning once every lock is granted.
+    async fn acquire_locks(&self, leader: &str, txn_id: &TransactionId, start_ts: HlcTimestamp, keys: Vec<String>) -> Result<()>;
+
+    /// Release the locks `txn_id` holds on the leader listening at `leader`.
+    async fn release_locks(&self, leader: &str, txn_id: &TransactionId) -> Result<()>;
+}
+
+/// Propose `command` to partition `partition_id` through the replica
+/// listening at `leader`, following its pointers to the current leader.
+pub(crate) async fn propose_on_leader(
+    transport: &dyn NodeTransport,
+    mut leader: String,
+    partition_id: u64,
+    command: &Command,
+) -> Result<CommandResponse> {
+    for _ in 0..MAX_LEADER_REDIRECTS {
+        match transport.propose(&leader, partition_id, command).await? {
+            Routed::Served(response) => return Ok(response),
+            Routed::Misrouted(RouteError::NotLeader { leader: Some((_, address)) }) => leader = address,
+            Routed::Misrouted(error) => return Err(error.into()),
+        }
+    }
+    Err(DatabaseError::NotLeader { partition: Some(partition_id), leader: None })
+}
+
+/// Transport over the node service.
+pub struct GrpcNodeTransport {
+    clock: Arc<HybridClock>,
+}
+
+impl GrpcNodeTransport {
+    pub fn new(clock: Arc<HybridClock>) -> Self {
+        Self { clock }
+    }
+
+    async fn client(&self, addr: &str, request: Duration) -> Result<NodeClient> {
+        let timeouts = Timeouts { request, ..Timeouts::default() };
+        NodeClient::connect_with_timeouts(addr, self.clock.clone(), timeouts).await
+    }
+}
+
+#[tonic::async_trait]
+impl NodeTransport for GrpcNodeTransport {
+    async fn add_learner(&self, leader: &str, partition_id: u64, node: &NodeId, address: &str) -> Result<()> {
+        let mut client = self.client(leader, MEMBERSHIP_CHANGE_TIMEOUT).await?;
+        client.add_learner(partition_id, node, address.to_string()).await?;
+        Ok(())
+    }
+
+    async fn change_membership(&self, leader: &str, target: &PartitionInfo) -> Result<()> {
+        let mut client = self.client(leader, MEMBERSHIP_CHANGE_TIMEOUT).await?;
+        client.change_membership(target.id, target.voters(), target.learners.clone()).await
+    }
+
+    async fn transfer_leader(&self, leader: &str, partition_id: u64, target: &NodeId) -> Result<()> {
+        let mut client = self.client(leader, Timeouts::default().request).await?;
+        client.transfer_leader(partition_id, target).await
+    }
+
+    async fn propose(&self, leader: &str, partition_id: u64, command: &Command) -> Result<Routed<CommandResponse>> {
+        let mut client = self.client(leader, Timeouts::default().request).await?;
+        client.propose(partition_id, command).await
+    }
+
+    async fn read(
+        &self,
+        replica: &str,
+        key: &str,
+        read_ts: HlcTimestamp,
+        consistency: ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<Option<VersionedValue>>>> {
+        let mut client = self.client(replica, Timeouts::default().request).await?;
+        client.read_at(key.to_string(), Some(read_ts), consistency).await
+    }
+
+    async fn scan(
+        &self,
+        replica: &str,
+        start_key: &str,
+        end_key: &str,
+        limit: usize,
+        read_ts: HlcTimestamp,
+        consistency: ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<(Vec<(String, Vec<u8>)>, bool)>>> {
+        let mut client = self.client(replica, Timeouts::default().request).await?;
+        client.scan_at(start_key.to_string(), end_key.to_string(), limit, Some(read_ts), consistency).await
+    }
+
+    async fn acquire_locks(&self, leader: &str, txn_id: &TransactionId, start_ts: HlcTimestamp, keys: Vec<String>) -> Result<()> {
+        let mut client = self.client(leader, LOCK_REQUEST_TIMEOUT).await?;
+        client.acquire_locks(txn_id, start_ts, keys).await
+    }
+
+    async fn release_locks(&self, leader: &str, txn_id: &TransactionId) -> Result<()> {
+        let mut client = self.client(leader, Timeouts::default().request).await?;
+        client.release_locks(txn_id).await
+    }
+}
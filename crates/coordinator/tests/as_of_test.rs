@@ -0,0 +1,55 @@
+mod common;
+
+use ::common::types::ReadConsistency;
+use ::common::util::timestamp_ms;
+use common::coordinator_with_node;
+use coordinator_lib::{resolve_as_of, Coordinator};
+use sql_parser::SqlValue;
+use std::collections::HashMap;
+
+#[test]
+fn test_resolve_as_of_values() {
+    let params = HashMap::from([("ts".to_string(), "-1m".to_string())]);
+    let now = timestamp_ms();
+
+    let resolved = resolve_as_of(&SqlValue::String("-10s".to_string()), &params).unwrap();
+    assert!(resolved <= now - 10_000 && resolved > now - 20_000);
+
+    let resolved = resolve_as_of(&SqlValue::Parameter("ts".to_string()), &params).unwrap();
+    assert!(resolved <= now - 60_000);
+
+    assert_eq!(resolve_as_of(&SqlValue::Integer(1_000), &params).unwrap(), 1_000);
+}
+
+#[test]
+fn test_resolve_as_of_rejects_invalid_values() {
+    let params = HashMap::new();
+
+    assert!(resolve_as_of(&SqlValue::String("10 seconds ago".to_string()), &params).is_err());
+    assert!(resolve_as_of(&SqlValue::Parameter("missing".to_string()), &params).is_err());
+    assert!(resolve_as_of(&SqlValue::Boolean(true), &params).is_err());
+
+    let future = (timestamp_ms() + 60_000) as i64;
+    assert!(resolve_as_of(&SqlValue::Integer(future), &params).is_err(), "Future timestamps should be rejected");
+}
+
+#[tokio::test]
+async fn test_select_as_of_system_time() {
+    let mut coordinator = Coordinator::new();
+
+    let result = coordinator.execute_query("SELECT * FROM users AS OF SYSTEM TIME '-5s'".to_string(), HashMap::new()).await;
+    assert!(result.is_ok());
+
+    let result = coordinator.execute_query("SELECT * FROM users AS OF SYSTEM TIME 'yesterday'".to_string(), HashMap::new()).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_reads_reject_future_timestamps() {
+    let mut coordinator = coordinator_with_node().await;
+    let future = timestamp_ms() + 60_000;
+
+    assert!(coordinator.get("a".to_string(), Some(future), ReadConsistency::Linearizable).await.is_err());
+    assert!(coordinator.scan("a".to_string(), "z".to_string(), 10, None, Some(future), ReadConsistency::Linearizable).await.is_err());
+    assert!(coordinator.scan("a".to_string(), "z".to_string(), 10, None, Some(timestamp_ms()), ReadConsistency::Linearizable).await.is_ok());
+}
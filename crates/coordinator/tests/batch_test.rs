@@ -0,0 +1,25 @@
+mod common;
+
+use ::common::types::BatchOperation;
+use common::{coordinator_with_node, coordinator_with_partitions};
+
+fn put(key: &str) -> BatchOperation {
+    BatchOperation::Put { key: key.to_string(), value: b"v".to_vec() }
+}
+
+#[tokio::test]
+async fn test_batch_within_one_partition() {
+    let mut coordinator = coordinator_with_node().await;
+
+    let operations = vec![put("apple"), put("banana"), BatchOperation::Delete { key: "cherry".to_string() }];
+    assert!(coordinator.batch(operations).await.is_ok());
+    assert!(coordinator.batch(vec![]).await.is_ok(), "Empty batch is a no-op");
+}
+
+#[tokio::test]
+async fn test_batch_spanning_partitions_is_rejected() {
+    let mut coordinator = coordinator_with_partitions();
+
+    let result = coordinator.batch(vec![put("apple"), put("orange")]).await;
+    assert!(result.is_err(), "Batch across partitions should be rejected");
+}
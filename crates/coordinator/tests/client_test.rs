@@ -10,6 +10,7 @@ async fn test_database_service() -> Result<(), Box<dyn std::error::Error>> {
     let query_request = tonic::Request::new(QueryRequest {
         query: "SELECT * FROM test".to_string(),
         parameters: std::collections::HashMap::new(),
+        ..Default::default()
     });
     
     let response = client.execute_query(query_request).await?;
@@ -18,6 +19,7 @@ async fn test_database_service() -> Result<(), Box<dyn std::error::Error>> {
     // Test get
     let get_request = tonic::Request::new(GetRequest {
         key: "test_key".to_string(),
+        ..Default::default()
     });
     
     let response = client.get(get_request).await?;
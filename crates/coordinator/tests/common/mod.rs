@@ -0,0 +1,303 @@
+//! Helpers shared by the coordinator tests.
+#![allow(dead_code)]
+
+use common::config::NodeConfig;
+use common::error::{DatabaseError, Result};
+use common::hlc::HlcTimestamp;
+use common::types::{
+    ClusterMetadata, Command, CommandResponse, GroupHeartbeat, GroupHeartbeatAck, KeyRange, Locality, Membership,
+    NodeId, NodeInfo, NodeStatus, PartitionInfo, ReadConsistency, ReadOutcome, TransactionId, VersionedValue,
+};
+use coordinator_lib::{Coordinator, NodeTransport};
+use raft_node::{MultiRaft, Node, RaftMessage, RaftTransport, Replica};
+use rpc::routing::{RouteError, Routed};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use storage::Storage;
+use tokio::sync::Notify;
+
+/// A coordinator without a transport whose partitions split the keys at `m`.
+///
+/// It can change its partitions but not read or write them; see
+/// [`coordinator_with_node`] for one that can.
+pub fn coordinator_with_partitions() -> Coordinator {
+    let mut coordinator = Coordinator::new();
+    for (id, start, end) in [(1, "a", "m"), (2, "m", "z")] {
+        coordinator.add_partition(PartitionInfo {
+            id,
+            range: KeyRange::new(start, end),
+            leader: NodeId::from("node1"),
+            followers: vec![],
+            learners: vec![],
+        });
+    }
+    coordinator
+}
+
+/// The partitions of [`coordinator_with_partitions`], served by a data node
+/// in this process once it leads both of them
+pub async fn coordinator_with_node() -> Coordinator {
+    coordinator_with_node_answering(Commits::Answered).await
+}
+
+/// A [`coordinator_with_node`] whose transport answers commits as `commits`
+pub async fn coordinator_with_node_answering(commits: Commits) -> Coordinator {
+    let mut coordinator = coordinator_with_partitions();
+    let partitions = [coordinator.partition(1).unwrap(), coordinator.partition(2).unwrap()];
+    let data_node = data_node(&coordinator, &partitions).await;
+    let config = NodeConfig::default();
+    coordinator.register_node(node(&config.node_id.0, &config.listen_addr));
+    coordinator.set_transport(Arc::new(LocalTransport { node: data_node, commits }));
+    coordinator
+}
+
+/// A data node with the default config leading `partitions` on its own,
+/// storing them in a fresh directory
+pub async fn data_node(coordinator: &Coordinator, partitions: &[PartitionInfo]) -> Arc<Node> {
+    static NODES: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "coordinator_test_node_{}_{}",
+        std::process::id(),
+        NODES.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = std::fs::remove_dir_all(&path);
+    let config = NodeConfig::default();
+    let storage = Arc::new(Storage::open(path).unwrap());
+    let data_node = Arc::new(Node::open(&config, coordinator.clock(), storage).unwrap());
+    for partition in partitions {
+        let membership = Membership {
+            configs: vec![vec![config.node_id.clone()]],
+            learners: Vec::new(),
+            addresses: HashMap::from([(config.node_id.clone(), config.listen_addr.clone())]),
+        };
+        let replica = Replica::bootstrap(data_node.storage(), partition.id, partition.range.clone(), membership, &config).unwrap();
+        data_node.host().lock().await.add_group(partition.id, replica).unwrap();
+    }
+    tokio::spawn(MultiRaft::run(data_node.host(), Arc::new(NoPeers)));
+    for partition in partitions {
+        for _ in 0..200 {
+            if let Routed::Served(_) = data_node.propose_to(partition.id, Command::Noop).await.unwrap() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    data_node
+}
+
+/// An active node listening at `address`.
+pub fn node(id: &str, address: &str) -> NodeInfo {
+    NodeInfo {
+        id: NodeId::from(id),
+        address: address.to_string(),
+        status: NodeStatus::Active,
+        last_heartbeat: None,
+        load: Default::default(),
+        locality: Default::default(),
+    }
+}
+
+/// A node listening at `{id}:9090` with `status`.
+pub fn node_with_status(id: &str, status: NodeStatus) -> NodeInfo {
+    NodeInfo { status, ..node(id, &format!("{}:9090", id)) }
+}
+
+/// An active node listening at `{id}:9090` in `locality`, e.g. "zone=a,rack=a1".
+pub fn node_in(id: &str, locality: &str) -> NodeInfo {
+    NodeInfo { locality: Locality::parse(locality).unwrap(), ..node(id, &format!("{}:9090", id)) }
+}
+
+/// Partition `id`, holding the keys from `{id:03}` to `{id+1:03}`.
+pub fn partition(id: u64, leader: &str, followers: &[&str]) -> PartitionInfo {
+    PartitionInfo {
+        id,
+        range: KeyRange::new(format!("{:03}", id), format!("{:03}", id + 1)),
+        leader: NodeId::from(leader),
+        followers: followers.iter().map(|f| NodeId::from(*f)).collect(),
+        learners: vec![],
+    }
+}
+
+/// Cluster metadata with `nodes` and `partitions` and nothing else.
+pub fn metadata(nodes: Vec<NodeInfo>, partitions: Vec<PartitionInfo>) -> ClusterMetadata {
+    ClusterMetadata {
+        nodes: nodes.into_iter().map(|info| (info.id.clone(), info)).collect(),
+        partitions: partitions.into_iter().map(|partition| (partition.id, partition)).collect(),
+        ..ClusterMetadata::default()
+    }
+}
+
+/// Records the requests partition leaders get, and fails them on demand
+#[derive(Default)]
+pub struct RecordingTransport {
+    pub requests: Mutex<Vec<String>>,
+    pub unavailable: bool,
+    /// New learners catch up only once this is notified
+    pub catch_up: Option<Arc<Notify>>,
+}
+
+#[tonic::async_trait]
+impl NodeTransport for RecordingTransport {
+    async fn add_learner(&self, leader: &str, partition_id: u64, node: &NodeId, address: &str) -> Result<()> {
+        if self.unavailable {
+            return Err(DatabaseError::Unavailable(format!("{} is down", leader)));
+        }
+        self.requests.lock().unwrap().push(format!("{} add_learner {} {} {}", leader, partition_id, node, address));
+        if let Some(catch_up) = &self.catch_up {
+            catch_up.notified().await;
+        }
+        Ok(())
+    }
+
+    async fn change_membership(&self, leader: &str, target: &PartitionInfo) -> Result<()> {
+        if self.unavailable {
+            return Err(DatabaseError::Unavailable(format!("{} is down", leader)));
+        }
+        let voters: Vec<String> = target.voters().into_iter().map(|node| node.0).collect();
+        let learners: Vec<String> = target.learners.iter().map(|node| node.0.clone()).collect();
+        self.requests.lock().unwrap()
+            .push(format!("{} change_membership {} {:?} {:?}", leader, target.id, voters, learners));
+        Ok(())
+    }
+
+    async fn transfer_leader(&self, leader: &str, partition_id: u64, target: &NodeId) -> Result<()> {
+        if self.unavailable {
+            return Err(DatabaseError::Unavailable(format!("{} is down", leader)));
+        }
+        self.requests.lock().unwrap().push(format!("{} transfer_leader {} {}", leader, partition_id, target));
+        Ok(())
+    }
+
+    async fn propose(&self, _leader: &str, _partition_id: u64, _command: &Command) -> Result<Routed<CommandResponse>> {
+        Ok(Routed::Served(CommandResponse::applied(None)))
+    }
+
+    async fn read(
+        &self,
+        _replica: &str,
+        _key: &str,
+        _read_ts: HlcTimestamp,
+        _consistency: ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<Option<VersionedValue>>>> {
+        Ok(Routed::Served(ReadOutcome::Read(None)))
+    }
+
+    async fn scan(
+        &self,
+        _replica: &str,
+        _start_key: &str,
+        _end_key: &str,
+        _limit: usize,
+        _read_ts: HlcTimestamp,
+        _consistency: ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<(Vec<(String, Vec<u8>)>, bool)>>> {
+        Ok(Routed::Served(ReadOutcome::Read((Vec::new(), false))))
+    }
+
+    async fn acquire_locks(&self, leader: &str, _txn_id: &TransactionId, _start_ts: HlcTimestamp, keys: Vec<String>) -> Result<()> {
+        self.requests.lock().unwrap().push(format!("{} acquire_locks {:?}", leader, keys));
+        Ok(())
+    }
+
+    async fn release_locks(&self, leader: &str, _txn_id: &TransactionId) -> Result<()> {
+        self.requests.lock().unwrap().push(format!("{} release_locks", leader));
+        Ok(())
+    }
+}
+
+/// Raft transport of a node without peers
+pub struct NoPeers;
+
+#[tonic::async_trait]
+impl RaftTransport for NoPeers {
+    async fn send_heartbeats(&self, _: &NodeId, _: &str, _: Vec<GroupHeartbeat>) -> Result<Vec<GroupHeartbeatAck>> {
+        Ok(Vec::new())
+    }
+
+    async fn send_message(&self, _: &NodeId, _: &str, _: RaftMessage) -> Result<Option<RaftMessage>> {
+        Ok(None)
+    }
+}
+
+/// How a [`LocalTransport`] answers the proposals that commit transactions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Commits {
+    Answered,
+    /// The commit is applied, but the answer is lost on the way back
+    AppliedButLost,
+    /// The commit never reaches the node
+    Lost,
+}
+
+/// Serves node requests from a data node running in this process
+pub struct LocalTransport {
+    pub node: Arc<Node>,
+    pub commits: Commits,
+}
+
+fn misrouted(error: RouteError) -> DatabaseError {
+    DatabaseError::Unavailable(format!("Misrouted: {:?}", error))
+}
+
+#[tonic::async_trait]
+impl NodeTransport for LocalTransport {
+    async fn add_learner(&self, _leader: &str, partition_id: u64, node: &NodeId, address: &str) -> Result<()> {
+        self.node.add_learner(partition_id, node.clone(), address.to_string()).await.map(|_| ())
+    }
+
+    async fn change_membership(&self, _leader: &str, target: &PartitionInfo) -> Result<()> {
+        self.node.change_membership(target.id, target.voters(), target.learners.clone()).await
+    }
+
+    async fn transfer_leader(&self, _leader: &str, partition_id: u64, target: &NodeId) -> Result<()> {
+        self.node.transfer_leader(partition_id, target.clone()).await
+    }
+
+    async fn propose(&self, _leader: &str, partition_id: u64, command: &Command) -> Result<Routed<CommandResponse>> {
+        let lost = || Err(DatabaseError::Rpc("Connection reset".to_string()));
+        match (command, self.commits) {
+            (Command::EndTransaction { .. }, Commits::Lost) => lost(),
+            (Command::EndTransaction { .. }, Commits::AppliedButLost) => {
+                self.node.propose_to(partition_id, command.clone()).await?;
+                lost()
+            },
+            _ => self.node.propose_to(partition_id, command.clone()).await,
+        }
+    }
+
+    async fn read(
+        &self,
+        _replica: &str,
+        key: &str,
+        read_ts: HlcTimestamp,
+        consistency: ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<Option<VersionedValue>>>> {
+        self.node.read(key, Some(read_ts), consistency).await
+    }
+
+    async fn scan(
+        &self,
+        _replica: &str,
+        start_key: &str,
+        end_key: &str,
+        limit: usize,
+        read_ts: HlcTimestamp,
+        consistency: ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<(Vec<(String, Vec<u8>)>, bool)>>> {
+        self.node.scan(start_key, end_key, limit, Some(read_ts), consistency).await
+    }
+
+    async fn acquire_locks(&self, _leader: &str, txn_id: &TransactionId, start_ts: HlcTimestamp, keys: Vec<String>) -> Result<()> {
+        match self.node.acquire_locks(txn_id, start_ts, &keys).await? {
+            Routed::Served(()) => Ok(()),
+            Routed::Misrouted(error) => Err(misrouted(error)),
+        }
+    }
+
+    async fn release_locks(&self, _leader: &str, txn_id: &TransactionId) -> Result<()> {
+        self.node.release_locks(txn_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use ::common::config::CoordinatorConfig;
+use ::common::hlc::HlcTimestamp;
+use ::common::types::{IsolationLevel, TransactionId, WaitForEdge};
+use common::{coordinator_with_partitions, data_node, node, Commits, LocalTransport};
+use coordinator_lib::{find_deadlock_victims, transaction_get_for_update_unlocked, DeadlockDetector};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+fn txn(id: &str) -> TransactionId {
+    TransactionId(id.to_string())
+}
+
+fn waits(waiter: &str, start_ms: u64, holder: &str, key: &str) -> WaitForEdge {
+    WaitForEdge {
+        waiter: txn(waiter),
+        waiter_start_ts: HlcTimestamp::new(start_ms, 0),
+        holder: txn(holder),
+        key: key.to_string(),
+    }
+}
+
+#[test]
+fn test_no_deadlock() {
+    let edges = vec![waits("b", 2, "a", "x"), waits("c", 3, "a", "x"), waits("c", 3, "b", "y")];
+    assert!(find_deadlock_victims(&edges).is_empty());
+}
+
+#[test]
+fn test_cross_partition_deadlock_aborts_youngest() {
+    // Reported by two different nodes: a waits for b on one partition, b for a on another
+    let edges = vec![waits("a", 1, "b", "apple"), waits("b", 2, "a", "orange")];
+    assert_eq!(find_deadlock_victims(&edges), vec![txn("b")]);
+}
+
+#[test]
+fn test_longer_and_independent_cycles() {
+    let edges = vec![
+        waits("a", 5, "b", "k1"),
+        waits("b", 1, "c", "k2"),
+        waits("c", 3, "a", "k3"),
+        // Waits on the cycle without being part of it
+        waits("d", 9, "a", "k1"),
+        waits("e", 2, "f", "k4"),
+        waits("f", 4, "e", "k5"),
+    ];
+
+    let mut victims = find_deadlock_victims(&edges);
+    victims.sort_by(|x, y| x.0.cmp(&y.0));
+    assert_eq!(victims, vec![txn("a"), txn("f")]);
+}
+
+#[tokio::test]
+async fn test_detector_breaks_deadlocks_on_the_registered_nodes() {
+    let node_addr = "127.0.0.1:50063";
+    let mut coordinator = coordinator_with_partitions();
+    let partitions = [coordinator.partition(1).unwrap(), coordinator.partition(2).unwrap()];
+    let data_node = data_node(&coordinator, &partitions).await;
+    coordinator.register_node(node("node1", "127.0.0.1:50064"));
+    coordinator.set_transport(Arc::new(LocalTransport { node: data_node.clone(), commits: Commits::Answered }));
+    tokio::spawn(raft_node::start_grpc_server(node_addr, data_node));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    coordinator.put("apple".to_string(), b"red".to_vec(), None).await.unwrap();
+    coordinator.put("orange".to_string(), b"orange".to_vec(), None).await.unwrap();
+    let older = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    let younger = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    let coordinator = Arc::new(Mutex::new(coordinator));
+    let detector = DeadlockDetector::new(&CoordinatorConfig::default(), coordinator.clone());
+
+    // Each holds one key and waits for the other's
+    transaction_get_for_update_unlocked(&coordinator, &older, "apple".to_string()).await.unwrap();
+    transaction_get_for_update_unlocked(&coordinator, &younger, "orange".to_string()).await.unwrap();
+    let lock = |txn: TransactionId, key: &str| {
+        let (coordinator, key) = (coordinator.clone(), key.to_string());
+        tokio::spawn(async move { transaction_get_for_update_unlocked(&coordinator, &txn, key).await })
+    };
+    let older_wait = lock(older.clone(), "orange");
+    let younger_wait = lock(younger.clone(), "apple");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // The node is looked up in the metadata every round, so a new address is
+    // picked up
+    assert!(detector.detect_once().await.unwrap().is_empty(), "Nothing listens at the old address");
+    coordinator.lock().await.register_node(node("node1", node_addr));
+    assert_eq!(detector.detect_once().await.unwrap(), vec![younger]);
+
+    let aborted = tokio::time::timeout(Duration::from_secs(2), younger_wait).await.unwrap().unwrap();
+    assert!(aborted.is_err_and(|e| e.is_conflict()), "The victim is rolled back");
+    let locked = tokio::time::timeout(Duration::from_secs(2), older_wait).await.unwrap().unwrap();
+    assert!(locked.is_ok(), "Got {:?}", locked);
+}
@@ -0,0 +1,78 @@
+use common::types::{KeyRange, NodeId, NodeInfo, NodeLoad, NodeStatus, PartitionInfo};
+use coordinator_lib::Coordinator;
+
+fn cluster(nodes: &[&str]) -> Coordinator {
+    let mut coordinator = Coordinator::new();
+    for id in nodes {
+        coordinator.register_node(NodeInfo {
+            id: NodeId::from(*id),
+            address: format!("{}:9090", id),
+            status: NodeStatus::Active,
+            last_heartbeat: None,
+            load: NodeLoad::default(),
+            locality: Default::default(),
+        });
+    }
+    for id in 1..=3 {
+        coordinator.add_partition(PartitionInfo {
+            id,
+            range: KeyRange::new(format!("{:03}", id), format!("{:03}", id + 1)),
+            leader: NodeId::from("node1"),
+            followers: vec![NodeId::from("node2"), NodeId::from("node3")],
+            learners: vec![],
+        });
+    }
+    coordinator
+}
+
+#[tokio::test]
+async fn test_decommission_node() {
+    let mut coordinator = cluster(&["node1", "node2", "node3", "node4", "node5"]);
+    let node1 = NodeId::from("node1");
+
+    let progress = coordinator.decommission_node(&node1).unwrap();
+    assert_eq!((progress.replicas_total, progress.replicas_left, progress.done), (3, 3, false));
+    assert!(progress.eta.is_none(), "No pace to estimate from yet");
+
+    let progress = coordinator.decommission_step(&node1, 2).await.unwrap();
+    assert_eq!((progress.replicas_left, progress.done), (1, false));
+    assert!(progress.eta.is_some());
+    for id in 1..=3 {
+        assert_ne!(coordinator.partition(id).unwrap().leader, node1, "Every leadership moves off first");
+    }
+
+    let progress = coordinator.decommission_step(&node1, 2).await.unwrap();
+    assert!(progress.done);
+    for id in 1..=3 {
+        let partition = coordinator.partition(id).unwrap();
+        assert!(!partition.has_replica(&node1));
+        assert_eq!(partition.voters().len(), 3, "No partition is left short of replicas");
+    }
+    assert!(coordinator.decommission_progress(&node1).unwrap().done);
+    assert!(coordinator.decommission_node(&node1).is_err(), "Removed nodes stay removed");
+}
+
+#[tokio::test]
+async fn test_abort_decommission() {
+    let mut coordinator = cluster(&["node1", "node2", "node3", "node4"]);
+    let node3 = NodeId::from("node3");
+    coordinator.decommission_node(&node3).unwrap();
+    coordinator.decommission_step(&node3, 1).await.unwrap();
+
+    let progress = coordinator.abort_decommission(&node3).unwrap();
+    assert_eq!(progress.replicas_left, 2);
+    assert!(coordinator.decommission_progress(&node3).is_err(), "No longer decommissioning");
+    assert!(!coordinator.partition(1).unwrap().has_replica(&node3), "Moved replicas stay moved");
+    assert!(coordinator.partition(2).unwrap().has_replica(&node3));
+}
+
+#[tokio::test]
+async fn test_decommission_needs_somewhere_to_go() {
+    let mut coordinator = cluster(&["node1", "node2", "node3"]);
+    let node2 = NodeId::from("node2");
+    coordinator.decommission_node(&node2).unwrap();
+
+    assert!(coordinator.decommission_step(&node2, 1).await.is_err());
+    assert_eq!(coordinator.decommission_progress(&node2).unwrap().replicas_left, 3);
+    assert!(coordinator.decommission_node(&NodeId::from("node9")).is_err());
+}
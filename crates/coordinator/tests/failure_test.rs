@@ -0,0 +1,145 @@
+mod common;
+
+use ::common::config::CoordinatorConfig;
+use ::common::types::{ClusterMetadata, NodeId, NodeInfo, NodeLoad, NodeStatus};
+use chrono::{Duration, Utc};
+use common::partition;
+use coordinator_lib::{plan_repairs, Coordinator, Repair};
+use std::collections::HashSet;
+
+fn heartbeat_all(coordinator: &mut Coordinator, nodes: &[&str], now: chrono::DateTime<Utc>) {
+    for id in nodes {
+        coordinator.record_heartbeat(NodeId::from(*id), format!("{}:9090", id), Default::default(), NodeLoad::default(), now);
+    }
+}
+
+#[test]
+fn test_silent_nodes_become_suspect_then_dead() {
+    let config = CoordinatorConfig { node_suspect_timeout_ms: 1000, node_dead_timeout_ms: 5000, ..CoordinatorConfig::default() };
+    let mut coordinator = Coordinator::with_config(&config);
+    let start = Utc::now();
+    assert_eq!(
+        coordinator.record_heartbeat(NodeId::from("node1"), "node1:9090".to_string(), Default::default(), NodeLoad::default(), start),
+        NodeStatus::Active,
+        "Unknown nodes join with their first heartbeat"
+    );
+    heartbeat_all(&mut coordinator, &["node2"], start);
+
+    assert!(coordinator.detect_failures(start + Duration::milliseconds(500)).is_empty());
+
+    heartbeat_all(&mut coordinator, &["node2"], start + Duration::milliseconds(1500));
+    let changes = coordinator.detect_failures(start + Duration::milliseconds(2000));
+    assert_eq!(changes, vec![(NodeId::from("node1"), NodeStatus::Suspect)]);
+
+    let changes = coordinator.detect_failures(start + Duration::milliseconds(7000));
+    assert_eq!(changes, vec![(NodeId::from("node1"), NodeStatus::Dead), (NodeId::from("node2"), NodeStatus::Dead)]);
+
+    let status = coordinator.record_heartbeat(NodeId::from("node1"), "node1:9090".to_string(), Default::default(), NodeLoad::default(), start + Duration::milliseconds(8000));
+    assert_eq!(status, NodeStatus::Active, "Nodes that report again are active again");
+}
+
+#[test]
+fn test_silent_inactive_and_leaving_nodes_die() {
+    let config = CoordinatorConfig { node_suspect_timeout_ms: 1000, node_dead_timeout_ms: 5000, ..CoordinatorConfig::default() };
+    let mut coordinator = Coordinator::with_config(&config);
+    let start = Utc::now();
+    heartbeat_all(&mut coordinator, &["node1", "node2"], start);
+    coordinator.set_node_status(&NodeId::from("node1"), NodeStatus::Inactive).unwrap();
+    coordinator.decommission_node(&NodeId::from("node2")).unwrap();
+
+    assert!(coordinator.detect_failures(start + Duration::milliseconds(2000)).is_empty(), "Only active nodes turn suspect");
+    let changes = coordinator.detect_failures(start + Duration::milliseconds(7000));
+    assert_eq!(changes, vec![(NodeId::from("node1"), NodeStatus::Dead), (NodeId::from("node2"), NodeStatus::Dead)]);
+    assert!(coordinator.decommission_progress(&NodeId::from("node2")).is_err(), "Dead nodes are repaired, not decommissioned");
+
+    heartbeat_all(&mut coordinator, &["node1"], start + Duration::milliseconds(8000));
+    heartbeat_all(&mut coordinator, &["node1"], start + Duration::milliseconds(9000));
+    coordinator.set_node_status(&NodeId::from("node1"), NodeStatus::Inactive).unwrap();
+    let status = coordinator.record_heartbeat(NodeId::from("node1"), "node1:9090".to_string(), Default::default(), NodeLoad::default(), start + Duration::milliseconds(9500));
+    assert_eq!(status, NodeStatus::Inactive, "Heartbeats leave operator set statuses alone");
+}
+
+#[test]
+fn test_dead_nodes_can_be_decommissioned() {
+    let config = CoordinatorConfig { node_suspect_timeout_ms: 1000, node_dead_timeout_ms: 5000, ..CoordinatorConfig::default() };
+    let mut coordinator = Coordinator::with_config(&config);
+    let died = Utc::now() - Duration::milliseconds(10_000);
+    heartbeat_all(&mut coordinator, &["node1", "node2", "node3", "node4"], died);
+    heartbeat_all(&mut coordinator, &["node1", "node2"], Utc::now());
+    coordinator.add_partition(partition(1, "node1", &["node2", "node3"]));
+    let changes = coordinator.detect_failures(Utc::now());
+    assert_eq!(changes, vec![(NodeId::from("node3"), NodeStatus::Dead), (NodeId::from("node4"), NodeStatus::Dead)]);
+
+    // Without replicas a dead node goes at once
+    assert!(coordinator.decommission_node(&NodeId::from("node4")).unwrap().done);
+    assert_eq!(coordinator.node(&NodeId::from("node4")).unwrap().status, NodeStatus::Removed);
+
+    // With replicas it leaves, and stays leaving though it is still silent
+    assert!(!coordinator.decommission_node(&NodeId::from("node3")).unwrap().done);
+    assert!(coordinator.detect_failures(Utc::now() + Duration::milliseconds(1000)).is_empty());
+    assert_eq!(coordinator.node(&NodeId::from("node3")).unwrap().status, NodeStatus::Leaving);
+}
+
+#[test]
+fn test_plan_repairs() {
+    let node = |id: &str, status: NodeStatus| NodeInfo {
+        id: NodeId::from(id),
+        address: format!("{}:9090", id),
+        status,
+        last_heartbeat: None,
+        load: NodeLoad::default(),
+        locality: Default::default(),
+    };
+    let mut metadata = ClusterMetadata::default();
+    for info in [
+        node("node1", NodeStatus::Active),
+        node("node2", NodeStatus::Active),
+        node("node3", NodeStatus::Dead),
+        node("node4", NodeStatus::Active),
+        node("node5", NodeStatus::Suspect),
+    ] {
+        metadata.nodes.insert(info.id.clone(), info);
+    }
+    metadata.partitions.insert(1, partition(1, "node1", &["node2", "node3"]));
+    metadata.partitions.insert(2, partition(2, "node1", &["node2", "node5"]));
+    metadata.partitions.insert(3, partition(3, "node2", &[]));
+
+    let repairs = plan_repairs(&metadata, &HashSet::new(), 3);
+    assert_eq!(repairs, vec![
+        Repair { partition_id: 1, add: vec![NodeId::from("node4")], remove: vec![NodeId::from("node3")] },
+        Repair { partition_id: 3, add: vec![NodeId::from("node4"), NodeId::from("node1")], remove: vec![] },
+    ], "Suspect voters still count, new replicas go to the least loaded nodes");
+
+    let draining = HashSet::from([NodeId::from("node4")]);
+    let repairs = plan_repairs(&metadata, &draining, 3);
+    assert_eq!(repairs[0], Repair { partition_id: 1, add: vec![], remove: vec![NodeId::from("node3")] }, "No healthy node left to take the replica");
+}
+
+#[tokio::test]
+async fn test_re_replicate_dead_leader() {
+    let config = CoordinatorConfig { node_suspect_timeout_ms: 1000, node_dead_timeout_ms: 5000, ..CoordinatorConfig::default() };
+    let mut coordinator = Coordinator::with_config(&config);
+    let start = Utc::now();
+    heartbeat_all(&mut coordinator, &["node1", "node2", "node3", "node4"], start);
+    coordinator.add_partition(partition(1, "node1", &["node2", "node3"]));
+
+    heartbeat_all(&mut coordinator, &["node2", "node3", "node4"], start + Duration::milliseconds(5500));
+    coordinator.detect_failures(start + Duration::milliseconds(6000));
+    let repaired = coordinator.re_replicate().await.unwrap();
+    assert_eq!(repaired.len(), 1);
+
+    let partition = coordinator.partition(1).unwrap();
+    assert_ne!(partition.leader, NodeId::from("node1"));
+    let mut voters: Vec<String> = partition.voters().into_iter().map(|node| node.0).collect();
+    voters.sort();
+    assert_eq!(voters, vec!["node2", "node3", "node4"]);
+    assert!(coordinator.re_replicate().await.unwrap().is_empty(), "Nothing left to repair");
+}
+
+#[test]
+fn test_failure_timeouts_are_validated() {
+    assert!(CoordinatorConfig::default().validate().is_ok());
+    let config = CoordinatorConfig { node_suspect_timeout_ms: 10_000, node_dead_timeout_ms: 5000, ..CoordinatorConfig::default() };
+    assert!(config.validate().is_err());
+    assert!(CoordinatorConfig { replication_factor: 0, ..CoordinatorConfig::default() }.validate().is_err());
+}
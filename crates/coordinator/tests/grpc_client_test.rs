@@ -14,6 +14,7 @@ async fn test_execute_query() -> Result<(), Box<dyn std::error::Error>> {
     let request = tonic::Request::new(QueryRequest {
         query: "SELECT id, name FROM users WHERE id = :param1".to_string(),
         parameters,
+        ..Default::default()
     });
     
     // Send the request and get the response
@@ -32,17 +32,17 @@ async fn test_coordinator_integration() -> Result<(), Box<dyn std::error::Error>
     let request = tonic::Request::new(QueryRequest {
         query: "SELECT id, name FROM users WHERE id = :id".to_string(),
         parameters,
+        ..Default::default()
     });
     
-    // Send the request
-    let response = client.execute_query(request).await?;
-    let response = response.into_inner();
+    // Send the request; a failed query comes back as an error status
+    let response = client.execute_query(request).await;
+    assert!(response.is_ok(), "Expected a successful response: {:?}", response.as_ref().err());
+    let response = response.unwrap().into_inner();
     
+    // This will be an empty result since our handlers are stubs
     println!("Response from server: {:?}", response);
     
-    // Verify the response (this will be an empty result since our handlers are stubs)
-    assert!(response.success, "Expected a successful response");
-    
     // Cleanup
     drop(client);
     server_handle.abort();
@@ -0,0 +1,154 @@
+mod common;
+
+use ::common::config::CoordinatorConfig;
+use ::common::types::{ClusterMetadata, KeyRange, Locality, NodeId, NodeLoad, PlacementConstraints};
+use chrono::Utc;
+use common::{metadata, node_in, partition};
+use coordinator_lib::{plan_leader_transfers, plan_repairs, plan_replica_moves, Coordinator, Repair};
+use std::collections::{HashMap, HashSet};
+
+fn zones(metadata: &ClusterMetadata, voters: &[NodeId]) -> HashSet<String> {
+    voters.iter().filter_map(|node| metadata.locality(node).get("zone").map(str::to_string)).collect()
+}
+
+#[test]
+fn test_repairs_spread_over_zones() {
+    let mut metadata = metadata(
+        vec![
+            node_in("node1", "zone=a,rack=a1"),
+            node_in("node2", "zone=a,rack=a2"),
+            node_in("node3", "zone=b,rack=b1"),
+            node_in("node4", "zone=c,rack=c1"),
+        ],
+        vec![partition(1, "node1", &["node3"]), partition(2, "node2", &["node4"]), partition(3, "node4", &[])],
+    );
+
+    // node2 holds fewer replicas than node4, but zone a already has one
+    let repairs = plan_repairs(&metadata, &HashSet::new(), 3);
+    assert_eq!(repairs[0], Repair { partition_id: 1, add: vec![NodeId::from("node4")], remove: vec![] });
+    assert_eq!(repairs[1], Repair { partition_id: 2, add: vec![NodeId::from("node3")], remove: vec![] });
+    assert_eq!(zones(&metadata, &repairs[2].add).len(), 2, "New replicas spread over zones too");
+
+    let mut constraints = PlacementConstraints::new(KeyRange::new("001", "002"));
+    constraints.num_replicas = Some(2);
+    metadata.constraints.push(constraints);
+    assert!(plan_repairs(&metadata, &HashSet::new(), 3).iter().all(|repair| repair.partition_id != 1), "Constraints set the replica count");
+}
+
+#[test]
+fn test_replicas_move_to_fix_constraints() {
+    let nodes = vec![
+        node_in("node1", "region=east,zone=a"),
+        node_in("node2", "region=east,zone=a"),
+        node_in("node3", "region=east,zone=b"),
+        node_in("node4", "region=east,zone=c"),
+        node_in("node5", "region=west,zone=d"),
+    ];
+    let mut metadata = metadata(nodes, vec![partition(1, "node1", &["node2", "node5"])]);
+    let mut constraints = PlacementConstraints::new(KeyRange::new("", "~"));
+    constraints.spread_over = Some("zone".to_string());
+    constraints.required = Locality::parse("region=east").unwrap();
+    metadata.constraints.push(constraints);
+
+    // Moves apply one per partition and plan, so plan until nothing is left
+    for _ in 0..3 {
+        let moves = plan_replica_moves(&metadata, &HashSet::new(), 10, 0.1);
+        let Some(planned) = moves.first() else { break };
+        let partition = metadata.partitions.get_mut(&planned.partition_id).unwrap();
+        let voters: Vec<NodeId> = partition.voters().into_iter()
+            .map(|voter| if voter == planned.from { planned.to.clone() } else { voter })
+            .collect();
+        partition.leader = voters[0].clone();
+        partition.followers = voters[1..].to_vec();
+    }
+
+    let voters = metadata.partitions[&1].voters();
+    assert_eq!(zones(&metadata, &voters).len(), 3, "One voter per zone, so a zone can fail without losing a quorum");
+    assert!(!voters.contains(&NodeId::from("node5")), "Replicas leave the region they are not allowed in");
+    assert!(plan_replica_moves(&metadata, &HashSet::new(), 10, 0.1).is_empty());
+}
+
+#[test]
+fn test_load_moves_keep_the_spread() {
+    // node3 holds every replica of zone b; moving one to the idle node4
+    // would balance replica counts but put two voters of a partition in zone a
+    let metadata = metadata(
+        vec![node_in("node1", "zone=a"), node_in("node2", "zone=c"), node_in("node3", "zone=b"), node_in("node4", "zone=a")],
+        (1..=4).map(|id| partition(id, "node1", &["node2", "node3"])).collect(),
+    );
+    let moves = plan_replica_moves(&metadata, &HashSet::new(), 10, 0.1);
+    assert!(moves.iter().all(|planned| planned.from == NodeId::from("node1")), "Only zone a's replicas may move to node4");
+    assert!(!moves.is_empty());
+}
+
+#[test]
+fn test_leaders_stay_in_preferred_locality() {
+    let mut metadata = metadata(
+        vec![node_in("node1", "zone=a"), node_in("node2", "zone=a"), node_in("node3", "zone=b")],
+        (1..=4).map(|id| partition(id, "node3", &["node1", "node2"])).collect(),
+    );
+    let mut constraints = PlacementConstraints::new(KeyRange::new("", "~"));
+    constraints.leader_locality = Locality::parse("zone=a").unwrap();
+    metadata.constraints.push(constraints);
+
+    let transfers = plan_leader_transfers(&metadata, &HashSet::new());
+    assert_eq!(transfers.len(), 4);
+    let leaders: Vec<&str> = transfers.iter().map(|(_, leader)| leader.0.as_str()).collect();
+    assert_eq!(leaders.iter().filter(|leader| **leader == "node1").count(), 2, "Leaders spread over zone a");
+    assert_eq!(leaders.iter().filter(|leader| **leader == "node2").count(), 2);
+
+    // With no eligible voter in zone a, the leader may stay elsewhere
+    let draining = HashSet::from([NodeId::from("node1"), NodeId::from("node2")]);
+    assert!(plan_leader_transfers(&metadata, &draining).is_empty());
+}
+
+#[tokio::test]
+async fn test_heartbeats_carry_locality() {
+    let config = CoordinatorConfig { replication_factor: 2, ..CoordinatorConfig::default() };
+    let mut coordinator = Coordinator::with_config(&config);
+    for (id, locality) in [("node1", "zone=a"), ("node2", "zone=a"), ("node3", "zone=b")] {
+        let locality = Locality::parse(locality).unwrap();
+        coordinator.record_heartbeat(NodeId::from(id), format!("{}:9090", id), locality, NodeLoad::default(), Utc::now());
+    }
+    coordinator.add_partition(partition(1, "node1", &[]));
+
+    coordinator.re_replicate().await.unwrap();
+    assert_eq!(coordinator.partition(1).unwrap().voters(), vec![NodeId::from("node1"), NodeId::from("node3")]);
+}
+
+#[test]
+fn test_set_placement_constraints() {
+    let mut coordinator = Coordinator::new();
+    let mut constraints = PlacementConstraints::new(KeyRange::new("a", "m"));
+    coordinator.set_placement_constraints(constraints.clone()).unwrap();
+    constraints.spread_over = Some("zone".to_string());
+    coordinator.set_placement_constraints(constraints.clone()).unwrap();
+    assert_eq!(coordinator.placement_constraints(), vec![constraints], "Setting a range again replaces its constraints");
+
+    assert!(coordinator.set_placement_constraints(PlacementConstraints::new(KeyRange::new("m", "a"))).is_err());
+    let mut constraints = PlacementConstraints::new(KeyRange::new("a", "b"));
+    constraints.num_replicas = Some(0);
+    assert!(coordinator.set_placement_constraints(constraints).is_err());
+}
+
+#[tokio::test]
+async fn test_table_placement_constraints() {
+    let mut coordinator = common::coordinator_with_partitions();
+    assert!(coordinator.set_placement_constraints(PlacementConstraints::for_table("users")).is_err(), "No such table");
+    coordinator.execute_query("CREATE TABLE users (id INT PRIMARY KEY, name TEXT)".to_string(), HashMap::new()).await.unwrap();
+    let mut constraints = PlacementConstraints::for_table("users");
+    constraints.spread_over = Some("zone".to_string());
+    coordinator.set_placement_constraints(constraints.clone()).unwrap();
+    assert_eq!(coordinator.placement_constraints(), vec![constraints]);
+
+    // Partition 2, "m" to "z", splits where the table's rows begin and end
+    let splits = coordinator.split_partitions(&[]).await.unwrap();
+    assert_eq!(splits.len(), 1);
+    assert_eq!(splits[0].1.range, KeyRange::new("users/", "z"));
+    let splits = coordinator.split_partitions(&[]).await.unwrap();
+    assert_eq!(splits[0].0.range, KeyRange::new("users/", "users0"));
+    assert!(coordinator.split_partitions(&[]).await.unwrap().is_empty());
+
+    let (users, after) = (&splits[0].0, &splits[0].1);
+    assert!(coordinator.merge_partition(users.id, after.id).await.is_err(), "The table's rows stay apart");
+}
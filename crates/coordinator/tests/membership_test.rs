@@ -0,0 +1,108 @@
+mod common;
+
+use ::common::types::{KeyRange, NodeId, PartitionInfo};
+use common::{node, RecordingTransport};
+use coordinator_lib::Coordinator;
+use std::sync::Arc;
+
+fn coordinator_with_partition() -> Coordinator {
+    let mut coordinator = Coordinator::new();
+    coordinator.add_partition(PartitionInfo {
+        id: 1,
+        range: KeyRange::new("a", "z"),
+        leader: NodeId::from("node1"),
+        followers: vec![NodeId::from("node2")],
+        learners: vec![],
+    });
+    coordinator
+}
+
+#[tokio::test]
+async fn test_membership_changes_go_through_the_leader() {
+    let mut coordinator = coordinator_with_partition();
+    coordinator.register_node(node("node1", "127.0.0.1:9091"));
+    let transport = Arc::new(RecordingTransport::default());
+    coordinator.set_transport(transport.clone());
+
+    coordinator.register_node(node("node3", "127.0.0.1:9093"));
+    coordinator.add_voter(1, NodeId::from("node3"), "127.0.0.1:9093".to_string()).await.unwrap();
+    coordinator.remove_replica(1, NodeId::from("node2")).await.unwrap();
+    coordinator.remove_replica(1, NodeId::from("node1")).await.unwrap();
+    assert_eq!(*transport.requests.lock().unwrap(), vec![
+        "127.0.0.1:9091 add_learner 1 node3 127.0.0.1:9093".to_string(),
+        r#"127.0.0.1:9091 change_membership 1 ["node1", "node2", "node3"] []"#.to_string(),
+        r#"127.0.0.1:9091 change_membership 1 ["node1", "node3"] []"#.to_string(),
+        "127.0.0.1:9091 transfer_leader 1 node3".to_string(),
+        r#"127.0.0.1:9093 change_membership 1 ["node3"] []"#.to_string(),
+    ]);
+}
+
+#[tokio::test]
+async fn test_failed_membership_change_leaves_metadata_alone() {
+    let mut coordinator = coordinator_with_partition();
+    coordinator.register_node(node("node1", "127.0.0.1:9091"));
+    coordinator.set_transport(Arc::new(RecordingTransport { unavailable: true, ..Default::default() }));
+    let version = coordinator.metadata_version();
+
+    assert!(coordinator.add_learner(1, NodeId::from("node3"), "127.0.0.1:9093".to_string()).await.is_err());
+    assert!(coordinator.remove_replica(1, NodeId::from("node2")).await.is_err());
+    let partition = coordinator.partition(1).unwrap();
+    assert!(partition.learners.is_empty());
+    assert_eq!(partition.voters(), vec![NodeId::from("node1"), NodeId::from("node2")]);
+    assert_eq!(coordinator.metadata_version(), version);
+}
+
+#[tokio::test]
+async fn test_learner_is_promoted_to_voter() {
+    let mut coordinator = coordinator_with_partition();
+    let node = NodeId::from("node3");
+
+    let partition = coordinator.add_learner(1, node.clone(), "127.0.0.1:9093".to_string()).await.unwrap();
+    assert_eq!(partition.learners, vec![node.clone()]);
+    assert_eq!(partition.voters().len(), 2, "Learners do not vote");
+
+    let result = coordinator.add_learner(1, node.clone(), "127.0.0.1:9093".to_string()).await;
+    assert!(result.is_err(), "A node holds one replica per partition");
+
+    let partition = coordinator.promote_learner(1, node.clone()).await.unwrap();
+    assert!(partition.learners.is_empty());
+    assert!(partition.voters().contains(&node));
+
+    assert!(coordinator.promote_learner(1, node).await.is_err(), "Only learners are promoted");
+}
+
+#[tokio::test]
+async fn test_add_voter() {
+    let mut coordinator = coordinator_with_partition();
+
+    let partition = coordinator.add_voter(1, NodeId::from("node3"), "127.0.0.1:9093".to_string()).await.unwrap();
+    assert_eq!(partition.voters(), vec![NodeId::from("node1"), NodeId::from("node2"), NodeId::from("node3")]);
+    assert_eq!(coordinator.partition(1).unwrap().voters().len(), 3);
+
+    assert!(coordinator.add_voter(2, NodeId::from("node3"), "127.0.0.1:9093".to_string()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_remove_replica() {
+    let mut coordinator = coordinator_with_partition();
+    coordinator.add_learner(1, NodeId::from("node3"), "127.0.0.1:9093".to_string()).await.unwrap();
+
+    let partition = coordinator.remove_replica(1, NodeId::from("node3")).await.unwrap();
+    assert!(partition.learners.is_empty());
+
+    let partition = coordinator.remove_replica(1, NodeId::from("node2")).await.unwrap();
+    assert_eq!(partition.voters(), vec![NodeId::from("node1")]);
+
+    assert!(coordinator.remove_replica(1, NodeId::from("node2")).await.is_err(), "Already removed");
+    assert!(coordinator.remove_replica(1, NodeId::from("node1")).await.is_err(), "The last voter stays");
+}
+
+#[tokio::test]
+async fn test_remove_leader_transfers_leadership_first() {
+    let mut coordinator = coordinator_with_partition();
+    coordinator.register_node(node("node2", "127.0.0.1:9092"));
+
+    let partition = coordinator.remove_replica(1, NodeId::from("node1")).await.unwrap();
+    assert_eq!(partition.leader, NodeId::from("node2"));
+    assert_eq!(partition.voters(), vec![NodeId::from("node2")]);
+}
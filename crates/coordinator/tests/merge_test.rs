@@ -0,0 +1,113 @@
+mod common;
+
+use ::common::config::CoordinatorConfig;
+use ::common::error::DatabaseError;
+use ::common::types::{ClusterMetadata, KeyRange, NodeId, NodeInfo, NodeStatus, PartitionInfo, PartitionStats};
+use common::{node, RecordingTransport};
+use coordinator_lib::{plan_merges, run_change_unlocked, Coordinator, PartitionChange};
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+
+fn partition(id: u64, start: &str, end: &str, leader: &str, followers: &[&str]) -> PartitionInfo {
+    PartitionInfo {
+        id,
+        range: KeyRange::new(start, end),
+        leader: NodeId::from(leader),
+        followers: followers.iter().map(|f| NodeId::from(*f)).collect(),
+        learners: vec![],
+    }
+}
+
+fn stats(partition_id: u64, size_bytes: u64, qps: f64) -> PartitionStats {
+    PartitionStats { partition_id, size_bytes, key_count: 10, qps, split_key: None }
+}
+
+#[test]
+fn test_plan_merges() {
+    let mut metadata = ClusterMetadata::default();
+    for (id, start, end) in [(1, "a", "f"), (2, "f", "m"), (3, "m", "s"), (4, "t", "z")] {
+        metadata.partitions.insert(id, partition(id, start, end, "node1", &[]));
+    }
+    let all = [stats(1, 100, 1.0), stats(2, 100, 1.0), stats(3, 100, 1.0), stats(4, 100, 1.0)];
+
+    assert_eq!(plan_merges(&metadata, &all, 1000, 10.0), vec![(1, 2)], "Each partition merges once per round, 3 and 4 are not adjacent");
+    assert!(plan_merges(&metadata, &all, 150, 10.0).is_empty(), "Too large together");
+    assert!(plan_merges(&metadata, &all, 1000, 1.5).is_empty(), "Too busy together");
+    assert_eq!(plan_merges(&metadata, &all[1..], 1000, 10.0), vec![(2, 3)], "Partitions without stats are left alone");
+}
+
+#[tokio::test]
+async fn test_merge_partition_colocates_replicas() {
+    let config = CoordinatorConfig { merge_size_bytes: 1000, merge_qps: 10.0, ..CoordinatorConfig::default() };
+    let mut coordinator = Coordinator::with_config(&config);
+    for id in ["node1", "node2", "node3", "node4"] {
+        coordinator.register_node(NodeInfo {
+            id: NodeId::from(id),
+            address: format!("{}:9090", id),
+            status: NodeStatus::Active,
+            last_heartbeat: None,
+            load: Default::default(),
+            locality: Default::default(),
+        });
+    }
+    coordinator.add_partition(partition(1, "a", "m", "node1", &["node2", "node3"]));
+    coordinator.add_partition(partition(2, "m", "z", "node4", &["node3", "node2"]));
+    coordinator.add_partition(partition(3, "0", "1", "node1", &[]));
+    coordinator.set_transport(Arc::new(RecordingTransport::default()));
+
+    assert!(coordinator.merge_partition(3, 1).await.is_err(), "Not adjacent");
+
+    let merges = coordinator.merge_partitions(&[stats(1, 100, 1.0), stats(2, 100, 1.0)]).await.unwrap();
+    assert_eq!(merges.len(), 1);
+    let merged = coordinator.partition(1).unwrap();
+    assert_eq!(merged.range, KeyRange::new("a", "z"));
+    assert_eq!(merged.voters(), vec![NodeId::from("node1"), NodeId::from("node2"), NodeId::from("node3")]);
+    assert!(coordinator.partition(2).is_err(), "The right partition is gone");
+    let result = coordinator.get("p".to_string(), None, Default::default()).await;
+    assert!(matches!(result, Err(DatabaseError::KeyNotFound { .. })), "Read from the merged partition: {:?}", result);
+}
+
+#[test]
+fn test_merge_thresholds_are_validated() {
+    assert!(CoordinatorConfig::default().validate().is_ok());
+    let config = CoordinatorConfig { merge_size_bytes: 64 * 1024 * 1024, ..CoordinatorConfig::default() };
+    assert!(config.validate().is_err(), "Merged partitions would split again");
+    let config = CoordinatorConfig { merge_qps: 5000.0, ..CoordinatorConfig::default() };
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_merge_runs_without_holding_the_coordinator() {
+    let mut coordinator = Coordinator::new();
+    for id in ["node1", "node2", "node3"] {
+        coordinator.register_node(node(id, &format!("{}:9090", id)));
+    }
+    coordinator.add_partition(partition(1, "a", "m", "node1", &[]));
+    coordinator.add_partition(partition(2, "m", "z", "node2", &[]));
+    let catch_up = Arc::new(Notify::new());
+    let transport = Arc::new(RecordingTransport { catch_up: Some(catch_up.clone()), ..RecordingTransport::default() });
+    coordinator.set_transport(transport.clone());
+    let coordinator = Arc::new(Mutex::new(coordinator));
+
+    let merge = tokio::spawn({
+        let coordinator = coordinator.clone();
+        async move { run_change_unlocked(&coordinator, &PartitionChange::Merge { left_id: 1, right_id: 2 }).await }
+    });
+    while transport.requests.lock().unwrap().is_empty() {
+        tokio::task::yield_now().await;
+    }
+
+    // node1 is catching up on partition 2, and the coordinator is free meanwhile
+    {
+        let mut coordinator = coordinator.lock().await;
+        assert_eq!(coordinator.partition(2).unwrap().voters(), vec![NodeId::from("node2")]);
+        let result = coordinator.add_learner(2, NodeId::from("node3"), "node3:9090".to_string()).await;
+        assert!(matches!(result, Err(DatabaseError::Partition { partition: Some(2), .. })), "Got {:?}", result);
+    }
+
+    catch_up.notify_one();
+    let merged = merge.await.unwrap().unwrap();
+    assert_eq!(merged[0].range, KeyRange::new("a", "z"));
+    assert_eq!(merged[0].voters(), vec![NodeId::from("node1")]);
+    assert!(coordinator.lock().await.partition(2).is_err());
+}
@@ -0,0 +1,229 @@
+mod common;
+
+use ::common::config::CoordinatorConfig;
+use ::common::error::DatabaseError;
+use ::common::types::{KeyRange, NodeId, NodeLoad, NodeStatus, PlacementConstraints};
+use ::common::util::timestamp_ms;
+use chrono::Utc;
+use common::{node_with_status, partition, RecordingTransport};
+use coordinator_lib::{run_change_unlocked, Coordinator, MemoryMetaStore, MetaStore, PartitionChange, ReplicaMove};
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+
+fn coordinator(listen_addr: &str, store: &Arc<MemoryMetaStore>) -> Coordinator {
+    let config = CoordinatorConfig { listen_addr: listen_addr.to_string(), ..CoordinatorConfig::default() };
+    let mut coordinator = Coordinator::with_config(&config);
+    let store: Arc<dyn MetaStore> = store.clone();
+    coordinator.set_meta_store(&config, store);
+    coordinator
+}
+
+#[tokio::test]
+async fn test_any_coordinator_serves_the_stored_metadata() {
+    let store = Arc::new(MemoryMetaStore::default());
+    let mut first = coordinator("coordinator1:50051", &store);
+    for id in ["node1", "node2", "node3"] {
+        first.register_node(node_with_status(id, NodeStatus::Active));
+    }
+    first.add_partition(partition(1, "node1", &["node2", "node3"]));
+    first.sync_metadata().await.unwrap();
+
+    let mut second = coordinator("coordinator2:50051", &store);
+    assert!(second.partition(1).is_err());
+    second.sync_metadata().await.unwrap();
+    assert_eq!(second.partition(1).unwrap().voters(), partition(1, "node1", &["node2", "node3"]).voters());
+
+    // A restarted coordinator starts from the stored metadata too
+    let mut restarted = coordinator("coordinator1:50051", &store);
+    restarted.sync_metadata().await.unwrap();
+    assert_eq!(restarted.partition(1).unwrap().voters(), partition(1, "node1", &["node2", "node3"]).voters());
+}
+
+#[tokio::test]
+async fn test_drained_nodes_stay_drained_on_other_coordinators() {
+    let store = Arc::new(MemoryMetaStore::default());
+    let mut first = coordinator("coordinator1:50051", &store);
+    for id in ["node1", "node2", "node3"] {
+        first.register_node(node_with_status(id, NodeStatus::Active));
+    }
+    first.add_partition(partition(1, "node1", &["node2", "node3"]));
+    first.drain_node(NodeId::from("node1")).await.unwrap();
+    first.sync_metadata().await.unwrap();
+
+    let mut second = coordinator("coordinator2:50051", &store);
+    second.sync_metadata().await.unwrap();
+    assert!(second.is_draining(&NodeId::from("node1")));
+    assert!(second.balance_leaders().await.unwrap().is_empty(), "The drained node gets no leaders back");
+
+    assert!(second.undrain_node(&NodeId::from("node1")));
+    second.sync_metadata().await.unwrap();
+    first.sync_metadata().await.unwrap();
+    assert!(!first.is_draining(&NodeId::from("node1")));
+}
+
+#[tokio::test]
+async fn test_stale_coordinators_cannot_overwrite_changes() {
+    let store = Arc::new(MemoryMetaStore::default());
+    let mut first = coordinator("coordinator1:50051", &store);
+    let mut second = coordinator("coordinator2:50051", &store);
+    first.add_partition(partition(1, "node1", &["node2", "node3"]));
+    first.sync_metadata().await.unwrap();
+    second.sync_metadata().await.unwrap();
+
+    first.set_placement_constraints(PlacementConstraints::new(KeyRange::new("a", "b"))).unwrap();
+    first.sync_metadata().await.unwrap();
+    second.set_placement_constraints(PlacementConstraints::new(KeyRange::new("c", "d"))).unwrap();
+    let result = second.sync_metadata().await;
+    assert!(result.is_err_and(|e| e.is_retryable()), "The save of a stale copy fails and may be retried");
+
+    assert_eq!(second.placement_constraints(), first.placement_constraints(), "The stale copy was replaced");
+    second.set_placement_constraints(PlacementConstraints::new(KeyRange::new("c", "d"))).unwrap();
+    second.sync_metadata().await.unwrap();
+    first.sync_metadata().await.unwrap();
+    assert_eq!(first.placement_constraints().len(), 2);
+}
+
+#[tokio::test]
+async fn test_heartbeats_reach_every_coordinator() {
+    let store = Arc::new(MemoryMetaStore::default());
+    let mut first = coordinator("coordinator1:50051", &store);
+    let mut second = coordinator("coordinator2:50051", &store);
+    let now = Utc::now();
+    first.record_heartbeat(NodeId::from("node1"), "node1:9090".to_string(), Default::default(), NodeLoad::default(), now);
+    first.sync_metadata().await.unwrap();
+    second.sync_metadata().await.unwrap();
+
+    // The heartbeat second heard is newer than the stored one and survives
+    // loading first's later changes
+    let later = now + chrono::Duration::seconds(1);
+    second.record_heartbeat(NodeId::from("node1"), "node1:9090".to_string(), Default::default(), NodeLoad::default(), later);
+    first.add_partition(partition(1, "node1", &["node2", "node3"]));
+    first.sync_metadata().await.unwrap();
+    second.sync_metadata().await.unwrap();
+    assert!(second.partition(1).is_ok(), "Saving heartbeats loads the changes saved first");
+    assert!(second.detect_failures(later + chrono::Duration::milliseconds(100)).is_empty());
+
+    first.sync_metadata().await.unwrap();
+    assert!(first.detect_failures(later + chrono::Duration::milliseconds(100)).is_empty(), "first sees second's heartbeat");
+}
+
+#[tokio::test]
+async fn test_one_coordinator_holds_the_lease() {
+    let store = Arc::new(MemoryMetaStore::default());
+    let mut first = coordinator("coordinator1:50051", &store);
+    let mut second = coordinator("coordinator2:50051", &store);
+    assert!(Coordinator::new().holds_lease(), "A coordinator keeping the metadata to itself runs everything");
+    assert!(!first.holds_lease());
+
+    let now = timestamp_ms();
+    assert!(first.renew_lease(now).await.unwrap());
+    assert!(first.holds_lease());
+    assert!(!second.renew_lease(now).await.unwrap());
+    assert!(!second.holds_lease());
+    assert!(first.renew_lease(now + 1000).await.unwrap(), "The holder renews its lease");
+
+    // first stopped renewing; its lease runs out 5 seconds after the renewal
+    let expired = now + 1000 + CoordinatorConfig::default().coordinator_lease_ms;
+    assert!(second.renew_lease(expired).await.unwrap());
+    assert!(!first.renew_lease(expired).await.unwrap());
+
+    let config = CoordinatorConfig { coordinator_lease_ms: 1000, ..CoordinatorConfig::default() };
+    assert!(config.validate().is_err(), "The lease must outlive the interval it is renewed at");
+}
+
+#[tokio::test]
+async fn test_decommissioning_continues_on_another_coordinator() {
+    let store = Arc::new(MemoryMetaStore::default());
+    let mut first = coordinator("coordinator1:50051", &store);
+    for id in ["node1", "node2", "node3", "node4"] {
+        first.register_node(node_with_status(id, NodeStatus::Active));
+    }
+    first.add_partition(partition(1, "node1", &["node2", "node3"]));
+    first.decommission_node(&NodeId::from("node3")).unwrap();
+    first.sync_metadata().await.unwrap();
+
+    let mut second = coordinator("coordinator2:50051", &store);
+    second.sync_metadata().await.unwrap();
+    let progress = second.decommission_step(&NodeId::from("node3"), 1).await.unwrap();
+    assert!(progress.done);
+    assert!(second.partition(1).unwrap().has_replica(&NodeId::from("node4")));
+}
+
+#[tokio::test]
+async fn test_partition_changes_are_saved_as_they_are_made() {
+    let store = Arc::new(MemoryMetaStore::default());
+    let mut first = coordinator("coordinator1:50051", &store);
+    for id in ["node1", "node2", "node3"] {
+        first.register_node(node_with_status(id, NodeStatus::Active));
+    }
+    first.add_partition(partition(1, "node1", &["node2", "node3"]));
+    first.sync_metadata().await.unwrap();
+
+    let (_, right) = first.split_partition(1, "001m".to_string()).await.unwrap();
+    let mut second = coordinator("coordinator2:50051", &store);
+    second.sync_metadata().await.unwrap();
+    assert_eq!(second.partition(1).unwrap().range, KeyRange::new("001", "001m"), "Saved without waiting for a sync");
+    assert_eq!(second.partition(right.id).unwrap().range, KeyRange::new("001m", "002"));
+}
+
+#[tokio::test]
+async fn test_stale_coordinators_fail_before_changing_partitions() {
+    let store = Arc::new(MemoryMetaStore::default());
+    let mut first = coordinator("coordinator1:50051", &store);
+    let mut second = coordinator("coordinator2:50051", &store);
+    for id in ["node1", "node2", "node3"] {
+        first.register_node(node_with_status(id, NodeStatus::Active));
+    }
+    first.add_partition(partition(1, "node1", &["node2", "node3"]));
+    first.sync_metadata().await.unwrap();
+    second.sync_metadata().await.unwrap();
+    let transport = Arc::new(RecordingTransport::default());
+    second.set_transport(transport.clone());
+
+    // Draining is saved before leaders are moved, and second's copy is stale
+    first.set_placement_constraints(PlacementConstraints::new(KeyRange::new("a", "b"))).unwrap();
+    first.sync_metadata().await.unwrap();
+    let result = second.drain_node(NodeId::from("node1")).await;
+    assert!(matches!(result, Err(DatabaseError::Conflict { .. })), "Got {:?}", result);
+    assert!(transport.requests.lock().unwrap().is_empty(), "No leader was asked to act on the stale copy");
+    assert!(!second.is_draining(&NodeId::from("node1")));
+    assert_eq!(second.placement_constraints(), first.placement_constraints());
+}
+
+#[tokio::test]
+async fn test_steps_done_are_saved_over_later_changes() {
+    let store = Arc::new(MemoryMetaStore::default());
+    let mut first = coordinator("coordinator1:50051", &store);
+    for id in ["node1", "node2", "node3", "node4"] {
+        first.register_node(node_with_status(id, NodeStatus::Active));
+    }
+    first.add_partition(partition(1, "node1", &["node2", "node3"]));
+    first.sync_metadata().await.unwrap();
+    let catch_up = Arc::new(Notify::new());
+    let transport = Arc::new(RecordingTransport { catch_up: Some(catch_up.clone()), ..RecordingTransport::default() });
+    first.set_transport(transport.clone());
+    let first = Arc::new(Mutex::new(first));
+
+    let planned = ReplicaMove { partition_id: 1, from: NodeId::from("node3"), to: NodeId::from("node4") };
+    let change = tokio::spawn({
+        let first = first.clone();
+        async move { run_change_unlocked(&first, &PartitionChange::MoveReplica(planned)).await }
+    });
+    while transport.requests.lock().unwrap().is_empty() {
+        tokio::task::yield_now().await;
+    }
+
+    // Another coordinator saves while the learner catches up
+    let mut second = coordinator("coordinator2:50051", &store);
+    second.sync_metadata().await.unwrap();
+    second.set_placement_constraints(PlacementConstraints::new(KeyRange::new("a", "b"))).unwrap();
+    second.sync_metadata().await.unwrap();
+    catch_up.notify_one();
+    let moved = change.await.unwrap().unwrap();
+    assert!(moved[0].voters().contains(&NodeId::from("node4")));
+
+    second.sync_metadata().await.unwrap();
+    let partition = second.partition(1).unwrap();
+    assert!(partition.voters().contains(&NodeId::from("node4")) && !partition.has_replica(&NodeId::from("node3")));
+    assert_eq!(second.placement_constraints().len(), 1, "The move did not undo the constraints");
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use ::common::types::{NodeId, NodeLoad, NodeStatus};
+use chrono::Utc;
+use common::{node_with_status, partition};
+use coordinator_lib::{Coordinator, MemoryMetaStore, MetaStore};
+use std::sync::Arc;
+
+#[test]
+fn test_list_and_describe_nodes() {
+    let mut coordinator = Coordinator::new();
+    for id in ["node3", "node1", "node2"] {
+        coordinator.register_node(node_with_status(id, NodeStatus::Active));
+    }
+    coordinator.add_partition(partition(2, "node1", &["node2"]));
+    coordinator.add_partition(partition(1, "node2", &["node1"]));
+    coordinator.add_partition(partition(3, "node2", &["node3"]));
+
+    let ids: Vec<String> = coordinator.nodes().into_iter().map(|info| info.id.0).collect();
+    assert_eq!(ids, vec!["node1", "node2", "node3"]);
+
+    let node1 = NodeId::from("node1");
+    assert_eq!(coordinator.node(&node1).unwrap().address, "node1:9090");
+    let held: Vec<u64> = coordinator.node_partitions(&node1).iter().map(|partition| partition.id).collect();
+    assert_eq!(held, vec![1, 2]);
+    assert!(coordinator.node(&NodeId::from("node9")).is_err());
+}
+
+#[tokio::test]
+async fn test_status_changes_follow_the_state_machine() {
+    let mut coordinator = Coordinator::new();
+    let node1 = NodeId::from("node1");
+    coordinator.register_node(node_with_status("node1", NodeStatus::Joining));
+
+    assert!(coordinator.set_node_status(&node1, NodeStatus::Leaving).is_err(), "Joining nodes must become active first");
+    let status = coordinator.record_heartbeat(node1.clone(), "node1:9090".to_string(), Default::default(), NodeLoad::default(), Utc::now());
+    assert_eq!(status, NodeStatus::Active, "The first heartbeat activates a joining node");
+
+    assert_eq!(coordinator.set_node_status(&node1, NodeStatus::Inactive).unwrap(), NodeStatus::Active);
+    let status = coordinator.record_heartbeat(node1.clone(), "node1:9090".to_string(), Default::default(), NodeLoad::default(), Utc::now());
+    assert_eq!(status, NodeStatus::Inactive, "Heartbeats do not bring inactive nodes back into service");
+
+    // Decommissioning an inactive node goes through the same transitions
+    coordinator.decommission_node(&node1).unwrap();
+    assert_eq!(coordinator.node(&node1).unwrap().status, NodeStatus::Leaving);
+    assert!(coordinator.decommission_step(&node1, 1).await.unwrap().done);
+    assert_eq!(coordinator.node(&node1).unwrap().status, NodeStatus::Removed);
+    assert!(coordinator.set_node_status(&node1, NodeStatus::Active).is_err());
+    assert!(coordinator.set_node_status(&NodeId::from("node9"), NodeStatus::Active).is_err());
+}
+
+#[tokio::test]
+async fn test_status_is_shared_between_coordinators() {
+    let store = Arc::new(MemoryMetaStore::default());
+    let config = ::common::config::CoordinatorConfig::default();
+    let mut first = Coordinator::with_config(&config);
+    let mut second = Coordinator::with_config(&config);
+    for coordinator in [&mut first, &mut second] {
+        let store: Arc<dyn MetaStore> = store.clone();
+        coordinator.set_meta_store(&config, store);
+    }
+
+    first.register_node(node_with_status("node1", NodeStatus::Active));
+    first.set_node_status(&NodeId::from("node1"), NodeStatus::Inactive).unwrap();
+    first.sync_metadata().await.unwrap();
+
+    second.sync_metadata().await.unwrap();
+    assert_eq!(second.node(&NodeId::from("node1")).unwrap().status, NodeStatus::Inactive);
+}
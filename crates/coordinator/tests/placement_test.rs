@@ -0,0 +1,114 @@
+mod common;
+
+use ::common::types::{ClusterMetadata, NodeId, NodeStatus};
+use common::{node_with_status, partition};
+use coordinator_lib::{plan_leader_transfers, Coordinator};
+use std::collections::{HashMap, HashSet};
+
+fn leader_counts(metadata: &ClusterMetadata, transfers: &[(u64, NodeId)]) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for partition in metadata.partitions.values() {
+        let leader = transfers.iter()
+            .find(|(id, _)| *id == partition.id)
+            .map_or(&partition.leader, |(_, leader)| leader);
+        *counts.entry(leader.0.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn three_node_cluster() -> Coordinator {
+    let mut coordinator = Coordinator::new();
+    for id in ["node1", "node2", "node3"] {
+        coordinator.register_node(node_with_status(id, NodeStatus::Active));
+    }
+    for id in 1..=6 {
+        coordinator.add_partition(partition(id, "node1", &["node2", "node3"]));
+    }
+    coordinator
+}
+
+#[test]
+fn test_plan_spreads_leaders_evenly() {
+    let mut metadata = ClusterMetadata::default();
+    for id in ["node1", "node2", "node3"] {
+        metadata.nodes.insert(NodeId::from(id), node_with_status(id, NodeStatus::Active));
+    }
+    for id in 1..=6 {
+        metadata.partitions.insert(id, partition(id, "node1", &["node2", "node3"]));
+    }
+
+    let transfers = plan_leader_transfers(&metadata, &HashSet::new());
+    assert_eq!(transfers.len(), 4);
+    let counts = leader_counts(&metadata, &transfers);
+    assert_eq!((counts["node1"], counts["node2"], counts["node3"]), (2, 2, 2));
+
+    // Applying the plan leaves nothing to do
+    for (id, leader) in &transfers {
+        let partition = metadata.partitions.get_mut(id).unwrap();
+        partition.followers = partition.voters().into_iter().filter(|voter| voter != leader).collect();
+        partition.leader = leader.clone();
+    }
+    assert!(plan_leader_transfers(&metadata, &HashSet::new()).is_empty());
+}
+
+#[test]
+fn test_plan_skips_ineligible_nodes() {
+    let mut metadata = ClusterMetadata::default();
+    metadata.nodes.insert(NodeId::from("node1"), node_with_status("node1", NodeStatus::Active));
+    metadata.nodes.insert(NodeId::from("node2"), node_with_status("node2", NodeStatus::Active));
+    metadata.nodes.insert(NodeId::from("node3"), node_with_status("node3", NodeStatus::Inactive));
+    for id in 1..=4 {
+        metadata.partitions.insert(id, partition(id, "node3", &["node1", "node2"]));
+    }
+
+    let transfers = plan_leader_transfers(&metadata, &HashSet::new());
+    let counts = leader_counts(&metadata, &transfers);
+    assert_eq!((counts["node1"], counts["node2"]), (2, 2));
+    assert!(!counts.contains_key("node3"));
+
+    let draining = HashSet::from([NodeId::from("node2")]);
+    let counts = leader_counts(&metadata, &plan_leader_transfers(&metadata, &draining));
+    assert_eq!(counts["node1"], 4, "Draining nodes get no leaders");
+}
+
+#[tokio::test]
+async fn test_transfer_leader() {
+    let mut coordinator = three_node_cluster();
+
+    let moved = coordinator.transfer_leader(1, Some(NodeId::from("node3"))).await.unwrap();
+    assert_eq!(moved.leader, NodeId::from("node3"));
+    assert_eq!(moved.voters().len(), 3);
+
+    assert!(coordinator.transfer_leader(1, Some(NodeId::from("node4"))).await.is_err(), "Only voters lead");
+
+    // The least loaded voter is picked by default
+    let moved = coordinator.transfer_leader(2, None).await.unwrap();
+    assert_eq!(moved.leader, NodeId::from("node2"));
+}
+
+#[tokio::test]
+async fn test_drain_node() {
+    let mut coordinator = three_node_cluster();
+
+    let moved = coordinator.drain_node(NodeId::from("node1")).await.unwrap();
+    assert_eq!(moved.len(), 6);
+    assert!(moved.iter().all(|partition| partition.leader != NodeId::from("node1")));
+    assert!(coordinator.is_draining(&NodeId::from("node1")));
+
+    let moved = coordinator.balance_leaders().await.unwrap();
+    assert!(moved.iter().all(|partition| partition.leader != NodeId::from("node1")), "Drained nodes stay drained");
+
+    assert!(coordinator.undrain_node(&NodeId::from("node1")));
+    let moved = coordinator.balance_leaders().await.unwrap();
+    assert_eq!(moved.len(), 2);
+    assert!(moved.iter().all(|partition| partition.leader == NodeId::from("node1")));
+}
+
+#[tokio::test]
+async fn test_drain_fails_without_other_voters() {
+    let mut coordinator = Coordinator::new();
+    coordinator.register_node(node_with_status("node1", NodeStatus::Active));
+    coordinator.add_partition(partition(1, "node1", &[]));
+
+    assert!(coordinator.drain_node(NodeId::from("node1")).await.is_err());
+}
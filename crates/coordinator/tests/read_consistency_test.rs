@@ -0,0 +1,55 @@
+mod common;
+
+use ::common::config::{LinearizableReadMode, NodeConfig};
+use ::common::types::{KeyRange, NodeId, PartitionInfo, ReadConsistency};
+use common::{data_node, node, Commits, LocalTransport};
+use coordinator_lib::Coordinator;
+use std::sync::Arc;
+
+// Every replica's reads are served by the one data node in this process
+async fn coordinator_with_followers() -> Coordinator {
+    let mut coordinator = Coordinator::new();
+    let partition = PartitionInfo {
+        id: 1,
+        range: KeyRange::new("a", "z"),
+        leader: NodeId::from("node1"),
+        followers: vec![NodeId::from("node2"), NodeId::from("node3")],
+        learners: vec![],
+    };
+    coordinator.add_partition(partition.clone());
+    for id in ["node1", "node2", "node3"] {
+        coordinator.register_node(node(id, &format!("{}:9090", id)));
+    }
+    let data_node = data_node(&coordinator, &[partition]).await;
+    coordinator.set_transport(Arc::new(LocalTransport { node: data_node, commits: Commits::Answered }));
+    coordinator
+}
+
+#[tokio::test]
+async fn test_reads_at_each_consistency() {
+    let mut coordinator = coordinator_with_followers().await;
+    let stale = ReadConsistency::Stale { max_staleness_ms: 5_000 };
+    coordinator.put("apple".to_string(), b"red".to_vec(), None).await.unwrap();
+
+    assert!(coordinator.get("apple".to_string(), None, ReadConsistency::Linearizable).await.is_ok());
+    assert!(coordinator.get("apple".to_string(), None, stale).await.is_ok());
+    assert!(coordinator.scan("a".to_string(), "m".to_string(), 10, None, None, stale).await.is_ok());
+
+    let result = coordinator.get("zebra".to_string(), None, stale).await;
+    assert!(result.is_err(), "No partition owns the key");
+}
+
+#[test]
+fn test_lease_must_end_before_elections() {
+    let mut config = NodeConfig {
+        linearizable_reads: LinearizableReadMode::Lease,
+        ..NodeConfig::default()
+    };
+    assert!(config.validate().is_ok());
+
+    config.leader_lease_ms = config.election_timeout_min_ms;
+    assert!(config.validate().is_err());
+
+    config.linearizable_reads = LinearizableReadMode::ReadIndex;
+    assert!(config.validate().is_ok(), "Without leases the lease length does not matter");
+}
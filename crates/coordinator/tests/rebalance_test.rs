@@ -0,0 +1,140 @@
+mod common;
+
+use ::common::config::CoordinatorConfig;
+use ::common::types::{ClusterMetadata, NodeId, NodeInfo, NodeLoad, NodeStatus};
+use common::{metadata, node_with_status, partition, RecordingTransport};
+use coordinator_lib::{plan_replica_moves, Coordinator, Rebalancer, ReplicaMove};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+
+fn node(id: &str, status: NodeStatus, disk_used_bytes: u64, qps: f64) -> NodeInfo {
+    NodeInfo { load: NodeLoad { disk_used_bytes, disk_capacity_bytes: 1000, qps }, ..node_with_status(id, status) }
+}
+
+fn replica_counts(metadata: &ClusterMetadata, moves: &[ReplicaMove]) -> HashMap<String, i64> {
+    let mut counts = HashMap::new();
+    for partition in metadata.partitions.values() {
+        for voter in partition.voters() {
+            *counts.entry(voter.0).or_insert(0) += 1;
+        }
+    }
+    for planned in moves {
+        *counts.entry(planned.from.0.clone()).or_insert(0) -= 1;
+        *counts.entry(planned.to.0.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[test]
+fn test_replicas_spread_to_new_node() {
+    let metadata = metadata(
+        vec![
+            node("node1", NodeStatus::Active, 0, 0.0),
+            node("node2", NodeStatus::Active, 0, 0.0),
+            node("node3", NodeStatus::Active, 0, 0.0),
+        ],
+        (1..=6).map(|id| partition(id, "node1", &["node2"])).collect(),
+    );
+
+    let moves = plan_replica_moves(&metadata, &HashSet::new(), 10, 0.1);
+    let counts = replica_counts(&metadata, &moves);
+    assert_eq!((counts["node1"], counts["node2"], counts["node3"]), (4, 4, 4));
+    let partitions: HashSet<u64> = moves.iter().map(|planned| planned.partition_id).collect();
+    assert_eq!(partitions.len(), moves.len(), "One move per partition");
+
+    assert_eq!(plan_replica_moves(&metadata, &HashSet::new(), 1, 0.1).len(), 1, "Moves are rate limited");
+    let draining = HashSet::from([NodeId::from("node3")]);
+    assert!(plan_replica_moves(&metadata, &draining, 10, 0.1).is_empty(), "Drained nodes take no replicas");
+}
+
+#[test]
+fn test_replicas_move_off_full_disks() {
+    // Replica counts are even, but node1's disk is nearly full
+    let metadata = metadata(
+        vec![
+            node("node1", NodeStatus::Active, 900, 10.0),
+            node("node2", NodeStatus::Active, 100, 10.0),
+            node("node3", NodeStatus::Active, 100, 10.0),
+            node("node4", NodeStatus::Active, 100, 10.0),
+        ],
+        vec![partition(1, "node1", &["node2"]), partition(2, "node3", &["node4"]), partition(3, "node1", &["node3"])],
+    );
+
+    let moves = plan_replica_moves(&metadata, &HashSet::new(), 1, 0.1);
+    assert_eq!(moves.len(), 1);
+    assert_eq!(moves[0].from, NodeId::from("node1"));
+    assert_eq!(moves[0].to, NodeId::from("node4"));
+
+    let inactive = metadata.nodes.values()
+        .map(|info| if info.id.0 == "node4" { NodeInfo { status: NodeStatus::Inactive, ..info.clone() } } else { info.clone() })
+        .collect();
+    let metadata = self::metadata(inactive, metadata.partitions.into_values().collect());
+    assert!(plan_replica_moves(&metadata, &HashSet::new(), 1, 0.1).iter().all(|planned| planned.to.0 != "node4"));
+}
+
+#[test]
+fn test_balanced_cluster_stays_put() {
+    let metadata = metadata(
+        vec![node("node1", NodeStatus::Active, 100, 10.0), node("node2", NodeStatus::Active, 100, 10.0)],
+        vec![partition(1, "node1", &["node2"]), partition(2, "node2", &["node1"])],
+    );
+    assert!(plan_replica_moves(&metadata, &HashSet::new(), 10, 0.1).is_empty());
+}
+
+#[tokio::test]
+async fn test_move_replica() {
+    let mut coordinator = Coordinator::new();
+    for id in ["node1", "node2", "node3"] {
+        coordinator.register_node(node(id, NodeStatus::Active, 0, 0.0));
+    }
+    coordinator.add_partition(partition(1, "node1", &["node2"]));
+    assert!(coordinator.record_node_load(&NodeId::from("node1"), NodeLoad { disk_used_bytes: 500, disk_capacity_bytes: 1000, qps: 1.0 }));
+    assert!(!coordinator.record_node_load(&NodeId::from("node9"), NodeLoad::default()));
+
+    let planned = ReplicaMove { partition_id: 1, from: NodeId::from("node1"), to: NodeId::from("node3") };
+    let partition = coordinator.move_replica(&planned).await.unwrap();
+    assert_eq!(partition.leader, NodeId::from("node2"), "Leadership leaves the old replica first");
+    assert_eq!(partition.voters(), vec![NodeId::from("node2"), NodeId::from("node3")]);
+
+    assert!(coordinator.move_replica(&planned).await.is_err(), "node1 no longer holds a replica");
+}
+
+#[tokio::test]
+async fn test_rebalancing_runs_without_holding_the_coordinator() {
+    let mut coordinator = Coordinator::new();
+    for id in ["node1", "node2", "node3"] {
+        coordinator.register_node(node(id, NodeStatus::Active, 0, 0.0));
+    }
+    coordinator.add_partition(partition(1, "node1", &["node2"]));
+    coordinator.add_partition(partition(2, "node1", &["node2"]));
+    let catch_up = Arc::new(Notify::new());
+    let transport = Arc::new(RecordingTransport { catch_up: Some(catch_up.clone()), ..RecordingTransport::default() });
+    coordinator.set_transport(transport.clone());
+    let clock = coordinator.clock();
+    let coordinator = Arc::new(Mutex::new(coordinator));
+
+    let rebalancer = Rebalancer::new(&CoordinatorConfig::default(), coordinator.clone(), clock);
+    let rebalance = tokio::spawn(async move { rebalancer.check_once().await });
+    while transport.requests.lock().unwrap().is_empty() {
+        tokio::task::yield_now().await;
+    }
+
+    // node3 is catching up, and the coordinator is free meanwhile
+    let partition_id = {
+        let coordinator = coordinator.lock().await;
+        (1..=2).find(|id| coordinator.partition(*id).unwrap().has_replica(&NodeId::from("node3")))
+    };
+    assert_eq!(partition_id, None, "Learners are recorded once caught up");
+
+    while !rebalance.is_finished() {
+        catch_up.notify_one();
+        tokio::task::yield_now().await;
+    }
+    let moved = rebalance.await.unwrap().unwrap();
+    assert!(!moved.is_empty());
+    let coordinator = coordinator.lock().await;
+    for planned in &moved {
+        assert!(coordinator.partition(planned.partition_id).unwrap().voters().contains(&planned.to));
+    }
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use ::common::error::DatabaseError;
+use ::common::types::{
+    BatchOperation, Command, KeyRange, NodeId, NodeInfo, NodeLoad, NodeStatus, PartitionInfo, PartitionRoute,
+    TransactionId, TransactionMeta,
+};
+use common::{coordinator_with_partitions, data_node, node, Commits, LocalTransport};
+use coordinator_lib::Coordinator;
+use rpc::routing::{RouteError, RoutingCache, RoutingClient};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+fn route(id: u64, start: &str, end: &str, leader: &str) -> PartitionRoute {
+    PartitionRoute {
+        partition_id: id,
+        range: KeyRange::new(start, end),
+        leader: NodeId::from(leader),
+        leader_address: format!("{}:9090", leader),
+    }
+}
+
+#[test]
+fn test_lookup_finds_the_owning_range() {
+    let mut cache = RoutingCache::new();
+    cache.update(1, vec![route(1, "a", "m", "node1"), route(2, "m", "z", "node2")]);
+    assert_eq!(cache.lookup("a").unwrap().partition_id, 1);
+    assert_eq!(cache.lookup("lzz").unwrap().partition_id, 1);
+    assert_eq!(cache.lookup("m").unwrap().partition_id, 2);
+    assert!(cache.lookup("z").is_none());
+    assert!(cache.lookup("0").is_none());
+}
+
+#[test]
+fn test_only_the_misrouted_range_is_refreshed() {
+    let mut cache = RoutingCache::new();
+    cache.update(1, vec![route(1, "a", "m", "node1"), route(2, "m", "z", "node2")]);
+
+    // A leader change the node names is fixed in place
+    let error = RouteError::NotLeader { leader: Some((NodeId::from("node3"), "node3:9090".to_string())) };
+    assert!(!cache.misrouted("b", &error));
+    assert_eq!(cache.lookup("b").unwrap().leader_address, "node3:9090");
+
+    // After a split, the old route goes and the new ones replace it
+    assert!(cache.misrouted("b", &RouteError::KeyOutOfRange));
+    assert!(cache.lookup("b").is_none());
+    assert_eq!(cache.lookup("n").unwrap().partition_id, 2, "Other ranges keep their routes");
+    cache.update(2, vec![route(3, "a", "f", "node1")]);
+    assert_eq!(cache.lookup("b").unwrap().partition_id, 3);
+    assert_eq!(cache.version(), 2);
+}
+
+#[test]
+fn test_stale_version_refreshes_the_range() {
+    // A node that has seen a newer map says so, unless it can name the leader
+    let leader = Some((NodeId::from("node2"), "node2:9090".to_string()));
+    assert_eq!(RouteError::KeyOutOfRange.at_version(3, 5), RouteError::StaleVersion { metadata_version: 5 });
+    assert_eq!(RouteError::NotLeader { leader: None }.at_version(3, 5), RouteError::StaleVersion { metadata_version: 5 });
+    assert_eq!(RouteError::NotLeader { leader: leader.clone() }.at_version(3, 5), RouteError::NotLeader { leader });
+    assert_eq!(RouteError::KeyOutOfRange.at_version(5, 5), RouteError::KeyOutOfRange, "Routed by the newest map");
+    assert_eq!(RouteError::KeyOutOfRange.at_version(0, 5), RouteError::KeyOutOfRange, "Routed by no map");
+
+    let mut cache = RoutingCache::new();
+    cache.update(3, vec![route(1, "a", "m", "node1"), route(2, "m", "z", "node2")]);
+    assert_eq!(cache.lookup_versioned("b").map(|(version, route)| (version, route.partition_id)), Some((3, 1)));
+    assert!(cache.misrouted("b", &RouteError::StaleVersion { metadata_version: 5 }));
+    assert!(cache.lookup("b").is_none());
+    assert_eq!(cache.lookup("n").unwrap().partition_id, 2, "Other ranges keep their routes");
+}
+
+#[test]
+fn test_older_routes_do_not_replace_newer_ones() {
+    let mut cache = RoutingCache::new();
+    cache.update(5, vec![route(3, "a", "f", "node1"), route(4, "f", "m", "node2")]);
+    cache.update(4, vec![route(1, "a", "m", "node1")]);
+    assert_eq!(cache.lookup("g").unwrap().partition_id, 4, "A lookup that raced a split cannot undo it");
+
+    cache.update(6, vec![route(5, "a", "m", "node2")]);
+    assert_eq!(cache.len(), 1, "A merged range replaces both halves");
+}
+
+#[test]
+fn test_partition_map() {
+    let mut coordinator = Coordinator::new();
+    coordinator.register_node(NodeInfo {
+        id: NodeId::from("node1"),
+        address: "node1:9090".to_string(),
+        status: NodeStatus::Active,
+        last_heartbeat: None,
+        load: NodeLoad::default(),
+        locality: Default::default(),
+    });
+    for (id, start, end, leader) in [(2, "m", "z", "node2"), (1, "a", "m", "node1")] {
+        coordinator.add_partition(PartitionInfo {
+            id,
+            range: KeyRange::new(start, end),
+            leader: NodeId::from(leader),
+            followers: vec![],
+            learners: vec![],
+        });
+    }
+
+    let (version, routes) = coordinator.partition_map(None).unwrap();
+    assert_eq!(routes, vec![route(1, "a", "m", "node1"), PartitionRoute { leader_address: String::new(), ..route(2, "m", "z", "node2") }]);
+    let (_, routes) = coordinator.partition_map(Some("q")).unwrap();
+    assert_eq!(routes.len(), 1);
+    assert_eq!(routes[0].partition_id, 2);
+    assert!(coordinator.partition_map(Some("zz")).is_err());
+
+    coordinator.add_partition(PartitionInfo {
+        id: 3,
+        range: KeyRange::new("z", "zz"),
+        leader: NodeId::from("node1"),
+        followers: vec![],
+        learners: vec![],
+    });
+    assert!(coordinator.partition_map(None).unwrap().0 > version, "Changes publish a newer version");
+}
+
+#[tokio::test]
+async fn test_routed_requests_conflict_with_open_transactions() {
+    let (node_addr, coordinator_addr) = ("127.0.0.1:50061", "127.0.0.1:50062");
+    let mut coordinator = coordinator_with_partitions();
+    let partitions = [coordinator.partition(1).unwrap(), coordinator.partition(2).unwrap()];
+    let data_node = data_node(&coordinator, &partitions).await;
+    coordinator.register_node(node("node1", node_addr));
+    coordinator.set_transport(Arc::new(LocalTransport { node: data_node.clone(), commits: Commits::Answered }));
+    let clock = coordinator.clock();
+    tokio::spawn(raft_node::start_grpc_server(node_addr, data_node.clone()));
+    tokio::spawn(coordinator_lib::start_grpc_server(coordinator_addr, Arc::new(Mutex::new(coordinator))));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // A transaction that has laid down its intent on "apple" and not committed
+    let txn = TransactionMeta { id: TransactionId::generate(), primary_key: "apple".to_string(), start_ts: clock.now() };
+    let operations = vec![BatchOperation::Put { key: "apple".to_string(), value: b"1".to_vec() }];
+    data_node.propose_to(1, Command::Prewrite { txn, operations, txn_keys: vec!["apple".to_string()] }).await.unwrap();
+
+    let mut client = RoutingClient::connect(coordinator_addr, clock).await.unwrap();
+    let results = [
+        client.put("apple".to_string(), b"2".to_vec(), 0).await,
+        client.delete("apple".to_string()).await,
+        client.get("apple".to_string()).await.map(|_| ()),
+    ];
+    for result in results {
+        assert!(matches!(result, Err(DatabaseError::Conflict { key: Some(ref key), .. }) if key == "apple"), "Got {:?}", result);
+    }
+
+    client.put("banana".to_string(), b"2".to_vec(), 0).await.unwrap();
+    assert_eq!(client.get("banana".to_string()).await.unwrap().unwrap().0, b"2".to_vec());
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use ::common::hlc::HlcTimestamp;
+use ::common::types::{ReadConsistency, ScanToken};
+use common::coordinator_with_node;
+
+#[tokio::test]
+async fn test_scan_last_page_has_no_token() {
+    let mut coordinator = coordinator_with_node().await;
+
+    let page = coordinator.scan("a".to_string(), "z".to_string(), 10, None, None, ReadConsistency::Linearizable).await.unwrap();
+    assert!(!page.has_more);
+    assert!(page.continuation_token.is_none());
+}
+
+#[tokio::test]
+async fn test_scan_rejects_invalid_token() {
+    let mut coordinator = coordinator_with_node().await;
+
+    let result = coordinator.scan("a".to_string(), "z".to_string(), 10, Some("garbage".to_string()), None, ReadConsistency::Linearizable).await;
+    assert!(result.is_err(), "Malformed token should be rejected");
+
+    // A token whose last key lies outside the requested range
+    let foreign = ScanToken::new("zz", HlcTimestamp::new(1, 0)).encode();
+    let result = coordinator.scan("a".to_string(), "z".to_string(), 10, Some(foreign), None, ReadConsistency::Linearizable).await;
+    assert!(result.is_err(), "Token from another range should be rejected");
+
+    let resumed = ScanToken::new("m", coordinator.clock().now()).encode();
+    let result = coordinator.scan("a".to_string(), "z".to_string(), 10, Some(resumed), None, ReadConsistency::Linearizable).await;
+    assert!(result.is_ok(), "Token inside the range should resume the scan");
+}
+
+#[tokio::test]
+async fn test_scan_through_the_last_partition() {
+    let mut coordinator = coordinator_with_node().await;
+    coordinator.put("apple".to_string(), b"1".to_vec(), None).await.unwrap();
+    coordinator.put("orange".to_string(), b"2".to_vec(), None).await.unwrap();
+
+    for end_key in ["", "zz"] {
+        let page = coordinator.scan("a".to_string(), end_key.to_string(), 10, None, None, ReadConsistency::Linearizable).await.unwrap();
+        let keys: Vec<&str> = page.items.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, vec!["apple", "orange"], "Scan to {:?}", end_key);
+        assert!(!page.has_more);
+    }
+}
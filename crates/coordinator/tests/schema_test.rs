@@ -0,0 +1,47 @@
+mod common;
+
+use ::common::error::DatabaseError;
+use common::coordinator_with_node;
+use coordinator_lib::Coordinator;
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn test_create_table_rejects_duplicate_columns() {
+    let mut coordinator = Coordinator::new();
+
+    let result = coordinator.execute_query("CREATE TABLE users (id INT PRIMARY KEY, id TEXT)".to_string(), HashMap::new()).await;
+    assert!(matches!(result, Err(DatabaseError::Schema(_))), "{:?}", result);
+
+    let result = coordinator.execute_query("CREATE TABLE users (id INT PRIMARY KEY, name TEXT)".to_string(), HashMap::new()).await;
+    assert!(result.is_ok(), "{:?}", result);
+}
+
+#[tokio::test]
+async fn test_rows_must_fit_the_schema() {
+    let mut coordinator = coordinator_with_node().await;
+    let query = |sql: &str| sql.to_string();
+
+    let result = coordinator.execute_query(query("INSERT INTO users (id, name) VALUES (1, 'Alice')"), HashMap::new()).await;
+    assert!(matches!(result, Err(DatabaseError::Schema(_))), "No such table: {:?}", result);
+    let result = coordinator.execute_query(query("CREATE TABLE users (id INT, name TEXT)"), HashMap::new()).await;
+    assert!(matches!(result, Err(DatabaseError::Schema(_))), "No primary key: {:?}", result);
+
+    let create = query("CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL)");
+    coordinator.execute_query(create.clone(), HashMap::new()).await.unwrap();
+    let result = coordinator.execute_query(create, HashMap::new()).await;
+    assert!(matches!(result, Err(DatabaseError::Schema(_))), "Exists already: {:?}", result);
+
+    for invalid in [
+        "INSERT INTO users (id) VALUES (1)",
+        "INSERT INTO users (id, name) VALUES ('one', 'Alice')",
+        "INSERT INTO users (id, name, age) VALUES (1, 'Alice', 30)",
+        "INSERT INTO users (id, name, name) VALUES (1, 'Alice', 'Bob')",
+        "UPDATE users SET id = 2 WHERE id = 1",
+    ] {
+        let result = coordinator.execute_query(query(invalid), HashMap::new()).await;
+        assert!(matches!(result, Err(DatabaseError::Schema(_))), "{}: {:?}", invalid, result);
+    }
+
+    let rows = coordinator.execute_query(query("INSERT INTO users (id, name) VALUES (1, 'Alice')"), HashMap::new()).await.unwrap();
+    assert_eq!(rows[0]["rows_affected"], "1");
+}
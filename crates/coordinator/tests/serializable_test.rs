@@ -0,0 +1,69 @@
+mod common;
+
+use ::common::error::{DatabaseError, Result};
+use ::common::hlc::HlcTimestamp;
+use ::common::types::{IsolationLevel, TransactionId};
+use common::coordinator_with_node;
+use coordinator_lib::Coordinator;
+use std::collections::HashMap;
+
+async fn sql(coordinator: &mut Coordinator, query: &str, txn: Option<&TransactionId>) -> Result<Vec<HashMap<String, String>>> {
+    coordinator.execute_query_in_transaction(query.to_string(), HashMap::new(), txn.cloned()).await
+}
+
+// Two doctors on call; each of two concurrent transactions checks that
+// someone else is on call and takes itself off. Returns the commit
+// outcomes and how many doctors are on call afterwards.
+async fn take_both_off_call(isolation: IsolationLevel) -> (Result<HlcTimestamp>, Result<HlcTimestamp>, usize) {
+    let mut coordinator = coordinator_with_node().await;
+    sql(&mut coordinator, "CREATE TABLE doctors (name TEXT PRIMARY KEY, on_call INT NOT NULL)", None).await.unwrap();
+    sql(&mut coordinator, "INSERT INTO doctors (name, on_call) VALUES ('alice', 1)", None).await.unwrap();
+    sql(&mut coordinator, "INSERT INTO doctors (name, on_call) VALUES ('bob', 1)", None).await.unwrap();
+
+    let alice = coordinator.begin_transaction(isolation);
+    let bob = coordinator.begin_transaction(isolation);
+    for (txn, name) in [(&alice, "alice"), (&bob, "bob")] {
+        let on_call = sql(&mut coordinator, "SELECT name FROM doctors WHERE on_call = 1", Some(txn)).await.unwrap();
+        assert_eq!(on_call.len(), 2);
+        let update = format!("UPDATE doctors SET on_call = 0 WHERE name = '{}'", name);
+        sql(&mut coordinator, &update, Some(txn)).await.unwrap();
+    }
+    let first = coordinator.commit_transaction(&alice).await;
+    let second = coordinator.commit_transaction(&bob).await;
+
+    let on_call = sql(&mut coordinator, "SELECT name FROM doctors WHERE on_call = 1", None).await.unwrap();
+    (first, second, on_call.len())
+}
+
+#[tokio::test]
+async fn test_snapshot_isolation_allows_write_skew() {
+    let (first, second, on_call) = take_both_off_call(IsolationLevel::Snapshot).await;
+    assert!(first.is_ok() && second.is_ok(), "The transactions write different rows");
+    assert_eq!(on_call, 0);
+}
+
+#[tokio::test]
+async fn test_serializable_prevents_write_skew() {
+    let (first, second, on_call) = take_both_off_call(IsolationLevel::Serializable).await;
+    assert!(first.is_ok());
+    assert!(matches!(second, Err(DatabaseError::Conflict { .. })), "Got {:?}", second);
+    assert_eq!(on_call, 1);
+}
+
+#[tokio::test]
+async fn test_serializable_scan_sees_phantoms() {
+    let mut coordinator = coordinator_with_node().await;
+    sql(&mut coordinator, "CREATE TABLE doctors (name TEXT PRIMARY KEY, on_call INT NOT NULL)", None).await.unwrap();
+    sql(&mut coordinator, "INSERT INTO doctors (name, on_call) VALUES ('alice', 1)", None).await.unwrap();
+
+    // Counts the doctors on call, then records the count elsewhere
+    let txn = coordinator.begin_transaction(IsolationLevel::Serializable);
+    let on_call = sql(&mut coordinator, "SELECT name FROM doctors WHERE on_call = 1", Some(&txn)).await.unwrap();
+    coordinator.transaction_put(&txn, "summary".to_string(), on_call.len().to_string().into_bytes()).unwrap();
+
+    // A row inserted into the scanned range after the transaction started
+    sql(&mut coordinator, "INSERT INTO doctors (name, on_call) VALUES ('carol', 1)", None).await.unwrap();
+
+    let result = coordinator.commit_transaction(&txn).await;
+    assert!(matches!(result, Err(DatabaseError::Conflict { key: Some(ref key), .. }) if key == "doctors/carol"), "Got {:?}", result);
+}
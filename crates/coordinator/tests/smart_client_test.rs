@@ -0,0 +1,50 @@
+use common::error::DatabaseError;
+use rpc::smart_client::{RetryPolicy, SmartClient};
+use std::time::Duration;
+
+#[test]
+fn test_backoff_grows_and_is_capped() {
+    let policy = RetryPolicy {
+        max_attempts: 10,
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_millis(1000),
+        multiplier: 2.0,
+        jitter: 0.5,
+    };
+    for (retry, max_ms) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (9, 1000)] {
+        let backoff = policy.backoff(retry);
+        assert!(backoff <= Duration::from_millis(max_ms), "retry {} waited {:?}", retry, backoff);
+        assert!(backoff >= Duration::from_millis(max_ms / 2), "retry {} waited {:?}", retry, backoff);
+    }
+}
+
+#[test]
+fn test_only_retryable_errors_are_retried() {
+    assert!(DatabaseError::Unavailable("connection refused".to_string()).is_retryable());
+    assert!(DatabaseError::Timeout("deadline exceeded".to_string()).is_retryable());
+    assert!(DatabaseError::ResourceExhausted("too many requests".to_string()).is_retryable());
+    assert!(DatabaseError::NotLeader { partition: Some(1), leader: None }.is_retryable());
+    assert!(DatabaseError::Conflict { key: None, message: "write conflict".to_string() }.is_retryable());
+    assert!(DatabaseError::Rpc("connection reset".to_string()).is_retryable());
+    assert!(DatabaseError::Raft { partition: Some(1), node: None, message: "proposal dropped".to_string() }.is_retryable());
+    assert!(!DatabaseError::Storage { key: None, message: "corrupt block".to_string() }.is_retryable());
+    assert!(!DatabaseError::InvalidArgument("empty key".to_string()).is_retryable());
+    assert!(!DatabaseError::Unknown("rejected by the server".to_string()).is_retryable());
+}
+
+#[tokio::test]
+async fn test_connect_needs_a_seed() {
+    let result = SmartClient::builder().connect().await;
+    assert!(matches!(result, Err(DatabaseError::Config(_))));
+}
+
+#[tokio::test]
+async fn test_connect_fails_when_no_seed_is_reachable() {
+    let result = SmartClient::builder()
+        .seeds(["127.0.0.1:1", "127.0.0.1:2"])
+        .connect_timeout(Duration::from_millis(200))
+        .retry_policy(RetryPolicy::none())
+        .connect()
+        .await;
+    assert!(matches!(result, Err(DatabaseError::Unavailable(_))));
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use ::common::config::CoordinatorConfig;
+use ::common::error::DatabaseError;
+use ::common::types::{KeyRange, NodeId, PartitionInfo, PartitionStats};
+use common::{node, RecordingTransport};
+use coordinator_lib::Coordinator;
+use std::sync::Arc;
+
+fn coordinator_with_partition() -> Coordinator {
+    let config = CoordinatorConfig { split_size_bytes: 1000, split_qps: 100.0, ..CoordinatorConfig::default() };
+    let mut coordinator = Coordinator::with_config(&config);
+    coordinator.add_partition(PartitionInfo {
+        id: 1,
+        range: KeyRange::new("a", "z"),
+        leader: NodeId::from("node1"),
+        followers: vec![NodeId::from("node2")],
+        learners: vec![],
+    });
+    coordinator
+}
+
+fn stats(partition_id: u64, size_bytes: u64, qps: f64, split_key: &str) -> PartitionStats {
+    PartitionStats { partition_id, size_bytes, key_count: 10, qps, split_key: Some(split_key.to_string()) }
+}
+
+#[tokio::test]
+async fn test_split_partition() {
+    let mut coordinator = coordinator_with_partition();
+    coordinator.register_node(node("node1", "node1:9090"));
+    coordinator.set_transport(Arc::new(RecordingTransport::default()));
+
+    let (left, right) = coordinator.split_partition(1, "m".to_string()).await.unwrap();
+    assert_eq!((left.id, &left.range), (1, &KeyRange::new("a", "m")));
+    assert_eq!((right.id, &right.range), (2, &KeyRange::new("m", "z")));
+    assert_eq!(right.voters(), left.voters(), "Both halves keep the replicas");
+
+    assert!(coordinator.split_partition(1, "a".to_string()).await.is_err(), "Left half would be empty");
+    assert!(coordinator.split_partition(1, "q".to_string()).await.is_err(), "Key of the other half");
+    let result = coordinator.get("p".to_string(), None, Default::default()).await;
+    assert!(matches!(result, Err(DatabaseError::KeyNotFound { .. })), "Read from the right half: {:?}", result);
+}
+
+#[tokio::test]
+async fn test_split_on_thresholds() {
+    let mut coordinator = coordinator_with_partition();
+    assert!(!coordinator.needs_split(&stats(1, 500, 50.0, "m")));
+    assert!(coordinator.needs_split(&stats(1, 5000, 50.0, "m")));
+    assert!(coordinator.needs_split(&stats(1, 500, 500.0, "m")));
+
+    let splits = coordinator.split_partitions(&[stats(1, 500, 50.0, "m")]).await.unwrap();
+    assert!(splits.is_empty());
+
+    let splits = coordinator.split_partitions(&[stats(1, 5000, 50.0, "m")]).await.unwrap();
+    assert_eq!(splits.len(), 1);
+
+    // Stale stats of the partition before the split are skipped
+    let splits = coordinator.split_partitions(&[stats(1, 5000, 50.0, "m")]).await.unwrap();
+    assert!(splits.is_empty());
+    assert_eq!(coordinator.partition(2).unwrap().range, KeyRange::new("m", "z"));
+}
+
+#[tokio::test]
+async fn test_split_ids_are_not_reused() {
+    let mut coordinator = coordinator_with_partition();
+    let (_, right) = coordinator.split_partition(1, "m".to_string()).await.unwrap();
+    coordinator.merge_partition(1, right.id).await.unwrap();
+
+    let (_, right) = coordinator.split_partition(1, "p".to_string()).await.unwrap();
+    assert_eq!(right.id, 3, "The id of the merged-away partition stays retired");
+}
@@ -1,12 +1,19 @@
-use coordinator_lib::Coordinator;
+mod common;
+
+use common::coordinator_with_node;
 use std::collections::HashMap;
 
 #[tokio::test]
 async fn test_sql_parser_integration() {
     // Create a coordinator
-    let mut coordinator = Coordinator::new();
+    let mut coordinator = coordinator_with_node().await;
     let params: HashMap<String, String> = HashMap::new();
     
+    // Test CREATE TABLE; the other statements need the table
+    let create_table_query = "CREATE TABLE users (id INT PRIMARY KEY, name TEXT)";
+    let result = coordinator.execute_query(create_table_query.to_string(), params.clone()).await;
+    assert!(result.is_ok(), "CREATE TABLE query should parse successfully");
+    
     // Test SELECT
     let select_query = "SELECT id, name FROM users";
     let result = coordinator.execute_query(select_query.to_string(), params.clone()).await;
@@ -27,11 +34,6 @@ async fn test_sql_parser_integration() {
     let result = coordinator.execute_query(delete_query.to_string(), params.clone()).await;
     assert!(result.is_ok(), "DELETE query should parse successfully");
     
-    // Test CREATE TABLE
-    let create_table_query = "CREATE TABLE users (id INT PRIMARY KEY, name TEXT)";
-    let result = coordinator.execute_query(create_table_query.to_string(), params.clone()).await;
-    assert!(result.is_ok(), "CREATE TABLE query should parse successfully");
-    
     // Test with parameters
     let param_query = "SELECT * FROM users WHERE id = :user_id";
     let mut params_with_values = HashMap::new();
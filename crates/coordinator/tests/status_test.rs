@@ -0,0 +1,95 @@
+use common::error::DatabaseError;
+use common::types::NodeId;
+use rpc::proto::error::ErrorKind;
+use rpc::status::{error_detail, from_status, to_status, to_status_at_version};
+use tonic::{Code, Status};
+
+fn every_error() -> Vec<DatabaseError> {
+    let message = || "something went wrong".to_string();
+    vec![
+        DatabaseError::Io(std::io::Error::other(message())),
+        DatabaseError::Serialization(message()),
+        DatabaseError::Deserialization(serde::de::Error::custom(message())),
+        DatabaseError::Rpc(message()),
+        DatabaseError::Unavailable(message()),
+        DatabaseError::Timeout(message()),
+        DatabaseError::ResourceExhausted(message()),
+        DatabaseError::Storage { key: Some("user:1".to_string()), message: message() },
+        DatabaseError::Raft { partition: Some(3), node: Some(NodeId::from("node2")), message: message() },
+        DatabaseError::Raft { partition: None, node: None, message: message() },
+        DatabaseError::NotLeader { partition: Some(3), leader: Some((NodeId::from("node2"), "node2:9090".to_string())) },
+        DatabaseError::NotLeader { partition: None, leader: None },
+        DatabaseError::SqlParse(message()),
+        DatabaseError::Schema(message()),
+        DatabaseError::Config(message()),
+        DatabaseError::NodeNotFound(NodeId::from("node9")),
+        DatabaseError::KeyNotFound { key: "user:1".to_string() },
+        DatabaseError::Partition { partition: Some(3), message: message() },
+        DatabaseError::InvalidArgument(message()),
+        DatabaseError::ClockOffset(message()),
+        DatabaseError::Transaction(message()),
+        DatabaseError::Conflict { key: Some("user:1".to_string()), message: message() },
+        DatabaseError::Conflict { key: None, message: message() },
+        DatabaseError::Unknown(message()),
+    ]
+}
+
+#[test]
+fn test_every_error_survives_a_status() {
+    for error in every_error() {
+        let expected = format!("{:?}", std::mem::discriminant(&error));
+        let display = error.to_string();
+        let retryable = error.is_retryable();
+        let context = (error.partition(), error.node().cloned(), error.key().map(str::to_string));
+        let decoded = from_status(to_status(error));
+        assert_eq!(format!("{:?}", std::mem::discriminant(&decoded)), expected, "{}", display);
+        assert_eq!(decoded.to_string(), display);
+        assert_eq!(decoded.is_retryable(), retryable, "{}", display);
+        assert_eq!((decoded.partition(), decoded.node().cloned(), decoded.key().map(str::to_string)), context, "{}", display);
+    }
+}
+
+#[test]
+fn test_status_carries_code_and_detail() {
+    let error = DatabaseError::NotLeader { partition: Some(3), leader: Some((NodeId::from("node2"), "node2:9090".to_string())) };
+    let status = to_status_at_version(error, 7);
+    assert_eq!(status.code(), Code::Unavailable);
+
+    let detail = error_detail(&status).unwrap();
+    assert_eq!(detail.kind(), ErrorKind::NotLeader);
+    assert!(detail.retryable);
+    assert_eq!(detail.leader_id, "node2");
+    assert_eq!(detail.leader_address, "node2:9090");
+    assert_eq!(detail.partition_id, Some(3));
+    assert_eq!(detail.metadata_version, 7);
+
+    let status = to_status(DatabaseError::Conflict { key: Some("user:1".to_string()), message: "write conflict".to_string() });
+    assert_eq!(status.code(), Code::Aborted);
+    assert_eq!(status.message(), "write conflict");
+    assert_eq!(error_detail(&status).unwrap().key.as_deref(), Some("user:1"));
+
+    let status = to_status(DatabaseError::Raft { partition: Some(3), node: None, message: "proposal dropped".to_string() });
+    assert_eq!(status.code(), Code::Unavailable);
+    assert!(error_detail(&status).unwrap().retryable);
+
+    let status = to_status(DatabaseError::KeyNotFound { key: "user:1".to_string() });
+    assert_eq!(status.code(), Code::NotFound);
+    assert!(!error_detail(&status).unwrap().retryable);
+}
+
+#[test]
+fn test_statuses_without_detail_fall_back_on_the_code() {
+    assert!(error_detail(&Status::unavailable("connection refused")).is_none());
+    assert!(matches!(from_status(Status::unavailable("connection refused")), DatabaseError::Unavailable(_)));
+    assert!(matches!(from_status(Status::deadline_exceeded("too slow")), DatabaseError::Timeout(_)));
+    assert!(matches!(from_status(Status::resource_exhausted("queue full")), DatabaseError::ResourceExhausted(_)));
+    assert!(matches!(from_status(Status::invalid_argument("empty key")), DatabaseError::InvalidArgument(_)));
+    assert!(matches!(from_status(Status::internal("bad frame")), DatabaseError::Rpc(_)));
+}
+
+#[test]
+fn test_node_not_found_keeps_the_node_id() {
+    let status = to_status(DatabaseError::NodeNotFound(NodeId::from("node9")));
+    let reworded = Status::with_details(status.code(), "no such node", status.details().to_vec().into());
+    assert!(matches!(from_status(reworded), DatabaseError::NodeNotFound(node) if node == NodeId::from("node9")));
+}
@@ -0,0 +1,238 @@
+mod common;
+
+use ::common::error::{DatabaseError, Result};
+use ::common::types::{IsolationLevel, TransactionId};
+use ::common::util::timestamp_ms;
+use common::{coordinator_with_node, coordinator_with_node_answering, coordinator_with_partitions, node, Commits, RecordingTransport};
+use coordinator_lib::{commit_transaction_unlocked, transaction_get_for_update_unlocked, Coordinator};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+#[tokio::test]
+async fn test_transaction_reads_its_own_writes() {
+    let mut coordinator = coordinator_with_partitions();
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+
+    coordinator.transaction_put(&txn, "apple".to_string(), b"red".to_vec()).unwrap();
+    coordinator.transaction_delete(&txn, "banana".to_string()).unwrap();
+
+    let value = coordinator.transaction_get(&txn, "apple".to_string()).await.unwrap();
+    assert_eq!(value.value, b"red".to_vec());
+    let result = coordinator.transaction_get(&txn, "banana".to_string()).await;
+    assert!(matches!(result, Err(DatabaseError::KeyNotFound { key }) if key == "banana"), "Deleted keys are not found");
+}
+
+#[tokio::test]
+async fn test_commit_across_partitions() {
+    let mut coordinator = coordinator_with_node().await;
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+
+    coordinator.transaction_put(&txn, "apple".to_string(), b"1".to_vec()).unwrap();
+    coordinator.transaction_put(&txn, "orange".to_string(), b"2".to_vec()).unwrap();
+    let commit_ts = coordinator.commit_transaction(&txn).await.unwrap();
+    assert!(commit_ts < coordinator.clock().now());
+
+    assert!(coordinator.commit_transaction(&txn).await.is_err(), "A transaction commits once");
+}
+
+#[tokio::test]
+async fn test_commit_with_a_lost_answer() {
+    let mut coordinator = coordinator_with_node_answering(Commits::AppliedButLost).await;
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    coordinator.transaction_put(&txn, "apple".to_string(), b"1".to_vec()).unwrap();
+    assert!(coordinator.commit_transaction(&txn).await.is_ok(), "The record shows the commit");
+    assert_eq!(coordinator.get("apple".to_string(), None, Default::default()).await.unwrap().value, b"1".to_vec());
+
+    let mut coordinator = coordinator_with_node_answering(Commits::Lost).await;
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    coordinator.transaction_put(&txn, "apple".to_string(), b"1".to_vec()).unwrap();
+    let result = coordinator.commit_transaction(&txn).await;
+    assert!(matches!(result, Err(DatabaseError::Transaction(_))), "Got {:?}", result);
+    assert!(!result.unwrap_err().is_retryable(), "The commit may still be applied");
+}
+
+#[tokio::test]
+async fn test_rollback_discards_transaction() {
+    let mut coordinator = coordinator_with_partitions();
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+
+    coordinator.transaction_put(&txn, "apple".to_string(), b"1".to_vec()).unwrap();
+    coordinator.rollback_transaction(&txn).await.unwrap();
+
+    assert!(coordinator.transaction_put(&txn, "apple".to_string(), b"2".to_vec()).is_err());
+    assert!(coordinator.commit_transaction(&txn).await.is_err());
+}
+
+#[tokio::test]
+async fn test_unknown_transaction() {
+    let mut coordinator = coordinator_with_partitions();
+    let txn = TransactionId("missing".to_string());
+
+    assert!(coordinator.transaction_get(&txn, "apple".to_string()).await.is_err());
+    assert!(coordinator.transaction_put(&txn, "apple".to_string(), b"1".to_vec()).is_err());
+    assert!(coordinator.rollback_transaction(&txn).await.is_err());
+}
+
+#[tokio::test]
+async fn test_sql_transaction_control() {
+    let mut coordinator = coordinator_with_partitions();
+
+    let rows = coordinator.execute_query("BEGIN".to_string(), HashMap::new()).await.unwrap();
+    let txn = TransactionId(rows[0]["transaction_id"].clone());
+
+    let nested = coordinator.execute_query_in_transaction("BEGIN".to_string(), HashMap::new(), Some(txn.clone())).await;
+    assert!(nested.is_err(), "Transactions do not nest");
+
+    let as_of = "SELECT * FROM users AS OF SYSTEM TIME '-5s'".to_string();
+    let result = coordinator.execute_query_in_transaction(as_of, HashMap::new(), Some(txn.clone())).await;
+    assert!(result.is_err(), "AS OF SYSTEM TIME is not allowed inside a transaction");
+
+    let result = coordinator.execute_query_in_transaction("COMMIT".to_string(), HashMap::new(), Some(txn)).await;
+    assert!(result.is_ok());
+
+    assert!(coordinator.execute_query("COMMIT".to_string(), HashMap::new()).await.is_err());
+    assert!(coordinator.execute_query("ROLLBACK".to_string(), HashMap::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_set_transaction_isolation_level() {
+    let mut coordinator = coordinator_with_node().await;
+    let set_serializable = || "SET TRANSACTION ISOLATION LEVEL SERIALIZABLE".to_string();
+    coordinator.put("apple".to_string(), b"0".to_vec(), None).await.unwrap();
+
+    assert!(coordinator.execute_query(set_serializable(), HashMap::new()).await.is_err(), "Needs an open transaction");
+
+    let rows = coordinator.execute_query("BEGIN".to_string(), HashMap::new()).await.unwrap();
+    let txn = TransactionId(rows[0]["transaction_id"].clone());
+    let result = coordinator.execute_query_in_transaction(set_serializable(), HashMap::new(), Some(txn.clone())).await;
+    assert!(result.is_ok());
+
+    // Serializable reads are tracked and validated at commit
+    coordinator.transaction_get(&txn, "apple".to_string()).await.unwrap();
+    coordinator.transaction_put(&txn, "orange".to_string(), b"1".to_vec()).unwrap();
+    assert!(coordinator.commit_transaction(&txn).await.is_ok());
+
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    coordinator.transaction_put(&txn, "apple".to_string(), b"1".to_vec()).unwrap();
+    let result = coordinator.set_transaction_isolation(&txn, IsolationLevel::Serializable);
+    assert!(result.is_err(), "Too late to change the isolation level after a write");
+}
+
+#[test]
+fn test_conflicts_are_retryable() {
+    assert!(DatabaseError::Conflict { key: Some("apple".to_string()), message: "write conflict".to_string() }.is_retryable());
+    assert!(!DatabaseError::Transaction("Unknown transaction".to_string()).is_retryable());
+}
+
+#[tokio::test]
+async fn test_select_for_update() {
+    let mut coordinator = coordinator_with_node().await;
+    let query = || "SELECT * FROM items WHERE id = 7 FOR UPDATE".to_string();
+
+    assert!(coordinator.execute_query(query(), HashMap::new()).await.is_err(), "Needs an open transaction");
+
+    coordinator.put("apple".to_string(), b"1".to_vec(), None).await.unwrap();
+    coordinator.put("orange".to_string(), b"1".to_vec(), None).await.unwrap();
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    assert!(coordinator.execute_query_in_transaction(query(), HashMap::new(), Some(txn.clone())).await.is_ok());
+
+    coordinator.transaction_get_for_update(&txn, "apple".to_string()).await.unwrap();
+    coordinator.transaction_put(&txn, "apple".to_string(), b"0".to_vec()).unwrap();
+    assert!(coordinator.commit_transaction(&txn).await.is_ok());
+
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    coordinator.transaction_get_for_update(&txn, "orange".to_string()).await.unwrap();
+    coordinator.rollback_transaction(&txn).await.unwrap();
+    assert!(coordinator.transaction_get_for_update(&txn, "orange".to_string()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_lock_waits_do_not_hold_up_the_coordinator() {
+    let mut coordinator = coordinator_with_node().await;
+    coordinator.put("apple".to_string(), b"0".to_vec(), None).await.unwrap();
+    let holder = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    let waiter = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    let coordinator = Arc::new(Mutex::new(coordinator));
+
+    transaction_get_for_update_unlocked(&coordinator, &holder, "apple".to_string()).await.unwrap();
+    let wait = tokio::spawn({
+        let coordinator = coordinator.clone();
+        async move { transaction_get_for_update_unlocked(&coordinator, &waiter, "apple".to_string()).await }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!wait.is_finished(), "The waiter queues behind the holder");
+
+    // The holder commits while the waiter waits, which lets the waiter in
+    coordinator.lock().await.transaction_put(&holder, "apple".to_string(), b"1".to_vec()).unwrap();
+    let commit = tokio::time::timeout(Duration::from_secs(2), commit_transaction_unlocked(&coordinator, &holder)).await;
+    assert!(matches!(commit, Ok(Ok(_))), "Got {:?}", commit);
+    let read = tokio::time::timeout(Duration::from_secs(2), wait).await.unwrap().unwrap();
+    assert_eq!(read.unwrap().value, b"0".to_vec(), "The waiter still reads its snapshot");
+}
+
+async fn sql(coordinator: &mut Coordinator, query: &str, txn: &TransactionId) -> Result<Vec<HashMap<String, String>>> {
+    coordinator.execute_query_in_transaction(query.to_string(), HashMap::new(), Some(txn.clone())).await
+}
+
+#[tokio::test]
+async fn test_sql_writes_go_through_the_transaction() {
+    let mut coordinator = coordinator_with_node().await;
+    let create = "CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL, age INT)";
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    assert!(sql(&mut coordinator, create, &txn).await.is_err(), "Tables are not created inside transactions");
+    coordinator.execute_query(create.to_string(), HashMap::new()).await.unwrap();
+
+    sql(&mut coordinator, "INSERT INTO users (id, name, age) VALUES (1, 'Alice', 30)", &txn).await.unwrap();
+    sql(&mut coordinator, "INSERT INTO users (id, name) VALUES (2, 'Bob')", &txn).await.unwrap();
+    let result = sql(&mut coordinator, "INSERT INTO users (id, name) VALUES (2, 'Carol')", &txn).await;
+    assert!(result.is_err(), "Duplicate primary key");
+
+    let rows = sql(&mut coordinator, "UPDATE users SET age = 31 WHERE name LIKE 'A%'", &txn).await.unwrap();
+    assert_eq!(rows[0]["rows_affected"], "1");
+    let rows = sql(&mut coordinator, "SELECT name, age FROM users WHERE age >= 31", &txn).await.unwrap();
+    assert_eq!(rows, vec![HashMap::from([("name".to_string(), "Alice".to_string()), ("age".to_string(), "31".to_string())])]);
+
+    let rows = sql(&mut coordinator, "DELETE FROM users WHERE id = 2", &txn).await.unwrap();
+    assert_eq!(rows[0]["rows_affected"], "1");
+    let rows = sql(&mut coordinator, "SELECT * FROM users", &txn).await.unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["id"], "1");
+
+    // Buffered, not written: only the commit lays them down as intents
+    coordinator.rollback_transaction(&txn).await.unwrap();
+    let rows = coordinator.execute_query("SELECT * FROM users".to_string(), HashMap::new()).await.unwrap();
+    assert!(rows.is_empty());
+}
+
+#[tokio::test]
+async fn test_abandoned_transactions_expire() {
+    let mut coordinator = coordinator_with_partitions();
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    coordinator.transaction_put(&txn, "apple".to_string(), b"1".to_vec()).unwrap();
+
+    assert_eq!(coordinator.expire_transactions(timestamp_ms()).await, 0, "Still in use");
+    assert_eq!(coordinator.expire_transactions(timestamp_ms() + 60_000).await, 1);
+    assert!(coordinator.commit_transaction(&txn).await.is_err(), "Rolled back");
+}
+
+#[tokio::test]
+async fn test_commit_locks_its_writes_on_the_partition_leaders() {
+    let mut coordinator = coordinator_with_partitions();
+    coordinator.register_node(node("node1", "127.0.0.1:9091"));
+    let transport = Arc::new(RecordingTransport::default());
+    coordinator.set_transport(transport.clone());
+
+    let txn = coordinator.begin_transaction(IsolationLevel::Snapshot);
+    coordinator.transaction_put(&txn, "apple".to_string(), b"1".to_vec()).unwrap();
+    coordinator.transaction_put(&txn, "orange".to_string(), b"2".to_vec()).unwrap();
+    coordinator.commit_transaction(&txn).await.unwrap();
+
+    assert_eq!(*transport.requests.lock().unwrap(), vec![
+        "127.0.0.1:9091 acquire_locks [\"apple\"]".to_string(),
+        "127.0.0.1:9091 acquire_locks [\"orange\"]".to_string(),
+        "127.0.0.1:9091 release_locks".to_string(),
+        "127.0.0.1:9091 release_locks".to_string(),
+    ]);
+}
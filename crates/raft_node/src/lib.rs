@@ -1,3 +1,31 @@
+//! Raft replication for data nodes.
+
+pub mod liveness;
+pub mod load;
+pub mod lock_table;
+pub mod message;
+pub mod multi_raft;
+pub mod node;
+pub mod raft_log;
+pub mod read;
+pub mod replica;
+pub mod server;
+pub mod state_machine;
+pub mod transport;
+
+// Re-export commonly used items
+pub use liveness::{CoordinatorTransport, LivenessReporter};
+pub use load::LoadTracker;
+pub use lock_table::LockTable;
+pub use message::{MessageBody, RaftMessage};
+pub use multi_raft::{GroupEvent, MultiRaft, RaftGroup, RaftTransport};
+pub use node::Node;
+pub use read::{ReadIndex, ReadState};
+pub use replica::Replica;
+pub use server::start_grpc_server;
+pub use state_machine::StateMachine;
+pub use transport::{GrpcCoordinatorTransport, GrpcRaftTransport};
+
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
@@ -0,0 +1,107 @@
+//! Liveness reports to the coordinator.
+//!
+//! A node heartbeats the coordinator at a fixed interval, carrying its disk
+//! usage and request rate, and the locality the coordinator places replicas
+//! by. The coordinator takes a node that stops reporting
+//! for suspect and later for dead, and replaces the replicas of dead nodes,
+//! so a node must keep reporting even while its Raft groups are idle.
+//! The coordinator answers with its metadata version, which tells the node
+//! when clients route by an outdated partition map.
+
+use async_trait::async_trait;
+use common::config::NodeConfig;
+use common::error::{DatabaseError, Result};
+use common::types::{Locality, NodeId, NodeLoad};
+use log::warn;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Sends heartbeats to the coordinator.
+#[async_trait]
+pub trait CoordinatorTransport: Send + Sync + 'static {
+    /// Send a heartbeat, and return the coordinator's metadata version
+    async fn heartbeat(&self, node: &NodeId, address: &str, locality: &Locality, load: NodeLoad) -> Result<u64>;
+}
+
+/// Reports a node's liveness and load to the coordinator.
+pub struct LivenessReporter<T> {
+    node_id: NodeId,
+    address: String,
+    locality: Locality,
+    interval: Duration,
+    transport: Arc<T>,
+}
+
+impl<T: CoordinatorTransport> LivenessReporter<T> {
+    /// Create a reporter for the node, which fails if the node has no
+    /// coordinator to report to.
+    pub fn new(config: &NodeConfig, transport: Arc<T>) -> Result<Self> {
+        if config.coordinator_addr.is_none() {
+            return Err(DatabaseError::Config("Node has no coordinator_addr to report to".to_string()));
+        }
+        Ok(Self {
+            node_id: config.node_id.clone(),
+            address: config.listen_addr.clone(),
+            locality: config.locality.clone(),
+            interval: Duration::from_millis(config.coordinator_heartbeat_interval_ms),
+            transport,
+        })
+    }
+
+    /// Send one heartbeat, and return the coordinator's metadata version.
+    pub async fn report_once(&self, load: NodeLoad) -> Result<u64> {
+        self.transport.heartbeat(&self.node_id, &self.address, &self.locality, load).await
+    }
+
+    /// Heartbeat forever with the load returned by `load`. Failed heartbeats
+    /// are logged and the next one is sent on schedule.
+    pub async fn run<F: FnMut() -> NodeLoad + Send>(self, mut load: F) {
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.report_once(load()).await {
+                warn!("Failed to heartbeat the coordinator: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingTransport {
+        heartbeats: Mutex<Vec<(NodeId, String, Locality, NodeLoad)>>,
+    }
+
+    #[async_trait]
+    impl CoordinatorTransport for RecordingTransport {
+        async fn heartbeat(&self, node: &NodeId, address: &str, locality: &Locality, load: NodeLoad) -> Result<u64> {
+            let mut heartbeats = self.heartbeats.lock().await;
+            heartbeats.push((node.clone(), address.to_string(), locality.clone(), load));
+            Ok(heartbeats.len() as u64)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_once() {
+        let transport = Arc::new(RecordingTransport::default());
+        assert!(LivenessReporter::new(&NodeConfig::default(), transport.clone()).is_err(), "No coordinator configured");
+
+        let config = NodeConfig {
+            coordinator_addr: Some("127.0.0.1:50051".to_string()),
+            locality: Locality::parse("zone=a,rack=r1").unwrap(),
+            ..NodeConfig::default()
+        };
+        let reporter = LivenessReporter::new(&config, transport.clone()).unwrap();
+        let load = NodeLoad { disk_used_bytes: 10, disk_capacity_bytes: 100, qps: 1.5 };
+        assert_eq!(reporter.report_once(load.clone()).await.unwrap(), 1, "The coordinator's metadata version");
+
+        let heartbeats = transport.heartbeats.lock().await;
+        assert_eq!(*heartbeats, vec![(config.node_id.clone(), config.listen_addr.clone(), config.locality.clone(), load)]);
+    }
+}
@@ -0,0 +1,77 @@
+//! Request rate of a partition.
+//!
+//! The leader counts the requests of each partition it leads, and reports
+//! the rate with the partition's size so the coordinator can split hot
+//! partitions. Requests are counted in fixed windows; the rate blends the
+//! last complete window with the current one in proportion to how far the
+//! current one has progressed, which smooths out the jump at each window
+//! boundary.
+
+use std::time::{Duration, Instant};
+
+/// Counts requests to estimate their rate.
+pub struct LoadTracker {
+    window: Duration,
+    window_start: Instant,
+    current: u64,
+    previous: u64,
+}
+
+impl LoadTracker {
+    pub fn new(window: Duration, now: Instant) -> Self {
+        Self { window, window_start: now, current: 0, previous: 0 }
+    }
+
+    /// Count one request.
+    pub fn record(&mut self, now: Instant) {
+        self.advance(now);
+        self.current += 1;
+    }
+
+    /// Requests per second over roughly the last window.
+    pub fn qps(&mut self, now: Instant) -> f64 {
+        self.advance(now);
+        let elapsed = now.saturating_duration_since(self.window_start).as_secs_f64() / self.window.as_secs_f64();
+        let requests = self.previous as f64 * (1.0 - elapsed) + self.current as f64;
+        requests / self.window.as_secs_f64()
+    }
+
+    fn advance(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < self.window {
+            return;
+        }
+        // Windows without requests in between count as empty
+        self.previous = if elapsed < self.window * 2 { self.current } else { 0 };
+        self.current = 0;
+        // After an idle stretch too long to count in windows, start afresh
+        match u32::try_from(elapsed.as_nanos() / self.window.as_nanos()) {
+            Ok(windows) => self.window_start += self.window * windows,
+            Err(_) => self.window_start = now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qps() {
+        let start = Instant::now();
+        let mut load = LoadTracker::new(Duration::from_secs(1), start);
+        for _ in 0..100 {
+            load.record(start);
+        }
+        assert_eq!(load.qps(start), 100.0);
+
+        // Half way into the next window, half of the last window still counts
+        let halfway = start + Duration::from_millis(1500);
+        for _ in 0..20 {
+            load.record(halfway);
+        }
+        assert_eq!(load.qps(halfway), 70.0);
+
+        assert_eq!(load.qps(start + Duration::from_secs(10)), 0.0, "Idle windows count as empty");
+    }
+}
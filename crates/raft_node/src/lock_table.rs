@@ -0,0 +1,230 @@
+//! Exclusive key locks for pessimistic transactions.
+//!
+//! `SELECT ... FOR UPDATE` locks the rows it reads until its transaction
+//! ends, and transactions lock the keys they write before prewriting them.
+//! A transaction that finds a key locked queues behind the holder instead of
+//! aborting, and is granted the lock in arrival order once it is released.
+//!
+//! Locks live in memory on the partition leader and are not replicated: if
+//! the leader changes, the locks are lost and the transactions fall back on
+//! the write-write conflict checks of the prewrite.
+//!
+//! Waiting can deadlock across partitions. Every lock table reports its
+//! wait-for edges so the deadlock detector can find cycles and abort a
+//! victim with `abort_waits`; waits also give up after a timeout.
+
+use common::error::{DatabaseError, Result};
+use common::hlc::HlcTimestamp;
+use common::types::{TransactionId, WaitForEdge};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Lock table of one partition.
+pub struct LockTable {
+    wait_timeout: Duration,
+    locks: Mutex<HashMap<String, Lock>>,
+}
+
+struct Lock {
+    holder: TransactionId,
+    waiters: VecDeque<Waiter>,
+}
+
+struct Waiter {
+    txn_id: TransactionId,
+    start_ts: HlcTimestamp,
+    /// Sent `Ok` when the lock is granted, or the reason the wait was aborted.
+    grant: oneshot::Sender<Result<()>>,
+}
+
+impl LockTable {
+    /// Create a lock table whose waits give up after `wait_timeout`.
+    pub fn new(wait_timeout: Duration) -> Self {
+        Self {
+            wait_timeout,
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Lock `key` for the transaction, waiting behind earlier requests if it
+    /// is held by another transaction. Locks are reentrant.
+    ///
+    /// Fails with `Timeout` if the lock is not granted within the wait
+    /// timeout, and with `Conflict` if the wait is aborted to
+    /// break a deadlock.
+    pub async fn acquire(&self, key: &str, txn_id: &TransactionId, start_ts: HlcTimestamp) -> Result<()> {
+        let granted = {
+            let mut locks = self.locks();
+            match locks.get_mut(key) {
+                None => {
+                    locks.insert(key.to_string(), Lock { holder: txn_id.clone(), waiters: VecDeque::new() });
+                    return Ok(());
+                },
+                Some(lock) if lock.holder == *txn_id => return Ok(()),
+                Some(lock) => {
+                    let (grant, granted) = oneshot::channel();
+                    lock.waiters.push_back(Waiter { txn_id: txn_id.clone(), start_ts, grant });
+                    granted
+                },
+            }
+        };
+
+        match tokio::time::timeout(self.wait_timeout, granted).await {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(_)) => Err(DatabaseError::Unknown(format!("Lock wait on {} was dropped", key))),
+            Err(_) => {
+                let mut locks = self.locks();
+                let Some(lock) = locks.get_mut(key) else {
+                    return Err(Self::timeout(key));
+                };
+                // Granted just as the wait timed out
+                if lock.holder == *txn_id {
+                    return Ok(());
+                }
+                lock.waiters.retain(|waiter| waiter.txn_id != *txn_id);
+                Err(Self::timeout(key))
+            },
+        }
+    }
+
+    /// Release every lock the transaction holds, handing each to the next
+    /// waiter in line.
+    pub fn release(&self, txn_id: &TransactionId) {
+        let mut locks = self.locks();
+        let held: Vec<String> = locks.iter()
+            .filter(|(_, lock)| lock.holder == *txn_id)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in held {
+            let Some(lock) = locks.get_mut(&key) else { continue };
+            // Skip waiters that already gave up
+            let next = std::iter::from_fn(|| lock.waiters.pop_front())
+                .find_map(|waiter| waiter.grant.send(Ok(())).ok().map(|_| waiter.txn_id));
+            match next {
+                Some(holder) => lock.holder = holder,
+                None => {
+                    locks.remove(&key);
+                },
+            }
+        }
+    }
+
+    /// Abort every wait of the transaction, failing its pending `acquire`
+    /// calls with a retryable conflict. Locks it already holds are kept
+    /// until its coordinator releases them.
+    pub fn abort_waits(&self, txn_id: &TransactionId, reason: &str) {
+        let mut locks = self.locks();
+        for (key, lock) in locks.iter_mut() {
+            let (aborted, waiting): (VecDeque<Waiter>, VecDeque<Waiter>) = lock.waiters.drain(..)
+                .partition(|waiter| waiter.txn_id == *txn_id);
+            lock.waiters = waiting;
+            for waiter in aborted {
+                let _ = waiter.grant.send(Err(DatabaseError::Conflict {
+                    key: Some(key.clone()),
+                    message: reason.to_string(),
+                }));
+            }
+        }
+    }
+
+    /// The transaction holding the lock on `key`, if any.
+    pub fn holder(&self, key: &str) -> Option<TransactionId> {
+        self.locks().get(key).map(|lock| lock.holder.clone())
+    }
+
+    /// Every waiter together with the holder it waits for.
+    pub fn wait_for_edges(&self) -> Vec<WaitForEdge> {
+        let locks = self.locks();
+        locks.iter()
+            .flat_map(|(key, lock)| lock.waiters.iter().map(move |waiter| WaitForEdge {
+                waiter: waiter.txn_id.clone(),
+                waiter_start_ts: waiter.start_ts,
+                holder: lock.holder.clone(),
+                key: key.clone(),
+            }))
+            .collect()
+    }
+
+    fn locks(&self) -> std::sync::MutexGuard<'_, HashMap<String, Lock>> {
+        self.locks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn timeout(key: &str) -> DatabaseError {
+        DatabaseError::Timeout(format!("Timed out waiting for the lock on {}", key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn txn(id: &str) -> TransactionId {
+        TransactionId(id.to_string())
+    }
+
+    // Let spawned waiters reach the queue
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    #[tokio::test]
+    async fn test_waiters_are_granted_in_order() {
+        let table = Arc::new(LockTable::new(Duration::from_secs(5)));
+        table.acquire("k", &txn("a"), HlcTimestamp::new(1, 0)).await.unwrap();
+        table.acquire("k", &txn("a"), HlcTimestamp::new(1, 0)).await.unwrap();
+
+        let b = tokio::spawn({
+            let table = table.clone();
+            async move { table.acquire("k", &txn("b"), HlcTimestamp::new(2, 0)).await }
+        });
+        settle().await;
+        let c = tokio::spawn({
+            let table = table.clone();
+            async move { table.acquire("k", &txn("c"), HlcTimestamp::new(3, 0)).await }
+        });
+        settle().await;
+        assert_eq!(table.wait_for_edges().len(), 2);
+
+        table.release(&txn("a"));
+        b.await.unwrap().unwrap();
+        assert_eq!(table.holder("k"), Some(txn("b")));
+
+        table.release(&txn("b"));
+        c.await.unwrap().unwrap();
+        table.release(&txn("c"));
+        assert_eq!(table.holder("k"), None);
+    }
+
+    #[tokio::test]
+    async fn test_wait_times_out() {
+        let table = LockTable::new(Duration::from_millis(20));
+        table.acquire("k", &txn("a"), HlcTimestamp::new(1, 0)).await.unwrap();
+
+        let result = table.acquire("k", &txn("b"), HlcTimestamp::new(2, 0)).await;
+        assert!(matches!(result, Err(DatabaseError::Timeout(_))));
+        assert!(table.wait_for_edges().is_empty(), "A timed out waiter leaves the queue");
+    }
+
+    #[tokio::test]
+    async fn test_abort_waits() {
+        let table = Arc::new(LockTable::new(Duration::from_secs(5)));
+        table.acquire("k", &txn("a"), HlcTimestamp::new(1, 0)).await.unwrap();
+
+        let b = tokio::spawn({
+            let table = table.clone();
+            async move { table.acquire("k", &txn("b"), HlcTimestamp::new(2, 0)).await }
+        });
+        settle().await;
+        let edges = table.wait_for_edges();
+        assert_eq!((edges[0].waiter.clone(), edges[0].holder.clone()), (txn("b"), txn("a")));
+
+        table.abort_waits(&txn("b"), "deadlock victim");
+        let result = b.await.unwrap();
+        assert!(result.is_err_and(|e| e.is_conflict()));
+        assert_eq!(table.holder("k"), Some(txn("a")));
+    }
+}
@@ -0,0 +1,69 @@
+//! Messages replicas of a Raft group exchange, other than heartbeats.
+//!
+//! Heartbeats are batched across groups by the host, see `multi_raft`;
+//! everything else travels as one `RaftMessage` per RPC. Requests are
+//! answered with the matching response, which the sender steps into the
+//! group like any other message.
+
+use common::types::{LogEntry, NodeId};
+
+/// A message between two replicas of group `group_id`.
+#[derive(Debug, Clone)]
+pub struct RaftMessage {
+    pub group_id: u64,
+    /// Term of the sender
+    pub term: u64,
+    pub body: MessageBody,
+}
+
+#[derive(Debug, Clone)]
+pub enum MessageBody {
+    /// Replicate `entries`, which follow the entry at `prev_log_index`.
+    AppendEntries {
+        leader: NodeId,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+    },
+    /// On success, the follower's log matches the leader's up to
+    /// `match_index`. On failure, `match_index` is an index up to which it
+    /// may match, where the leader retries from.
+    AppendEntriesResponse { success: bool, match_index: u64 },
+    /// With `pre_vote`, only asks whether the vote would be granted in the
+    /// message's term, without the recipient changing its term or vote.
+    /// With `transfer`, the leader handed over to the candidate, so voters
+    /// grant the vote even though they heard from the leader recently.
+    RequestVote {
+        candidate: NodeId,
+        last_log_index: u64,
+        last_log_term: u64,
+        pre_vote: bool,
+        transfer: bool,
+    },
+    RequestVoteResponse { vote_granted: bool, pre_vote: bool },
+    /// One chunk of the state as of `last_included_index`, for a replica
+    /// the log has been compacted past.
+    InstallSnapshot {
+        leader: NodeId,
+        last_included_index: u64,
+        last_included_term: u64,
+        offset: u64,
+        data: Vec<u8>,
+        done: bool,
+    },
+    InstallSnapshotResponse,
+    /// Start an election right away, from a leader handing over to the
+    /// recipient, whose log it brought up to date.
+    TimeoutNow { leader: NodeId },
+}
+
+impl RaftMessage {
+    /// Whether the message asks for an answer, as opposed to being one.
+    pub fn is_request(&self) -> bool {
+        matches!(
+            self.body,
+            MessageBody::AppendEntries { .. } | MessageBody::RequestVote { .. } | MessageBody::InstallSnapshot { .. }
+        )
+    }
+}
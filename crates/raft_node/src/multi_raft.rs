@@ -0,0 +1,444 @@
+//! Multi-Raft: many Raft groups in one process.
+//!
+//! Every partition is its own Raft group, and a node holds replicas of many
+//! partitions. Giving each group its own timers and its own heartbeat RPCs
+//! would cost thousands of timers and messages per heartbeat interval on a
+//! busy node. Instead one ticker drives every group: groups count their
+//! election and heartbeat timeouts in ticks, and the heartbeats the groups
+//! led by this node owe a peer in a tick go out as a single batch. Answers
+//! come back as one batch too and are routed to their groups by id.
+//!
+//! Other Raft messages, which carry log entries, votes and snapshots, are
+//! sent one per RPC as soon as a group queues them.
+
+use crate::message::{MessageBody, RaftMessage};
+use async_trait::async_trait;
+use common::config::NodeConfig;
+use common::error::{DatabaseError, Result};
+use common::types::{GroupHeartbeat, GroupHeartbeatAck, NodeId};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Notify};
+use tokio::time::MissedTickBehavior;
+
+/// A replica of one Raft group, driven by the host.
+pub trait RaftGroup: Send + 'static {
+    /// Advance the group's timers by one tick. A leader returns the
+    /// heartbeats it owes its followers.
+    fn tick(&mut self) -> Vec<(NodeId, GroupHeartbeat)>;
+
+    /// Handle a heartbeat from the group's leader.
+    fn on_heartbeat(&mut self, heartbeat: &GroupHeartbeat) -> GroupHeartbeatAck;
+
+    /// Handle a follower's answer to a heartbeat sent at `sent_at`.
+    fn on_heartbeat_ack(&mut self, from: &NodeId, ack: &GroupHeartbeatAck, sent_at: Instant);
+
+    /// Handle a message from another replica, returning the answer to a request.
+    fn step(&mut self, _from: &NodeId, _message: RaftMessage) -> Option<RaftMessage> {
+        None
+    }
+
+    /// Messages queued for other replicas since the last call.
+    fn take_messages(&mut self) -> Vec<(NodeId, RaftMessage)> {
+        Vec::new()
+    }
+
+    /// Groups to start or stop hosting, e.g. after applying a split.
+    fn take_events(&mut self) -> Vec<GroupEvent<Self>>
+    where
+        Self: Sized,
+    {
+        Vec::new()
+    }
+
+    /// Address of the node of another replica.
+    fn address(&self, _node: &NodeId) -> Option<&str> {
+        None
+    }
+
+    /// Remove the replica's state, once the node stops hosting it for good.
+    fn destroy(self)
+    where
+        Self: Sized,
+    {
+    }
+}
+
+/// A change to the groups a node hosts, raised by one of its groups.
+pub enum GroupEvent<G> {
+    /// Host a new group, e.g. the right half of a split.
+    Created(u64, G),
+    /// Stop hosting a group and remove its state, e.g. once merged away.
+    Removed(u64),
+}
+
+/// Sends Raft messages to peers.
+#[async_trait]
+pub trait RaftTransport: Send + Sync + 'static {
+    async fn send_heartbeats(
+        &self,
+        peer: &NodeId,
+        address: &str,
+        heartbeats: Vec<GroupHeartbeat>,
+    ) -> Result<Vec<GroupHeartbeatAck>>;
+
+    /// Send a message and wait for the answer, if it is a request.
+    async fn send_message(&self, peer: &NodeId, address: &str, message: RaftMessage) -> Result<Option<RaftMessage>>;
+}
+
+/// Creates the replica of a group not hosted yet, when a snapshot for it arrives.
+pub type GroupFactory<G> = Box<dyn FnMut(u64) -> Result<G> + Send>;
+
+/// The Raft groups hosted by a node.
+pub struct MultiRaft<G> {
+    tick_interval: Duration,
+    groups: HashMap<u64, G>,
+    factory: Option<GroupFactory<G>>,
+    /// Woken when a group queued messages outside of a tick
+    flush: Arc<Notify>,
+}
+
+impl<G: RaftGroup> MultiRaft<G> {
+    /// Create a host that ticks its groups once per heartbeat interval.
+    pub fn new(config: &NodeConfig) -> Self {
+        Self {
+            tick_interval: Duration::from_millis(config.heartbeat_interval_ms),
+            groups: HashMap::new(),
+            factory: None,
+            flush: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn tick_interval(&self) -> Duration {
+        self.tick_interval
+    }
+
+    /// Create replicas with `factory` for groups that a leader starts
+    /// sending a snapshot to, such as a replica the group was just given.
+    pub fn set_group_factory(&mut self, factory: GroupFactory<G>) {
+        self.factory = Some(factory);
+    }
+
+    /// Host a replica of a group.
+    pub fn add_group(&mut self, group_id: u64, group: G) -> Result<()> {
+        if self.groups.contains_key(&group_id) {
+            return Err(DatabaseError::Raft {
+                partition: Some(group_id),
+                node: None,
+                message: format!("Raft group {} is already hosted", group_id),
+            });
+        }
+        self.groups.insert(group_id, group);
+        Ok(())
+    }
+
+    /// Stop hosting a group, e.g. once its replica was removed from this node.
+    pub fn remove_group(&mut self, group_id: u64) -> Option<G> {
+        self.groups.remove(&group_id)
+    }
+
+    pub fn group(&self, group_id: u64) -> Option<&G> {
+        self.groups.get(&group_id)
+    }
+
+    pub fn group_mut(&mut self, group_id: u64) -> Option<&mut G> {
+        self.groups.get_mut(&group_id)
+    }
+
+    /// Ids of the hosted groups.
+    pub fn group_ids(&self) -> Vec<u64> {
+        self.groups.keys().copied().collect()
+    }
+
+    /// Number of hosted groups.
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Have `run` send the messages groups queued, e.g. after a proposal.
+    pub fn wake(&self) {
+        self.flush.notify_one();
+    }
+
+    /// Tick every group once and return the heartbeats to send, batched per peer.
+    pub fn tick(&mut self) -> HashMap<NodeId, Vec<GroupHeartbeat>> {
+        let mut batches: HashMap<NodeId, Vec<GroupHeartbeat>> = HashMap::new();
+        let mut events = Vec::new();
+        for group in self.groups.values_mut() {
+            for (peer, heartbeat) in group.tick() {
+                batches.entry(peer).or_default().push(heartbeat);
+            }
+            events.extend(group.take_events());
+        }
+        self.handle_events(events);
+        batches
+    }
+
+    /// Handle a batch of heartbeats from a peer. Heartbeats for groups not
+    /// hosted here, such as a replica still being added, go unanswered.
+    pub fn handle_heartbeats(&mut self, heartbeats: &[GroupHeartbeat]) -> Vec<GroupHeartbeatAck> {
+        let mut events = Vec::new();
+        let acks = heartbeats.iter()
+            .filter_map(|heartbeat| {
+                let group = self.groups.get_mut(&heartbeat.group_id)?;
+                let ack = group.on_heartbeat(heartbeat);
+                events.extend(group.take_events());
+                Some(ack)
+            })
+            .collect();
+        self.handle_events(events);
+        acks
+    }
+
+    /// Route a peer's answers to a heartbeat batch sent at `sent_at` to their groups.
+    pub fn handle_heartbeat_acks(&mut self, from: &NodeId, acks: &[GroupHeartbeatAck], sent_at: Instant) {
+        for ack in acks {
+            if let Some(group) = self.groups.get_mut(&ack.group_id) {
+                group.on_heartbeat_ack(from, ack, sent_at);
+            }
+        }
+        self.wake();
+    }
+
+    /// Route a message from a peer to its group, returning the group's
+    /// answer. Only the first chunk of a snapshot creates a group not hosted
+    /// here. Entries for such a group are refused as if nothing matched,
+    /// which makes the leader send a snapshot; other messages go unanswered.
+    pub fn step(&mut self, from: &NodeId, message: RaftMessage) -> Option<RaftMessage> {
+        let group_id = message.group_id;
+        if !self.groups.contains_key(&group_id) {
+            if let MessageBody::AppendEntries { .. } = &message.body {
+                let body = MessageBody::AppendEntriesResponse { success: false, match_index: 0 };
+                return Some(RaftMessage { group_id, term: message.term, body });
+            }
+            let MessageBody::InstallSnapshot { offset: 0, .. } = &message.body else {
+                return None;
+            };
+            let factory = self.factory.as_mut()?;
+            match factory(group_id) {
+                Ok(group) => {
+                    info!("Creating a replica of group {} for a snapshot from {}", group_id, from);
+                    self.groups.insert(group_id, group);
+                },
+                Err(e) => {
+                    warn!("Failed to create a replica of group {}: {}", group_id, e);
+                    return None;
+                },
+            }
+        }
+
+        let group = self.groups.get_mut(&group_id)?;
+        let answer = group.step(from, message);
+        let events = group.take_events();
+        self.handle_events(events);
+        self.wake();
+        answer
+    }
+
+    /// Messages every group queued, with the addresses of their recipients.
+    pub fn take_messages(&mut self) -> Vec<(NodeId, String, RaftMessage)> {
+        let mut messages = Vec::new();
+        for group in self.groups.values_mut() {
+            for (peer, message) in group.take_messages() {
+                match group.address(&peer) {
+                    Some(address) => messages.push((peer, address.to_string(), message)),
+                    None => warn!("No address for {} in group {}", peer, message.group_id),
+                }
+            }
+        }
+        messages
+    }
+
+    /// Address of `node`, as known to any hosted group.
+    pub fn address(&self, node: &NodeId) -> Option<String> {
+        self.groups.values().find_map(|group| group.address(node)).map(str::to_string)
+    }
+
+    fn handle_events(&mut self, events: Vec<GroupEvent<G>>) {
+        for event in events {
+            match event {
+                GroupEvent::Created(group_id, group) => {
+                    if self.groups.contains_key(&group_id) {
+                        continue;
+                    }
+                    info!("Hosting new group {}", group_id);
+                    self.groups.insert(group_id, group);
+                },
+                GroupEvent::Removed(group_id) => {
+                    if let Some(group) = self.groups.remove(&group_id) {
+                        info!("No longer hosting group {}", group_id);
+                        group.destroy();
+                    }
+                },
+            }
+        }
+    }
+
+    /// Tick the groups of `host` forever, sending each tick's heartbeats to
+    /// every peer in one batch, and every other message as soon as it is queued.
+    pub async fn run<T: RaftTransport>(host: Arc<Mutex<Self>>, transport: Arc<T>) {
+        let (tick_interval, flush) = {
+            let host = host.lock().await;
+            (host.tick_interval(), Arc::clone(&host.flush))
+        };
+        let mut ticker = tokio::time::interval(tick_interval);
+        // A late tick is not made up for with a burst of heartbeats
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let sent_at = Instant::now();
+                    let (batches, messages) = {
+                        let mut host = host.lock().await;
+                        let batches: Vec<_> = host.tick().into_iter()
+                            .filter_map(|(peer, heartbeats)| {
+                                let address = host.address(&peer)?;
+                                Some((peer, address, heartbeats))
+                            })
+                            .collect();
+                        (batches, host.take_messages())
+                    };
+                    for (peer, address, heartbeats) in batches {
+                        let host = host.clone();
+                        let transport = transport.clone();
+                        tokio::spawn(async move {
+                            match transport.send_heartbeats(&peer, &address, heartbeats).await {
+                                Ok(acks) => host.lock().await.handle_heartbeat_acks(&peer, &acks, sent_at),
+                                Err(e) => warn!("Failed to send heartbeats to {}: {}", peer, e),
+                            }
+                        });
+                    }
+                    Self::send_messages(&host, &transport, messages);
+                },
+                _ = flush.notified() => {
+                    let messages = host.lock().await.take_messages();
+                    Self::send_messages(&host, &transport, messages);
+                },
+            }
+        }
+    }
+
+    fn send_messages<T: RaftTransport>(
+        host: &Arc<Mutex<Self>>,
+        transport: &Arc<T>,
+        messages: Vec<(NodeId, String, RaftMessage)>,
+    ) {
+        for (peer, address, message) in messages {
+            let host = host.clone();
+            let transport = transport.clone();
+            tokio::spawn(async move {
+                match transport.send_message(&peer, &address, message).await {
+                    Ok(Some(answer)) => {
+                        host.lock().await.step(&peer, answer);
+                    },
+                    Ok(None) => {},
+                    Err(e) => warn!("Failed to send a Raft message to {}: {}", peer, e),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A group that heartbeats its followers every tick while it leads.
+    struct TestGroup {
+        id: u64,
+        term: u64,
+        leader: NodeId,
+        followers: Vec<NodeId>,
+        leads: bool,
+        acked: Vec<NodeId>,
+    }
+
+    impl TestGroup {
+        fn new(id: u64, leader: &str, followers: &[&str], leads: bool) -> Self {
+            Self {
+                id,
+                term: 1,
+                leader: NodeId::from(leader),
+                followers: followers.iter().map(|f| NodeId::from(*f)).collect(),
+                leads,
+                acked: Vec::new(),
+            }
+        }
+    }
+
+    impl RaftGroup for TestGroup {
+        fn tick(&mut self) -> Vec<(NodeId, GroupHeartbeat)> {
+            if !self.leads {
+                return Vec::new();
+            }
+            self.followers.iter()
+                .map(|follower| (follower.clone(), GroupHeartbeat {
+                    group_id: self.id,
+                    term: self.term,
+                    leader: self.leader.clone(),
+                    leader_commit: 0,
+                    leader_ms: 0,
+                }))
+                .collect()
+        }
+
+        fn on_heartbeat(&mut self, heartbeat: &GroupHeartbeat) -> GroupHeartbeatAck {
+            GroupHeartbeatAck { group_id: self.id, term: self.term, success: heartbeat.term >= self.term }
+        }
+
+        fn on_heartbeat_ack(&mut self, from: &NodeId, _ack: &GroupHeartbeatAck, _sent_at: Instant) {
+            self.acked.push(from.clone());
+        }
+    }
+
+    #[test]
+    fn test_heartbeats_are_batched_per_peer() {
+        let mut host = MultiRaft::new(&NodeConfig::default());
+        host.add_group(1, TestGroup::new(1, "node1", &["node2", "node3"], true)).unwrap();
+        host.add_group(2, TestGroup::new(2, "node1", &["node2"], true)).unwrap();
+        host.add_group(3, TestGroup::new(3, "node2", &["node1"], false)).unwrap();
+        assert!(host.add_group(3, TestGroup::new(3, "node2", &["node1"], false)).is_err());
+
+        let batches = host.tick();
+        assert_eq!(batches.len(), 2);
+        let mut to_node2: Vec<u64> = batches[&NodeId::from("node2")].iter().map(|h| h.group_id).collect();
+        to_node2.sort();
+        assert_eq!(to_node2, vec![1, 2]);
+        assert_eq!(batches[&NodeId::from("node3")].len(), 1);
+
+        let acks = [
+            GroupHeartbeatAck { group_id: 1, term: 1, success: true },
+            GroupHeartbeatAck { group_id: 2, term: 1, success: true },
+            GroupHeartbeatAck { group_id: 9, term: 1, success: true },
+        ];
+        host.handle_heartbeat_acks(&NodeId::from("node2"), &acks, Instant::now());
+        assert_eq!(host.group(1).unwrap().acked, vec![NodeId::from("node2")]);
+        assert_eq!(host.group(2).unwrap().acked, vec![NodeId::from("node2")]);
+    }
+
+    #[test]
+    fn test_heartbeats_are_routed_to_groups() {
+        let mut host = MultiRaft::new(&NodeConfig::default());
+        host.add_group(3, TestGroup::new(3, "node2", &["node1"], false)).unwrap();
+
+        let heartbeat = |group_id, term| GroupHeartbeat {
+            group_id,
+            term,
+            leader: NodeId::from("node2"),
+            leader_commit: 0,
+            leader_ms: 0,
+        };
+        let acks = host.handle_heartbeats(&[heartbeat(3, 1), heartbeat(4, 1)]);
+        assert_eq!(acks, vec![GroupHeartbeatAck { group_id: 3, term: 1, success: true }], "Unknown groups go unanswered");
+
+        assert!(host.remove_group(3).is_some());
+        assert!(host.handle_heartbeats(&[heartbeat(3, 1)]).is_empty());
+        assert!(host.is_empty());
+    }
+}
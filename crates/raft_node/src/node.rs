@@ -0,0 +1,636 @@
+//! A data node: the Raft groups it hosts and the requests it serves.
+//!
+//! Requests name keys rather than partitions. The node serves a key through
+//! the replica whose range holds it: writes and linearizable reads need that
+//! replica to lead its group, while stale reads may be served by any replica
+//! that is recent enough. A request for a key the node does not serve is
+//! turned down with a route error, naming the leader if the node knows it,
+//! or the newer metadata version it has seen if the request was routed by an
+//! older partition map.
+//!
+//! Linearizable reads go through the replica's `ReadState`: the leader takes
+//! its commit index as the read index, confirms its leadership with a
+//! heartbeat round sent after the read arrived unless its lease covers the
+//! read, and serves the read once the state machine has applied up to the
+//! read index.
+//!
+//! Pessimistic locks are taken in the lock table of the partition holding
+//! the key, which lives on the partition's leader.
+
+use crate::load::LoadTracker;
+use crate::lock_table::LockTable;
+use crate::multi_raft::MultiRaft;
+use crate::read::ReadIndex;
+use crate::replica::{ProposalResult, Replica};
+use crate::state_machine::StateMachine;
+use common::config::NodeConfig;
+use common::error::{DatabaseError, Result};
+use common::hlc::{HlcTimestamp, HybridClock};
+use common::types::{
+    Command, CommandResponse, KeyRange, Membership, NodeId, NodeLoad, PartitionStats, ReadConsistency, ReadOutcome,
+    TransactionId, TransactionMeta, VersionedValue, WaitForEdge, META_GROUP_ID,
+};
+use common::util::timestamp_ms;
+use rpc::routing::{RouteError, Routed};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use storage::Storage;
+use tokio::sync::Mutex;
+
+/// How long a request waits for its proposal to apply, or for its read to
+/// become safe to serve.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long adding a learner waits for it to catch up, possibly from a snapshot.
+const CATCH_UP_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often a waiting request checks on its replica.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// Window the request rate of each partition is measured over.
+const LOAD_WINDOW: Duration = Duration::from_secs(10);
+
+/// The replicas hosted by a node, and the requests served through them.
+pub struct Node {
+    id: NodeId,
+    clock: Arc<HybridClock>,
+    storage: Arc<Storage>,
+    host: Arc<Mutex<MultiRaft<Replica>>>,
+    /// Request rates of the hosted partitions, by group id
+    loads: std::sync::Mutex<HashMap<u64, LoadTracker>>,
+    /// Lock tables of the partitions this node leads or led, by group id
+    locks: std::sync::Mutex<HashMap<u64, Arc<LockTable>>>,
+    lock_wait_timeout: Duration,
+    /// Newest cluster metadata version the node has heard of
+    metadata_version: AtomicU64,
+}
+
+impl Node {
+    /// Open the replicas stored in `storage`. Replicas of groups the node
+    /// joins later are created when their first snapshot arrives.
+    pub fn open(config: &NodeConfig, clock: Arc<HybridClock>, storage: Arc<Storage>) -> Result<Self> {
+        let mut host = MultiRaft::new(config);
+        for group_id in StateMachine::hosted_groups(&storage)? {
+            host.add_group(group_id, Replica::open(Arc::clone(&storage), group_id, config)?)?;
+        }
+        let factory_storage = Arc::clone(&storage);
+        let factory_config = config.clone();
+        host.set_group_factory(Box::new(move |group_id| {
+            Replica::open(Arc::clone(&factory_storage), group_id, &factory_config)
+        }));
+
+        Ok(Self {
+            id: config.node_id.clone(),
+            clock,
+            storage,
+            host: Arc::new(Mutex::new(host)),
+            loads: std::sync::Mutex::new(HashMap::new()),
+            locks: std::sync::Mutex::new(HashMap::new()),
+            lock_wait_timeout: Duration::from_millis(config.lock_wait_timeout_ms),
+            metadata_version: AtomicU64::new(0),
+        })
+    }
+
+    /// Create the meta group with the replicas of `membership`, all of which
+    /// are bootstrapped the same way, unless this node hosts it already.
+    pub async fn bootstrap_meta_group(&self, config: &NodeConfig, membership: Membership) -> Result<()> {
+        let mut host = self.host.lock().await;
+        if host.group(META_GROUP_ID).is_some() {
+            return Ok(());
+        }
+        let replica = Replica::bootstrap(Arc::clone(&self.storage), META_GROUP_ID, KeyRange::meta(), membership, config)?;
+        host.add_group(META_GROUP_ID, replica)
+    }
+
+    pub fn id(&self) -> &NodeId {
+        &self.id
+    }
+
+    pub fn clock(&self) -> Arc<HybridClock> {
+        self.clock.clone()
+    }
+
+    pub fn storage(&self) -> Arc<Storage> {
+        self.storage.clone()
+    }
+
+    /// Newest cluster metadata version the node has heard of; 0 if none
+    pub fn metadata_version(&self) -> u64 {
+        self.metadata_version.load(Ordering::Relaxed)
+    }
+
+    /// Record a cluster metadata version the node has heard of
+    pub fn observe_metadata_version(&self, version: u64) {
+        self.metadata_version.fetch_max(version, Ordering::Relaxed);
+    }
+
+    /// The hosted groups, for the Raft service and the loop driving them.
+    pub fn host(&self) -> Arc<Mutex<MultiRaft<Replica>>> {
+        self.host.clone()
+    }
+
+    /// Replicate `command` through the group whose range holds `key`, and
+    /// return the command's response once applied.
+    pub async fn propose(&self, key: &str, command: Command) -> Result<Routed<CommandResponse>> {
+        let group_id = {
+            let host = self.host.lock().await;
+            match Self::group_for_key(&host, key) {
+                Some(group_id) => group_id,
+                None => return Ok(Routed::Misrouted(RouteError::KeyOutOfRange)),
+            }
+        };
+        self.propose_to(group_id, command).await
+    }
+
+    /// Replicate `command` through group `group_id`, and return the
+    /// command's response once applied.
+    pub async fn propose_to(&self, group_id: u64, command: Command) -> Result<Routed<CommandResponse>> {
+        let proposal = {
+            let mut host = self.host.lock().await;
+            let proposal = match Self::hosted(&mut host, group_id)?.propose(command, self.clock.now()) {
+                Ok(proposal) => proposal,
+                Err(DatabaseError::NotLeader { leader, .. }) => {
+                    return Ok(Routed::Misrouted(RouteError::NotLeader { leader }));
+                },
+                Err(e) => return Err(e),
+            };
+            host.wake();
+            proposal
+        };
+        self.record_request(group_id);
+
+        let response = Self::wait(group_id, proposal).await?;
+        // Routed before a split or merge moved a key elsewhere
+        if response.out_of_range.is_some() {
+            return Ok(Routed::Misrouted(RouteError::KeyOutOfRange));
+        }
+        Ok(Routed::Served(response))
+    }
+
+    /// Read `key` as of `read_ts`, or its latest version if unset. The read
+    /// is blocked by the intent of a transaction that started at or before
+    /// `read_ts`, since it may commit below it.
+    pub async fn read(
+        &self,
+        key: &str,
+        read_ts: Option<HlcTimestamp>,
+        consistency: ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<Option<VersionedValue>>>> {
+        if let Routed::Misrouted(error) = self.prepare_read(key, consistency).await? {
+            return Ok(Routed::Misrouted(error));
+        }
+        let blocking = self.storage.get_intent(key)?.filter(|intent| Self::blocks(&intent.txn, read_ts));
+        if let Some(intent) = blocking {
+            return Ok(Routed::Served(ReadOutcome::Blocked { key: key.to_string(), txn: intent.txn }));
+        }
+        let value = match read_ts {
+            Some(read_ts) => self.storage.get_as_of(key, read_ts)?,
+            None => self.storage.get(key)?,
+        };
+        Ok(Routed::Served(ReadOutcome::Read(value)))
+    }
+
+    /// Up to `limit` live pairs from `start_key` up to `end_key`, as of
+    /// `read_ts` or the latest versions, and whether the scan stopped short
+    /// of `end_key`. The scan ends at the end of the range of the replica
+    /// holding `start_key`; an empty `end_key` scans to the end of the keyspace.
+    /// Intents block the scan as they block reads.
+    pub async fn scan(
+        &self,
+        start_key: &str,
+        end_key: &str,
+        limit: usize,
+        read_ts: Option<HlcTimestamp>,
+        consistency: ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<(Vec<(String, Vec<u8>)>, bool)>>> {
+        let range = match self.prepare_read(start_key, consistency).await? {
+            Routed::Served(range) => range,
+            Routed::Misrouted(error) => return Ok(Routed::Misrouted(error)),
+        };
+        let clipped = end_key.is_empty() || range.end.as_str() < end_key;
+        let end_key = if clipped { range.end.as_str() } else { end_key };
+        let items = match read_ts {
+            Some(read_ts) => self.storage.scan_as_of(start_key, end_key, limit, read_ts)?,
+            None => self.storage.scan(start_key, end_key, limit)?,
+        };
+        let full = limit > 0 && items.len() == limit;
+
+        // Only intents among the keys the scan covered matter
+        let covered_end = match items.last() {
+            Some((last_key, _)) if full => format!("{}\0", last_key),
+            _ => end_key.to_string(),
+        };
+        let blocking = self.storage.intents_in_range(start_key, &covered_end)?
+            .into_iter()
+            .find(|(_, intent)| Self::blocks(&intent.txn, read_ts));
+        if let Some((key, intent)) = blocking {
+            return Ok(Routed::Served(ReadOutcome::Blocked { key, txn: intent.txn }));
+        }
+        Ok(Routed::Served(ReadOutcome::Read((items, clipped || full))))
+    }
+
+    // Wait until the replica holding `key` may serve a read at
+    // `consistency`, and return its range
+    async fn prepare_read(&self, key: &str, consistency: ReadConsistency) -> Result<Routed<KeyRange>> {
+        let (group_id, range, index, requested_at) = {
+            let host = self.host.lock().await;
+            let Some(group_id) = Self::group_for_key(&host, key) else {
+                return Ok(Routed::Misrouted(RouteError::KeyOutOfRange));
+            };
+            let replica = host.group(group_id).expect("group was just found");
+            let range = replica.state_machine().range().cloned().expect("groups serving keys have a range");
+            match consistency {
+                ReadConsistency::Stale { max_staleness_ms } => {
+                    replica.read_state().check_stale_read(max_staleness_ms, timestamp_ms())?;
+                    drop(host);
+                    self.record_request(group_id);
+                    return Ok(Routed::Served(range));
+                },
+                ReadConsistency::Linearizable => {
+                    let now = Instant::now();
+                    match replica.read_state().read_index(now) {
+                        Ok(ReadIndex::Ready { index }) => (group_id, range, index, None),
+                        Ok(ReadIndex::ConfirmLeadership { index }) => (group_id, range, index, Some(now)),
+                        Err(DatabaseError::NotLeader { .. }) => {
+                            return Ok(Routed::Misrouted(RouteError::NotLeader { leader: replica.leader() }));
+                        },
+                        Err(e) => return Err(e),
+                    }
+                },
+            }
+        };
+        self.record_request(group_id);
+
+        let ready = self.poll(group_id, REQUEST_TIMEOUT, "the read to be safe to serve", |replica| {
+            if !replica.is_leader() {
+                return Err(DatabaseError::NotLeader { partition: Some(group_id), leader: replica.leader() });
+            }
+            // Only a heartbeat round sent after the read arrived confirms leadership for it
+            let confirmed = requested_at.is_none_or(|requested_at| {
+                replica.leadership_confirmed_at().is_some_and(|confirmed_at| confirmed_at > requested_at)
+            });
+            Ok((confirmed && replica.read_state().is_applied(index)).then_some(()))
+        });
+        match ready.await {
+            Ok(()) => Ok(Routed::Served(range)),
+            Err(DatabaseError::NotLeader { leader, .. }) => Ok(Routed::Misrouted(RouteError::NotLeader { leader })),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Lock `keys` for the transaction in the lock tables of their
+    /// partitions, in order, waiting for transactions holding them.
+    pub async fn acquire_locks(
+        &self,
+        txn_id: &TransactionId,
+        start_ts: HlcTimestamp,
+        keys: &[String],
+    ) -> Result<Routed<()>> {
+        for key in keys {
+            let table = {
+                let host = self.host.lock().await;
+                let Some(group_id) = Self::group_for_key(&host, key) else {
+                    return Ok(Routed::Misrouted(RouteError::KeyOutOfRange));
+                };
+                let replica = host.group(group_id).expect("group was just found");
+                if !replica.is_leader() {
+                    return Ok(Routed::Misrouted(RouteError::NotLeader { leader: replica.leader() }));
+                }
+                self.lock_table(group_id)
+            };
+            table.acquire(key, txn_id, start_ts).await?;
+        }
+        Ok(Routed::Served(()))
+    }
+
+    /// Release every lock the transaction holds on this node.
+    pub fn release_locks(&self, txn_id: &TransactionId) {
+        for table in self.lock_tables() {
+            table.release(txn_id);
+        }
+    }
+
+    /// Abort every lock wait of the transaction on this node.
+    pub fn abort_lock_waits(&self, txn_id: &TransactionId, reason: &str) {
+        for table in self.lock_tables() {
+            table.abort_waits(txn_id, reason);
+        }
+    }
+
+    /// The lock waits on this node, for the deadlock detector.
+    pub fn wait_for_edges(&self) -> Vec<WaitForEdge> {
+        self.lock_tables().iter().flat_map(|table| table.wait_for_edges()).collect()
+    }
+
+    /// Add `node`, listening at `address`, as a learner of group `group_id`,
+    /// which this node leads. Returns the index the learner has replicated
+    /// once it caught up with the log.
+    pub async fn add_learner(&self, group_id: u64, node: NodeId, address: String) -> Result<u64> {
+        let proposal = {
+            let mut host = self.host.lock().await;
+            let proposal = Self::hosted(&mut host, group_id)?.add_learner(node.clone(), address, self.clock.now())?;
+            host.wake();
+            proposal
+        };
+        Self::wait(group_id, proposal).await?;
+        self.poll(group_id, CATCH_UP_TIMEOUT, "the learner to catch up", |replica| {
+            let Some(matched) = replica.match_index(&node) else {
+                return Err(DatabaseError::NotLeader { partition: Some(group_id), leader: replica.leader() });
+            };
+            Ok((matched >= replica.commit_index()).then_some(matched))
+        }).await
+    }
+
+    /// Move group `group_id`, which this node leads, to `voters` and
+    /// `learners`. Returns once the new configuration is committed.
+    pub async fn change_membership(&self, group_id: u64, voters: Vec<NodeId>, learners: Vec<NodeId>) -> Result<()> {
+        let proposal = {
+            let mut host = self.host.lock().await;
+            let proposal = Self::hosted(&mut host, group_id)?.change_membership(voters, learners, self.clock.now())?;
+            host.wake();
+            proposal
+        };
+        Self::wait(group_id, proposal).await?;
+        Ok(())
+    }
+
+    /// Hand leadership of group `group_id`, which this node leads, to the
+    /// voter `target`. Returns once this replica follows the target.
+    pub async fn transfer_leader(&self, group_id: u64, target: NodeId) -> Result<()> {
+        {
+            let mut host = self.host.lock().await;
+            Self::hosted(&mut host, group_id)?.transfer_leader(&target)?;
+            host.wake();
+        }
+        self.poll(group_id, REQUEST_TIMEOUT, "the new leader to take over", |replica| {
+            Ok(replica.leader().is_some_and(|(leader, _)| leader == target).then_some(()))
+        }).await
+    }
+
+    /// Size and request rate of the partitions this node leads.
+    pub async fn partition_stats(&self) -> Result<Vec<PartitionStats>> {
+        let led: Vec<(u64, KeyRange)> = {
+            let host = self.host.lock().await;
+            host.group_ids().into_iter()
+                .filter_map(|group_id| {
+                    let replica = host.group(group_id)?;
+                    let range = replica.state_machine().range()?;
+                    (replica.is_leader() && group_id != META_GROUP_ID).then(|| (group_id, range.clone()))
+                })
+                .collect()
+        };
+        // Sizing walks the data, which must not hold up the groups
+        led.into_iter()
+            .map(|(group_id, range)| {
+                let stats = self.storage.range_stats(&range.start, &range.end)?;
+                Ok(PartitionStats {
+                    partition_id: group_id,
+                    size_bytes: stats.size_bytes,
+                    key_count: stats.key_count,
+                    qps: self.qps(group_id),
+                    split_key: stats.midpoint_key,
+                })
+            })
+            .collect()
+    }
+
+    /// Size of the data of every hosted replica, and the node's request rate.
+    pub async fn load(&self) -> Result<NodeLoad> {
+        let ranges: Vec<KeyRange> = {
+            let host = self.host.lock().await;
+            host.group_ids().into_iter()
+                .filter_map(|group_id| host.group(group_id)?.state_machine().range().cloned())
+                .collect()
+        };
+        let mut disk_used_bytes = 0;
+        for range in ranges {
+            disk_used_bytes += self.storage.range_stats(&range.start, &range.end)?.size_bytes;
+        }
+        let now = Instant::now();
+        let qps = self.loads().values_mut().map(|load| load.qps(now)).sum();
+        Ok(NodeLoad { disk_used_bytes, disk_capacity_bytes: 0, qps })
+    }
+
+    /// Number of hosted groups, and of those this node leads.
+    pub async fn group_counts(&self) -> (usize, usize) {
+        let host = self.host.lock().await;
+        let led = host.group_ids().into_iter()
+            .filter(|group_id| host.group(*group_id).is_some_and(Replica::is_leader))
+            .count();
+        (host.len(), led)
+    }
+
+    // The replica serving `key`. A partition frozen for a merge still has
+    // its range until its replica is removed, but the left partition serves it.
+    fn group_for_key(host: &MultiRaft<Replica>, key: &str) -> Option<u64> {
+        let serves = |group_id: &u64| {
+            host.group(*group_id).is_some_and(|replica| {
+                let state_machine = replica.state_machine();
+                !state_machine.is_frozen() && state_machine.range().is_some_and(|range| range.contains(key))
+            })
+        };
+        host.group_ids().into_iter().find(serves)
+    }
+
+    // Whether an intent of `txn` hides the value a read at `read_ts` sees
+    fn blocks(txn: &TransactionMeta, read_ts: Option<HlcTimestamp>) -> bool {
+        read_ts.is_none_or(|read_ts| txn.start_ts <= read_ts)
+    }
+
+    fn hosted(host: &mut MultiRaft<Replica>, group_id: u64) -> Result<&mut Replica> {
+        host.group_mut(group_id).ok_or_else(|| DatabaseError::Partition {
+            partition: Some(group_id),
+            message: format!("Partition {} has no replica on this node", group_id),
+        })
+    }
+
+    // Wait for the answer to a proposal to group `group_id`
+    async fn wait(group_id: u64, proposal: ProposalResult) -> Result<CommandResponse> {
+        match tokio::time::timeout(REQUEST_TIMEOUT, proposal).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => Err(DatabaseError::Raft {
+                partition: Some(group_id),
+                node: None,
+                message: format!("The replica of partition {} went away before the proposal applied", group_id),
+            }),
+            Err(_) => Err(DatabaseError::Timeout(format!(
+                "Proposal to partition {} did not apply within {:?}",
+                group_id, REQUEST_TIMEOUT
+            ))),
+        }
+    }
+
+    // Check on the replica of group `group_id` until `ready` returns a value
+    // or fails, waiting at most `timeout` for what `waiting_for` describes
+    async fn poll<T>(
+        &self,
+        group_id: u64,
+        timeout: Duration,
+        waiting_for: &str,
+        mut ready: impl FnMut(&Replica) -> Result<Option<T>>,
+    ) -> Result<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            {
+                let mut host = self.host.lock().await;
+                if let Some(value) = ready(Self::hosted(&mut host, group_id)?)? {
+                    return Ok(value);
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(DatabaseError::Timeout(format!(
+                    "Timed out waiting for {} on partition {}",
+                    waiting_for, group_id
+                )));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    fn record_request(&self, group_id: u64) {
+        let now = Instant::now();
+        self.loads().entry(group_id).or_insert_with(|| LoadTracker::new(LOAD_WINDOW, now)).record(now);
+    }
+
+    fn qps(&self, group_id: u64) -> f64 {
+        self.loads().get_mut(&group_id).map_or(0.0, |load| load.qps(Instant::now()))
+    }
+
+    fn loads(&self) -> std::sync::MutexGuard<'_, HashMap<u64, LoadTracker>> {
+        self.loads.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_table(&self, group_id: u64) -> Arc<LockTable> {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.entry(group_id).or_insert_with(|| Arc::new(LockTable::new(self.lock_wait_timeout))).clone()
+    }
+
+    fn lock_tables(&self) -> Vec<Arc<LockTable>> {
+        self.locks.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multi_raft::RaftTransport;
+    use crate::message::RaftMessage;
+    use async_trait::async_trait;
+    use common::types::{GroupHeartbeat, GroupHeartbeatAck};
+
+    /// A transport for a node without peers.
+    struct NoPeers;
+
+    #[async_trait]
+    impl RaftTransport for NoPeers {
+        async fn send_heartbeats(&self, _: &NodeId, _: &str, _: Vec<GroupHeartbeat>) -> Result<Vec<GroupHeartbeatAck>> {
+            Ok(Vec::new())
+        }
+
+        async fn send_message(&self, _: &NodeId, _: &str, _: RaftMessage) -> Result<Option<RaftMessage>> {
+            Ok(None)
+        }
+    }
+
+    // A node that is the only replica of one group serving "a" to "m"
+    async fn single_node(name: &str) -> Arc<Node> {
+        let path = std::env::temp_dir().join(format!("raft_node_node_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let storage = Arc::new(Storage::open(path).unwrap());
+        let config = NodeConfig::default();
+        let node = Arc::new(Node::open(&config, Arc::new(HybridClock::new(config.max_clock_offset_ms)), storage).unwrap());
+        let membership = Membership {
+            configs: vec![vec![config.node_id.clone()]],
+            learners: Vec::new(),
+            addresses: HashMap::from([(config.node_id.clone(), config.listen_addr.clone())]),
+        };
+        let replica = Replica::bootstrap(node.storage(), 1, KeyRange::new("a", "m"), membership, &config).unwrap();
+        node.host().lock().await.add_group(1, replica).unwrap();
+        tokio::spawn(MultiRaft::run(node.host(), Arc::new(NoPeers)));
+        node
+    }
+
+    // Retry `command` until the replica has won its election
+    async fn propose_on_leader(node: &Node, key: &str, command: Command) -> CommandResponse {
+        for _ in 0..200 {
+            match node.propose(key, command.clone()).await.unwrap() {
+                Routed::Served(response) => return response,
+                Routed::Misrouted(RouteError::NotLeader { .. }) => tokio::time::sleep(POLL_INTERVAL).await,
+                Routed::Misrouted(error) => panic!("Misrouted: {:?}", error),
+            }
+        }
+        panic!("The replica never became leader");
+    }
+
+    #[tokio::test]
+    async fn test_linearizable_read_sees_write() {
+        let node = single_node("read").await;
+        let command = Command::Write { key: "b".to_string(), value: b"1".to_vec(), expires_at: None };
+        assert!(propose_on_leader(&node, "b", command).await.succeeded);
+
+        let read = node.read("b", None, ReadConsistency::Linearizable).await.unwrap();
+        assert!(matches!(read, Routed::Served(ReadOutcome::Read(Some(value))) if value.value == b"1"));
+        let read = node.read("c", None, ReadConsistency::Linearizable).await.unwrap();
+        assert!(matches!(read, Routed::Served(ReadOutcome::Read(None))));
+    }
+
+    #[tokio::test]
+    async fn test_intents_block_reads_at_or_after_their_start() {
+        let node = single_node("intents").await;
+        let before = node.clock().now();
+        let txn = TransactionMeta {
+            id: TransactionId("t1".to_string()),
+            primary_key: "b".to_string(),
+            start_ts: node.clock().now(),
+        };
+        let operations = vec![common::types::BatchOperation::Put { key: "b".to_string(), value: b"1".to_vec() }];
+        let command = Command::Prewrite { txn: txn.clone(), operations, txn_keys: vec!["b".to_string()] };
+        assert!(propose_on_leader(&node, "b", command).await.succeeded);
+
+        let read = node.read("b", None, ReadConsistency::Linearizable).await.unwrap();
+        assert!(matches!(read, Routed::Served(ReadOutcome::Blocked { txn: blocker, .. }) if blocker == txn));
+        let scan = node.scan("a", "c", 0, Some(node.clock().now()), ReadConsistency::Linearizable).await.unwrap();
+        assert!(matches!(scan, Routed::Served(ReadOutcome::Blocked { key, .. }) if key == "b"));
+
+        // The transaction cannot commit below its start
+        let read = node.read("b", Some(before), ReadConsistency::Linearizable).await.unwrap();
+        assert!(matches!(read, Routed::Served(ReadOutcome::Read(None))));
+    }
+
+    #[tokio::test]
+    async fn test_lock_waits_for_release() {
+        let node = single_node("locks").await;
+        let command = Command::Write { key: "b".to_string(), value: b"1".to_vec(), expires_at: None };
+        propose_on_leader(&node, "b", command).await;
+
+        let (first, second) = (TransactionId("t1".to_string()), TransactionId("t2".to_string()));
+        let keys = vec!["b".to_string()];
+        let locked = node.acquire_locks(&first, HlcTimestamp::new(1, 0), &keys).await.unwrap();
+        assert!(matches!(locked, Routed::Served(())));
+
+        let waiting = {
+            let node = node.clone();
+            let keys = keys.clone();
+            tokio::spawn(async move { node.acquire_locks(&second, HlcTimestamp::new(2, 0), &keys).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let edges = node.wait_for_edges();
+        assert_eq!(edges.len(), 1);
+        assert_eq!(edges[0].holder, first);
+
+        node.release_locks(&first);
+        assert!(matches!(waiting.await.unwrap().unwrap(), Routed::Served(())));
+    }
+
+    #[tokio::test]
+    async fn test_keys_outside_hosted_ranges_are_misrouted() {
+        let node = single_node("misrouted").await;
+        let read = node.read("x", None, ReadConsistency::Linearizable).await.unwrap();
+        assert!(matches!(read, Routed::Misrouted(RouteError::KeyOutOfRange)));
+
+        // Scans stop at the end of the range holding their start
+        let command = Command::Write { key: "l".to_string(), value: b"1".to_vec(), expires_at: None };
+        propose_on_leader(&node, "l", command).await;
+        let scan = node.scan("a", "", 0, None, ReadConsistency::Linearizable).await.unwrap();
+        assert!(matches!(scan, Routed::Served(ReadOutcome::Read((items, true))) if items.len() == 1));
+    }
+}
@@ -0,0 +1,181 @@
+//! The Raft log of one group, persisted in the node's storage engine.
+//!
+//! Entries live under the group's state as `l` plus the index in big-endian,
+//! next to the hard state, `h`, and the position the log starts after, `s`.
+//! Entries up to that position have been applied and compacted away; a
+//! replica that needs them gets a snapshot of the state machine instead.
+//! Every entry is also kept in memory, which compaction keeps bounded.
+
+use common::error::Result;
+use common::types::{LogEntry, NodeId};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use storage::Storage;
+
+const HARD_STATE: &[u8] = b"h";
+const LOG_START: &[u8] = b"s";
+const ENTRY_TAG: u8 = b'l';
+
+/// What a replica must remember across restarts besides its log.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardState {
+    pub term: u64,
+    /// Candidate voted for in `term`
+    pub voted_for: Option<NodeId>,
+}
+
+/// Index and term of a log entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogPosition {
+    pub index: u64,
+    pub term: u64,
+}
+
+/// The log of Raft group `group_id`.
+pub struct RaftLog {
+    storage: Arc<Storage>,
+    group_id: u64,
+    /// The last compacted entry; entries start right after it.
+    start: LogPosition,
+    entries: Vec<LogEntry>,
+}
+
+impl RaftLog {
+    /// Load the log and hard state of group `group_id`. `applied` is the
+    /// state machine's last applied entry; a log that starts before it is
+    /// moved up to it, as after a snapshot was installed.
+    pub fn open(storage: Arc<Storage>, group_id: u64, applied: LogPosition) -> Result<(Self, HardState)> {
+        let hard_state = match storage.group_state(group_id, HARD_STATE)? {
+            Some(raw) => serde_json::from_slice(&raw)?,
+            None => HardState::default(),
+        };
+        let start: LogPosition = match storage.group_state(group_id, LOG_START)? {
+            Some(raw) => serde_json::from_slice(&raw)?,
+            None => LogPosition::default(),
+        };
+
+        let mut entries = Vec::new();
+        for (_, raw) in storage.group_states(group_id, &[ENTRY_TAG])? {
+            let entry: LogEntry = serde_json::from_slice(&raw)?;
+            if entry.index > start.index {
+                entries.push(entry);
+            }
+        }
+
+        let mut log = Self { storage, group_id, start, entries };
+        if applied.index > log.start.index || log.storage.group_state(group_id, LOG_START)?.is_none() {
+            log.compact_to(applied)?;
+        }
+        Ok((log, hard_state))
+    }
+
+    /// Persist the hard state; must happen before any message relying on it is sent.
+    pub fn save_hard_state(&self, hard_state: &HardState) -> Result<()> {
+        let mut batch = self.storage.batch();
+        batch.put_group_state(self.group_id, HARD_STATE, &serde_json::to_vec(hard_state)?);
+        batch.commit()
+    }
+
+    /// The last compacted entry.
+    pub fn start(&self) -> LogPosition {
+        self.start
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.entries.last().map_or(self.start.index, |entry| entry.index)
+    }
+
+    pub fn last_term(&self) -> u64 {
+        self.entries.last().map_or(self.start.term, |entry| entry.term)
+    }
+
+    /// Term of the entry at `index`; `None` if it was compacted away or
+    /// does not exist yet.
+    pub fn term(&self, index: u64) -> Option<u64> {
+        if index == self.start.index {
+            return Some(self.start.term);
+        }
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    pub fn entry(&self, index: u64) -> Option<&LogEntry> {
+        let offset = index.checked_sub(self.start.index + 1)?;
+        self.entries.get(usize::try_from(offset).ok()?)
+    }
+
+    /// Up to `max` entries starting at `from`, which must not be compacted away.
+    pub fn entries(&self, from: u64, max: usize) -> &[LogEntry] {
+        let offset = usize::try_from(from.saturating_sub(self.start.index + 1)).unwrap_or(usize::MAX);
+        let entries = self.entries.get(offset..).unwrap_or_default();
+        &entries[..entries.len().min(max)]
+    }
+
+    /// Append `entries` after the entry at `prev`, which must be in the log.
+    /// Entries already in the log are kept unless they conflict with the
+    /// new ones, in which case they and everything after them are replaced.
+    pub fn append(&mut self, prev: u64, entries: &[LogEntry]) -> Result<()> {
+        let mut batch = self.storage.batch();
+        let mut index = prev;
+        for entry in entries {
+            index += 1;
+            // Already compacted, and so committed and the same as the leader's
+            if index <= self.start.index {
+                continue;
+            }
+            match self.term(index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => {
+                    let offset = self.offset(index);
+                    for stale in self.entries.drain(offset..) {
+                        batch.delete_group_state(self.group_id, &entry_name(stale.index));
+                    }
+                },
+                None => {},
+            }
+            batch.put_group_state(self.group_id, &entry_name(entry.index), &serde_json::to_vec(entry)?);
+            self.entries.push(entry.clone());
+        }
+        batch.commit()
+    }
+
+    /// Drop every entry up to and including `position`, which has been
+    /// applied. Entries after it are kept if they agree with it.
+    pub fn compact_to(&mut self, position: LogPosition) -> Result<()> {
+        let mut batch = self.storage.batch();
+        let keep = self.term(position.index) == Some(position.term);
+        let removed: Vec<LogEntry> = if keep {
+            let compacted = self.offset(position.index + 1).min(self.entries.len());
+            self.entries.drain(..compacted).collect()
+        } else {
+            self.entries.drain(..).collect()
+        };
+        for entry in removed {
+            batch.delete_group_state(self.group_id, &entry_name(entry.index));
+        }
+        batch.put_group_state(self.group_id, LOG_START, &serde_json::to_vec(&position)?);
+        batch.commit()?;
+        self.start = position;
+        Ok(())
+    }
+
+    /// Remove the log and hard state, once the replica leaves the group.
+    pub fn destroy(self) -> Result<()> {
+        let mut batch = self.storage.batch();
+        for entry in &self.entries {
+            batch.delete_group_state(self.group_id, &entry_name(entry.index));
+        }
+        batch.delete_group_state(self.group_id, HARD_STATE);
+        batch.delete_group_state(self.group_id, LOG_START);
+        batch.commit()
+    }
+
+    fn offset(&self, index: u64) -> usize {
+        usize::try_from(index.saturating_sub(self.start.index + 1)).unwrap_or(usize::MAX)
+    }
+}
+
+fn entry_name(index: u64) -> Vec<u8> {
+    let mut name = vec![ENTRY_TAG];
+    name.extend_from_slice(&index.to_be_bytes());
+    name
+}
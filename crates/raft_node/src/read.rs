@@ -0,0 +1,283 @@
+//! Read consistency on a replica.
+//!
+//! The leader serves linearizable reads once it knows that it is still the
+//! leader and that its state machine has applied everything committed when
+//! the read arrived. With ReadIndex, the leader takes its commit index as the
+//! read index and confirms its leadership with a round of heartbeats. With a
+//! leader lease, a heartbeat round acknowledged by a quorum within the last
+//! lease period stands in for that confirmation.
+//!
+//! A leader transfer breaks the lease: the target starts an election as soon
+//! as it is told to, without waiting for an election timeout. The leader
+//! gives up its lease when it starts a transfer.
+//!
+//! A new leader does not know the final commit index of its predecessor
+//! until it has committed an entry of its own term, so it serves no
+//! linearizable reads before then.
+//!
+//! Followers serve stale reads. Every heartbeat tells a follower the
+//! leader's commit index and the leader's time; once the follower has applied
+//! up to that index, its state is complete as of that time. Staleness is
+//! measured against the local clock, so it is only accurate to within the
+//! maximum clock offset.
+
+use common::config::{LinearizableReadMode, NodeConfig};
+use common::error::{DatabaseError, Result};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// What the leader must do before serving a linearizable read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadIndex {
+    /// Serve once entries up to `index` are applied.
+    Ready { index: u64 },
+    /// Confirm leadership with a heartbeat round sent after this call, then
+    /// serve once entries up to `index` are applied.
+    ConfirmLeadership { index: u64 },
+}
+
+/// Tracks what reads a replica may serve.
+pub struct ReadState {
+    mode: LinearizableReadMode,
+    lease: Duration,
+    applied_index: u64,
+    role: Role,
+}
+
+enum Role {
+    Leader {
+        commit_index: u64,
+        /// Index of the first entry of the leader's term.
+        term_start_index: u64,
+        lease_expires: Option<Instant>,
+        /// Leadership is being handed to another voter.
+        transferring: bool,
+    },
+    Follower {
+        /// Leader time (ms) as of which the applied state is complete.
+        safe_ms: u64,
+        /// Heartbeats whose commit index is not applied yet, as (index, ms).
+        pending: VecDeque<(u64, u64)>,
+    },
+}
+
+impl ReadState {
+    /// Start as a follower that has applied nothing.
+    pub fn new(config: &NodeConfig) -> Self {
+        Self {
+            mode: config.linearizable_reads,
+            lease: Duration::from_millis(config.leader_lease_ms),
+            applied_index: 0,
+            role: Role::Follower { safe_ms: 0, pending: VecDeque::new() },
+        }
+    }
+
+    /// Take over as leader; `term_start_index` is the index of the first
+    /// entry appended in the new term.
+    pub fn become_leader(&mut self, term_start_index: u64, commit_index: u64) {
+        self.role = Role::Leader { commit_index, term_start_index, lease_expires: None, transferring: false };
+    }
+
+    /// Step down. Any lease is dropped along with the leadership.
+    pub fn become_follower(&mut self) {
+        self.role = Role::Follower { safe_ms: 0, pending: VecDeque::new() };
+    }
+
+    /// The leader's commit index advanced.
+    pub fn on_commit(&mut self, index: u64) {
+        if let Role::Leader { commit_index, .. } = &mut self.role {
+            *commit_index = (*commit_index).max(index);
+        }
+    }
+
+    /// A quorum acknowledged a heartbeat round sent at `sent_at`, which
+    /// confirms leadership and extends the lease. The lease counts from the
+    /// send time, since followers reset their election timers no earlier.
+    pub fn on_heartbeat_quorum(&mut self, sent_at: Instant) {
+        if let Role::Leader { lease_expires, transferring: false, .. } = &mut self.role {
+            let expires = sent_at + self.lease;
+            *lease_expires = Some(lease_expires.map_or(expires, |current| current.max(expires)));
+        }
+    }
+
+    /// Start handing leadership to another voter. The lease is dropped and
+    /// not extended again unless the transfer is abandoned.
+    pub fn begin_transfer(&mut self) {
+        if let Role::Leader { lease_expires, transferring, .. } = &mut self.role {
+            *lease_expires = None;
+            *transferring = true;
+        }
+    }
+
+    /// The transfer was abandoned and this replica is still the leader.
+    pub fn abort_transfer(&mut self) {
+        if let Role::Leader { transferring, .. } = &mut self.role {
+            *transferring = false;
+        }
+    }
+
+    /// A follower received a heartbeat carrying the leader's commit index
+    /// and the leader's time (ms).
+    pub fn on_leader_heartbeat(&mut self, leader_commit: u64, leader_ms: u64) {
+        let applied_index = self.applied_index;
+        if let Role::Follower { safe_ms, pending } = &mut self.role {
+            if leader_commit <= applied_index {
+                *safe_ms = (*safe_ms).max(leader_ms);
+                return;
+            }
+            // Coalesce heartbeats that wait for the same index
+            match pending.back_mut() {
+                Some((index, ms)) if *index >= leader_commit => *ms = (*ms).max(leader_ms),
+                _ => pending.push_back((leader_commit, leader_ms)),
+            }
+        }
+    }
+
+    /// The state machine applied entries up to `index`.
+    pub fn on_applied(&mut self, index: u64) {
+        self.applied_index = self.applied_index.max(index);
+        let applied_index = self.applied_index;
+        if let Role::Follower { safe_ms, pending } = &mut self.role {
+            while let Some((_, ms)) = pending.front().copied().filter(|(i, _)| *i <= applied_index) {
+                *safe_ms = (*safe_ms).max(ms);
+                pending.pop_front();
+            }
+        }
+    }
+
+    /// Start a linearizable read on the leader.
+    pub fn read_index(&self, now: Instant) -> Result<ReadIndex> {
+        let Role::Leader { commit_index, term_start_index, lease_expires, .. } = &self.role else {
+            return Err(DatabaseError::NotLeader { partition: None, leader: None });
+        };
+        if commit_index < term_start_index {
+            return Err(DatabaseError::Unavailable(
+                "The leader has not committed an entry of its term yet".to_string(),
+            ));
+        }
+
+        let index = *commit_index;
+        let leased = self.mode == LinearizableReadMode::Lease && lease_expires.is_some_and(|expires| now < expires);
+        Ok(if leased {
+            ReadIndex::Ready { index }
+        } else {
+            ReadIndex::ConfirmLeadership { index }
+        })
+    }
+
+    /// Whether entries up to `index` are applied, so a read at that index
+    /// may be served.
+    pub fn is_applied(&self, index: u64) -> bool {
+        self.applied_index >= index
+    }
+
+    /// Check that this replica may serve a read at most `max_staleness_ms`
+    /// behind the leader. The leader is always up to date.
+    pub fn check_stale_read(&self, max_staleness_ms: u64, now_ms: u64) -> Result<()> {
+        match &self.role {
+            Role::Leader { .. } => Ok(()),
+            Role::Follower { safe_ms, .. } => {
+                let staleness = now_ms.saturating_sub(*safe_ms);
+                if staleness > max_staleness_ms {
+                    return Err(DatabaseError::Unavailable(format!(
+                        "Replica is {} ms behind the leader, more than the allowed {} ms",
+                        staleness, max_staleness_ms
+                    )));
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(mode: LinearizableReadMode) -> NodeConfig {
+        NodeConfig { linearizable_reads: mode, leader_lease_ms: 100, ..NodeConfig::default() }
+    }
+
+    #[test]
+    fn test_read_index_confirms_leadership() {
+        let mut state = ReadState::new(&config(LinearizableReadMode::ReadIndex));
+        let now = Instant::now();
+        assert!(matches!(state.read_index(now), Err(DatabaseError::NotLeader { .. })), "Followers serve no linearizable reads");
+
+        state.become_leader(11, 10);
+        assert!(state.read_index(now).is_err(), "No entry of the new term is committed yet");
+
+        state.on_commit(11);
+        state.on_heartbeat_quorum(now);
+        assert_eq!(state.read_index(now).unwrap(), ReadIndex::ConfirmLeadership { index: 11 });
+
+        assert!(!state.is_applied(11));
+        state.on_applied(11);
+        assert!(state.is_applied(11));
+    }
+
+    #[test]
+    fn test_lease_skips_heartbeat_round() {
+        let mut state = ReadState::new(&config(LinearizableReadMode::Lease));
+        let sent_at = Instant::now();
+        state.become_leader(5, 5);
+
+        assert_eq!(state.read_index(sent_at).unwrap(), ReadIndex::ConfirmLeadership { index: 5 });
+
+        state.on_heartbeat_quorum(sent_at);
+        assert_eq!(state.read_index(sent_at + Duration::from_millis(50)).unwrap(), ReadIndex::Ready { index: 5 });
+        assert_eq!(
+            state.read_index(sent_at + Duration::from_millis(100)).unwrap(),
+            ReadIndex::ConfirmLeadership { index: 5 },
+            "Expired lease"
+        );
+
+        state.become_follower();
+        state.become_leader(7, 7);
+        assert_eq!(state.read_index(sent_at).unwrap(), ReadIndex::ConfirmLeadership { index: 7 }, "Leases do not survive a term");
+    }
+
+    #[test]
+    fn test_transfer_drops_lease() {
+        let mut state = ReadState::new(&config(LinearizableReadMode::Lease));
+        let sent_at = Instant::now();
+        state.become_leader(5, 5);
+        state.on_heartbeat_quorum(sent_at);
+        assert_eq!(state.read_index(sent_at).unwrap(), ReadIndex::Ready { index: 5 });
+
+        state.begin_transfer();
+        assert_eq!(state.read_index(sent_at).unwrap(), ReadIndex::ConfirmLeadership { index: 5 });
+        state.on_heartbeat_quorum(sent_at);
+        assert_eq!(
+            state.read_index(sent_at).unwrap(),
+            ReadIndex::ConfirmLeadership { index: 5 },
+            "No lease during a transfer"
+        );
+
+        state.abort_transfer();
+        state.on_heartbeat_quorum(sent_at);
+        assert_eq!(state.read_index(sent_at).unwrap(), ReadIndex::Ready { index: 5 });
+    }
+
+    #[test]
+    fn test_follower_staleness() {
+        let mut state = ReadState::new(&config(LinearizableReadMode::ReadIndex));
+        assert!(state.check_stale_read(1_000, 10_000).is_err(), "Nothing applied yet");
+
+        // Heartbeats ahead of the applied index only count once applied
+        state.on_leader_heartbeat(3, 9_000);
+        state.on_leader_heartbeat(3, 9_500);
+        state.on_leader_heartbeat(4, 9_800);
+        assert!(state.check_stale_read(1_000, 10_000).is_err());
+
+        state.on_applied(3);
+        assert!(state.check_stale_read(1_000, 10_000).is_ok());
+        assert!(state.check_stale_read(400, 10_000).is_err());
+
+        state.on_applied(4);
+        assert!(state.check_stale_read(400, 10_000).is_ok());
+
+        state.become_leader(5, 4);
+        assert!(state.check_stale_read(0, 20_000).is_ok());
+    }
+}
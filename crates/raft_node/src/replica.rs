@@ -0,0 +1,1597 @@
+//! A replica of one Raft group.
+//!
+//! The replica is a plain state machine over Raft's protocol: the host ticks
+//! it, steps the messages it receives into it and sends the messages it
+//! queues. It never blocks and owns no timers or sockets, which is what lets
+//! one `MultiRaft` host drive thousands of groups.
+//!
+//! Elections and replication follow the Raft paper, with three additions.
+//! A replica whose election timeout passes first asks the voters whether it
+//! could win, and only starts an election, raising its term, if a quorum
+//! says so (Pre-Vote). A replica that was cut off from the group therefore
+//! does not come back with a term that deposes a healthy leader. A follower
+//! that heard from a leader within the minimum election timeout refuses its
+//! vote, which keeps leader leases safe. And a leader that has not heard
+//! from a quorum for a maximum election timeout steps down.
+//!
+//! Log entries carry the timestamp the proposer assigned, and are applied
+//! through the `StateMachine` in log order. An entry that fails to apply,
+//! such as a merge whose right partition is not frozen on this node yet,
+//! stops the apply loop until the next tick. Once the log outgrows the
+//! snapshot threshold it is compacted up to the applied index; replicas that
+//! fall behind the compacted log are sent a snapshot of the state machine.
+//!
+//! A configuration takes effect as soon as its entry is in a replica's log.
+//! The leader changes one thing at a time: it adds learners, which a
+//! snapshot brings up to date, and moves the voters through a joint
+//! configuration, which it leaves as soon as that is applied. A replica a
+//! committed configuration leaves out removes itself, data included.
+
+use crate::message::{MessageBody, RaftMessage};
+use crate::multi_raft::{GroupEvent, RaftGroup};
+use crate::raft_log::{HardState, LogPosition, RaftLog};
+use crate::read::ReadState;
+use crate::state_machine::StateMachine;
+use common::config::NodeConfig;
+use common::error::{DatabaseError, Result};
+use common::hlc::HlcTimestamp;
+use common::types::{
+    Command, CommandResponse, GroupHeartbeat, GroupHeartbeatAck, KeyRange, LogEntry, Membership, NodeId,
+};
+use common::util::timestamp_ms;
+use log::{info, warn};
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+use storage::Storage;
+use tokio::sync::oneshot;
+
+/// Most entries sent in one AppendEntries message.
+const MAX_ENTRIES_PER_MESSAGE: usize = 64;
+/// Size of the chunks a snapshot is sent in.
+const SNAPSHOT_CHUNK_BYTES: usize = 1024 * 1024;
+
+/// Answer to a proposal, once its entry is applied.
+pub type ProposalResult = oneshot::Receiver<Result<CommandResponse>>;
+
+/// A replica of one Raft group hosted on this node.
+pub struct Replica {
+    id: NodeId,
+    group_id: u64,
+    config: NodeConfig,
+    storage: Arc<Storage>,
+    state_machine: StateMachine,
+    log: RaftLog,
+    hard_state: HardState,
+    /// Latest configuration in the log, which is the one in effect
+    membership: Membership,
+    /// Index of the entry that introduced `membership`; 0 if it predates the log
+    membership_index: u64,
+    commit_index: u64,
+    /// Highest index known to match the log of the current leader
+    verified_index: u64,
+    role: Role,
+    leader: Option<NodeId>,
+    /// Ticks since the leader was last heard from, or since the election started
+    election_elapsed: u32,
+    election_timeout: u32,
+    read_state: ReadState,
+    /// Proposals waiting for their entries to apply, by index, with the
+    /// term the entry was proposed in
+    proposals: HashMap<u64, (u64, oneshot::Sender<Result<CommandResponse>>)>,
+    /// Snapshot being received from the leader
+    incoming_snapshot: Option<IncomingSnapshot>,
+    /// Term to take over leadership in, for the right half of a split this
+    /// replica led
+    lead_at: Option<u64>,
+    /// Set once a committed configuration no longer includes this replica
+    removed: bool,
+    messages: Vec<(NodeId, RaftMessage)>,
+    events: Vec<GroupEvent<Replica>>,
+}
+
+enum Role {
+    Follower,
+    /// Asking for votes for the next term, without having raised its own
+    PreCandidate { votes: HashSet<NodeId> },
+    Candidate { votes: HashSet<NodeId> },
+    Leader(LeaderState),
+}
+
+struct LeaderState {
+    progress: HashMap<NodeId, Progress>,
+    /// Latest heartbeat round each replica acknowledged, by send time
+    acked: HashMap<NodeId, Instant>,
+    /// Replicas heard from since the last quorum check
+    active: HashSet<NodeId>,
+    ticks_since_quorum_check: u32,
+    /// Send time of the latest heartbeat round a quorum acknowledged
+    confirmed_at: Option<Instant>,
+    /// Voter leadership is being handed to, with the ticks since
+    transfer: Option<(NodeId, u32)>,
+}
+
+/// What the leader knows about one replica's log.
+struct Progress {
+    next_index: u64,
+    match_index: u64,
+    /// Ticks since the unanswered message to the replica was sent
+    in_flight: Option<u32>,
+    snapshot: Option<OutgoingSnapshot>,
+}
+
+impl Progress {
+    fn new(next_index: u64) -> Self {
+        Self { next_index, match_index: 0, in_flight: None, snapshot: None }
+    }
+}
+
+struct OutgoingSnapshot {
+    position: LogPosition,
+    data: Vec<u8>,
+    /// Bytes the replica acknowledged
+    offset: usize,
+}
+
+struct IncomingSnapshot {
+    position: LogPosition,
+    data: Vec<u8>,
+}
+
+impl Replica {
+    /// Open this node's replica of group `group_id`, picking up its log,
+    /// hard state and applied state from before a restart. A group this node
+    /// holds nothing of yet starts empty and waits for a snapshot.
+    pub fn open(storage: Arc<Storage>, group_id: u64, config: &NodeConfig) -> Result<Self> {
+        let state_machine = StateMachine::open(Arc::clone(&storage), group_id)?;
+        Self::with_state_machine(storage, state_machine, config)
+    }
+
+    /// Create group `group_id` owning `range`, with this node as one of the
+    /// replicas in `membership`, all of which start from the same empty
+    /// state. Opens the replica instead if it exists already.
+    pub fn bootstrap(
+        storage: Arc<Storage>,
+        group_id: u64,
+        range: KeyRange,
+        membership: Membership,
+        config: &NodeConfig,
+    ) -> Result<Self> {
+        let mut state_machine = StateMachine::for_range(Arc::clone(&storage), group_id, range)?;
+        state_machine.initialize(membership)?;
+        Self::with_state_machine(storage, state_machine, config)
+    }
+
+    fn with_state_machine(storage: Arc<Storage>, state_machine: StateMachine, config: &NodeConfig) -> Result<Self> {
+        let group_id = state_machine.group_id();
+        let applied = LogPosition { index: state_machine.last_applied(), term: state_machine.last_applied_term() };
+        let (log, mut hard_state) = RaftLog::open(Arc::clone(&storage), group_id, applied)?;
+        // A group split off another starts in the term of the split
+        hard_state.term = hard_state.term.max(log.last_term());
+
+        let mut read_state = ReadState::new(config);
+        read_state.on_applied(applied.index);
+        let mut replica = Self {
+            id: config.node_id.clone(),
+            group_id,
+            config: config.clone(),
+            storage,
+            membership: Membership::default(),
+            membership_index: 0,
+            state_machine,
+            commit_index: applied.index,
+            verified_index: 0,
+            log,
+            hard_state,
+            role: Role::Follower,
+            leader: None,
+            election_elapsed: 0,
+            election_timeout: 0,
+            read_state,
+            proposals: HashMap::new(),
+            incoming_snapshot: None,
+            lead_at: None,
+            removed: false,
+            messages: Vec::new(),
+            events: Vec::new(),
+        };
+        replica.refresh_membership();
+        replica.reset_election_timeout();
+        Ok(replica)
+    }
+
+    pub fn group_id(&self) -> u64 {
+        self.group_id
+    }
+
+    pub fn term(&self) -> u64 {
+        self.hard_state.term
+    }
+
+    pub fn is_leader(&self) -> bool {
+        matches!(self.role, Role::Leader(_))
+    }
+
+    /// The leader this replica knows of, with its address.
+    pub fn leader(&self) -> Option<(NodeId, String)> {
+        let leader = self.leader.clone()?;
+        let address = self.membership.addresses.get(&leader).cloned().unwrap_or_default();
+        Some((leader, address))
+    }
+
+    pub fn membership(&self) -> &Membership {
+        &self.membership
+    }
+
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    pub fn state_machine(&self) -> &StateMachine {
+        &self.state_machine
+    }
+
+    pub fn read_state(&self) -> &ReadState {
+        &self.read_state
+    }
+
+    /// Send time of the latest heartbeat round a quorum acknowledged while
+    /// this replica led, which confirms leadership for reads started before it.
+    pub fn leadership_confirmed_at(&self) -> Option<Instant> {
+        match &self.role {
+            Role::Leader(leader) => leader.confirmed_at,
+            _ => None,
+        }
+    }
+
+    /// Append `command` to the log, stamped with `timestamp`. The returned
+    /// receiver gets the command's response once the entry is applied, or an
+    /// error if another leader's entry took its place.
+    pub fn propose(&mut self, command: Command, timestamp: HlcTimestamp) -> Result<ProposalResult> {
+        let Role::Leader(leader) = &self.role else {
+            return Err(DatabaseError::NotLeader { partition: Some(self.group_id), leader: self.leader() });
+        };
+        // Entries the target lacks would hold up the handover
+        if let Some((target, _)) = &leader.transfer {
+            return Err(DatabaseError::Raft {
+                partition: Some(self.group_id),
+                node: Some(target.clone()),
+                message: format!("Leadership of group {} is being transferred to {}", self.group_id, target),
+            });
+        }
+        let index = self.append_entry(command, timestamp)?;
+        let (sender, receiver) = oneshot::channel();
+        self.proposals.insert(index, (self.term(), sender));
+        self.broadcast_append();
+        self.maybe_commit();
+        Ok(receiver)
+    }
+
+    /// Add `node`, listening at `address`, to the group as a learner. It
+    /// receives the log from then on, with a snapshot first, but does not
+    /// vote; `match_index` tells how far it got.
+    pub fn add_learner(&mut self, node: NodeId, address: String, timestamp: HlcTimestamp) -> Result<ProposalResult> {
+        if self.membership.is_voter(&node) {
+            return Err(DatabaseError::Partition {
+                partition: Some(self.group_id),
+                message: format!("Node {} already votes in group {}", node, self.group_id),
+            });
+        }
+        let mut membership = self.membership.clone();
+        if !membership.learners.contains(&node) {
+            membership.learners.push(node.clone());
+        }
+        membership.addresses.insert(node, address);
+        self.propose_membership(membership, timestamp)
+    }
+
+    /// Move the group to `voters` and `learners`, all of which must be
+    /// replicas already. A change of voters goes through a joint
+    /// configuration of the old and the new voters first; the returned
+    /// receiver gets the answer once the new voters alone are applied.
+    pub fn change_membership(
+        &mut self,
+        voters: Vec<NodeId>,
+        learners: Vec<NodeId>,
+        timestamp: HlcTimestamp,
+    ) -> Result<ProposalResult> {
+        let replicas = self.membership.replicas();
+        if let Some(node) = voters.iter().chain(&learners).find(|node| !replicas.contains(node)) {
+            return Err(DatabaseError::Partition {
+                partition: Some(self.group_id),
+                message: format!("Node {} must join group {} as a learner first", node, self.group_id),
+            });
+        }
+        if voters.is_empty() {
+            return Err(DatabaseError::InvalidArgument(format!("Group {} needs at least one voter", self.group_id)));
+        }
+
+        let mut membership = self.membership.clone();
+        let old_voters = membership.configs.last().cloned().unwrap_or_default();
+        let same_voters = old_voters.len() == voters.len() && voters.iter().all(|node| old_voters.contains(node));
+        membership.configs = if same_voters { vec![voters] } else { vec![old_voters, voters] };
+        membership.learners = learners;
+        self.propose_membership(membership, timestamp)
+    }
+
+    // One change at a time: a configuration must be committed, and a joint
+    // one left, before the next is proposed
+    fn propose_membership(&mut self, mut membership: Membership, timestamp: HlcTimestamp) -> Result<ProposalResult> {
+        if !self.is_leader() {
+            return Err(DatabaseError::NotLeader { partition: Some(self.group_id), leader: self.leader() });
+        }
+        if self.membership_index > self.commit_index || self.membership.configs.len() > 1 {
+            return Err(DatabaseError::Raft {
+                partition: Some(self.group_id),
+                node: Some(self.id.clone()),
+                message: format!("A membership change of group {} is in progress", self.group_id),
+            });
+        }
+        // Replicas leaving keep their address until the next change, so
+        // they can still be told the change committed
+        let replicas = self.membership.replicas();
+        let kept = membership.replicas();
+        membership.addresses.retain(|node, _| replicas.contains(node) || kept.contains(node));
+        self.propose(Command::ChangeMembership { membership }, timestamp)
+    }
+
+    /// Hand leadership to the voter `target`. The leader stops taking
+    /// proposals, brings the target's log up to date and then tells it to
+    /// start an election, which it wins unless another voter has a newer
+    /// log. The transfer is given up after an election timeout.
+    pub fn transfer_leader(&mut self, target: &NodeId) -> Result<()> {
+        if !self.is_leader() {
+            return Err(DatabaseError::NotLeader { partition: Some(self.group_id), leader: self.leader() });
+        }
+        if !self.membership.is_voter(target) {
+            return Err(DatabaseError::Partition {
+                partition: Some(self.group_id),
+                message: format!("Node {} is not a voter of group {}", target, self.group_id),
+            });
+        }
+        if *target == self.id {
+            return Ok(());
+        }
+        if let Role::Leader(leader) = &mut self.role {
+            info!("Leader of group {} transfers leadership to {}", self.group_id, target);
+            leader.transfer = Some((target.clone(), 0));
+        }
+        if self.needs_append(target) {
+            self.send_append(target);
+        } else {
+            self.maybe_send_timeout_now(target);
+        }
+        Ok(())
+    }
+
+    // Tell the transfer target to campaign once its log is up to date
+    fn maybe_send_timeout_now(&mut self, peer: &NodeId) {
+        let Role::Leader(leader) = &self.role else {
+            return;
+        };
+        let is_target = leader.transfer.as_ref().is_some_and(|(target, _)| target == peer);
+        let caught_up = leader.progress.get(peer).is_some_and(|progress| progress.match_index == self.log.last_index());
+        if is_target && caught_up {
+            self.send(peer.clone(), MessageBody::TimeoutNow { leader: self.id.clone() });
+        }
+    }
+
+    /// Highest index known to be in the log of `node`, while this replica leads.
+    pub fn match_index(&self, node: &NodeId) -> Option<u64> {
+        match &self.role {
+            Role::Leader(_) if *node == self.id => Some(self.log.last_index()),
+            Role::Leader(leader) => leader.progress.get(node).map(|progress| progress.match_index),
+            _ => None,
+        }
+    }
+
+    fn append_entry(&mut self, command: Command, timestamp: HlcTimestamp) -> Result<u64> {
+        let membership = match &command {
+            Command::ChangeMembership { membership } => Some(membership.clone()),
+            _ => None,
+        };
+        let entry = LogEntry { term: self.term(), index: self.log.last_index() + 1, command, timestamp };
+        let index = entry.index;
+        self.log.append(index - 1, &[entry])?;
+        if let Some(membership) = membership {
+            self.set_membership(index, membership);
+        }
+        Ok(index)
+    }
+
+    // Take up the latest configuration in the log, or the applied one if the
+    // log holds none, e.g. after the entry that held it was replaced
+    fn refresh_membership(&mut self) {
+        let (index, membership) = self.log.entries(self.log.start().index + 1, usize::MAX).iter().rev()
+            .find_map(|entry| match &entry.command {
+                Command::ChangeMembership { membership } => Some((entry.index, membership.clone())),
+                _ => None,
+            })
+            .unwrap_or_else(|| (0, self.state_machine.membership().clone()));
+        self.set_membership(index, membership);
+    }
+
+    // A configuration takes effect as soon as it is in the log; the leader
+    // starts replicating to the replicas it adds
+    fn set_membership(&mut self, index: u64, membership: Membership) {
+        self.membership_index = index;
+        self.membership = membership;
+        let next_index = self.log.last_index() + 1;
+        let peers = self.peers();
+        if let Role::Leader(leader) = &mut self.role {
+            for peer in peers {
+                leader.progress.entry(peer).or_insert_with(|| Progress::new(next_index));
+            }
+        }
+    }
+
+    fn reset_election_timeout(&mut self) {
+        let (min, max) = self.election_ticks();
+        self.election_elapsed = 0;
+        self.election_timeout = rand::thread_rng().gen_range(min..=max);
+    }
+
+    // Election timeouts in ticks. A follower that heard from the leader
+    // `min` ticks ago may have heard from it up to one tick interval later
+    // than that, so a tick is added to never undercut the timeout.
+    fn election_ticks(&self) -> (u32, u32) {
+        let interval = self.config.heartbeat_interval_ms.max(1);
+        let ticks = |ms: u64| u32::try_from(ms.div_ceil(interval) + 1).unwrap_or(u32::MAX);
+        let min = ticks(self.config.election_timeout_min_ms);
+        (min, ticks(self.config.election_timeout_max_ms).max(min))
+    }
+
+    fn save_hard_state(&mut self, term: u64, voted_for: Option<NodeId>) {
+        self.hard_state = HardState { term, voted_for };
+        if let Err(e) = self.log.save_hard_state(&self.hard_state) {
+            warn!("Failed to persist the hard state of group {}: {}", self.group_id, e);
+        }
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<NodeId>) {
+        if term > self.term() {
+            self.save_hard_state(term, None);
+            self.verified_index = 0;
+        }
+        if leader.is_some() && leader != self.leader {
+            self.verified_index = 0;
+        }
+        if self.is_leader() {
+            info!("Replica of group {} steps down in term {}", self.group_id, term);
+            self.read_state.become_follower();
+        }
+        self.role = Role::Follower;
+        self.leader = leader;
+        self.reset_election_timeout();
+    }
+
+    // Ask the voters whether they would vote for this replica in the next term
+    fn pre_campaign(&mut self) {
+        if self.membership.is_quorum(|node| *node == self.id) {
+            self.campaign(false);
+            return;
+        }
+        self.leader = None;
+        self.role = Role::PreCandidate { votes: HashSet::from([self.id.clone()]) };
+        self.reset_election_timeout();
+        self.request_votes(self.term() + 1, true, false);
+    }
+
+    // Start an election; with `transfer`, on the leader's request
+    fn campaign(&mut self, transfer: bool) {
+        let term = self.term() + 1;
+        self.save_hard_state(term, Some(self.id.clone()));
+        self.read_state.become_follower();
+        self.leader = None;
+        self.role = Role::Candidate { votes: HashSet::from([self.id.clone()]) };
+        self.reset_election_timeout();
+        info!("Replica of group {} starts an election in term {}", self.group_id, term);
+
+        if self.membership.is_quorum(|node| *node == self.id) {
+            self.become_leader();
+            return;
+        }
+        self.request_votes(term, false, transfer);
+    }
+
+    fn request_votes(&mut self, term: u64, pre_vote: bool, transfer: bool) {
+        let body = MessageBody::RequestVote {
+            candidate: self.id.clone(),
+            last_log_index: self.log.last_index(),
+            last_log_term: self.log.last_term(),
+            pre_vote,
+            transfer,
+        };
+        let voters: Vec<NodeId> = self.peers().into_iter().filter(|peer| self.membership.is_voter(peer)).collect();
+        for voter in voters {
+            let message = RaftMessage { group_id: self.group_id, term, body: body.clone() };
+            self.messages.push((voter, message));
+        }
+    }
+
+    fn become_leader(&mut self) {
+        info!("Replica of group {} becomes leader in term {}", self.group_id, self.term());
+        let next_index = self.log.last_index() + 1;
+        let progress = self.peers().into_iter().map(|peer| (peer, Progress::new(next_index))).collect();
+        self.role = Role::Leader(LeaderState {
+            progress,
+            acked: HashMap::new(),
+            active: HashSet::new(),
+            ticks_since_quorum_check: 0,
+            confirmed_at: None,
+            transfer: None,
+        });
+        self.leader = Some(self.id.clone());
+
+        // Entries of earlier terms only commit along with one of this term
+        match self.append_entry(Command::Noop, HlcTimestamp::new(timestamp_ms(), 0)) {
+            Ok(index) => self.read_state.become_leader(index, self.commit_index),
+            Err(e) => {
+                warn!("Replica of group {} failed to start its term: {}", self.group_id, e);
+                self.become_follower(self.term(), None);
+                return;
+            },
+        }
+        self.broadcast_append();
+        self.maybe_commit();
+    }
+
+    // Every other replica, voting or not
+    fn peers(&self) -> Vec<NodeId> {
+        self.membership.replicas().into_iter().filter(|node| *node != self.id).collect()
+    }
+
+    fn send(&mut self, to: NodeId, body: MessageBody) {
+        let message = RaftMessage { group_id: self.group_id, term: self.term(), body };
+        self.messages.push((to, message));
+    }
+
+    fn broadcast_append(&mut self) {
+        for peer in self.peers() {
+            self.send_append(&peer);
+        }
+    }
+
+    // Send `peer` the entries it is missing, or the next snapshot chunk if
+    // the log no longer holds them, unless a message to it is in flight
+    fn send_append(&mut self, peer: &NodeId) {
+        let Role::Leader(leader) = &mut self.role else {
+            return;
+        };
+        let Some(progress) = leader.progress.get_mut(peer) else {
+            return;
+        };
+        if progress.in_flight.is_some() {
+            return;
+        }
+
+        if progress.snapshot.is_none() && progress.next_index <= self.log.start().index {
+            let snapshot = match self.state_machine.snapshot() {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("Failed to take a snapshot of group {}: {}", self.group_id, e);
+                    return;
+                },
+            };
+            let position = LogPosition {
+                index: self.state_machine.last_applied(),
+                term: self.state_machine.last_applied_term(),
+            };
+            info!("Sending a snapshot of group {} at index {} to {}", self.group_id, position.index, peer);
+            progress.snapshot = Some(OutgoingSnapshot { position, data: snapshot, offset: 0 });
+        }
+
+        progress.in_flight = Some(0);
+        let body = match &progress.snapshot {
+            Some(snapshot) => {
+                let end = (snapshot.offset + SNAPSHOT_CHUNK_BYTES).min(snapshot.data.len());
+                MessageBody::InstallSnapshot {
+                    leader: self.id.clone(),
+                    last_included_index: snapshot.position.index,
+                    last_included_term: snapshot.position.term,
+                    offset: snapshot.offset as u64,
+                    data: snapshot.data[snapshot.offset..end].to_vec(),
+                    done: end == snapshot.data.len(),
+                }
+            },
+            None => {
+                let prev_log_index = progress.next_index - 1;
+                MessageBody::AppendEntries {
+                    leader: self.id.clone(),
+                    prev_log_index,
+                    prev_log_term: self.log.term(prev_log_index).unwrap_or_default(),
+                    entries: self.log.entries(progress.next_index, MAX_ENTRIES_PER_MESSAGE).to_vec(),
+                    leader_commit: self.commit_index,
+                }
+            },
+        };
+        self.send(peer.clone(), body);
+    }
+
+    // Commit the latest entry of the current term that a quorum holds
+    fn maybe_commit(&mut self) {
+        let Role::Leader(leader) = &self.role else {
+            return;
+        };
+        let matched = |node: &NodeId, index: u64| {
+            *node == self.id || leader.progress.get(node).is_some_and(|progress| progress.match_index >= index)
+        };
+        let committed = (self.commit_index + 1..=self.log.last_index()).rev()
+            .take_while(|index| self.log.term(*index) == Some(self.term()))
+            .find(|index| self.membership.is_quorum(|node| matched(node, *index)));
+
+        if let Some(index) = committed {
+            self.commit_index = index;
+            self.read_state.on_commit(index);
+            self.apply_committed();
+        }
+    }
+
+    fn advance_follower_commit(&mut self, leader_commit: u64) {
+        let commit = leader_commit.min(self.verified_index);
+        if commit > self.commit_index {
+            self.commit_index = commit;
+            self.apply_committed();
+        }
+    }
+
+    // Apply committed entries in order, stopping at the first that fails
+    fn apply_committed(&mut self) {
+        while self.state_machine.last_applied() < self.commit_index {
+            let index = self.state_machine.last_applied() + 1;
+            let Some(entry) = self.log.entry(index).cloned() else {
+                break;
+            };
+            match self.state_machine.apply(&entry) {
+                Ok(response) => {
+                    self.on_applied(&entry);
+                    if let Some((term, sender)) = self.proposals.remove(&index) {
+                        let result = if term == entry.term {
+                            Ok(response)
+                        } else {
+                            Err(DatabaseError::NotLeader { partition: Some(self.group_id), leader: self.leader() })
+                        };
+                        let _ = sender.send(result);
+                    }
+                },
+                Err(e) => {
+                    warn!("Group {} could not apply entry {} yet: {}", self.group_id, index, e);
+                    break;
+                },
+            }
+        }
+        self.read_state.on_applied(self.state_machine.last_applied());
+        self.maybe_compact();
+    }
+
+    fn on_applied(&mut self, entry: &LogEntry) {
+        match &entry.command {
+            Command::SplitPartition { right, .. } => {
+                match Replica::open(Arc::clone(&self.storage), right.id, &self.config) {
+                    Ok(mut replica) => {
+                        // Only the leader of the split's term may lead the new group in it
+                        if self.is_leader() && self.term() == entry.term {
+                            replica.lead_at = Some(entry.term);
+                        }
+                        self.events.push(GroupEvent::Created(right.id, replica));
+                    },
+                    Err(e) => warn!("Failed to open group {} split off group {}: {}", right.id, self.group_id, e),
+                }
+            },
+            Command::MergePartitions { right_id, .. } => self.events.push(GroupEvent::Removed(*right_id)),
+            Command::ChangeMembership { membership } => self.on_membership_applied(entry, membership),
+            _ => {},
+        }
+    }
+
+    // Leave a joint configuration, and let go of the replicas a committed
+    // configuration no longer includes, which may be this one
+    fn on_membership_applied(&mut self, entry: &LogEntry, membership: &Membership) {
+        self.maybe_leave_joint();
+        let committed = membership.replicas();
+        let is_replica = committed.contains(&self.id);
+        if let Role::Leader(leader) = &mut self.role {
+            // Replicas dropped from the group learn that the change committed
+            // only from this last message, which carries the entries they lack
+            // up to it; they remove themselves on applying it
+            let replicas = self.membership.replicas();
+            let departed: Vec<(NodeId, u64)> = leader.progress.iter()
+                .filter(|(peer, _)| !is_replica || !committed.contains(peer))
+                .map(|(peer, progress)| (peer.clone(), progress.match_index))
+                .collect();
+            for (peer, _) in &departed {
+                if !replicas.contains(peer) {
+                    leader.progress.remove(peer);
+                }
+            }
+            for (peer, match_index) in departed {
+                let (prev_log_index, entries) = match self.log.term(match_index) {
+                    Some(_) => (match_index, self.log.entries(match_index + 1, usize::MAX)[..]
+                        .iter()
+                        .take_while(|later| later.index <= entry.index)
+                        .cloned()
+                        .collect()),
+                    None => (entry.index, Vec::new()),
+                };
+                self.send(peer, MessageBody::AppendEntries {
+                    leader: self.id.clone(),
+                    prev_log_index,
+                    prev_log_term: self.log.term(prev_log_index).unwrap_or(entry.term),
+                    entries,
+                    leader_commit: self.commit_index,
+                });
+            }
+        }
+        if !is_replica {
+            info!("Replica of group {} was removed from the group", self.group_id);
+            if self.is_leader() {
+                self.become_follower(self.term(), None);
+            }
+            self.removed = true;
+        }
+    }
+
+    // Once a joint configuration is applied, the leader moves on to the new
+    // voters alone
+    fn maybe_leave_joint(&mut self) {
+        if !self.is_leader() || self.membership.configs.len() < 2 || self.membership_index > self.state_machine.last_applied() {
+            return;
+        }
+        let joint_index = self.membership_index;
+        let mut membership = self.membership.clone();
+        membership.configs.drain(..membership.configs.len() - 1);
+        match self.append_entry(Command::ChangeMembership { membership }, HlcTimestamp::new(timestamp_ms(), 0)) {
+            Ok(index) => {
+                // Whoever asked for the change waits for the new voters
+                if let Some((_, sender)) = self.proposals.remove(&joint_index) {
+                    self.proposals.insert(index, (self.term(), sender));
+                }
+                self.broadcast_append();
+                self.maybe_commit();
+            },
+            Err(e) => warn!("Leader of group {} failed to leave its joint configuration: {}", self.group_id, e),
+        }
+    }
+
+    fn maybe_compact(&mut self) {
+        let applied = LogPosition {
+            index: self.state_machine.last_applied(),
+            term: self.state_machine.last_applied_term(),
+        };
+        if applied.index.saturating_sub(self.log.start().index) <= self.config.snapshot_threshold {
+            return;
+        }
+        if let Err(e) = self.log.compact_to(applied) {
+            warn!("Failed to compact the log of group {}: {}", self.group_id, e);
+        }
+    }
+
+    fn handle_append_entries(
+        &mut self,
+        from: &NodeId,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+    ) -> MessageBody {
+        let reject = |match_index| MessageBody::AppendEntriesResponse { success: false, match_index };
+        // A replica that holds nothing of the group must start from a snapshot
+        if self.membership.configs.is_empty() {
+            return reject(0);
+        }
+        if prev_log_index > self.log.last_index() {
+            return reject(self.log.last_index());
+        }
+        // Everything up to the compacted position is committed and matches
+        if prev_log_index < self.log.start().index {
+            return reject(self.commit_index.max(self.log.start().index));
+        }
+        if self.log.term(prev_log_index) != Some(prev_log_term) {
+            // Committed entries match, so retry from no earlier than the commit index
+            return reject(self.commit_index.max(self.log.start().index).min(prev_log_index - 1));
+        }
+
+        let match_index = prev_log_index + entries.len() as u64;
+        let has_membership = entries.iter().any(|entry| matches!(entry.command, Command::ChangeMembership { .. }));
+        if let Err(e) = self.log.append(prev_log_index, &entries) {
+            warn!("Failed to append entries from {} to group {}: {}", from, self.group_id, e);
+            return reject(self.commit_index);
+        }
+        // A configuration appended, or one after `prev_log_index` replaced
+        if has_membership || self.membership_index > prev_log_index {
+            self.refresh_membership();
+        }
+        self.verified_index = self.verified_index.max(match_index);
+        self.advance_follower_commit(leader_commit);
+        MessageBody::AppendEntriesResponse { success: true, match_index }
+    }
+
+    fn handle_install_snapshot(&mut self, position: LogPosition, offset: u64, data: Vec<u8>, done: bool) {
+        if position.index <= self.commit_index {
+            return;
+        }
+        let incoming = self.incoming_snapshot.get_or_insert_with(|| IncomingSnapshot { position, data: Vec::new() });
+        if incoming.position != position || offset == 0 {
+            *incoming = IncomingSnapshot { position, data: Vec::new() };
+        }
+        // A chunk sent again after its answer was lost replaces the earlier copy
+        let Ok(offset) = usize::try_from(offset) else {
+            return;
+        };
+        if offset > incoming.data.len() {
+            self.incoming_snapshot = None;
+            return;
+        }
+        incoming.data.truncate(offset);
+        incoming.data.extend_from_slice(&data);
+        if !done {
+            return;
+        }
+
+        let Some(snapshot) = self.incoming_snapshot.take() else {
+            return;
+        };
+        if let Err(e) = self.install_snapshot(position, &snapshot.data) {
+            warn!("Failed to install a snapshot of group {}: {}", self.group_id, e);
+        }
+    }
+
+    fn install_snapshot(&mut self, position: LogPosition, data: &[u8]) -> Result<()> {
+        // A new replica of a partition being merged away would only be
+        // removed again, after overwriting data its neighbour took over
+        if self.membership.configs.is_empty() && StateMachine::is_frozen_snapshot(data)? {
+            return Ok(());
+        }
+        self.state_machine.install_snapshot(data)?;
+        self.log.compact_to(position)?;
+        self.refresh_membership();
+        self.commit_index = self.commit_index.max(position.index);
+        self.verified_index = self.verified_index.max(position.index);
+        self.read_state.on_applied(position.index);
+        info!("Group {} installed a snapshot at index {}", self.group_id, position.index);
+        Ok(())
+    }
+
+    fn handle_request_vote(
+        &mut self,
+        term: u64,
+        candidate: &NodeId,
+        last_log_index: u64,
+        last_log_term: u64,
+        pre_vote: bool,
+        transfer: bool,
+    ) -> bool {
+        // Stick with a leader heard from within the minimum election
+        // timeout, unless it handed over to the candidate
+        let (min_ticks, _) = self.election_ticks();
+        if self.leader.is_some() && self.election_elapsed < min_ticks && !transfer {
+            return false;
+        }
+        let up_to_date = (last_log_term, last_log_index) >= (self.log.last_term(), self.log.last_index());
+        if pre_vote {
+            return term > self.term() && up_to_date;
+        }
+
+        if term > self.term() {
+            self.become_follower(term, None);
+        }
+        if term < self.term() {
+            return false;
+        }
+        let can_vote = self.hard_state.voted_for.as_ref().is_none_or(|voted| voted == candidate);
+        if !(up_to_date && can_vote) {
+            return false;
+        }
+        self.save_hard_state(term, Some(candidate.clone()));
+        self.reset_election_timeout();
+        true
+    }
+
+    fn handle_pre_vote_response(&mut self, from: &NodeId, term: u64) {
+        let next_term = self.term() + 1;
+        let Role::PreCandidate { votes } = &mut self.role else {
+            return;
+        };
+        if term != next_term {
+            return;
+        }
+        votes.insert(from.clone());
+        let votes = votes.clone();
+        if self.membership.is_quorum(|node| votes.contains(node)) {
+            self.campaign(false);
+        }
+    }
+
+    fn handle_vote_response(&mut self, from: &NodeId, vote_granted: bool) {
+        let Role::Candidate { votes } = &mut self.role else {
+            return;
+        };
+        if vote_granted {
+            votes.insert(from.clone());
+        }
+        let votes = votes.clone();
+        if self.membership.is_quorum(|node| votes.contains(node)) {
+            self.become_leader();
+        }
+    }
+
+    fn handle_append_response(&mut self, from: &NodeId, success: bool, match_index: u64) {
+        let Role::Leader(leader) = &mut self.role else {
+            return;
+        };
+        leader.active.insert(from.clone());
+        let Some(progress) = leader.progress.get_mut(from) else {
+            return;
+        };
+        progress.in_flight = None;
+        if success {
+            progress.match_index = progress.match_index.max(match_index);
+            progress.next_index = progress.match_index + 1;
+            self.maybe_commit();
+            self.maybe_send_timeout_now(from);
+        } else {
+            // Nothing matches: the replica needs a snapshot
+            progress.next_index = if match_index == 0 { 0 } else { match_index + 1 };
+        }
+        if self.needs_append(from) {
+            self.send_append(from);
+        }
+    }
+
+    fn handle_snapshot_response(&mut self, from: &NodeId) {
+        let Role::Leader(leader) = &mut self.role else {
+            return;
+        };
+        leader.active.insert(from.clone());
+        let Some(progress) = leader.progress.get_mut(from) else {
+            return;
+        };
+        progress.in_flight = None;
+        let Some(snapshot) = &mut progress.snapshot else {
+            return;
+        };
+        snapshot.offset = (snapshot.offset + SNAPSHOT_CHUNK_BYTES).min(snapshot.data.len());
+        if snapshot.offset == snapshot.data.len() {
+            progress.match_index = progress.match_index.max(snapshot.position.index);
+            progress.next_index = snapshot.position.index + 1;
+            progress.snapshot = None;
+        }
+        if self.needs_append(from) {
+            self.send_append(from);
+        }
+    }
+
+    // Whether the leader has entries or snapshot chunks `peer` still lacks
+    fn needs_append(&self, peer: &NodeId) -> bool {
+        let Role::Leader(leader) = &self.role else {
+            return false;
+        };
+        leader.progress.get(peer).is_some_and(|progress| {
+            progress.snapshot.is_some() || progress.next_index <= self.log.last_index()
+        })
+    }
+
+    // Tick the leader: heartbeat every replica, retry replication that
+    // stalled and step down if no quorum was heard from in a while
+    fn tick_leader(&mut self) -> Vec<(NodeId, GroupHeartbeat)> {
+        let (min_ticks, max_ticks) = self.election_ticks();
+        let single_voter = self.membership.is_quorum(|node| *node == self.id);
+        let Role::Leader(leader) = &mut self.role else {
+            return Vec::new();
+        };
+
+        for progress in leader.progress.values_mut() {
+            if let Some(ticks) = &mut progress.in_flight {
+                *ticks += 1;
+                if *ticks >= min_ticks {
+                    progress.in_flight = None;
+                }
+            }
+        }
+
+        if let Some((target, ticks)) = &mut leader.transfer {
+            *ticks += 1;
+            if *ticks >= max_ticks {
+                warn!("Leader of group {} gives up transferring leadership to {}", self.group_id, target);
+                leader.transfer = None;
+            }
+        }
+
+        leader.ticks_since_quorum_check += 1;
+        if leader.ticks_since_quorum_check >= max_ticks {
+            leader.ticks_since_quorum_check = 0;
+            let active = std::mem::take(&mut leader.active);
+            if !self.membership.is_quorum(|node| *node == self.id || active.contains(node)) {
+                warn!("Leader of group {} lost contact with a quorum", self.group_id);
+                self.become_follower(self.term(), None);
+                return Vec::new();
+            }
+        }
+
+        // Nobody else needs to acknowledge a lone voter's leadership
+        if single_voter {
+            let now = Instant::now();
+            leader.confirmed_at = Some(now);
+            self.read_state.on_heartbeat_quorum(now);
+        }
+
+        for peer in self.peers() {
+            if self.needs_append(&peer) {
+                self.send_append(&peer);
+            }
+        }
+
+        let heartbeat = GroupHeartbeat {
+            group_id: self.group_id,
+            term: self.term(),
+            leader: self.id.clone(),
+            leader_commit: self.commit_index,
+            leader_ms: timestamp_ms(),
+        };
+        self.peers().into_iter().map(|peer| (peer, heartbeat.clone())).collect()
+    }
+}
+
+impl RaftGroup for Replica {
+    fn tick(&mut self) -> Vec<(NodeId, GroupHeartbeat)> {
+        // Messages queued when it was removed went out since
+        if self.removed {
+            self.events.push(GroupEvent::Removed(self.group_id));
+            return Vec::new();
+        }
+        if let Some(term) = self.lead_at.take().filter(|term| *term >= self.term()) {
+            self.save_hard_state(term, Some(self.id.clone()));
+            self.become_leader();
+        }
+        // Retry entries that could not be applied before, and a joint
+        // configuration a former leader did not get to leave
+        self.apply_committed();
+        self.maybe_leave_joint();
+
+        if self.is_leader() {
+            return self.tick_leader();
+        }
+        self.election_elapsed += 1;
+        if self.election_elapsed >= self.election_timeout && self.membership.is_voter(&self.id) {
+            self.pre_campaign();
+        }
+        Vec::new()
+    }
+
+    fn on_heartbeat(&mut self, heartbeat: &GroupHeartbeat) -> GroupHeartbeatAck {
+        if heartbeat.term < self.term() {
+            return GroupHeartbeatAck { group_id: self.group_id, term: self.term(), success: false };
+        }
+        if heartbeat.term > self.term() || !matches!(self.role, Role::Follower) || self.leader.as_ref() != Some(&heartbeat.leader) {
+            self.become_follower(heartbeat.term, Some(heartbeat.leader.clone()));
+        }
+        self.election_elapsed = 0;
+        self.read_state.on_leader_heartbeat(heartbeat.leader_commit, heartbeat.leader_ms);
+        self.advance_follower_commit(heartbeat.leader_commit);
+        // Ask for entries if the log is not known to hold what the leader committed
+        let success = self.commit_index >= heartbeat.leader_commit;
+        GroupHeartbeatAck { group_id: self.group_id, term: self.term(), success }
+    }
+
+    fn on_heartbeat_ack(&mut self, from: &NodeId, ack: &GroupHeartbeatAck, sent_at: Instant) {
+        if ack.term > self.term() {
+            self.become_follower(ack.term, None);
+            return;
+        }
+        let Role::Leader(leader) = &mut self.role else {
+            return;
+        };
+        if ack.term != self.hard_state.term {
+            return;
+        }
+        leader.active.insert(from.clone());
+        let acked = leader.acked.entry(from.clone()).or_insert(sent_at);
+        *acked = (*acked).max(sent_at);
+
+        // The latest round that a quorum, counting this replica, acknowledged
+        let mut rounds: Vec<Instant> = leader.acked.values().copied().collect();
+        rounds.sort_unstable_by(|a, b| b.cmp(a));
+        let confirmed = rounds.into_iter().find(|round| {
+            self.membership.is_quorum(|node| *node == self.id || leader.acked.get(node).is_some_and(|at| at >= round))
+        });
+        if let Some(round) = confirmed {
+            leader.confirmed_at = Some(leader.confirmed_at.map_or(round, |at| at.max(round)));
+            self.read_state.on_heartbeat_quorum(round);
+        }
+
+        if !ack.success {
+            if let Some(progress) = leader.progress.get_mut(from) {
+                progress.next_index = progress.next_index.min(progress.match_index + 1);
+            }
+            self.send_append(from);
+        }
+    }
+
+    fn step(&mut self, from: &NodeId, message: RaftMessage) -> Option<RaftMessage> {
+        let is_request = message.is_request();
+        if message.term < self.term() {
+            // Tell a stale leader or candidate about the newer term
+            let body = match message.body {
+                MessageBody::AppendEntries { .. } => MessageBody::AppendEntriesResponse { success: false, match_index: 0 },
+                MessageBody::RequestVote { pre_vote, .. } => {
+                    MessageBody::RequestVoteResponse { vote_granted: false, pre_vote }
+                },
+                MessageBody::InstallSnapshot { .. } => MessageBody::InstallSnapshotResponse,
+                _ => return None,
+            };
+            return Some(RaftMessage { group_id: self.group_id, term: self.term(), body });
+        }
+
+        let body = match message.body {
+            MessageBody::AppendEntries { leader, prev_log_index, prev_log_term, entries, leader_commit } => {
+                self.follow(message.term, leader);
+                Some(self.handle_append_entries(from, prev_log_index, prev_log_term, entries, leader_commit))
+            },
+            MessageBody::InstallSnapshot {
+                leader,
+                last_included_index,
+                last_included_term,
+                offset,
+                data,
+                done,
+            } => {
+                self.follow(message.term, leader);
+                let position = LogPosition { index: last_included_index, term: last_included_term };
+                self.handle_install_snapshot(position, offset, data, done);
+                Some(MessageBody::InstallSnapshotResponse)
+            },
+            MessageBody::RequestVote { candidate, last_log_index, last_log_term, pre_vote, transfer } => {
+                let vote_granted = self.handle_request_vote(
+                    message.term,
+                    &candidate,
+                    last_log_index,
+                    last_log_term,
+                    pre_vote,
+                    transfer,
+                );
+                // A granted pre-vote answers in the term it was asked for
+                let term = if pre_vote && vote_granted { message.term } else { self.term() };
+                let body = MessageBody::RequestVoteResponse { vote_granted, pre_vote };
+                return Some(RaftMessage { group_id: self.group_id, term, body });
+            },
+            MessageBody::TimeoutNow { leader } => {
+                self.follow(message.term, leader);
+                if self.membership.is_voter(&self.id) {
+                    info!("Replica of group {} takes over leadership", self.group_id);
+                    self.campaign(true);
+                }
+                None
+            },
+            MessageBody::RequestVoteResponse { vote_granted: true, pre_vote: true } => {
+                self.handle_pre_vote_response(from, message.term);
+                None
+            },
+            response => {
+                if message.term > self.term() {
+                    self.become_follower(message.term, None);
+                    return None;
+                }
+                match response {
+                    MessageBody::AppendEntriesResponse { success, match_index } => {
+                        self.handle_append_response(from, success, match_index)
+                    },
+                    MessageBody::RequestVoteResponse { vote_granted, pre_vote: false } => {
+                        self.handle_vote_response(from, vote_granted)
+                    },
+                    MessageBody::InstallSnapshotResponse => self.handle_snapshot_response(from),
+                    _ => {},
+                }
+                None
+            },
+        };
+        debug_assert_eq!(body.is_some(), is_request);
+        body.map(|body| RaftMessage { group_id: self.group_id, term: self.term(), body })
+    }
+
+    fn take_messages(&mut self) -> Vec<(NodeId, RaftMessage)> {
+        std::mem::take(&mut self.messages)
+    }
+
+    fn take_events(&mut self) -> Vec<GroupEvent<Self>> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn address(&self, node: &NodeId) -> Option<&str> {
+        self.membership.addresses.get(node).map(String::as_str)
+    }
+
+    fn destroy(self) {
+        if let Err(e) = self.log.destroy() {
+            warn!("Failed to remove the log of group {}: {}", self.group_id, e);
+        }
+        // A partition merged away leaves its data to the one it merged into
+        let group_id = self.group_id;
+        let removed = if self.removed { self.state_machine.destroy() } else { Ok(()) };
+        if let Err(e) = removed {
+            warn!("Failed to remove the data of group {}: {}", group_id, e);
+        }
+    }
+}
+
+impl Replica {
+    // Accept `leader` as the leader of `term`, which is at least the current term
+    fn follow(&mut self, term: u64, leader: NodeId) {
+        if term > self.term() || !matches!(self.role, Role::Follower) || self.leader.as_ref() != Some(&leader) {
+            self.become_follower(term, Some(leader));
+        }
+        self.election_elapsed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multi_raft::MultiRaft;
+    use common::types::PartitionInfo;
+    use std::collections::HashSet;
+
+    /// Hosts of several nodes, connected by a network that delivers every
+    /// message at once unless the recipient is cut off.
+    struct Cluster {
+        name: String,
+        snapshot_threshold: u64,
+        hosts: Vec<(NodeId, MultiRaft<Replica>)>,
+        storages: HashMap<NodeId, Arc<Storage>>,
+        cut_off: HashSet<NodeId>,
+    }
+
+    impl Cluster {
+        fn new(name: &str, nodes: &[&str], snapshot_threshold: u64) -> Self {
+            let membership = Membership {
+                configs: vec![nodes.iter().map(|node| NodeId::from(*node)).collect()],
+                learners: Vec::new(),
+                addresses: nodes.iter().map(|node| (NodeId::from(*node), format!("{}:9090", node))).collect(),
+            };
+            let mut cluster = Self {
+                name: name.to_string(),
+                snapshot_threshold,
+                hosts: Vec::new(),
+                storages: HashMap::new(),
+                cut_off: HashSet::new(),
+            };
+            for node in nodes {
+                cluster.add_host(node);
+                let node = NodeId::from(*node);
+                let config = NodeConfig { node_id: node.clone(), snapshot_threshold, ..NodeConfig::default() };
+                let storage = Arc::clone(&cluster.storages[&node]);
+                let replica =
+                    Replica::bootstrap(storage, 1, KeyRange::new("a", "z"), membership.clone(), &config).unwrap();
+                cluster.host(&node).add_group(1, replica).unwrap();
+            }
+            cluster
+        }
+
+        // A host without any group yet
+        fn add_host(&mut self, node: &str) {
+            let config = NodeConfig {
+                node_id: NodeId::from(node),
+                snapshot_threshold: self.snapshot_threshold,
+                ..NodeConfig::default()
+            };
+            let storage = open_storage(&format!("{}_{}", self.name, node));
+            self.storages.insert(NodeId::from(node), Arc::clone(&storage));
+            let mut host = MultiRaft::new(&config);
+            host.set_group_factory(Box::new(move |group_id| Replica::open(Arc::clone(&storage), group_id, &config)));
+            self.hosts.push((NodeId::from(node), host));
+        }
+
+        fn host(&mut self, node: &NodeId) -> &mut MultiRaft<Replica> {
+            &mut self.hosts.iter_mut().find(|(id, _)| id == node).unwrap().1
+        }
+
+        fn tick(&mut self) {
+            for i in 0..self.hosts.len() {
+                let from = self.hosts[i].0.clone();
+                let batches = self.hosts[i].1.tick();
+                for (peer, heartbeats) in batches {
+                    if self.cut_off.contains(&peer) || self.cut_off.contains(&from) {
+                        continue;
+                    }
+                    let acks = self.host(&peer).handle_heartbeats(&heartbeats);
+                    self.host(&from).handle_heartbeat_acks(&peer, &acks, Instant::now());
+                }
+                self.deliver();
+            }
+        }
+
+        fn deliver(&mut self) {
+            loop {
+                let mut messages = Vec::new();
+                for (from, host) in &mut self.hosts {
+                    messages.extend(host.take_messages().into_iter().map(|(to, _, message)| (from.clone(), to, message)));
+                }
+                if messages.is_empty() {
+                    return;
+                }
+                for (from, to, message) in messages {
+                    if self.cut_off.contains(&from) || self.cut_off.contains(&to) {
+                        continue;
+                    }
+                    if let Some(answer) = self.host(&to).step(&from, message) {
+                        self.host(&from).step(&to, answer);
+                    }
+                }
+            }
+        }
+
+        fn run(&mut self, ticks: usize) {
+            for _ in 0..ticks {
+                self.tick();
+            }
+        }
+
+        fn leader(&mut self, group_id: u64) -> NodeId {
+            let leaders: Vec<NodeId> = self.hosts.iter()
+                .filter(|(node, host)| {
+                    !self.cut_off.contains(node) && host.group(group_id).is_some_and(|replica| replica.is_leader())
+                })
+                .map(|(node, _)| node.clone())
+                .collect();
+            assert_eq!(leaders.len(), 1, "Expected one leader of group {}, got {:?}", group_id, leaders);
+            leaders[0].clone()
+        }
+
+        fn write(&mut self, group_id: u64, key: &str, value: &str) -> CommandResponse {
+            let leader = self.leader(group_id);
+            let command = Command::Write { key: key.to_string(), value: value.as_bytes().to_vec(), expires_at: None };
+            let mut answer = self.host(&leader).group_mut(group_id).unwrap()
+                .propose(command, HlcTimestamp::new(timestamp_ms(), 0))
+                .unwrap();
+            self.deliver();
+            self.run(1);
+            answer.try_recv().unwrap().unwrap()
+        }
+
+        fn value(&mut self, node: &str, group_id: u64, key: &str) -> Option<Vec<u8>> {
+            let replica = self.host(&NodeId::from(node)).group(group_id).unwrap();
+            replica.state_machine().storage().get(key).unwrap().map(|value| value.value)
+        }
+
+        // Run until the answer to a proposal arrives
+        fn wait(&mut self, mut answer: ProposalResult) -> CommandResponse {
+            for _ in 0..20 {
+                self.deliver();
+                if let Ok(response) = answer.try_recv() {
+                    return response.unwrap();
+                }
+                self.run(1);
+            }
+            panic!("No answer to the proposal");
+        }
+    }
+
+    fn voters(nodes: &[&str]) -> Vec<NodeId> {
+        nodes.iter().map(|node| NodeId::from(*node)).collect()
+    }
+
+    fn open_storage(name: &str) -> Arc<Storage> {
+        let path = std::env::temp_dir().join(format!("raft_node_replica_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        Arc::new(Storage::open(path).unwrap())
+    }
+
+    #[test]
+    fn test_elects_a_leader_and_replicates() {
+        let mut cluster = Cluster::new("replicate", &["n1", "n2", "n3"], 1000);
+        cluster.run(10);
+        let leader = cluster.leader(1);
+
+        assert!(cluster.write(1, "k", "v").succeeded);
+        for node in ["n1", "n2", "n3"] {
+            assert_eq!(cluster.value(node, 1, "k"), Some(b"v".to_vec()), "Missing on {}", node);
+        }
+
+        // Followers refuse proposals and point at the leader
+        let follower = cluster.hosts.iter().map(|(node, _)| node.clone()).find(|node| *node != leader).unwrap();
+        let replica = cluster.host(&follower).group_mut(1).unwrap();
+        let result = replica.propose(Command::Noop, HlcTimestamp::new(timestamp_ms(), 0));
+        assert!(matches!(result, Err(DatabaseError::NotLeader { leader: Some((hint, _)), .. }) if hint == leader));
+    }
+
+    #[test]
+    fn test_new_leader_after_leader_is_cut_off() {
+        let mut cluster = Cluster::new("failover", &["n1", "n2", "n3"], 1000);
+        cluster.run(10);
+        let old_leader = cluster.leader(1);
+        assert!(cluster.write(1, "a", "1").succeeded);
+
+        cluster.cut_off.insert(old_leader.clone());
+        cluster.run(20);
+        let new_leader = cluster.leader(1);
+        assert_ne!(new_leader, old_leader);
+        assert!(cluster.write(1, "b", "2").succeeded);
+
+        // The old leader steps down for lack of a quorum and catches up once back
+        assert!(!cluster.host(&old_leader).group(1).unwrap().is_leader());
+        cluster.cut_off.clear();
+        cluster.run(10);
+        assert_eq!(cluster.leader(1), new_leader);
+        assert_eq!(cluster.value(&old_leader.0, 1, "b"), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn test_lagging_replica_catches_up_from_snapshot() {
+        let mut cluster = Cluster::new("snapshot", &["n1", "n2", "n3"], 5);
+        cluster.run(10);
+        let leader = cluster.leader(1);
+        let lagging = cluster.hosts.iter().map(|(node, _)| node.clone()).find(|node| *node != leader).unwrap();
+
+        cluster.cut_off.insert(lagging.clone());
+        for i in 0..20 {
+            assert!(cluster.write(1, &format!("k{:02}", i), &i.to_string()).succeeded);
+        }
+        assert!(cluster.host(&leader).group(1).unwrap().log.start().index > 0, "The leader compacted its log");
+
+        cluster.cut_off.clear();
+        cluster.run(5);
+        let replica = cluster.host(&lagging).group(1).unwrap();
+        assert_eq!(replica.commit_index(), cluster.host(&leader).group(1).unwrap().commit_index());
+        for i in 0..20 {
+            assert_eq!(cluster.value(&lagging.0, 1, &format!("k{:02}", i)), Some(i.to_string().into_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_split_starts_the_right_group_under_the_same_leader() {
+        let mut cluster = Cluster::new("split", &["n1", "n2", "n3"], 1000);
+        cluster.run(10);
+        let leader = cluster.leader(1);
+        assert!(cluster.write(1, "apple", "1").succeeded);
+        assert!(cluster.write(1, "pear", "2").succeeded);
+
+        let partition = |id, start: &str, end: &str| PartitionInfo {
+            id,
+            range: KeyRange::new(start, end),
+            leader: leader.clone(),
+            followers: Vec::new(),
+            learners: Vec::new(),
+        };
+        let split = Command::SplitPartition { left: partition(1, "a", "m"), right: partition(2, "m", "z") };
+        let mut answer = cluster.host(&leader).group_mut(1).unwrap()
+            .propose(split, HlcTimestamp::new(timestamp_ms(), 0))
+            .unwrap();
+        cluster.deliver();
+        cluster.run(2);
+        assert!(answer.try_recv().unwrap().unwrap().succeeded);
+
+        assert_eq!(cluster.leader(2), leader);
+        for node in ["n1", "n2", "n3"] {
+            let right = cluster.host(&NodeId::from(node)).group(2).expect("Every replica hosts the new group");
+            assert_eq!(right.state_machine().range(), Some(&KeyRange::new("m", "z")));
+        }
+        assert!(cluster.write(2, "plum", "3").succeeded);
+        assert_eq!(cluster.value("n2", 2, "plum"), Some(b"3".to_vec()));
+        assert!(!cluster.write(1, "plum", "4").succeeded, "The left group no longer owns the key");
+    }
+
+    #[test]
+    fn test_learner_catches_up_and_is_promoted() {
+        let mut cluster = Cluster::new("promote", &["n1", "n2", "n3"], 5);
+        cluster.run(10);
+        for i in 0..10 {
+            assert!(cluster.write(1, &format!("k{}", i), &i.to_string()).succeeded);
+        }
+
+        cluster.add_host("n4");
+        let leader = cluster.leader(1);
+        let answer = cluster.host(&leader).group_mut(1).unwrap()
+            .add_learner(NodeId::from("n4"), "n4:9090".to_string(), HlcTimestamp::new(timestamp_ms(), 0))
+            .unwrap();
+        assert!(cluster.wait(answer).succeeded);
+        cluster.run(3);
+        let last_index = cluster.host(&leader).group(1).unwrap().log.last_index();
+        assert_eq!(cluster.host(&leader).group(1).unwrap().match_index(&NodeId::from("n4")), Some(last_index));
+        assert_eq!(cluster.value("n4", 1, "k9"), Some(b"9".to_vec()), "The learner caught up from a snapshot");
+        assert!(!cluster.host(&NodeId::from("n4")).group(1).unwrap().membership().is_voter(&NodeId::from("n4")));
+
+        let answer = cluster.host(&leader).group_mut(1).unwrap()
+            .change_membership(voters(&["n1", "n2", "n3", "n4"]), Vec::new(), HlcTimestamp::new(timestamp_ms(), 0))
+            .unwrap();
+        assert!(cluster.wait(answer).succeeded);
+        cluster.run(1);
+        for node in ["n1", "n2", "n3", "n4"] {
+            let membership = cluster.host(&NodeId::from(node)).group(1).unwrap().membership().clone();
+            assert_eq!(membership.configs, vec![voters(&["n1", "n2", "n3", "n4"])], "Joint configuration left on {}", node);
+        }
+
+        // Four voters need three of them to make progress
+        cluster.cut_off.insert(leader.clone());
+        cluster.run(20);
+        assert!(cluster.write(1, "after", "1").succeeded);
+        assert_eq!(cluster.value("n4", 1, "after"), Some(b"1".to_vec()));
+    }
+
+    #[test]
+    fn test_removed_follower_drops_its_replica() {
+        let mut cluster = Cluster::new("remove", &["n1", "n2", "n3"], 1000);
+        cluster.run(10);
+        assert!(cluster.write(1, "k", "v").succeeded);
+        let leader = cluster.leader(1);
+        let removed = cluster.hosts.iter().map(|(node, _)| node.clone()).find(|node| *node != leader).unwrap();
+
+        let remaining: Vec<NodeId> = voters(&["n1", "n2", "n3"]).into_iter().filter(|node| *node != removed).collect();
+        let answer = cluster.host(&leader).group_mut(1).unwrap()
+            .change_membership(remaining.clone(), Vec::new(), HlcTimestamp::new(timestamp_ms(), 0))
+            .unwrap();
+        assert!(cluster.wait(answer).succeeded);
+        cluster.run(2);
+
+        assert!(cluster.host(&removed).group(1).is_none(), "The removed node no longer hosts the group");
+        assert_eq!(cluster.storages[&removed].get("k").unwrap(), None, "Its copy of the data is gone");
+        assert_eq!(cluster.host(&leader).group(1).unwrap().membership().configs, vec![remaining]);
+        assert!(cluster.write(1, "k2", "v2").succeeded);
+    }
+
+    #[test]
+    fn test_removed_leader_steps_down() {
+        let mut cluster = Cluster::new("remove_leader", &["n1", "n2", "n3"], 1000);
+        cluster.run(10);
+        let leader = cluster.leader(1);
+        let remaining: Vec<NodeId> = voters(&["n1", "n2", "n3"]).into_iter().filter(|node| *node != leader).collect();
+
+        let answer = cluster.host(&leader).group_mut(1).unwrap()
+            .change_membership(remaining.clone(), Vec::new(), HlcTimestamp::new(timestamp_ms(), 0))
+            .unwrap();
+        assert!(cluster.wait(answer).succeeded);
+        cluster.run(20);
+
+        assert!(cluster.host(&leader).group(1).is_none());
+        let new_leader = cluster.leader(1);
+        assert!(remaining.contains(&new_leader));
+        assert!(cluster.write(1, "k", "v").succeeded);
+    }
+
+    #[test]
+    fn test_leadership_is_transferred_to_a_voter() {
+        let mut cluster = Cluster::new("transfer", &["n1", "n2", "n3"], 1000);
+        cluster.run(10);
+        assert!(cluster.write(1, "k", "v").succeeded);
+        let leader = cluster.leader(1);
+        let target = cluster.hosts.iter().map(|(node, _)| node.clone()).find(|node| *node != leader).unwrap();
+
+        let replica = cluster.host(&leader).group_mut(1).unwrap();
+        replica.transfer_leader(&target).unwrap();
+        let result = replica.propose(Command::Noop, HlcTimestamp::new(timestamp_ms(), 0));
+        assert!(matches!(result, Err(DatabaseError::Raft { .. })), "No proposals while handing over");
+        assert!(replica.transfer_leader(&NodeId::from("n9")).is_err(), "Only voters lead");
+
+        cluster.deliver();
+        cluster.run(2);
+        assert_eq!(cluster.leader(1), target);
+        assert!(cluster.write(1, "k2", "v2").succeeded);
+        assert_eq!(cluster.value(&leader.0, 1, "k2"), Some(b"v2".to_vec()));
+    }
+
+    #[test]
+    fn test_replica_recovers_its_log_and_term_after_restart() {
+        let storage = open_storage("restart");
+        let config = NodeConfig { node_id: NodeId::from("n1"), ..NodeConfig::default() };
+        let membership = Membership {
+            configs: vec![vec![NodeId::from("n1")]],
+            learners: Vec::new(),
+            addresses: HashMap::new(),
+        };
+        let mut replica =
+            Replica::bootstrap(Arc::clone(&storage), 1, KeyRange::new("a", "z"), membership.clone(), &config).unwrap();
+        while !replica.is_leader() {
+            replica.tick();
+        }
+        let command = Command::Write { key: "k".to_string(), value: b"v".to_vec(), expires_at: None };
+        let mut answer = replica.propose(command, HlcTimestamp::new(timestamp_ms(), 0)).unwrap();
+        assert!(answer.try_recv().unwrap().unwrap().succeeded);
+        let (term, last_index) = (replica.term(), replica.log.last_index());
+        drop(replica);
+
+        let replica = Replica::bootstrap(storage, 1, KeyRange::new("a", "z"), membership, &config).unwrap();
+        assert_eq!(replica.term(), term);
+        assert_eq!(replica.log.last_index(), last_index);
+        assert_eq!(replica.state_machine().last_applied(), last_index);
+        assert!(!replica.is_leader(), "Leadership is not persisted");
+    }
+}
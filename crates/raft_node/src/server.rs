@@ -0,0 +1,561 @@
+//! The gRPC services of a data node.
+//!
+//! The node service serves the coordinator and routing clients, the Raft
+//! service the node's peers. Both are thin: requests are converted and handed
+//! to the `Node` or to the groups it hosts.
+
+use crate::message::{MessageBody, RaftMessage};
+use crate::node::Node;
+use crate::transport::decode_entry;
+use common::error::{DatabaseError, Result as DbResult};
+use common::hlc::HlcTimestamp;
+use common::types::{BatchOperation, Command, CommandResponse, NodeId, ReadConsistency, ReadOutcome, TransactionId};
+use log::info;
+use rpc::proto::node::conditional_write_request::Condition;
+use rpc::proto::node::node_service_server::{NodeService, NodeServiceServer};
+use rpc::proto::node::{
+    AbortLockWaitsRequest, AbortLockWaitsResponse, AcquireLocksRequest, AcquireLocksResponse, AddLearnerRequest,
+    AddLearnerResponse, BatchRequest, BatchResponse, ChangeMembershipRequest, ChangeMembershipResponse,
+    ConditionalWriteRequest, ConditionalWriteResponse, EndTransactionRequest, EndTransactionResponse,
+    IncrementRequest, IncrementResponse, KeyValue, NodeLoadRequest, NodeLoadResponse, PartitionStatsRequest,
+    PartitionStatsResponse, PrewriteRequest, PrewriteResponse, ProposeRequest, ProposeResponse, ReadRequest, ReadResponse, RecoverTransactionRequest,
+    RecoverTransactionResponse, ReleaseLocksRequest, ReleaseLocksResponse, ResolveIntentsRequest,
+    ResolveIntentsResponse, ScanRequest, ScanResponse, StatusRequest, StatusResponse, TransferLeaderRequest,
+    TransferLeaderResponse, ValidateReadsRequest, ValidateReadsResponse, WaitForGraphRequest, WaitForGraphResponse,
+    WriteRequest, WriteResponse,
+};
+use rpc::proto::raft::raft_service_server::{RaftService, RaftServiceServer};
+use rpc::proto::raft::{
+    AppendEntriesRequest, AppendEntriesResponse, HeartbeatBatchRequest, HeartbeatBatchResponse,
+    InstallSnapshotRequest, InstallSnapshotResponse, RequestVoteRequest, RequestVoteResponse, TimeoutNowRequest,
+    TimeoutNowResponse,
+};
+use rpc::routing::{RouteError, Routed};
+use rpc::status::to_status;
+use std::sync::Arc;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+// Node service implementation
+pub struct NodeServiceImpl {
+    node: Arc<Node>,
+}
+
+impl NodeServiceImpl {
+    pub fn new(node: Arc<Node>) -> Self {
+        Self { node }
+    }
+
+    // Helper to advance the local clock past the caller's clock reading.
+    // Callers whose clock runs too far ahead are rejected.
+    #[allow(clippy::result_large_err)]
+    fn observe_clock(&self, hlc: Option<rpc::proto::node::HlcTimestamp>) -> Result<(), Status> {
+        if let Some(hlc) = hlc {
+            self.node.clock().update(hlc.into()).map_err(to_status)?;
+        }
+        Ok(())
+    }
+
+    // The local clock reading every response carries
+    fn hlc(&self) -> Option<rpc::proto::node::HlcTimestamp> {
+        Some(self.node.clock().now().into())
+    }
+
+    // The route error turning down a request routed by the partition map at
+    // `metadata_version`
+    fn route_error(&self, error: RouteError, metadata_version: u64) -> rpc::proto::node::RouteError {
+        error.at_version(metadata_version, self.node.metadata_version()).into()
+    }
+
+    // Helper to read the consistency of a read or scan
+    fn read_consistency(consistency: i32, max_staleness_ms: u64) -> ReadConsistency {
+        match rpc::proto::node::ReadConsistency::try_from(consistency) {
+            Ok(rpc::proto::node::ReadConsistency::Stale) => ReadConsistency::Stale { max_staleness_ms },
+            _ => ReadConsistency::Linearizable,
+        }
+    }
+
+    // Helper to propose a command whose response has no room for a route
+    // error, which is returned as the error status instead
+    async fn propose(&self, key: &str, command: Command) -> Result<CommandResponse, Status> {
+        match self.node.propose(key, command).await.map_err(to_status)? {
+            Routed::Served(response) => Ok(response),
+            Routed::Misrouted(error) => Err(to_status(error.into())),
+        }
+    }
+
+    // A transaction command failed because the transaction already ended
+    fn settled(response: &CommandResponse) -> Status {
+        let status = response.transaction.as_ref().map(|record| record.status);
+        to_status(DatabaseError::Transaction(format!("Transaction is no longer pending: {:?}", status)))
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn batch_operations(operations: Vec<rpc::proto::node::BatchOperation>) -> Result<Vec<BatchOperation>, Status> {
+        operations.into_iter().map(|operation| operation.try_into().map_err(to_status)).collect()
+    }
+}
+
+#[tonic::async_trait]
+impl NodeService for NodeServiceImpl {
+    async fn write(&self, request: Request<WriteRequest>) -> Result<Response<WriteResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let expires_at = Some(req.expires_at_ms).filter(|ms| *ms > 0);
+        let command = Command::Write { key: req.key.clone(), value: req.value, expires_at };
+        let (route_error, blocked_by) = match self.node.propose(&req.key, command).await.map_err(to_status)? {
+            Routed::Served(response) => (None, response.conflict.map(Into::into)),
+            Routed::Misrouted(error) => (Some(self.route_error(error, req.metadata_version)), None),
+        };
+        Ok(Response::new(WriteResponse { hlc: self.hlc(), route_error, blocked_by }))
+    }
+
+    async fn read(&self, request: Request<ReadRequest>) -> Result<Response<ReadResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let read_ts = req.read_timestamp.map(HlcTimestamp::from);
+        let consistency = Self::read_consistency(req.consistency, req.max_staleness_ms);
+        let response = match self.node.read(&req.key, read_ts, consistency).await.map_err(to_status)? {
+            Routed::Served(ReadOutcome::Read(Some(value))) => ReadResponse {
+                found: true,
+                value: value.value,
+                version: value.version,
+                ..Default::default()
+            },
+            Routed::Served(ReadOutcome::Read(None)) => ReadResponse::default(),
+            Routed::Served(ReadOutcome::Blocked { txn, .. }) => {
+                ReadResponse { blocked_by: Some(txn.into()), ..Default::default() }
+            },
+            Routed::Misrouted(error) => {
+                ReadResponse { route_error: Some(self.route_error(error, req.metadata_version)), ..Default::default() }
+            },
+        };
+        Ok(Response::new(ReadResponse { hlc: self.hlc(), ..response }))
+    }
+
+    async fn scan(&self, request: Request<ScanRequest>) -> Result<Response<ScanResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let read_ts = req.read_timestamp.map(HlcTimestamp::from);
+        let consistency = Self::read_consistency(req.consistency, req.max_staleness_ms);
+        let limit = usize::try_from(req.limit).unwrap_or(0);
+        let scanned = self.node.scan(&req.start_key, &req.end_key, limit, read_ts, consistency).await;
+        let response = match scanned.map_err(to_status)? {
+            Routed::Served(ReadOutcome::Read((items, has_more))) => ScanResponse {
+                items: items.into_iter().map(|(key, value)| KeyValue { key, value }).collect(),
+                has_more,
+                ..Default::default()
+            },
+            Routed::Served(ReadOutcome::Blocked { key, txn }) => {
+                ScanResponse { blocked_by: Some(txn.into()), blocked_key: key, ..Default::default() }
+            },
+            Routed::Misrouted(error) => ScanResponse { route_error: Some(error.into()), ..Default::default() },
+        };
+        Ok(Response::new(ScanResponse { hlc: self.hlc(), ..response }))
+    }
+
+    async fn batch(&self, request: Request<BatchRequest>) -> Result<Response<BatchResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let operations = Self::batch_operations(req.operations)?;
+        let Some(key) = operations.first().map(|operation| operation.key().to_string()) else {
+            return Ok(Response::new(BatchResponse { hlc: self.hlc(), ..Default::default() }));
+        };
+        let (route_error, blocked_by) = match self.node.propose(&key, Command::Batch { operations }).await.map_err(to_status)? {
+            Routed::Served(response) => (None, response.conflict.map(Into::into)),
+            Routed::Misrouted(error) => (Some(self.route_error(error, req.metadata_version)), None),
+        };
+        Ok(Response::new(BatchResponse { hlc: self.hlc(), route_error, blocked_by }))
+    }
+
+    async fn conditional_write(
+        &self,
+        request: Request<ConditionalWriteRequest>,
+    ) -> Result<Response<ConditionalWriteResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let key = req.key;
+        let command = match req.condition {
+            Some(Condition::PutIfAbsent(value)) => Command::PutIfAbsent { key: key.clone(), value },
+            Some(Condition::PutIfVersion(put)) => Command::PutIfVersion {
+                key: key.clone(),
+                value: put.value,
+                expected_version: put.expected_version,
+            },
+            Some(Condition::DeleteIfValue(expected_value)) => Command::DeleteIfValue { key: key.clone(), expected_value },
+            None => return Err(Status::invalid_argument("Conditional write needs a condition")),
+        };
+        let response = self.propose(&key, command).await?;
+        let current = response.current.unwrap_or_default();
+        Ok(Response::new(ConditionalWriteResponse {
+            applied: response.succeeded,
+            found: current.version > 0,
+            value: current.value,
+            version: current.version,
+            hlc: self.hlc(),
+        }))
+    }
+
+    async fn increment(&self, request: Request<IncrementRequest>) -> Result<Response<IncrementResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let response = self.propose(&req.key, Command::Increment { key: req.key.clone(), delta: req.delta }).await?;
+        let value = response.current
+            .filter(|_| response.succeeded)
+            .and_then(|current| String::from_utf8(current.value).ok())
+            .and_then(|value| value.parse::<i64>().ok())
+            .ok_or_else(|| {
+                Status::invalid_argument(format!("Value of {} is not an integer, or the increment overflows", req.key))
+            })?;
+        Ok(Response::new(IncrementResponse { value, hlc: self.hlc() }))
+    }
+
+    async fn prewrite(&self, request: Request<PrewriteRequest>) -> Result<Response<PrewriteResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let txn = req.txn.ok_or_else(|| Status::invalid_argument("Prewrite needs a transaction"))?.into();
+        let operations = Self::batch_operations(req.operations)?;
+        let Some(key) = operations.first().map(|operation| operation.key().to_string()) else {
+            return Ok(Response::new(PrewriteResponse { conflict: None, hlc: self.hlc() }));
+        };
+        let response = self.propose(&key, Command::Prewrite { txn, operations, txn_keys: req.txn_keys }).await?;
+        if !response.succeeded && response.conflict.is_none() {
+            return Err(Self::settled(&response));
+        }
+        Ok(Response::new(PrewriteResponse { conflict: response.conflict.map(Into::into), hlc: self.hlc() }))
+    }
+
+    async fn end_transaction(
+        &self,
+        request: Request<EndTransactionRequest>,
+    ) -> Result<Response<EndTransactionResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let txn: common::types::TransactionMeta =
+            req.txn.ok_or_else(|| Status::invalid_argument("EndTransaction needs a transaction"))?.into();
+        let key = txn.primary_key.clone();
+        let commit_ts = req.commit_ts.map(HlcTimestamp::from);
+        let response = self.propose(&key, Command::EndTransaction { txn, commit_ts }).await?;
+        Ok(Response::new(EndTransactionResponse { record: response.transaction.map(Into::into), hlc: self.hlc() }))
+    }
+
+    async fn resolve_intents(
+        &self,
+        request: Request<ResolveIntentsRequest>,
+    ) -> Result<Response<ResolveIntentsResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        if let Some(key) = req.keys.first().cloned() {
+            let commit_ts = req.commit_ts.map(HlcTimestamp::from);
+            let command = Command::ResolveIntents { txn_id: TransactionId(req.txn_id), keys: req.keys, commit_ts };
+            self.propose(&key, command).await?;
+        }
+        Ok(Response::new(ResolveIntentsResponse { hlc: self.hlc() }))
+    }
+
+    async fn recover_transaction(
+        &self,
+        request: Request<RecoverTransactionRequest>,
+    ) -> Result<Response<RecoverTransactionResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let txn: common::types::TransactionMeta =
+            req.txn.ok_or_else(|| Status::invalid_argument("RecoverTransaction needs a transaction"))?.into();
+        let key = txn.primary_key.clone();
+        let command = Command::RecoverTransaction { txn, abandoned_before_ms: req.abandoned_before_ms };
+        let response = self.propose(&key, command).await?;
+        Ok(Response::new(RecoverTransactionResponse {
+            record: response.transaction.map(Into::into),
+            hlc: self.hlc(),
+        }))
+    }
+
+    async fn validate_reads(
+        &self,
+        request: Request<ValidateReadsRequest>,
+    ) -> Result<Response<ValidateReadsResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let txn = req.txn.ok_or_else(|| Status::invalid_argument("ValidateReads needs a transaction"))?.into();
+        let Some(key) = req.keys.first().cloned() else {
+            return Ok(Response::new(ValidateReadsResponse { conflict: None, hlc: self.hlc() }));
+        };
+        let response = self.propose(&key, Command::ValidateReads { txn, keys: req.keys, ranges: Vec::new() }).await?;
+        if !response.succeeded && response.conflict.is_none() {
+            return Err(to_status(DatabaseError::Conflict {
+                key: response.conflict_key,
+                message: "A key the transaction read was written since it started".to_string(),
+            }));
+        }
+        Ok(Response::new(ValidateReadsResponse { conflict: response.conflict.map(Into::into), hlc: self.hlc() }))
+    }
+
+    async fn propose(&self, request: Request<ProposeRequest>) -> Result<Response<ProposeResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let command: Command = serde_json::from_slice(&req.command)
+            .map_err(|e| Status::invalid_argument(format!("Invalid command: {}", e)))?;
+        let response = match self.node.propose_to(req.partition_id, command).await.map_err(to_status)? {
+            Routed::Served(response) => ProposeResponse {
+                response: serde_json::to_vec(&response).map_err(|e| to_status(e.into()))?,
+                ..Default::default()
+            },
+            Routed::Misrouted(error) => ProposeResponse { route_error: Some(error.into()), ..Default::default() },
+        };
+        Ok(Response::new(ProposeResponse { hlc: self.hlc(), ..response }))
+    }
+
+    async fn acquire_locks(
+        &self,
+        request: Request<AcquireLocksRequest>,
+    ) -> Result<Response<AcquireLocksResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let start_ts = req.start_ts.map(HlcTimestamp::from).unwrap_or_default();
+        let txn_id = TransactionId(req.txn_id);
+        match self.node.acquire_locks(&txn_id, start_ts, &req.keys).await.map_err(to_status)? {
+            Routed::Served(()) => Ok(Response::new(AcquireLocksResponse { hlc: self.hlc() })),
+            Routed::Misrouted(error) => Err(to_status(error.into())),
+        }
+    }
+
+    async fn release_locks(
+        &self,
+        request: Request<ReleaseLocksRequest>,
+    ) -> Result<Response<ReleaseLocksResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        self.node.release_locks(&TransactionId(req.txn_id));
+        Ok(Response::new(ReleaseLocksResponse { hlc: self.hlc() }))
+    }
+
+    async fn get_wait_for_graph(
+        &self,
+        request: Request<WaitForGraphRequest>,
+    ) -> Result<Response<WaitForGraphResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let edges = self.node.wait_for_edges().into_iter().map(Into::into).collect();
+        Ok(Response::new(WaitForGraphResponse { edges, hlc: self.hlc() }))
+    }
+
+    async fn abort_lock_waits(
+        &self,
+        request: Request<AbortLockWaitsRequest>,
+    ) -> Result<Response<AbortLockWaitsResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        self.node.abort_lock_waits(&TransactionId(req.txn_id), &req.reason);
+        Ok(Response::new(AbortLockWaitsResponse { hlc: self.hlc() }))
+    }
+
+    async fn add_learner(&self, request: Request<AddLearnerRequest>) -> Result<Response<AddLearnerResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let matched_index = self.node.add_learner(req.partition_id, NodeId(req.node_id), req.address)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(AddLearnerResponse { matched_index, hlc: self.hlc() }))
+    }
+
+    async fn change_membership(
+        &self,
+        request: Request<ChangeMembershipRequest>,
+    ) -> Result<Response<ChangeMembershipResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let voters = req.voters.into_iter().map(NodeId).collect();
+        let learners = req.learners.into_iter().map(NodeId).collect();
+        self.node.change_membership(req.partition_id, voters, learners).await.map_err(to_status)?;
+        Ok(Response::new(ChangeMembershipResponse { hlc: self.hlc() }))
+    }
+
+    async fn transfer_leader(
+        &self,
+        request: Request<TransferLeaderRequest>,
+    ) -> Result<Response<TransferLeaderResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        self.node.transfer_leader(req.partition_id, NodeId(req.target)).await.map_err(to_status)?;
+        Ok(Response::new(TransferLeaderResponse { hlc: self.hlc() }))
+    }
+
+    async fn get_partition_stats(
+        &self,
+        request: Request<PartitionStatsRequest>,
+    ) -> Result<Response<PartitionStatsResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let partitions = self.node.partition_stats().await.map_err(to_status)?;
+        Ok(Response::new(PartitionStatsResponse {
+            partitions: partitions.into_iter().map(Into::into).collect(),
+            hlc: self.hlc(),
+        }))
+    }
+
+    async fn get_node_load(&self, request: Request<NodeLoadRequest>) -> Result<Response<NodeLoadResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        let load = self.node.load().await.map_err(to_status)?;
+        Ok(Response::new(NodeLoadResponse {
+            node_id: self.node.id().0.clone(),
+            load: Some(load.into()),
+            hlc: self.hlc(),
+        }))
+    }
+
+    async fn get_status(&self, request: Request<StatusRequest>) -> Result<Response<StatusResponse>, Status> {
+        let req = request.into_inner();
+        self.observe_clock(req.hlc)?;
+        // The node hosts many groups; their terms and indexes are per group
+        let (hosted, led) = self.node.group_counts().await;
+        Ok(Response::new(StatusResponse {
+            node_id: self.node.id().0.clone(),
+            status: format!("Serving {} partitions, leading {}", hosted, led),
+            hlc: self.hlc(),
+            ..Default::default()
+        }))
+    }
+}
+
+// Raft service implementation
+pub struct RaftServiceImpl {
+    node: Arc<Node>,
+}
+
+impl RaftServiceImpl {
+    pub fn new(node: Arc<Node>) -> Self {
+        Self { node }
+    }
+
+    // Helper to hand a message to its group, returning the group's answer
+    async fn step(&self, from: String, message: RaftMessage) -> Option<RaftMessage> {
+        let host = self.node.host();
+        let mut host = host.lock().await;
+        host.step(&NodeId(from), message)
+    }
+
+    // The group of a message does not have a replica here
+    fn not_hosted(group_id: u64) -> Status {
+        to_status(DatabaseError::Partition {
+            partition: Some(group_id),
+            message: format!("Raft group {} has no replica on this node", group_id),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl RaftService for RaftServiceImpl {
+    async fn append_entries(
+        &self,
+        request: Request<AppendEntriesRequest>,
+    ) -> Result<Response<AppendEntriesResponse>, Status> {
+        let req = request.into_inner();
+        let entries = req.entries.iter().map(decode_entry).collect::<DbResult<_>>().map_err(to_status)?;
+        let message = RaftMessage {
+            group_id: req.group_id,
+            term: req.term,
+            body: MessageBody::AppendEntries {
+                leader: NodeId(req.leader_id.clone()),
+                prev_log_index: req.prev_log_index,
+                prev_log_term: req.prev_log_term,
+                entries,
+                leader_commit: req.leader_commit,
+            },
+        };
+        match self.step(req.leader_id, message).await {
+            Some(RaftMessage { group_id, term, body: MessageBody::AppendEntriesResponse { success, match_index } }) => {
+                Ok(Response::new(AppendEntriesResponse { term, success, match_index, group_id }))
+            },
+            _ => Err(Self::not_hosted(req.group_id)),
+        }
+    }
+
+    async fn request_vote(
+        &self,
+        request: Request<RequestVoteRequest>,
+    ) -> Result<Response<RequestVoteResponse>, Status> {
+        let req = request.into_inner();
+        let message = RaftMessage {
+            group_id: req.group_id,
+            term: req.term,
+            body: MessageBody::RequestVote {
+                candidate: NodeId(req.candidate_id.clone()),
+                last_log_index: req.last_log_index,
+                last_log_term: req.last_log_term,
+                pre_vote: req.pre_vote,
+                transfer: req.transfer,
+            },
+        };
+        match self.step(req.candidate_id, message).await {
+            Some(RaftMessage { group_id, term, body: MessageBody::RequestVoteResponse { vote_granted, pre_vote } }) => {
+                Ok(Response::new(RequestVoteResponse { term, vote_granted, group_id, pre_vote }))
+            },
+            _ => Err(Self::not_hosted(req.group_id)),
+        }
+    }
+
+    async fn install_snapshot(
+        &self,
+        request: Request<InstallSnapshotRequest>,
+    ) -> Result<Response<InstallSnapshotResponse>, Status> {
+        let req = request.into_inner();
+        let message = RaftMessage {
+            group_id: req.group_id,
+            term: req.term,
+            body: MessageBody::InstallSnapshot {
+                leader: NodeId(req.leader_id.clone()),
+                last_included_index: req.last_included_index,
+                last_included_term: req.last_included_term,
+                offset: req.offset,
+                data: req.data,
+                done: req.done,
+            },
+        };
+        match self.step(req.leader_id, message).await {
+            Some(RaftMessage { group_id, term, body: MessageBody::InstallSnapshotResponse }) => {
+                Ok(Response::new(InstallSnapshotResponse { term, group_id }))
+            },
+            _ => Err(Self::not_hosted(req.group_id)),
+        }
+    }
+
+    async fn timeout_now(&self, request: Request<TimeoutNowRequest>) -> Result<Response<TimeoutNowResponse>, Status> {
+        let req = request.into_inner();
+        let message = RaftMessage {
+            group_id: req.group_id,
+            term: req.term,
+            body: MessageBody::TimeoutNow { leader: NodeId(req.leader_id.clone()) },
+        };
+        self.step(req.leader_id, message).await;
+        Ok(Response::new(TimeoutNowResponse { term: req.term, group_id: req.group_id }))
+    }
+
+    async fn heartbeat(
+        &self,
+        request: Request<HeartbeatBatchRequest>,
+    ) -> Result<Response<HeartbeatBatchResponse>, Status> {
+        let req = request.into_inner();
+        let heartbeats: Vec<_> = req.heartbeats.into_iter().map(Into::into).collect();
+        let host = self.node.host();
+        let acks = host.lock().await.handle_heartbeats(&heartbeats);
+        Ok(Response::new(HeartbeatBatchResponse { acks: acks.into_iter().map(Into::into).collect() }))
+    }
+}
+
+pub async fn start_grpc_server(
+    addr: &str,
+    node: Arc<Node>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let node_service = NodeServiceImpl::new(node.clone());
+    let raft_service = RaftServiceImpl::new(node);
+
+    info!("Starting gRPC server on {}...", addr);
+    Server::builder()
+        .add_service(NodeServiceServer::new(node_service))
+        .add_service(RaftServiceServer::new(raft_service))
+        .serve(addr.parse()?)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,932 @@
+//! The replicated state machine that applies committed log entries.
+//!
+//! Every entry is applied in a single storage write batch that also records
+//! the entry's index as applied, together with the partition's range, so a
+//! node that crashes mid-way through its log neither loses nor repeats an
+//! entry when it replays the log on restart. Several groups share one
+//! storage engine; each keeps its applied state under its own group id.
+//!
+//! The applied state also holds the group's membership as of the last
+//! applied entry, so a snapshot of the state machine is all a replica needs
+//! to rejoin the group.
+
+use common::error::{DatabaseError, Result};
+use common::hlc::HlcTimestamp;
+use common::types::{
+    Command, CommandResponse, KeyRange, LogEntry, Membership, TransactionMeta, TransactionRecord, TransactionStatus,
+    VersionedValue, META_GROUP_ID,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use storage::{Batch, RangeSnapshot, RangeStats, Storage, WriteVersion};
+
+/// Name of the applied state among the state a group stores.
+const APPLIED_STATE: &[u8] = b"a";
+
+/// How far a group has applied its log, persisted with every entry.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct AppliedState {
+    index: u64,
+    #[serde(default)]
+    term: u64,
+    timestamp: HlcTimestamp,
+    /// Keys owned by the partition; `None` owns the whole keyspace.
+    range: Option<KeyRange>,
+    /// Range of the partition this one is being merged into, once frozen.
+    frozen_into: Option<KeyRange>,
+    #[serde(default)]
+    membership: Membership,
+}
+
+/// Applies committed Raft log entries to the node's storage engine.
+pub struct StateMachine {
+    storage: Arc<Storage>,
+    group_id: u64,
+    applied: AppliedState,
+}
+
+impl StateMachine {
+    /// State machine of group `group_id`, owning the whole keyspace unless
+    /// the group persisted a range. Picks up after the last entry the group
+    /// applied before a restart.
+    pub fn open(storage: Arc<Storage>, group_id: u64) -> Result<Self> {
+        let applied = Self::load(&storage, group_id)?.unwrap_or_default();
+        Ok(Self { storage, group_id, applied })
+    }
+
+    /// State machine of a partition owning `range`, or the range it persisted
+    /// since. Commands touching keys outside it are answered with
+    /// `out_of_range` and not applied.
+    pub fn for_range(storage: Arc<Storage>, group_id: u64, range: KeyRange) -> Result<Self> {
+        let applied = Self::load(&storage, group_id)?
+            .unwrap_or(AppliedState { range: Some(range), ..AppliedState::default() });
+        Ok(Self { storage, group_id, applied })
+    }
+
+    /// State machine of the meta group, which owns the reserved keys the
+    /// cluster metadata is stored under.
+    pub fn meta(storage: Arc<Storage>) -> Result<Self> {
+        Self::for_range(storage, META_GROUP_ID, KeyRange::meta())
+    }
+
+    /// Ids of the groups with a replica in `storage`, e.g. to reopen them
+    /// after a restart.
+    pub fn hosted_groups(storage: &Storage) -> Result<Vec<u64>> {
+        storage.groups_with_state(APPLIED_STATE)
+    }
+
+    fn load(storage: &Storage, group_id: u64) -> Result<Option<AppliedState>> {
+        match storage.group_state(group_id, APPLIED_STATE)? {
+            Some(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Id of the Raft group whose log this applies.
+    pub fn group_id(&self) -> u64 {
+        self.group_id
+    }
+
+    /// Keys owned by the partition, as changed by splits and merges.
+    pub fn range(&self) -> Option<&KeyRange> {
+        self.applied.range.as_ref()
+    }
+
+    /// Whether the partition was frozen to be merged into its left neighbour.
+    pub fn is_frozen(&self) -> bool {
+        self.applied.frozen_into.is_some()
+    }
+
+    /// Size of the partition's data and the key to split it at.
+    pub fn stats(&self) -> Result<RangeStats> {
+        let (start, end) = self.bounds();
+        self.storage.range_stats(start, end)
+    }
+
+    /// Index of the last entry applied to storage.
+    pub fn last_applied(&self) -> u64 {
+        self.applied.index
+    }
+
+    /// Term of the last entry applied to storage.
+    pub fn last_applied_term(&self) -> u64 {
+        self.applied.term
+    }
+
+    /// Replicas of the group as of the last applied entry.
+    pub fn membership(&self) -> &Membership {
+        &self.applied.membership
+    }
+
+    /// Persist the membership a new group starts with. A group that already
+    /// has one, e.g. from before a restart, keeps it.
+    pub fn initialize(&mut self, membership: Membership) -> Result<()> {
+        if !self.applied.membership.configs.is_empty() {
+            return Ok(());
+        }
+        let applied = AppliedState { membership, ..self.applied.clone() };
+        let mut batch = self.storage.batch();
+        batch.put_group_state(self.group_id, APPLIED_STATE, &serde_json::to_vec(&applied)?);
+        batch.commit()?;
+        self.applied = applied;
+        Ok(())
+    }
+
+    /// Everything a replica needs to take over the state as of the last
+    /// applied entry: the applied state followed by the partition's data.
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        let (start, end) = self.bounds();
+        let applied = serde_json::to_vec(&self.applied)?;
+        let mut raw = (applied.len() as u32).to_be_bytes().to_vec();
+        raw.extend_from_slice(&applied);
+        raw.extend_from_slice(&self.storage.range_snapshot(start, end)?.encode());
+        Ok(raw)
+    }
+
+    /// Whether a snapshot taken by `snapshot` is of a partition frozen for a merge.
+    pub fn is_frozen_snapshot(raw: &[u8]) -> Result<bool> {
+        let (applied, _) = Self::decode_snapshot(raw)?;
+        Ok(applied.frozen_into.is_some())
+    }
+
+    /// Replace the state with a snapshot taken by `snapshot` on another
+    /// replica. Data this replica held outside the snapshot's range, if the
+    /// partition was split since, is left to the group that owns it now.
+    pub fn install_snapshot(&mut self, raw: &[u8]) -> Result<()> {
+        let (applied, data) = Self::decode_snapshot(raw)?;
+        let data = RangeSnapshot::decode(data)?;
+
+        let mut batch = self.storage.batch();
+        let (start, end) = match &applied.range {
+            Some(range) => (range.start.as_str(), range.end.as_str()),
+            None => ("", ""),
+        };
+        batch.install_range_snapshot(start, end, &data)?;
+        batch.put_group_state(self.group_id, APPLIED_STATE, &serde_json::to_vec(&applied)?);
+        batch.commit()?;
+        self.applied = applied;
+        Ok(())
+    }
+
+    fn decode_snapshot(raw: &[u8]) -> Result<(AppliedState, &[u8])> {
+        let truncated = || DatabaseError::Storage { key: None, message: "Truncated snapshot".to_string() };
+        let len = raw.get(..4).ok_or_else(truncated)?;
+        let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        let applied = serde_json::from_slice(raw.get(4..4 + len).ok_or_else(truncated)?)?;
+        Ok((applied, &raw[4 + len..]))
+    }
+
+    fn bounds(&self) -> (&str, &str) {
+        match &self.applied.range {
+            Some(range) => (&range.start, &range.end),
+            None => ("", ""),
+        }
+    }
+
+    /// Remove the partition's data and applied state, once this node no
+    /// longer holds a replica of the group. A group without a range keeps
+    /// its data, which other groups may share.
+    pub fn destroy(self) -> Result<()> {
+        let mut batch = self.storage.batch();
+        if let Some(range) = &self.applied.range {
+            batch.install_range_snapshot(&range.start, &range.end, &RangeSnapshot::default())?;
+        }
+        batch.delete_group_state(self.group_id, APPLIED_STATE);
+        batch.commit()
+    }
+
+    /// The underlying storage engine, for serving reads.
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    /// Apply a committed log entry.
+    ///
+    /// Entries at or below `last_applied` have already been applied and are
+    /// skipped, so replaying the log after a restart is harmless. Conditional
+    /// commands are evaluated here, against the state produced by every earlier
+    /// entry, which keeps them linearizable. An entry that fails leaves
+    /// nothing behind and must be applied again before any later one.
+    pub fn apply(&mut self, entry: &LogEntry) -> Result<CommandResponse> {
+        if entry.index <= self.applied.index {
+            return Ok(CommandResponse::applied(None));
+        }
+
+        // Versions are ordered by timestamp, so never let a later entry carry
+        // an earlier timestamp than one already applied. Leaders stamp entries
+        // from their HLC, which only goes backwards if a new leader's clock
+        // never observed its predecessor's timestamps.
+        let timestamp = entry.timestamp.max(self.applied.timestamp);
+        let version = WriteVersion { index: entry.index, timestamp };
+
+        let mut applied = AppliedState { index: entry.index, term: entry.term, timestamp, ..self.applied.clone() };
+        let mut batch = self.storage.batch();
+        let response = match &entry.command {
+            // From here on the group owns only the left half; the right half
+            // is served by a new group created from the same data, whose log
+            // starts after this entry. A replica of the new group that was
+            // created from a snapshot already is further along.
+            Command::SplitPartition { left, right } => {
+                if Self::load(&self.storage, right.id)?.is_none() {
+                    let right_state = AppliedState {
+                        range: Some(right.range.clone()),
+                        frozen_into: None,
+                        ..applied.clone()
+                    };
+                    batch.put_group_state(right.id, APPLIED_STATE, &serde_json::to_vec(&right_state)?);
+                }
+                applied.range = Some(left.range.clone());
+                CommandResponse::applied(None)
+            },
+            Command::FreezeForMerge { merged } => {
+                applied.frozen_into = Some(merged.range.clone());
+                CommandResponse::applied(None)
+            },
+            // Taking over the range before this node's copy of the right
+            // partition froze would lose the writes it has yet to apply
+            Command::MergePartitions { merged, right_id } => {
+                let right = Self::load(&self.storage, *right_id)?;
+                if right.and_then(|right| right.frozen_into).as_ref() != Some(&merged.range) {
+                    return Err(DatabaseError::Partition {
+                        partition: Some(*right_id),
+                        message: format!("Partition {} is not frozen for the merge on this node yet", right_id),
+                    });
+                }
+                batch.delete_group_state(*right_id, APPLIED_STATE);
+                applied.range = Some(merged.range.clone());
+                CommandResponse::applied(None)
+            },
+            Command::ChangeMembership { membership } => {
+                applied.membership = membership.clone();
+                CommandResponse::applied(None)
+            },
+            command => match self.misrouted(command) {
+                Some(range) => CommandResponse::out_of_range(range),
+                None => self.execute(&mut batch, command, version)?,
+            },
+        };
+
+        batch.put_group_state(self.group_id, APPLIED_STATE, &serde_json::to_vec(&applied)?);
+        batch.commit()?;
+        self.applied = applied;
+        Ok(response)
+    }
+
+    /// The range to route `command` to instead, if this partition does not
+    /// serve all of its keys.
+    fn misrouted(&self, command: &Command) -> Option<KeyRange> {
+        let keys = command.keys();
+        if let Some(merged) = self.applied.frozen_into.as_ref().filter(|_| !keys.is_empty()) {
+            return Some(merged.clone());
+        }
+        let range = self.applied.range.as_ref()?;
+        keys.iter().any(|key| !range.contains(key)).then(|| range.clone())
+    }
+
+    // Writes go to `batch`; reads see the state before the entry
+    fn execute(&self, batch: &mut Batch, command: &Command, version: WriteVersion) -> Result<CommandResponse> {
+        let storage = &self.storage;
+        // Expiry is judged by the entry's timestamp, not the local clock, so
+        // every replica makes the same decision
+        let now_ms = version.timestamp.wall_ms;
+
+        // Plain writes may not overwrite a key another transaction has an intent on
+        if let Some(txn) = self.blocking_intent(command)? {
+            return Ok(CommandResponse::conflict(txn));
+        }
+
+        match command {
+            Command::Write { key, value, expires_at } => {
+                batch.put(key, value, version, *expires_at);
+                Ok(CommandResponse::applied(None))
+            },
+            Command::Delete { key } => {
+                batch.delete(key, version);
+                Ok(CommandResponse::applied(None))
+            },
+            // The whole batch lands in the entry's write batch, so it is atomic
+            Command::Batch { operations } => {
+                batch.write_operations(operations, version);
+                Ok(CommandResponse::applied(None))
+            },
+            Command::PutIfAbsent { key, value } => match storage.get_at(key, now_ms)? {
+                Some(current) => Ok(CommandResponse::rejected(Some(current))),
+                None => Ok(Self::put_versioned(batch, key, value.clone(), version)),
+            },
+            Command::PutIfVersion { key, value, expected_version } => {
+                let current = storage.get_at(key, now_ms)?;
+                let current_version = current.as_ref().map_or(0, |c| c.version);
+                if current_version != *expected_version {
+                    return Ok(CommandResponse::rejected(current));
+                }
+                Ok(Self::put_versioned(batch, key, value.clone(), version))
+            },
+            Command::DeleteIfValue { key, expected_value } => match storage.get_at(key, now_ms)? {
+                Some(current) if current.value == *expected_value => {
+                    batch.delete(key, version);
+                    Ok(CommandResponse::applied(None))
+                },
+                current => Ok(CommandResponse::rejected(current)),
+            },
+            Command::Increment { key, delta } => {
+                let current = storage.get_at(key, now_ms)?;
+                let base = match &current {
+                    None => Some(0),
+                    Some(c) => std::str::from_utf8(&c.value).ok().and_then(|s| s.parse::<i64>().ok()),
+                };
+                // Non-numeric values and overflow leave the key untouched
+                match base.and_then(|n| n.checked_add(*delta)) {
+                    Some(n) => Ok(Self::put_versioned(batch, key, n.to_string().into_bytes(), version)),
+                    None => Ok(CommandResponse::rejected(current)),
+                }
+            },
+            Command::Prewrite { txn, operations, txn_keys } => {
+                // A transaction aborted by recovery must not lay down new intents
+                let settled = storage.get_transaction(&txn.id)?
+                    .filter(|record| record.status != TransactionStatus::Pending);
+                if let Some(record) = settled {
+                    return Ok(CommandResponse::transaction(false, Some(record)));
+                }
+                for operation in operations {
+                    let foreign = storage.get_intent(operation.key())?
+                        .filter(|intent| intent.txn.id != txn.id);
+                    if let Some(intent) = foreign {
+                        return Ok(CommandResponse::conflict(intent.txn).on_key(operation.key()));
+                    }
+                    // Someone committed a write after this transaction started
+                    if storage.last_write(operation.key())?.is_some_and(|last| last.timestamp > txn.start_ts) {
+                        return Ok(CommandResponse::rejected(None).on_key(operation.key()));
+                    }
+                }
+
+                let holds_primary = operations.iter().any(|op| op.key() == txn.primary_key);
+                let record = holds_primary.then(|| TransactionRecord {
+                    meta: txn.clone(),
+                    status: TransactionStatus::Pending,
+                    commit_ts: None,
+                    keys: txn_keys.clone(),
+                    last_active_ms: now_ms,
+                });
+                batch.write_intents(txn, operations, record.as_ref())?;
+                Ok(CommandResponse::transaction(true, record))
+            },
+            Command::EndTransaction { txn, commit_ts } => {
+                let record = storage.get_transaction(&txn.id)?;
+                match (record, commit_ts) {
+                    (Some(mut record), Some(commit_ts)) if record.status == TransactionStatus::Pending => {
+                        record.status = TransactionStatus::Committed;
+                        record.commit_ts = Some(*commit_ts);
+                        record.last_active_ms = now_ms;
+                        batch.put_transaction(&record)?;
+                        Ok(CommandResponse::transaction(true, Some(record)))
+                    },
+                    // Committing twice is fine; committing an aborted transaction is not
+                    (Some(record), Some(_)) => {
+                        Ok(CommandResponse::transaction(record.status == TransactionStatus::Committed, Some(record)))
+                    },
+                    (None, Some(_)) => Ok(CommandResponse::transaction(false, None)),
+                    (Some(record), None) if record.status == TransactionStatus::Committed => {
+                        Ok(CommandResponse::transaction(false, Some(record)))
+                    },
+                    (record, None) => Self::abort_transaction(batch, txn, record, now_ms),
+                }
+            },
+            Command::ResolveIntents { txn_id, keys, commit_ts } => {
+                let commit = commit_ts.map(|timestamp| WriteVersion { index: version.index, timestamp });
+                batch.resolve_intents(txn_id, keys, commit)?;
+                Ok(CommandResponse::applied(None))
+            },
+            Command::RecoverTransaction { txn, abandoned_before_ms } => {
+                match storage.get_transaction(&txn.id)? {
+                    Some(record) if record.status != TransactionStatus::Pending || record.last_active_ms >= *abandoned_before_ms => {
+                        Ok(CommandResponse::transaction(true, Some(record)))
+                    },
+                    // Abandoned, or never reached its primary: abort so it cannot commit later
+                    record => Self::abort_transaction(batch, txn, record, now_ms),
+                }
+            },
+            Command::ValidateReads { txn, keys, ranges } => {
+                for key in keys {
+                    let foreign = storage.get_intent(key)?
+                        .filter(|intent| intent.txn.id != txn.id);
+                    if let Some(intent) = foreign {
+                        return Ok(CommandResponse::conflict(intent.txn).on_key(key));
+                    }
+                    if storage.last_write(key)?.is_some_and(|last| last.timestamp > txn.start_ts) {
+                        return Ok(CommandResponse::rejected(None).on_key(key));
+                    }
+                }
+                for range in ranges {
+                    let foreign = storage.intents_in_range(&range.start, &range.end)?
+                        .into_iter()
+                        .find(|(_, intent)| intent.txn.id != txn.id);
+                    if let Some((key, intent)) = foreign {
+                        return Ok(CommandResponse::conflict(intent.txn).on_key(&key));
+                    }
+                    if let Some(key) = storage.first_write_since(&range.start, &range.end, txn.start_ts)? {
+                        return Ok(CommandResponse::rejected(None).on_key(&key));
+                    }
+                }
+                Ok(CommandResponse::applied(None))
+            },
+            // Partition changes are handled by the metadata layer, not the
+            // data store; splits and merges only change the range, in `apply`.
+            // No-ops only mark the start of a leader's term.
+            Command::Noop
+            | Command::CreatePartition { .. }
+            | Command::UpdatePartition { .. }
+            | Command::DeletePartition { .. }
+            | Command::SplitPartition { .. }
+            | Command::FreezeForMerge { .. }
+            | Command::MergePartitions { .. }
+            | Command::ChangeMembership { .. } => Ok(CommandResponse::applied(None)),
+        }
+    }
+
+    // The transaction that holds an intent on a key a plain write touches
+    fn blocking_intent(&self, command: &Command) -> Result<Option<TransactionMeta>> {
+        for key in command.write_keys() {
+            if let Some(intent) = self.storage.get_intent(key)? {
+                return Ok(Some(intent.txn));
+            }
+        }
+        Ok(None)
+    }
+
+    fn abort_transaction(
+        batch: &mut Batch,
+        txn: &TransactionMeta,
+        record: Option<TransactionRecord>,
+        now_ms: u64,
+    ) -> Result<CommandResponse> {
+        let mut record = record.unwrap_or_else(|| TransactionRecord {
+            meta: txn.clone(),
+            status: TransactionStatus::Pending,
+            commit_ts: None,
+            keys: Vec::new(),
+            last_active_ms: now_ms,
+        });
+        record.status = TransactionStatus::Aborted;
+        record.last_active_ms = now_ms;
+        batch.put_transaction(&record)?;
+        Ok(CommandResponse::transaction(true, Some(record)))
+    }
+
+    fn put_versioned(batch: &mut Batch, key: &str, value: Vec<u8>, version: WriteVersion) -> CommandResponse {
+        batch.put(key, &value, version, None);
+        CommandResponse::applied(Some(VersionedValue { value, version: version.index }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::{BatchOperation, NodeId, PartitionInfo, TransactionId, CLUSTER_METADATA_KEY};
+    use common::util::timestamp_ms;
+    use storage::StorageOptions;
+
+    fn open_state_machine(name: &str) -> StateMachine {
+        let path = std::env::temp_dir().join(format!("raft_node_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        StateMachine::open(Arc::new(Storage::open(path).unwrap()), 1).unwrap()
+    }
+
+    fn entry(index: u64, command: Command) -> LogEntry {
+        LogEntry { term: 1, index, command, timestamp: HlcTimestamp::new(timestamp_ms(), 0) }
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let mut sm = open_state_machine("batch");
+        sm.apply(&entry(1, Command::Write { key: "a".to_string(), value: b"old".to_vec(), expires_at: None })).unwrap();
+
+        let operations = vec![
+            BatchOperation::Put { key: "b".to_string(), value: b"1".to_vec() },
+            BatchOperation::Put { key: "c".to_string(), value: b"2".to_vec() },
+            BatchOperation::Delete { key: "a".to_string() },
+        ];
+        sm.apply(&entry(2, Command::Batch { operations })).unwrap();
+
+        assert_eq!(sm.storage().get("a").unwrap(), None);
+        assert_eq!(sm.storage().get("b").unwrap().unwrap().value, b"1".to_vec());
+        assert_eq!(sm.storage().get("c").unwrap().unwrap().value, b"2".to_vec());
+        assert_eq!(sm.last_applied(), 2);
+    }
+
+    #[test]
+    fn test_replayed_entries_are_skipped() {
+        let mut sm = open_state_machine("replay");
+        sm.apply(&entry(1, Command::Write { key: "k".to_string(), value: b"v1".to_vec(), expires_at: None })).unwrap();
+        sm.apply(&entry(2, Command::Write { key: "k".to_string(), value: b"v2".to_vec(), expires_at: None })).unwrap();
+        sm.apply(&entry(1, Command::Write { key: "k".to_string(), value: b"v1".to_vec(), expires_at: None })).unwrap();
+
+        assert_eq!(sm.storage().get("k").unwrap().unwrap().value, b"v2".to_vec());
+    }
+
+    #[test]
+    fn test_applied_state_survives_restart() {
+        let path = std::env::temp_dir().join(format!("raft_node_restart_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let storage = Arc::new(Storage::open(&path).unwrap());
+        let mut sm = StateMachine::for_range(Arc::clone(&storage), 1, KeyRange::new("a", "z")).unwrap();
+        let mut other = StateMachine::open(Arc::clone(&storage), 2).unwrap();
+        sm.apply(&entry(1, Command::Increment { key: "n".to_string(), delta: 1 })).unwrap();
+        sm.apply(&entry(2, Command::Increment { key: "n".to_string(), delta: 1 })).unwrap();
+        other.apply(&entry(1, Command::Write { key: "x".to_string(), value: b"1".to_vec(), expires_at: None })).unwrap();
+        drop((sm, other, storage));
+
+        let storage = Arc::new(Storage::open(&path).unwrap());
+        let mut sm = StateMachine::for_range(Arc::clone(&storage), 1, KeyRange::new("a", "b")).unwrap();
+        assert_eq!(sm.last_applied(), 2);
+        assert_eq!(sm.range(), Some(&KeyRange::new("a", "z")), "The persisted range wins");
+        assert_eq!(StateMachine::open(storage, 2).unwrap().last_applied(), 1, "Groups keep separate state");
+
+        // Replaying the log does not increment twice
+        sm.apply(&entry(2, Command::Increment { key: "n".to_string(), delta: 1 })).unwrap();
+        assert_eq!(sm.storage().get("n").unwrap().unwrap().value, b"2".to_vec());
+    }
+
+    #[test]
+    fn test_conditional_writes() {
+        let mut sm = open_state_machine("conditional");
+        let key = "lock".to_string();
+
+        let first = sm.apply(&entry(1, Command::PutIfAbsent { key: key.clone(), value: b"a".to_vec() })).unwrap();
+        assert!(first.succeeded);
+        let second = sm.apply(&entry(2, Command::PutIfAbsent { key: key.clone(), value: b"b".to_vec() })).unwrap();
+        assert!(!second.succeeded);
+        assert_eq!(second.current, Some(VersionedValue { value: b"a".to_vec(), version: 1 }));
+
+        let stale = sm.apply(&entry(3, Command::PutIfVersion { key: key.clone(), value: b"c".to_vec(), expected_version: 0 })).unwrap();
+        assert!(!stale.succeeded);
+        let swapped = sm.apply(&entry(4, Command::PutIfVersion { key: key.clone(), value: b"c".to_vec(), expected_version: 1 })).unwrap();
+        assert!(swapped.succeeded);
+        assert_eq!(swapped.current.unwrap().version, 4);
+
+        let wrong = sm.apply(&entry(5, Command::DeleteIfValue { key: key.clone(), expected_value: b"a".to_vec() })).unwrap();
+        assert!(!wrong.succeeded);
+        let deleted = sm.apply(&entry(6, Command::DeleteIfValue { key: key.clone(), expected_value: b"c".to_vec() })).unwrap();
+        assert!(deleted.succeeded);
+        assert_eq!(sm.storage().get(&key).unwrap(), None);
+    }
+
+    #[test]
+    fn test_increment() {
+        let mut sm = open_state_machine("increment");
+
+        sm.apply(&entry(1, Command::Increment { key: "n".to_string(), delta: 5 })).unwrap();
+        let response = sm.apply(&entry(2, Command::Increment { key: "n".to_string(), delta: -2 })).unwrap();
+        assert_eq!(response.current.unwrap().value, b"3".to_vec());
+
+        sm.apply(&entry(3, Command::Write { key: "s".to_string(), value: b"abc".to_vec(), expires_at: None })).unwrap();
+        let response = sm.apply(&entry(4, Command::Increment { key: "s".to_string(), delta: 1 })).unwrap();
+        assert!(!response.succeeded, "Non-numeric values cannot be incremented");
+        assert_eq!(sm.last_applied(), 4);
+    }
+
+    #[test]
+    fn test_expired_keys_are_absent() {
+        let mut sm = open_state_machine("ttl");
+        let expired = timestamp_ms() - 1;
+
+        sm.apply(&entry(1, Command::Write { key: "session".to_string(), value: b"s".to_vec(), expires_at: Some(expired) })).unwrap();
+        assert_eq!(sm.storage().get("session").unwrap(), None);
+
+        let response = sm.apply(&entry(2, Command::PutIfAbsent { key: "session".to_string(), value: b"t".to_vec() })).unwrap();
+        assert!(response.succeeded, "An expired key counts as absent");
+    }
+
+    #[test]
+    fn test_reads_as_of_timestamp() {
+        let mut sm = open_state_machine("as_of");
+        let t0 = timestamp_ms() - 3_000;
+        let at = |index, ts: u64, command| LogEntry { term: 1, index, command, timestamp: HlcTimestamp::new(ts, 0) };
+
+        sm.apply(&at(1, t0, Command::Write { key: "k".to_string(), value: b"v1".to_vec(), expires_at: None })).unwrap();
+        sm.apply(&at(2, t0 + 1_000, Command::Write { key: "k".to_string(), value: b"v2".to_vec(), expires_at: None })).unwrap();
+        sm.apply(&at(3, t0 + 2_000, Command::Delete { key: "k".to_string() })).unwrap();
+
+        let storage = sm.storage();
+        assert_eq!(storage.get("k").unwrap(), None);
+        assert_eq!(storage.get_as_of("k", HlcTimestamp::latest_at(t0 - 1)).unwrap(), None);
+        assert_eq!(storage.get_as_of("k", HlcTimestamp::latest_at(t0 + 500)).unwrap().unwrap().value, b"v1".to_vec());
+        assert_eq!(storage.get_as_of("k", HlcTimestamp::latest_at(t0 + 1_999)).unwrap().unwrap().version, 2);
+        assert_eq!(storage.scan_as_of("a", "z", 0, HlcTimestamp::new(t0 + 1_000, 0)).unwrap(), vec![("k".to_string(), b"v2".to_vec())]);
+
+        // An entry with an older timestamp than one already applied must still win
+        sm.apply(&at(4, t0, Command::Write { key: "k".to_string(), value: b"v4".to_vec(), expires_at: None })).unwrap();
+        assert_eq!(sm.storage().get("k").unwrap().unwrap().version, 4);
+    }
+
+    #[test]
+    fn test_garbage_collection() {
+        let path = std::env::temp_dir().join(format!("raft_node_gc_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let options = StorageOptions { gc_window_ms: 0 };
+        let mut sm = StateMachine::open(Arc::new(Storage::open_with_options(path, options).unwrap()), 1).unwrap();
+
+        sm.apply(&entry(1, Command::Write { key: "a".to_string(), value: b"1".to_vec(), expires_at: None })).unwrap();
+        sm.apply(&entry(2, Command::Write { key: "a".to_string(), value: b"2".to_vec(), expires_at: None })).unwrap();
+        sm.apply(&entry(3, Command::Write { key: "b".to_string(), value: b"1".to_vec(), expires_at: None })).unwrap();
+        sm.apply(&entry(4, Command::Delete { key: "b".to_string() })).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        // The overwritten version of "a" and both versions of deleted "b" go
+        assert_eq!(sm.storage().collect_garbage().unwrap(), 3);
+        assert_eq!(sm.storage().get("a").unwrap().unwrap().value, b"2".to_vec());
+        assert!(sm.storage().get_as_of("a", HlcTimestamp::new(1, 0)).is_err(), "Reads before the GC window are rejected");
+    }
+
+    fn txn_meta(primary_key: &str, start_ms: u64) -> TransactionMeta {
+        TransactionMeta {
+            id: TransactionId::generate(),
+            primary_key: primary_key.to_string(),
+            start_ts: HlcTimestamp::new(start_ms, 0),
+        }
+    }
+
+    fn put(key: &str, value: &[u8]) -> BatchOperation {
+        BatchOperation::Put { key: key.to_string(), value: value.to_vec() }
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        let mut sm = open_state_machine("txn_commit");
+        let now = timestamp_ms();
+        let txn = txn_meta("a", now - 1_000);
+        let keys = vec!["a".to_string(), "b".to_string()];
+        let at = |index, ts: u64, command| LogEntry { term: 1, index, command, timestamp: HlcTimestamp::new(ts, 0) };
+
+        // The primary's partition gets the record; the other only intents
+        let primary = sm.apply(&at(1, now - 900, Command::Prewrite { txn: txn.clone(), operations: vec![put("a", b"1")], txn_keys: keys.clone() })).unwrap();
+        assert!(primary.succeeded);
+        assert_eq!(primary.transaction.unwrap().status, TransactionStatus::Pending);
+        sm.apply(&at(2, now - 900, Command::Prewrite { txn: txn.clone(), operations: vec![BatchOperation::Delete { key: "b".to_string() }], txn_keys: vec![] })).unwrap();
+
+        let blocked = sm.apply(&at(3, now - 800, Command::Write { key: "b".to_string(), value: b"x".to_vec(), expires_at: None })).unwrap();
+        assert_eq!(blocked.conflict, Some(txn.clone()), "Plain writes must not overwrite an intent");
+        assert_eq!(sm.storage().get("a").unwrap(), None, "Intents are invisible to reads");
+
+        let commit_ts = HlcTimestamp::new(now - 500, 0);
+        let ended = sm.apply(&at(4, now - 500, Command::EndTransaction { txn: txn.clone(), commit_ts: Some(commit_ts) })).unwrap();
+        assert!(ended.succeeded);
+        sm.apply(&at(5, now - 400, Command::ResolveIntents { txn_id: txn.id.clone(), keys, commit_ts: Some(commit_ts) })).unwrap();
+
+        let storage = sm.storage();
+        assert_eq!(storage.get("a").unwrap().unwrap().value, b"1".to_vec());
+        assert_eq!(storage.get("b").unwrap(), None);
+        assert_eq!(storage.get_as_of("a", HlcTimestamp::new(now - 600, 0)).unwrap(), None);
+        assert_eq!(storage.get_intent("b").unwrap(), None);
+        assert_eq!(storage.get_transaction(&txn.id).unwrap().unwrap().commit_ts, Some(commit_ts));
+    }
+
+    #[test]
+    fn test_prewrite_conflicts() {
+        let mut sm = open_state_machine("txn_conflict");
+        let now = timestamp_ms();
+        let at = |index, ts: u64, command| LogEntry { term: 1, index, command, timestamp: HlcTimestamp::new(ts, 0) };
+
+        // A write committed after the transaction started
+        let late = txn_meta("k", now - 1_000);
+        sm.apply(&at(1, now - 500, Command::Write { key: "k".to_string(), value: b"v".to_vec(), expires_at: None })).unwrap();
+        let response = sm.apply(&at(2, now - 400, Command::Prewrite { txn: late, operations: vec![put("k", b"t")], txn_keys: vec![] })).unwrap();
+        assert!(!response.succeeded);
+        assert_eq!(response.conflict, None);
+
+        // An intent of another transaction
+        let first = txn_meta("k", now - 300);
+        let second = txn_meta("k", now - 300);
+        sm.apply(&at(3, now - 200, Command::Prewrite { txn: first.clone(), operations: vec![put("k", b"1")], txn_keys: vec!["k".to_string()] })).unwrap();
+        let response = sm.apply(&at(4, now - 200, Command::Prewrite { txn: second, operations: vec![put("k", b"2")], txn_keys: vec!["k".to_string()] })).unwrap();
+        assert_eq!(response.conflict, Some(first));
+    }
+
+    #[test]
+    fn test_recover_abandoned_transaction() {
+        let mut sm = open_state_machine("txn_recover");
+        let now = timestamp_ms();
+        let txn = txn_meta("a", now - 10_000);
+        let at = |index, ts: u64, command| LogEntry { term: 1, index, command, timestamp: HlcTimestamp::new(ts, 0) };
+
+        sm.apply(&at(1, now - 9_000, Command::Prewrite { txn: txn.clone(), operations: vec![put("a", b"1")], txn_keys: vec!["a".to_string()] })).unwrap();
+
+        // Still active: recovery leaves it alone
+        let response = sm.apply(&at(2, now, Command::RecoverTransaction { txn: txn.clone(), abandoned_before_ms: now - 9_500 })).unwrap();
+        assert_eq!(response.transaction.unwrap().status, TransactionStatus::Pending);
+
+        let response = sm.apply(&at(3, now, Command::RecoverTransaction { txn: txn.clone(), abandoned_before_ms: now - 5_000 })).unwrap();
+        assert_eq!(response.transaction.unwrap().status, TransactionStatus::Aborted);
+
+        // The crashed coordinator comes back and tries to commit
+        let commit_ts = Some(HlcTimestamp::new(now, 1));
+        let response = sm.apply(&at(4, now, Command::EndTransaction { txn: txn.clone(), commit_ts })).unwrap();
+        assert!(!response.succeeded);
+
+        sm.apply(&at(5, now, Command::ResolveIntents { txn_id: txn.id.clone(), keys: vec!["a".to_string()], commit_ts: None })).unwrap();
+        assert_eq!(sm.storage().get_intent("a").unwrap(), None);
+        assert_eq!(sm.storage().get("a").unwrap(), None);
+        assert!(sm.storage().transaction_records().unwrap().iter().all(|r| r.status == TransactionStatus::Aborted));
+    }
+
+    #[test]
+    fn test_validate_reads() {
+        let mut sm = open_state_machine("txn_validate");
+        let now = timestamp_ms();
+        let at = |index, ts: u64, command| LogEntry { term: 1, index, command, timestamp: HlcTimestamp::new(ts, 0) };
+        let reader = txn_meta("x", now - 1_000);
+        let keys = vec!["x".to_string(), "y".to_string()];
+
+        sm.apply(&at(1, now - 2_000, Command::Write { key: "x".to_string(), value: b"1".to_vec(), expires_at: None })).unwrap();
+        let response = sm.apply(&at(2, now - 900, Command::ValidateReads { txn: reader.clone(), keys: keys.clone(), ranges: Vec::new() })).unwrap();
+        assert!(response.succeeded, "Writes before the start timestamp do not invalidate reads");
+
+        // A concurrent transaction's intent
+        let writer = txn_meta("y", now - 800);
+        sm.apply(&at(3, now - 700, Command::Prewrite { txn: writer.clone(), operations: vec![put("y", b"2")], txn_keys: vec!["y".to_string()] })).unwrap();
+        let response = sm.apply(&at(4, now - 600, Command::ValidateReads { txn: reader.clone(), keys: keys.clone(), ranges: Vec::new() })).unwrap();
+        assert_eq!(response.conflict, Some(writer.clone()));
+
+        // The same write once committed
+        let commit_ts = Some(HlcTimestamp::new(now - 500, 0));
+        sm.apply(&at(5, now - 500, Command::EndTransaction { txn: writer.clone(), commit_ts })).unwrap();
+        sm.apply(&at(6, now - 500, Command::ResolveIntents { txn_id: writer.id.clone(), keys: vec!["y".to_string()], commit_ts })).unwrap();
+        let response = sm.apply(&at(7, now - 400, Command::ValidateReads { txn: reader, keys, ranges: Vec::new() })).unwrap();
+        assert!(!response.succeeded);
+        assert_eq!(response.conflict, None);
+    }
+
+    #[test]
+    fn test_validate_scanned_ranges() {
+        let mut sm = open_state_machine("txn_validate_ranges");
+        let now = timestamp_ms();
+        let at = |index, ts: u64, command| LogEntry { term: 1, index, command, timestamp: HlcTimestamp::new(ts, 0) };
+        let reader = txn_meta("x", now - 1_000);
+        let validate = |index| at(index, now - 100, Command::ValidateReads {
+            txn: reader.clone(),
+            keys: Vec::new(),
+            ranges: vec![KeyRange::new("b", "d")],
+        });
+
+        sm.apply(&at(1, now - 2_000, Command::Write { key: "b".to_string(), value: b"1".to_vec(), expires_at: None })).unwrap();
+        sm.apply(&at(2, now - 900, Command::Write { key: "d".to_string(), value: b"1".to_vec(), expires_at: None })).unwrap();
+        assert!(sm.apply(&validate(3)).unwrap().succeeded, "Only older writes and writes outside the range");
+
+        // A row inserted into the scanned range is a phantom
+        let writer = txn_meta("c", now - 800);
+        sm.apply(&at(4, now - 700, Command::Prewrite { txn: writer.clone(), operations: vec![put("c", b"2")], txn_keys: vec!["c".to_string()] })).unwrap();
+        let response = sm.apply(&validate(5)).unwrap();
+        assert_eq!(response.conflict, Some(writer.clone()));
+        assert_eq!(response.conflict_key.as_deref(), Some("c"));
+
+        let commit_ts = Some(HlcTimestamp::new(now - 500, 0));
+        sm.apply(&at(6, now - 500, Command::EndTransaction { txn: writer.clone(), commit_ts })).unwrap();
+        sm.apply(&at(7, now - 500, Command::ResolveIntents { txn_id: writer.id.clone(), keys: vec!["c".to_string()], commit_ts })).unwrap();
+        let response = sm.apply(&validate(8)).unwrap();
+        assert!(!response.succeeded);
+        assert_eq!(response.conflict_key.as_deref(), Some("c"));
+    }
+
+    // Two doctors are on call and at least one must stay on call. Two
+    // concurrent transactions each see both on call and take one doctor off.
+    // Returns how many doctors are still on call afterwards.
+    fn run_write_skew(name: &str, serializable: bool) -> usize {
+        let mut sm = open_state_machine(name);
+        let now = timestamp_ms();
+        let at = |index, ts: u64, command| LogEntry { term: 1, index, command, timestamp: HlcTimestamp::new(ts, 0) };
+        let doctors = ["alice", "bob"];
+        let on_call = |sm: &StateMachine, ts: HlcTimestamp| {
+            doctors.iter()
+                .filter(|d| sm.storage().get_as_of(d, ts).unwrap().is_some_and(|v| v.value == b"1".to_vec()))
+                .count()
+        };
+
+        sm.apply(&at(1, now - 1_000, Command::Batch { operations: vec![put("alice", b"1"), put("bob", b"1")] })).unwrap();
+
+        let txns = [txn_meta("alice", now - 900), txn_meta("bob", now - 900)];
+        for txn in &txns {
+            assert_eq!(on_call(&sm, txn.start_ts), 2);
+        }
+
+        let mut index = 2;
+        for (txn, doctor) in txns.iter().zip(doctors) {
+            let command = Command::Prewrite { txn: txn.clone(), operations: vec![put(doctor, b"0")], txn_keys: vec![doctor.to_string()] };
+            assert!(sm.apply(&at(index, now - 800, command)).unwrap().succeeded, "The write sets do not overlap");
+            index += 1;
+        }
+
+        let mut outcomes = Vec::new();
+        for (txn, other) in txns.iter().zip(doctors.iter().rev()) {
+            let valid = !serializable || {
+                let command = Command::ValidateReads { txn: txn.clone(), keys: vec![other.to_string()], ranges: Vec::new() };
+                index += 1;
+                sm.apply(&at(index, now - 700, command)).unwrap().succeeded
+            };
+            outcomes.push(valid);
+        }
+
+        for ((txn, doctor), committed) in txns.iter().zip(doctors).zip(outcomes) {
+            let commit_ts = Some(HlcTimestamp::new(now - 600, 0)).filter(|_| committed);
+            index += 1;
+            sm.apply(&at(index, now - 600, Command::EndTransaction { txn: txn.clone(), commit_ts })).unwrap();
+            index += 1;
+            let command = Command::ResolveIntents { txn_id: txn.id.clone(), keys: vec![doctor.to_string()], commit_ts };
+            sm.apply(&at(index, now - 600, command)).unwrap();
+        }
+
+        on_call(&sm, HlcTimestamp::latest_at(now))
+    }
+
+    #[test]
+    fn test_snapshot_isolation_allows_write_skew() {
+        assert_eq!(run_write_skew("skew_snapshot", false), 0);
+    }
+
+    #[test]
+    fn test_serializable_prevents_write_skew() {
+        assert!(run_write_skew("skew_serializable", true) >= 1);
+    }
+
+    #[test]
+    fn test_split_narrows_range() {
+        let path = std::env::temp_dir().join(format!("raft_node_split_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let mut sm = StateMachine::for_range(Arc::new(Storage::open(path).unwrap()), 1, KeyRange::new("a", "z")).unwrap();
+        for (index, key) in [(1, "b"), (2, "c"), (3, "m"), (4, "n")] {
+            sm.apply(&entry(index, Command::Write { key: key.to_string(), value: b"value".to_vec(), expires_at: None })).unwrap();
+        }
+
+        let stats = sm.stats().unwrap();
+        assert_eq!((stats.key_count, stats.size_bytes), (4, 24));
+        assert_eq!(stats.midpoint_key.as_deref(), Some("m"));
+
+        let partition = |id, start: &str, end: &str| PartitionInfo {
+            id,
+            range: KeyRange::new(start, end),
+            leader: NodeId::from("node1"),
+            followers: vec![],
+            learners: vec![],
+        };
+        let split = Command::SplitPartition { left: partition(1, "a", "m"), right: partition(2, "m", "z") };
+        assert!(sm.apply(&entry(5, split)).unwrap().succeeded);
+        assert_eq!(sm.range(), Some(&KeyRange::new("a", "m")));
+
+        let moved = sm.apply(&entry(6, Command::Delete { key: "n".to_string() })).unwrap();
+        assert_eq!(moved.out_of_range, Some(KeyRange::new("a", "m")));
+        assert!(sm.storage().get("n").unwrap().is_some(), "Nothing written outside the range");
+        assert_eq!(sm.last_applied(), 6);
+
+        assert!(sm.apply(&entry(7, Command::Delete { key: "b".to_string() })).unwrap().succeeded);
+        assert_eq!(sm.stats().unwrap().key_count, 1);
+    }
+
+    #[test]
+    fn test_meta_group_owns_reserved_keys() {
+        let path = std::env::temp_dir().join(format!("raft_node_meta_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let mut sm = StateMachine::meta(Arc::new(Storage::open(path).unwrap())).unwrap();
+
+        let save = |expected_version| Command::PutIfVersion {
+            key: CLUSTER_METADATA_KEY.to_string(),
+            value: b"{}".to_vec(),
+            expected_version,
+        };
+        assert!(sm.apply(&entry(1, save(0))).unwrap().succeeded);
+        assert!(!sm.apply(&entry(2, save(0))).unwrap().succeeded, "Saves of stale copies fail");
+
+        let user_write = Command::Write { key: "users/1".to_string(), value: b"value".to_vec(), expires_at: None };
+        assert_eq!(sm.apply(&entry(3, user_write)).unwrap().out_of_range, Some(KeyRange::meta()));
+    }
+
+    #[test]
+    fn test_merge_freezes_right_and_widens_left() {
+        let path = std::env::temp_dir().join(format!("raft_node_merge_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let storage = Arc::new(Storage::open(path).unwrap());
+        let write = |key: &str| Command::Write { key: key.to_string(), value: b"value".to_vec(), expires_at: None };
+        let merged = PartitionInfo {
+            id: 1,
+            range: KeyRange::new("a", "z"),
+            leader: NodeId::from("node1"),
+            followers: vec![],
+            learners: vec![],
+        };
+
+        let mut right = StateMachine::for_range(Arc::clone(&storage), 2, KeyRange::new("m", "z")).unwrap();
+        let mut left = StateMachine::for_range(Arc::clone(&storage), 1, KeyRange::new("a", "m")).unwrap();
+        assert!(right.apply(&entry(1, write("n"))).unwrap().succeeded);
+        let merge = Command::MergePartitions { merged: merged.clone(), right_id: 2 };
+        assert!(left.apply(&entry(1, merge.clone())).is_err(), "The right partition is not frozen yet");
+        assert_eq!(left.last_applied(), 0);
+
+        assert!(right.apply(&entry(2, Command::FreezeForMerge { merged: merged.clone() })).unwrap().succeeded);
+        assert!(right.is_frozen());
+        let moved = right.apply(&entry(3, write("o"))).unwrap();
+        assert_eq!(moved.out_of_range, Some(KeyRange::new("a", "z")), "Frozen partitions send writes to the merged range");
+        assert!(right.storage().get("o").unwrap().is_none());
+
+        assert!(left.apply(&entry(1, merge)).unwrap().succeeded);
+        assert_eq!(left.range(), Some(&KeyRange::new("a", "z")));
+        assert_eq!(left.storage().get("n").unwrap().unwrap().value, b"value".to_vec(), "The right's data stays in place");
+        assert!(left.apply(&entry(2, write("o"))).unwrap().succeeded);
+    }
+}
@@ -0,0 +1,177 @@
+//! Raft messages and liveness reports over gRPC.
+//!
+//! Every `RaftMessage` maps to one call of the Raft service and its answer
+//! to the call's response; log entries travel JSON encoded. Connections to
+//! peers are opened on first use and dropped when a call through them fails,
+//! so the next message reconnects.
+
+use crate::liveness::CoordinatorTransport;
+use crate::message::{MessageBody, RaftMessage};
+use crate::multi_raft::RaftTransport;
+use async_trait::async_trait;
+use common::error::{DatabaseError, Result};
+use common::types::{GroupHeartbeat, GroupHeartbeatAck, Locality, LogEntry, NodeId, NodeLoad};
+use rpc::client::{ClusterClient, RaftClient};
+use rpc::proto::raft::{
+    AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse, RequestVoteRequest,
+    RequestVoteResponse, TimeoutNowRequest,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Sends Raft messages to peers over the Raft service.
+pub struct GrpcRaftTransport {
+    from: NodeId,
+    clients: Mutex<HashMap<String, RaftClient>>,
+}
+
+impl GrpcRaftTransport {
+    /// Create a transport for the node `from`.
+    pub fn new(from: NodeId) -> Self {
+        Self { from, clients: Mutex::new(HashMap::new()) }
+    }
+
+    async fn client(&self, address: &str) -> Result<RaftClient> {
+        if let Some(client) = self.clients().get(address) {
+            return Ok(client.clone());
+        }
+        let client = RaftClient::connect(address).await?;
+        self.clients().insert(address.to_string(), client.clone());
+        Ok(client)
+    }
+
+    fn clients(&self) -> std::sync::MutexGuard<'_, HashMap<String, RaftClient>> {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn send(&self, address: &str, message: RaftMessage) -> Result<Option<RaftMessage>> {
+        let mut client = self.client(address).await?;
+        let group_id = message.group_id;
+        let term = message.term;
+        match message.body {
+            MessageBody::AppendEntries { leader, prev_log_index, prev_log_term, entries, leader_commit } => {
+                let request = AppendEntriesRequest {
+                    term,
+                    leader_id: leader.0,
+                    prev_log_index,
+                    prev_log_term,
+                    entries: entries.iter().map(encode_entry).collect::<Result<_>>()?,
+                    leader_commit,
+                    group_id,
+                };
+                let response = client.append_entries(request).await?;
+                Ok(Some(from_append_entries_response(response)))
+            },
+            MessageBody::RequestVote { candidate, last_log_index, last_log_term, pre_vote, transfer } => {
+                let request = RequestVoteRequest {
+                    term,
+                    candidate_id: candidate.0,
+                    last_log_index,
+                    last_log_term,
+                    group_id,
+                    pre_vote,
+                    transfer,
+                };
+                let response = client.request_vote(request).await?;
+                Ok(Some(from_request_vote_response(response)))
+            },
+            MessageBody::InstallSnapshot { leader, last_included_index, last_included_term, offset, data, done } => {
+                let request = InstallSnapshotRequest {
+                    term,
+                    leader_id: leader.0,
+                    last_included_index,
+                    last_included_term,
+                    offset,
+                    data,
+                    done,
+                    group_id,
+                };
+                let response = client.install_snapshot(request).await?;
+                Ok(Some(from_install_snapshot_response(response)))
+            },
+            MessageBody::TimeoutNow { leader } => {
+                client.timeout_now(TimeoutNowRequest { term, leader_id: leader.0, group_id }).await?;
+                Ok(None)
+            },
+            body => Err(DatabaseError::InvalidArgument(format!("{:?} is an answer, not a request", body))),
+        }
+    }
+}
+
+#[async_trait]
+impl RaftTransport for GrpcRaftTransport {
+    async fn send_heartbeats(
+        &self,
+        _peer: &NodeId,
+        address: &str,
+        heartbeats: Vec<GroupHeartbeat>,
+    ) -> Result<Vec<GroupHeartbeatAck>> {
+        let mut client = self.client(address).await?;
+        let acks = client.heartbeat(&self.from, heartbeats).await;
+        if acks.is_err() {
+            self.clients().remove(address);
+        }
+        acks
+    }
+
+    async fn send_message(&self, _peer: &NodeId, address: &str, message: RaftMessage) -> Result<Option<RaftMessage>> {
+        let answer = self.send(address, message).await;
+        if answer.is_err() {
+            self.clients().remove(address);
+        }
+        answer
+    }
+}
+
+/// Reports liveness over the coordinator's cluster service.
+pub struct GrpcCoordinatorTransport {
+    address: String,
+}
+
+impl GrpcCoordinatorTransport {
+    /// Create a transport to the coordinator at `address`.
+    pub fn new(address: impl Into<String>) -> Self {
+        Self { address: address.into() }
+    }
+}
+
+#[async_trait]
+impl CoordinatorTransport for GrpcCoordinatorTransport {
+    async fn heartbeat(&self, node: &NodeId, address: &str, locality: &Locality, load: NodeLoad) -> Result<u64> {
+        let mut client = ClusterClient::connect(&self.address).await?;
+        let response = client.heartbeat(node, address.to_string(), locality, load).await?;
+        Ok(response.metadata_version)
+    }
+}
+
+fn encode_entry(entry: &LogEntry) -> Result<rpc::proto::raft::LogEntry> {
+    Ok(rpc::proto::raft::LogEntry {
+        term: entry.term,
+        index: entry.index,
+        data: serde_json::to_vec(entry)?,
+    })
+}
+
+pub(crate) fn decode_entry(entry: &rpc::proto::raft::LogEntry) -> Result<LogEntry> {
+    Ok(serde_json::from_slice(&entry.data)?)
+}
+
+fn from_append_entries_response(response: AppendEntriesResponse) -> RaftMessage {
+    RaftMessage {
+        group_id: response.group_id,
+        term: response.term,
+        body: MessageBody::AppendEntriesResponse { success: response.success, match_index: response.match_index },
+    }
+}
+
+fn from_request_vote_response(response: RequestVoteResponse) -> RaftMessage {
+    RaftMessage {
+        group_id: response.group_id,
+        term: response.term,
+        body: MessageBody::RequestVoteResponse { vote_granted: response.vote_granted, pre_vote: response.pre_vote },
+    }
+}
+
+fn from_install_snapshot_response(response: InstallSnapshotResponse) -> RaftMessage {
+    RaftMessage { group_id: response.group_id, term: response.term, body: MessageBody::InstallSnapshotResponse }
+}
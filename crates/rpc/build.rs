@@ -8,6 +8,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "proto/database.proto",
                 "proto/node.proto",
                 "proto/raft.proto",
+                "proto/admin.proto",
+                "proto/cluster.proto",
+                "proto/error.proto",
             ],
             &["proto"],
         )?;
@@ -1,13 +1,60 @@
 //! Client implementations for connecting to services.
 
 use tonic::transport::{Channel, Endpoint};
+use crate::proto::admin::admin_service_client::AdminServiceClient;
+use crate::proto::admin::{AddReplicaRequest, GetMembershipRequest, MembershipResponse, PromoteLearnerRequest, RemoveReplicaRequest};
+use crate::proto::admin::{BalanceLeadersRequest, DrainNodeRequest, LeaderTransfersResponse, TransferLeaderRequest, UndrainNodeRequest, UndrainNodeResponse};
+use crate::proto::admin::{AbortDecommissionRequest, DecommissionNodeRequest, DecommissionResponse, DecommissionStatusRequest};
+use crate::proto::admin::{ListPlacementConstraintsRequest, PlacementConstraintsResponse, SetPlacementConstraintsRequest};
+use crate::proto::admin::{DescribeNodeRequest, DescribeNodeResponse, ListNodesRequest, ListNodesResponse};
+use crate::proto::cluster::cluster_service_client::ClusterServiceClient;
+use crate::proto::cluster::{NodeHeartbeatRequest, NodeHeartbeatResponse};
 use crate::proto::database::database_service_client::DatabaseServiceClient;
 use crate::proto::node::node_service_client::NodeServiceClient;
 use crate::proto::raft::raft_service_client::RaftServiceClient;
-use crate::proto::database::{GetRequest, PutRequest, DeleteRequest, ScanRequest, QueryRequest};
+use crate::proto::raft::{AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse};
+use crate::proto::raft::{RequestVoteRequest, RequestVoteResponse, TimeoutNowRequest, TimeoutNowResponse};
+use crate::proto::database::{GetRequest, PutRequest, DeleteRequest, ScanRequest, QueryRequest, BatchRequest, BatchOperation};
+use crate::proto::database::{ConditionalWriteRequest, IncrementRequest, PutIfVersion, ReadConsistency};
+use crate::proto::database::{BeginTransactionRequest, CommitTransactionRequest, IsolationLevel, RollbackTransactionRequest};
+use crate::proto::database::{GetPartitionMapRequest, GetPartitionMapResponse};
+use crate::proto::database::batch_operation::Operation;
+use crate::proto::database::conditional_write_request::Condition;
+use crate::routing::Routed;
+use crate::status::from_status;
 use common::error::{DatabaseError, Result};
+use common::hlc::{HlcTimestamp, HybridClock};
+use common::types::{
+    Command, CommandResponse, GroupHeartbeat, GroupHeartbeatAck, Locality, NodeId, NodeLoad, PartitionStats,
+    ReadOutcome, TransactionId, VersionedValue, WaitForEdge,
+};
+use std::sync::Arc;
 use std::time::Duration;
 
+/// Timeouts of a client connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    /// Limit on establishing the connection
+    pub connect: Duration,
+    /// Limit on each request
+    pub request: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self { connect: Duration::from_secs(5), request: Duration::from_secs(5) }
+    }
+}
+
+impl Timeouts {
+    fn endpoint(&self, addr: &str) -> Result<Endpoint> {
+        Ok(Endpoint::from_shared(format!("http://{}", addr))
+            .map_err(|e| DatabaseError::InvalidArgument(format!("Invalid endpoint {}: {}", addr, e)))?
+            .connect_timeout(self.connect)
+            .timeout(self.request))
+    }
+}
+
 /// Client for the database service.
 pub struct DatabaseClient {
     client: DatabaseServiceClient<Channel>,
@@ -16,98 +63,956 @@ pub struct DatabaseClient {
 impl DatabaseClient {
     /// Create a new database client.
     pub async fn connect(addr: &str) -> Result<Self> {
-        let endpoint = Endpoint::from_shared(format!("http://{}", addr))
-            .map_err(|e| DatabaseError::Rpc(format!("Invalid endpoint: {}", e)))?
-            .timeout(Duration::from_secs(5));
+        Self::connect_with_timeouts(addr, Timeouts::default()).await
+    }
+
+    /// Create a new database client with the given timeouts.
+    pub async fn connect_with_timeouts(addr: &str, timeouts: Timeouts) -> Result<Self> {
+        let endpoint = timeouts.endpoint(addr)?;
         
         let client = DatabaseServiceClient::connect(endpoint)
             .await
-            .map_err(|e| DatabaseError::Rpc(format!("Failed to connect: {}", e)))?;
+            .map_err(|e| DatabaseError::Unavailable(format!("Failed to connect to {}: {}", addr, e)))?;
         
         Ok(Self { client })
     }
 
     /// Execute a SQL query
     pub async fn execute_query(&mut self, query: String, parameters: std::collections::HashMap<String, String>) -> Result<crate::proto::database::QueryResponse> {
-        let request = QueryRequest {
-            query,
-            parameters,
-        };
-        
+        self.query_request(QueryRequest { query, parameters, ..Default::default() }).await
+    }
+
+    async fn query_request(&mut self, request: QueryRequest) -> Result<crate::proto::database::QueryResponse> {
         self.client.execute_query(request)
             .await
             .map(|r| r.into_inner())
-            .map_err(|e| DatabaseError::Rpc(format!("Query execution failed: {}", e)))
+            .map_err(from_status)
     }
 
     /// Get a value by key
     pub async fn get(&mut self, key: String) -> Result<crate::proto::database::GetResponse> {
-        let request = GetRequest { key };
-        
+        self.get_request(GetRequest { key, ..Default::default() }).await
+    }
+
+    /// Get the value a key had at `read_timestamp` (milliseconds since the epoch)
+    pub async fn get_as_of(&mut self, key: String, read_timestamp: u64) -> Result<crate::proto::database::GetResponse> {
+        self.get_request(GetRequest { key, read_timestamp, ..Default::default() }).await
+    }
+
+    /// Get a value from any replica at most `max_staleness` behind the leader
+    pub async fn get_stale(&mut self, key: String, max_staleness: Duration) -> Result<crate::proto::database::GetResponse> {
+        self.get_request(GetRequest {
+            key,
+            consistency: ReadConsistency::Stale as i32,
+            max_staleness_ms: (max_staleness.as_millis() as u64).max(1),
+            ..Default::default()
+        }).await
+    }
+
+    async fn get_request(&mut self, request: GetRequest) -> Result<crate::proto::database::GetResponse> {
         self.client.get(request)
             .await
             .map(|r| r.into_inner())
-            .map_err(|e| DatabaseError::Rpc(format!("Get operation failed: {}", e)))
+            .map_err(from_status)
     }
 
     /// Put a key-value pair
     pub async fn put(&mut self, key: String, value: Vec<u8>) -> Result<crate::proto::database::PutResponse> {
-        let request = PutRequest { key, value };
-        
+        self.put_request(PutRequest { key, value, ..Default::default() }).await
+    }
+
+    /// Put a key-value pair that expires after `ttl`
+    pub async fn put_with_ttl(&mut self, key: String, value: Vec<u8>, ttl: Duration) -> Result<crate::proto::database::PutResponse> {
+        let ttl_ms = (ttl.as_millis() as u64).max(1);
+        self.put_request(PutRequest { key, value, ttl_ms, ..Default::default() }).await
+    }
+
+    /// Put a key-value pair that expires at `expires_at_ms` (milliseconds since the epoch)
+    pub async fn put_until(&mut self, key: String, value: Vec<u8>, expires_at_ms: u64) -> Result<crate::proto::database::PutResponse> {
+        self.put_request(PutRequest { key, value, expires_at_ms, ..Default::default() }).await
+    }
+
+    async fn put_request(&mut self, request: PutRequest) -> Result<crate::proto::database::PutResponse> {
         self.client.put(request)
             .await
             .map(|r| r.into_inner())
-            .map_err(|e| DatabaseError::Rpc(format!("Put operation failed: {}", e)))
+            .map_err(from_status)
     }
 
     /// Delete a key
     pub async fn delete(&mut self, key: String) -> Result<crate::proto::database::DeleteResponse> {
-        let request = DeleteRequest { key };
-        
+        self.delete_request(DeleteRequest { key, ..Default::default() }).await
+    }
+
+    async fn delete_request(&mut self, request: DeleteRequest) -> Result<crate::proto::database::DeleteResponse> {
         self.client.delete(request)
             .await
             .map(|r| r.into_inner())
-            .map_err(|e| DatabaseError::Rpc(format!("Delete operation failed: {}", e)))
+            .map_err(from_status)
+    }
+
+    /// Put a value only if the key does not exist
+    pub async fn put_if_absent(&mut self, key: String, value: Vec<u8>) -> Result<crate::proto::database::ConditionalWriteResponse> {
+        self.conditional_write(key, Condition::PutIfAbsent(value)).await
+    }
+
+    /// Put a value only if the key is still at `expected_version` (0 = absent)
+    pub async fn compare_and_swap(&mut self, key: String, value: Vec<u8>, expected_version: u64) -> Result<crate::proto::database::ConditionalWriteResponse> {
+        self.conditional_write(key, Condition::PutIfVersion(PutIfVersion { value, expected_version })).await
+    }
+
+    /// Delete a key only if it holds `expected_value`
+    pub async fn delete_if_value(&mut self, key: String, expected_value: Vec<u8>) -> Result<crate::proto::database::ConditionalWriteResponse> {
+        self.conditional_write(key, Condition::DeleteIfValue(expected_value)).await
+    }
+
+    async fn conditional_write(&mut self, key: String, condition: Condition) -> Result<crate::proto::database::ConditionalWriteResponse> {
+        let request = ConditionalWriteRequest { key, condition: Some(condition) };
+        
+        self.client.conditional_write(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Atomically add `delta` to an integer value
+    pub async fn increment(&mut self, key: String, delta: i64) -> Result<crate::proto::database::IncrementResponse> {
+        let request = IncrementRequest { key, delta };
+        
+        self.client.increment(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Begin a snapshot isolation transaction
+    pub async fn begin_transaction(&mut self) -> Result<Transaction<'_>> {
+        self.begin_transaction_with_isolation(IsolationLevel::Snapshot).await
+    }
+
+    /// Begin a transaction at the given isolation level
+    ///
+    /// Commits that fail with a retryable error conflicted with a concurrent
+    /// transaction; run the transaction again from the start.
+    pub async fn begin_transaction_with_isolation(&mut self, isolation_level: IsolationLevel) -> Result<Transaction<'_>> {
+        let request = BeginTransactionRequest { isolation_level: isolation_level as i32 };
+        let response = self.client.begin_transaction(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+
+        Ok(Transaction {
+            client: self,
+            id: response.transaction_id,
+        })
+    }
+
+    /// Start building an atomic batch of writes
+    pub fn batch(&mut self) -> BatchBuilder<'_> {
+        BatchBuilder {
+            client: self,
+            operations: Vec::new(),
+        }
     }
 
     /// Scan a range of keys
-    pub async fn scan(&mut self, start_key: String, end_key: String, limit: i32) -> Result<crate::proto::database::ScanResponse> {
-        let request = ScanRequest {
+    ///
+    /// Pass the `continuation_token` of the previous response to fetch the next page.
+    pub async fn scan(&mut self, start_key: String, end_key: String, limit: i32, continuation_token: Option<String>) -> Result<crate::proto::database::ScanResponse> {
+        self.scan_request(ScanRequest {
             start_key,
             end_key,
             limit,
-        };
-        
+            continuation_token: continuation_token.unwrap_or_default(),
+            ..Default::default()
+        }).await
+    }
+
+    /// Scan a range of keys from any replica at most `max_staleness` behind the leader
+    ///
+    /// Pass the `continuation_token` of the previous response to fetch the next page.
+    pub async fn scan_stale(
+        &mut self,
+        start_key: String,
+        end_key: String,
+        limit: i32,
+        continuation_token: Option<String>,
+        max_staleness: Duration,
+    ) -> Result<crate::proto::database::ScanResponse> {
+        self.scan_request(ScanRequest {
+            start_key,
+            end_key,
+            limit,
+            continuation_token: continuation_token.unwrap_or_default(),
+            consistency: ReadConsistency::Stale as i32,
+            max_staleness_ms: (max_staleness.as_millis() as u64).max(1),
+            ..Default::default()
+        }).await
+    }
+
+    /// Scan a range of keys as it was at `read_timestamp` (milliseconds since the epoch)
+    ///
+    /// Continue with `scan` and the returned continuation token; later pages
+    /// keep reading at the same timestamp.
+    pub async fn scan_as_of(&mut self, start_key: String, end_key: String, limit: i32, read_timestamp: u64) -> Result<crate::proto::database::ScanResponse> {
+        self.scan_request(ScanRequest {
+            start_key,
+            end_key,
+            limit,
+            read_timestamp,
+            ..Default::default()
+        }).await
+    }
+
+    async fn scan_request(&mut self, request: ScanRequest) -> Result<crate::proto::database::ScanResponse> {
         self.client.scan(request)
             .await
             .map(|r| r.into_inner())
-            .map_err(|e| DatabaseError::Rpc(format!("Scan operation failed: {}", e)))
+            .map_err(from_status)
+    }
+
+    /// Get the route of every partition, or only of the partition owning `key`
+    pub async fn partition_map(&mut self, key: Option<String>) -> Result<GetPartitionMapResponse> {
+        self.client.get_partition_map(GetPartitionMapRequest { key: key.unwrap_or_default() })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+}
+
+/// Builder for an atomic batch of puts and deletes.
+///
+/// All keys in a batch must belong to the same partition.
+pub struct BatchBuilder<'a> {
+    client: &'a mut DatabaseClient,
+    operations: Vec<BatchOperation>,
+}
+
+impl<'a> BatchBuilder<'a> {
+    /// Add a put to the batch
+    pub fn put(mut self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Self {
+        self.operations.push(BatchOperation {
+            operation: Some(Operation::Put(PutRequest {
+                key: key.into(),
+                value: value.into(),
+                ..Default::default()
+            })),
+        });
+        self
+    }
+
+    /// Add a delete to the batch
+    pub fn delete(mut self, key: impl Into<String>) -> Self {
+        self.operations.push(BatchOperation {
+            operation: Some(Operation::Delete(DeleteRequest { key: key.into(), ..Default::default() })),
+        });
+        self
+    }
+
+    /// Number of operations in the batch
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    /// Whether the batch has no operations
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Send the batch to be applied atomically
+    pub async fn execute(self) -> Result<crate::proto::database::BatchResponse> {
+        let request = BatchRequest { operations: self.operations };
+        
+        self.client.client.batch(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+}
+
+/// An open transaction.
+///
+/// Writes are buffered by the coordinator and become visible atomically when
+/// the transaction commits. Dropping the handle leaves the transaction open on
+/// the coordinator; call `rollback` to discard it.
+pub struct Transaction<'a> {
+    client: &'a mut DatabaseClient,
+    id: String,
+}
+
+impl<'a> Transaction<'a> {
+    /// The transaction id, as passed in the transaction_id request fields
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Execute a SQL query inside the transaction
+    pub async fn execute_query(&mut self, query: String, parameters: std::collections::HashMap<String, String>) -> Result<crate::proto::database::QueryResponse> {
+        let transaction_id = self.id.clone();
+        self.client.query_request(QueryRequest { query, parameters, transaction_id }).await
+    }
+
+    /// Get a value, seeing the transaction's own writes
+    pub async fn get(&mut self, key: String) -> Result<crate::proto::database::GetResponse> {
+        let transaction_id = self.id.clone();
+        self.client.get_request(GetRequest { key, transaction_id, ..Default::default() }).await
+    }
+
+    /// Lock a key until the transaction ends and read it
+    ///
+    /// Waits while another transaction holds the lock. A retryable error
+    /// means the transaction was rolled back to break a deadlock; run it
+    /// again from the start.
+    pub async fn get_for_update(&mut self, key: String) -> Result<crate::proto::database::GetResponse> {
+        let transaction_id = self.id.clone();
+        self.client.get_request(GetRequest { key, transaction_id, for_update: true, ..Default::default() }).await
+    }
+
+    /// Put a key-value pair inside the transaction
+    pub async fn put(&mut self, key: String, value: Vec<u8>) -> Result<crate::proto::database::PutResponse> {
+        let transaction_id = self.id.clone();
+        self.client.put_request(PutRequest { key, value, transaction_id, ..Default::default() }).await
+    }
+
+    /// Delete a key inside the transaction
+    pub async fn delete(&mut self, key: String) -> Result<crate::proto::database::DeleteResponse> {
+        let transaction_id = self.id.clone();
+        self.client.delete_request(DeleteRequest { key, transaction_id }).await
+    }
+
+    /// Commit the transaction
+    pub async fn commit(self) -> Result<crate::proto::database::CommitTransactionResponse> {
+        let request = CommitTransactionRequest { transaction_id: self.id };
+        
+        self.client.client.commit_transaction(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Discard the transaction and its writes
+    pub async fn rollback(self) -> Result<crate::proto::database::RollbackTransactionResponse> {
+        let request = RollbackTransactionRequest { transaction_id: self.id };
+        
+        self.client.client.rollback_transaction(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+}
+
+/// Client for the coordinator's admin service.
+pub struct AdminClient {
+    client: AdminServiceClient<Channel>,
+}
+
+impl AdminClient {
+    /// Create a new admin client. Requests have no timeout, since adding a
+    /// replica waits for it to catch up.
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let endpoint = Endpoint::from_shared(format!("http://{}", addr))
+            .map_err(|e| DatabaseError::InvalidArgument(format!("Invalid endpoint {}: {}", addr, e)))?;
+        
+        let client = AdminServiceClient::connect(endpoint)
+            .await
+            .map_err(|e| DatabaseError::Unavailable(format!("Failed to connect to {}: {}", addr, e)))?;
+        
+        Ok(Self { client })
+    }
+
+    /// Add a voting replica of a partition on `node_id`. Returns once the
+    /// node has caught up as a learner and been promoted.
+    pub async fn add_voter(&mut self, partition_id: u64, node_id: String, address: String) -> Result<MembershipResponse> {
+        self.add_replica(AddReplicaRequest { partition_id, node_id, address, as_learner: false }).await
+    }
+
+    /// Add a non-voting replica of a partition on `node_id`
+    pub async fn add_learner(&mut self, partition_id: u64, node_id: String, address: String) -> Result<MembershipResponse> {
+        self.add_replica(AddReplicaRequest { partition_id, node_id, address, as_learner: true }).await
+    }
+
+    async fn add_replica(&mut self, request: AddReplicaRequest) -> Result<MembershipResponse> {
+        self.client.add_replica(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Promote a learner of a partition to a voter
+    pub async fn promote_learner(&mut self, partition_id: u64, node_id: String) -> Result<MembershipResponse> {
+        self.client.promote_learner(PromoteLearnerRequest { partition_id, node_id })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Remove the replica of a partition on `node_id`
+    pub async fn remove_replica(&mut self, partition_id: u64, node_id: String) -> Result<MembershipResponse> {
+        self.client.remove_replica(RemoveReplicaRequest { partition_id, node_id })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Get the replicas of a partition
+    pub async fn membership(&mut self, partition_id: u64) -> Result<MembershipResponse> {
+        self.client.get_membership(GetMembershipRequest { partition_id })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Move leadership of a partition to `target`, or to the least loaded voter
+    pub async fn transfer_leader(&mut self, partition_id: u64, target: Option<String>) -> Result<MembershipResponse> {
+        let request = TransferLeaderRequest { partition_id, target: target.unwrap_or_default() };
+        self.client.transfer_leader(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Move all leaderships off a node before maintenance
+    pub async fn drain_node(&mut self, node_id: String) -> Result<LeaderTransfersResponse> {
+        self.client.drain_node(DrainNodeRequest { node_id })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Let a drained node lead partitions again
+    pub async fn undrain_node(&mut self, node_id: String) -> Result<UndrainNodeResponse> {
+        self.client.undrain_node(UndrainNodeRequest { node_id })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Spread leaders evenly over the active nodes
+    pub async fn balance_leaders(&mut self) -> Result<LeaderTransfersResponse> {
+        self.client.balance_leaders(BalanceLeadersRequest {})
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Start moving every replica and leadership off a node
+    pub async fn decommission_node(&mut self, node_id: String) -> Result<DecommissionResponse> {
+        self.client.decommission_node(DecommissionNodeRequest { node_id })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Progress of decommissioning a node
+    pub async fn decommission_status(&mut self, node_id: String) -> Result<DecommissionResponse> {
+        self.client.get_decommission_status(DecommissionStatusRequest { node_id })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Stop decommissioning a node
+    pub async fn abort_decommission(&mut self, node_id: String) -> Result<DecommissionResponse> {
+        self.client.abort_decommission(AbortDecommissionRequest { node_id })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Set where the replicas and leader of a key range may live
+    pub async fn set_placement_constraints(
+        &mut self,
+        constraints: common::types::PlacementConstraints,
+    ) -> Result<PlacementConstraintsResponse> {
+        self.client.set_placement_constraints(SetPlacementConstraintsRequest { constraints: Some(constraints.into()) })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Placement constraints of every constrained key range
+    pub async fn list_placement_constraints(&mut self) -> Result<PlacementConstraintsResponse> {
+        self.client.list_placement_constraints(ListPlacementConstraintsRequest {})
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// List the nodes of the cluster
+    pub async fn list_nodes(&mut self) -> Result<ListNodesResponse> {
+        self.client.list_nodes(ListNodesRequest {})
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Describe a node and the partitions it holds replicas of
+    pub async fn describe_node(&mut self, node_id: String) -> Result<DescribeNodeResponse> {
+        self.client.describe_node(DescribeNodeRequest { node_id })
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+}
+
+/// Client for the coordinator's cluster service, used by the data nodes.
+pub struct ClusterClient {
+    client: ClusterServiceClient<Channel>,
+}
+
+impl ClusterClient {
+    /// Create a new cluster client.
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let endpoint = Endpoint::from_shared(format!("http://{}", addr))
+            .map_err(|e| DatabaseError::InvalidArgument(format!("Invalid endpoint {}: {}", addr, e)))?
+            .timeout(Duration::from_secs(5));
+        
+        let client = ClusterServiceClient::connect(endpoint)
+            .await
+            .map_err(|e| DatabaseError::Unavailable(format!("Failed to connect to {}: {}", addr, e)))?;
+        
+        Ok(Self { client })
+    }
+
+    /// Report that the node is alive, with its locality and current load
+    pub async fn heartbeat(
+        &mut self,
+        node_id: &NodeId,
+        address: String,
+        locality: &Locality,
+        load: NodeLoad,
+    ) -> Result<NodeHeartbeatResponse> {
+        let request = NodeHeartbeatRequest {
+            node_id: node_id.0.clone(),
+            address,
+            disk_used_bytes: load.disk_used_bytes,
+            disk_capacity_bytes: load.disk_capacity_bytes,
+            qps: load.qps,
+            locality: locality.to_string(),
+        };
+        self.client.heartbeat(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
     }
 }
 
 /// Client for the node service.
+///
+/// Requests are stamped with the local hybrid logical clock, and the clock is
+/// advanced past the timestamp of every response.
 pub struct NodeClient {
     client: NodeServiceClient<Channel>,
+    clock: Arc<HybridClock>,
 }
 
 impl NodeClient {
-    /// Create a new node client.
-    pub async fn connect(addr: &str) -> Result<Self> {
-        let endpoint = Endpoint::from_shared(format!("http://{}", addr))
-            .map_err(|e| DatabaseError::Rpc(format!("Invalid endpoint: {}", e)))?
-            .timeout(Duration::from_secs(5));
+    /// Create a new node client that stamps requests with `clock`.
+    pub async fn connect(addr: &str, clock: Arc<HybridClock>) -> Result<Self> {
+        Self::connect_with_timeouts(addr, clock, Timeouts::default()).await
+    }
+
+    /// Create a new node client with the given timeouts.
+    pub async fn connect_with_timeouts(addr: &str, clock: Arc<HybridClock>, timeouts: Timeouts) -> Result<Self> {
+        let endpoint = timeouts.endpoint(addr)?;
         
         let client = NodeServiceClient::connect(endpoint)
             .await
-            .map_err(|e| DatabaseError::Rpc(format!("Failed to connect: {}", e)))?;
+            .map_err(|e| DatabaseError::Unavailable(format!("Failed to connect to {}: {}", addr, e)))?;
         
-        Ok(Self { client })
+        Ok(Self { client, clock })
     }
 
-    // Add methods for interacting with the node service
+    /// Apply a batch of writes and deletes on the node as a single Raft entry;
+    /// fails with a conflict if a pending transaction holds one of the keys
+    pub async fn batch(&mut self, operations: Vec<crate::proto::node::BatchOperation>) -> Result<crate::proto::node::BatchResponse> {
+        let response = self.batch_routed(operations, 0).await?;
+        if let Some(txn) = response.blocked_by {
+            return Err(Self::blocked(None, "Batch", txn));
+        }
+        Ok(response)
+    }
+
+    // A batch routed by the partition map at `metadata_version`
+    async fn batch_routed(
+        &mut self,
+        operations: Vec<crate::proto::node::BatchOperation>,
+        metadata_version: u64,
+    ) -> Result<crate::proto::node::BatchResponse> {
+        let request = crate::proto::node::BatchRequest {
+            operations,
+            hlc: Some(self.clock.now().into()),
+            metadata_version,
+        };
+        
+        let response = self.client.batch(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        Ok(response)
+    }
+
+    /// Lock waits on the node, for deadlock detection
+    pub async fn wait_for_graph(&mut self) -> Result<Vec<WaitForEdge>> {
+        let request = crate::proto::node::WaitForGraphRequest {
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.get_wait_for_graph(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        Ok(response.edges.into_iter().map(Into::into).collect())
+    }
+
+    /// Lock `keys` for a transaction in the lock tables of the partitions the
+    /// node leads, waiting while other transactions hold them
+    pub async fn acquire_locks(&mut self, txn_id: &TransactionId, start_ts: HlcTimestamp, keys: Vec<String>) -> Result<()> {
+        let request = crate::proto::node::AcquireLocksRequest {
+            txn_id: txn_id.0.clone(),
+            start_ts: Some(start_ts.into()),
+            keys,
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.acquire_locks(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)
+    }
+
+    /// Release every lock a transaction holds on the node
+    pub async fn release_locks(&mut self, txn_id: &TransactionId) -> Result<()> {
+        let request = crate::proto::node::ReleaseLocksRequest {
+            txn_id: txn_id.0.clone(),
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.release_locks(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)
+    }
+
+    /// Abort the lock waits of a transaction on the node
+    pub async fn abort_lock_waits(&mut self, txn_id: &TransactionId, reason: String) -> Result<()> {
+        let request = crate::proto::node::AbortLockWaitsRequest {
+            txn_id: txn_id.0.clone(),
+            reason,
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.abort_lock_waits(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)
+    }
+
+    /// Size and load of the partitions the node leads
+    pub async fn partition_stats(&mut self) -> Result<Vec<PartitionStats>> {
+        let request = crate::proto::node::PartitionStatsRequest {
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.get_partition_stats(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        Ok(response.partitions.into_iter().map(Into::into).collect())
+    }
+
+    /// Latest value and version of a key, read through the leader of its
+    /// partition; `None` if the key does not exist
+    pub async fn read(&mut self, key: String) -> Result<Option<(Vec<u8>, u64)>> {
+        match self.read_routed(key, 0).await? {
+            Routed::Served(value) => Ok(value),
+            Routed::Misrouted(error) => Err(error.into()),
+        }
+    }
+
+    /// Latest value and version of a key, if the node leads its partition.
+    /// `metadata_version` is that of the partition map the read was routed
+    /// by; 0 if none. Fails with a conflict if the intent of a pending
+    /// transaction hides the value.
+    pub async fn read_routed(&mut self, key: String, metadata_version: u64) -> Result<Routed<Option<(Vec<u8>, u64)>>> {
+        let request = crate::proto::node::ReadRequest {
+            key: key.clone(),
+            hlc: Some(self.clock.now().into()),
+            metadata_version,
+            ..Default::default()
+        };
+        
+        let response = self.client.read(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        if let Some(error) = response.route_error {
+            return Ok(Routed::Misrouted(error.into()));
+        }
+        if let Some(txn) = response.blocked_by {
+            return Err(Self::blocked(Some(key), "Read", txn));
+        }
+        Ok(Routed::Served(response.found.then_some((response.value, response.version))))
+    }
+
+    // A request held up by the intent of a pending transaction; worth
+    // retrying once the transaction has committed or aborted
+    fn blocked(key: Option<String>, request: &str, txn: crate::proto::node::TransactionMeta) -> DatabaseError {
+        DatabaseError::Conflict { key, message: format!("{} blocked by pending transaction {}", request, txn.id) }
+    }
+
+    /// Value and version of a key as of `read_ts`, or its latest version if
+    /// unset, read at `consistency` if the node serves the key
+    pub async fn read_at(
+        &mut self,
+        key: String,
+        read_ts: Option<HlcTimestamp>,
+        consistency: common::types::ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<Option<VersionedValue>>>> {
+        let (consistency, max_staleness_ms) = Self::consistency(consistency);
+        let request = crate::proto::node::ReadRequest {
+            key: key.clone(),
+            read_timestamp: read_ts.map(Into::into),
+            hlc: Some(self.clock.now().into()),
+            consistency,
+            max_staleness_ms,
+            metadata_version: 0,
+        };
+        
+        let response = self.client.read(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        if let Some(error) = response.route_error {
+            return Ok(Routed::Misrouted(error.into()));
+        }
+        if let Some(txn) = response.blocked_by {
+            return Ok(Routed::Served(ReadOutcome::Blocked { key, txn: txn.into() }));
+        }
+        let value = response.found.then_some(VersionedValue { value: response.value, version: response.version });
+        Ok(Routed::Served(ReadOutcome::Read(value)))
+    }
+
+    /// Up to `limit` pairs (all if 0) from `start_key` up to `end_key` as of
+    /// `read_ts`, and whether the scan stopped short of `end_key`. The node
+    /// stops at the end of the range holding `start_key`.
+    pub async fn scan_at(
+        &mut self,
+        start_key: String,
+        end_key: String,
+        limit: usize,
+        read_ts: Option<HlcTimestamp>,
+        consistency: common::types::ReadConsistency,
+    ) -> Result<Routed<ReadOutcome<(Vec<(String, Vec<u8>)>, bool)>>> {
+        let (consistency, max_staleness_ms) = Self::consistency(consistency);
+        let request = crate::proto::node::ScanRequest {
+            start_key,
+            end_key,
+            limit: i32::try_from(limit).unwrap_or(i32::MAX),
+            read_timestamp: read_ts.map(Into::into),
+            hlc: Some(self.clock.now().into()),
+            consistency,
+            max_staleness_ms,
+        };
+        
+        let response = self.client.scan(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        if let Some(error) = response.route_error {
+            return Ok(Routed::Misrouted(error.into()));
+        }
+        if let Some(txn) = response.blocked_by {
+            return Ok(Routed::Served(ReadOutcome::Blocked { key: response.blocked_key, txn: txn.into() }));
+        }
+        let items = response.items.into_iter().map(|item| (item.key, item.value)).collect();
+        Ok(Routed::Served(ReadOutcome::Read((items, response.has_more))))
+    }
+
+    /// Replicate `command` through the Raft group of partition
+    /// `partition_id`, if the node leads it, and return the command's response
+    pub async fn propose(&mut self, partition_id: u64, command: &Command) -> Result<Routed<CommandResponse>> {
+        let request = crate::proto::node::ProposeRequest {
+            partition_id,
+            command: serde_json::to_vec(command)?,
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.propose(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        if let Some(error) = response.route_error {
+            return Ok(Routed::Misrouted(error.into()));
+        }
+        Ok(Routed::Served(serde_json::from_slice(&response.response)?))
+    }
+
+    /// Write a key, if the node leads its partition. `expires_at_ms` is the
+    /// absolute expiry; 0 means the key never expires. `metadata_version` is
+    /// that of the partition map the write was routed by; 0 if none. Fails
+    /// with a conflict if a pending transaction holds the key.
+    pub async fn write(&mut self, key: String, value: Vec<u8>, expires_at_ms: u64, metadata_version: u64) -> Result<Routed<()>> {
+        let request = crate::proto::node::WriteRequest {
+            key: key.clone(),
+            value,
+            expires_at_ms,
+            hlc: Some(self.clock.now().into()),
+            metadata_version,
+        };
+        
+        let response = self.client.write(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        if let Some(error) = response.route_error {
+            return Ok(Routed::Misrouted(error.into()));
+        }
+        if let Some(txn) = response.blocked_by {
+            return Err(Self::blocked(Some(key), "Write", txn));
+        }
+        Ok(Routed::Served(()))
+    }
+
+    /// Delete a key, if the node leads its partition. `metadata_version` is
+    /// that of the partition map the delete was routed by; 0 if none. Fails
+    /// with a conflict if a pending transaction holds the key.
+    pub async fn delete(&mut self, key: String, metadata_version: u64) -> Result<Routed<()>> {
+        let operation = crate::proto::node::BatchOperation {
+            operation: Some(crate::proto::node::batch_operation::Operation::DeleteKey(key.clone())),
+        };
+        let response = self.batch_routed(vec![operation], metadata_version).await?;
+        if let Some(error) = response.route_error {
+            return Ok(Routed::Misrouted(error.into()));
+        }
+        if let Some(txn) = response.blocked_by {
+            return Err(Self::blocked(Some(key), "Delete", txn));
+        }
+        Ok(Routed::Served(()))
+    }
+
+    /// Store a value only if the key is still at `expected_version` (0 = absent)
+    pub async fn put_if_version(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        expected_version: u64,
+    ) -> Result<crate::proto::node::ConditionalWriteResponse> {
+        let request = crate::proto::node::ConditionalWriteRequest {
+            key,
+            condition: Some(crate::proto::node::conditional_write_request::Condition::PutIfVersion(
+                crate::proto::node::PutIfVersion { value, expected_version },
+            )),
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.conditional_write(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        Ok(response)
+    }
+
+    /// Add `node` to a partition the node leads as a learner; returns the
+    /// log index the learner has replicated once it caught up
+    pub async fn add_learner(&mut self, partition_id: u64, node: &NodeId, address: String) -> Result<u64> {
+        let request = crate::proto::node::AddLearnerRequest {
+            partition_id,
+            node_id: node.0.clone(),
+            address,
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.add_learner(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        Ok(response.matched_index)
+    }
+
+    /// Move the Raft group of a partition the node leads to `voters` and
+    /// `learners`; returns once the new configuration is committed
+    pub async fn change_membership(&mut self, partition_id: u64, voters: Vec<NodeId>, learners: Vec<NodeId>) -> Result<()> {
+        let request = crate::proto::node::ChangeMembershipRequest {
+            partition_id,
+            voters: voters.into_iter().map(|node| node.0).collect(),
+            learners: learners.into_iter().map(|node| node.0).collect(),
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.change_membership(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)
+    }
+
+    /// Hand leadership of a partition the node leads to the voter `target`;
+    /// returns once the target has won the election
+    pub async fn transfer_leader(&mut self, partition_id: u64, target: &NodeId) -> Result<()> {
+        let request = crate::proto::node::TransferLeaderRequest {
+            partition_id,
+            target: target.0.clone(),
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.transfer_leader(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)
+    }
+
+    /// Disk usage and request rate of the node, with the node's id
+    pub async fn node_load(&mut self) -> Result<(NodeId, NodeLoad)> {
+        let request = crate::proto::node::NodeLoadRequest {
+            hlc: Some(self.clock.now().into()),
+        };
+        
+        let response = self.client.get_node_load(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        self.observe(response.hlc)?;
+        Ok((NodeId::from(response.node_id), response.load.unwrap_or_default().into()))
+    }
+
+    // Advance the local clock past the node's clock reading
+    fn observe(&self, hlc: Option<crate::proto::node::HlcTimestamp>) -> Result<()> {
+        if let Some(hlc) = hlc {
+            self.clock.update(hlc.into())?;
+        }
+        Ok(())
+    }
+
+    // The proto consistency of a read and its staleness bound
+    fn consistency(consistency: common::types::ReadConsistency) -> (i32, u64) {
+        match consistency {
+            common::types::ReadConsistency::Linearizable => (crate::proto::node::ReadConsistency::Linearizable as i32, 0),
+            common::types::ReadConsistency::Stale { max_staleness_ms } => {
+                (crate::proto::node::ReadConsistency::Stale as i32, max_staleness_ms)
+            },
+        }
+    }
 }
 
 /// Client for the Raft service.
+///
+/// Clones share the connection.
+#[derive(Clone)]
 pub struct RaftClient {
     client: RaftServiceClient<Channel>,
 }
@@ -116,15 +1021,59 @@ impl RaftClient {
     /// Create a new Raft client.
     pub async fn connect(addr: &str) -> Result<Self> {
         let endpoint = Endpoint::from_shared(format!("http://{}", addr))
-            .map_err(|e| DatabaseError::Rpc(format!("Invalid endpoint: {}", e)))?
+            .map_err(|e| DatabaseError::InvalidArgument(format!("Invalid endpoint {}: {}", addr, e)))?
             .timeout(Duration::from_secs(5));
         
         let client = RaftServiceClient::connect(endpoint)
             .await
-            .map_err(|e| DatabaseError::Rpc(format!("Failed to connect: {}", e)))?;
+            .map_err(|e| DatabaseError::Unavailable(format!("Failed to connect to {}: {}", addr, e)))?;
         
         Ok(Self { client })
     }
 
-    // Add methods for interacting with the Raft service
+    /// Send the heartbeats of every group `from` leads with a replica on the peer
+    pub async fn heartbeat(&mut self, from: &NodeId, heartbeats: Vec<GroupHeartbeat>) -> Result<Vec<GroupHeartbeatAck>> {
+        let request = crate::proto::raft::HeartbeatBatchRequest {
+            from: from.0.clone(),
+            heartbeats: heartbeats.into_iter().map(Into::into).collect(),
+        };
+        
+        let response = self.client.heartbeat(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)?;
+        Ok(response.acks.into_iter().map(Into::into).collect())
+    }
+
+    /// Replicate log entries to a replica of one group
+    pub async fn append_entries(&mut self, request: AppendEntriesRequest) -> Result<AppendEntriesResponse> {
+        self.client.append_entries(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Ask a replica of one group for its vote
+    pub async fn request_vote(&mut self, request: RequestVoteRequest) -> Result<RequestVoteResponse> {
+        self.client.request_vote(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Send one chunk of a snapshot to a replica of one group
+    pub async fn install_snapshot(&mut self, request: InstallSnapshotRequest) -> Result<InstallSnapshotResponse> {
+        self.client.install_snapshot(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
+
+    /// Tell the target of a leader transfer to start an election
+    pub async fn timeout_now(&mut self, request: TimeoutNowRequest) -> Result<TimeoutNowResponse> {
+        self.client.timeout_now(request)
+            .await
+            .map(|r| r.into_inner())
+            .map_err(from_status)
+    }
 }
\ No newline at end of file
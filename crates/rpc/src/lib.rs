@@ -14,12 +14,352 @@ pub mod proto {
     pub mod raft {
         tonic::include_proto!("raft");
     }
+
+    pub mod admin {
+        tonic::include_proto!("admin");
+    }
+
+    pub mod cluster {
+        tonic::include_proto!("cluster");
+    }
+
+    pub mod error {
+        tonic::include_proto!("error");
+    }
+}
+
+impl From<common::hlc::HlcTimestamp> for proto::node::HlcTimestamp {
+    fn from(ts: common::hlc::HlcTimestamp) -> Self {
+        Self { wall_ms: ts.wall_ms, logical: ts.logical }
+    }
+}
+
+impl From<proto::node::HlcTimestamp> for common::hlc::HlcTimestamp {
+    fn from(ts: proto::node::HlcTimestamp) -> Self {
+        Self::new(ts.wall_ms, ts.logical)
+    }
+}
+
+impl From<common::types::WaitForEdge> for proto::node::WaitForEdge {
+    fn from(edge: common::types::WaitForEdge) -> Self {
+        Self {
+            waiter: edge.waiter.0,
+            waiter_start_ts: Some(edge.waiter_start_ts.into()),
+            holder: edge.holder.0,
+            key: edge.key,
+        }
+    }
+}
+
+impl From<proto::node::WaitForEdge> for common::types::WaitForEdge {
+    fn from(edge: proto::node::WaitForEdge) -> Self {
+        Self {
+            waiter: common::types::TransactionId(edge.waiter),
+            waiter_start_ts: edge.waiter_start_ts.map(Into::into).unwrap_or_default(),
+            holder: common::types::TransactionId(edge.holder),
+            key: edge.key,
+        }
+    }
+}
+
+impl From<common::types::PartitionStats> for proto::node::PartitionStats {
+    fn from(stats: common::types::PartitionStats) -> Self {
+        Self {
+            partition_id: stats.partition_id,
+            size_bytes: stats.size_bytes,
+            key_count: stats.key_count,
+            qps: stats.qps,
+            split_key: stats.split_key.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<proto::node::PartitionStats> for common::types::PartitionStats {
+    fn from(stats: proto::node::PartitionStats) -> Self {
+        Self {
+            partition_id: stats.partition_id,
+            size_bytes: stats.size_bytes,
+            key_count: stats.key_count,
+            qps: stats.qps,
+            split_key: Some(stats.split_key).filter(|key| !key.is_empty()),
+        }
+    }
+}
+
+impl From<common::types::NodeLoad> for proto::node::NodeLoad {
+    fn from(load: common::types::NodeLoad) -> Self {
+        Self {
+            disk_used_bytes: load.disk_used_bytes,
+            disk_capacity_bytes: load.disk_capacity_bytes,
+            qps: load.qps,
+        }
+    }
+}
+
+impl From<proto::node::NodeLoad> for common::types::NodeLoad {
+    fn from(load: proto::node::NodeLoad) -> Self {
+        Self {
+            disk_used_bytes: load.disk_used_bytes,
+            disk_capacity_bytes: load.disk_capacity_bytes,
+            qps: load.qps,
+        }
+    }
+}
+
+impl From<common::types::TransactionMeta> for proto::node::TransactionMeta {
+    fn from(txn: common::types::TransactionMeta) -> Self {
+        Self {
+            id: txn.id.0,
+            primary_key: txn.primary_key,
+            start_ts: Some(txn.start_ts.into()),
+        }
+    }
+}
+
+impl From<proto::node::TransactionMeta> for common::types::TransactionMeta {
+    fn from(txn: proto::node::TransactionMeta) -> Self {
+        Self {
+            id: common::types::TransactionId(txn.id),
+            primary_key: txn.primary_key,
+            start_ts: txn.start_ts.map(Into::into).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<common::types::TransactionRecord> for proto::node::TransactionRecord {
+    fn from(record: common::types::TransactionRecord) -> Self {
+        let status = match record.status {
+            common::types::TransactionStatus::Pending => proto::node::TransactionStatus::Pending,
+            common::types::TransactionStatus::Committed => proto::node::TransactionStatus::Committed,
+            common::types::TransactionStatus::Aborted => proto::node::TransactionStatus::Aborted,
+        };
+        Self {
+            meta: Some(record.meta.into()),
+            status: status.into(),
+            commit_ts: record.commit_ts.map(Into::into),
+            keys: record.keys,
+        }
+    }
+}
+
+impl TryFrom<proto::node::BatchOperation> for common::types::BatchOperation {
+    type Error = common::error::DatabaseError;
+
+    fn try_from(operation: proto::node::BatchOperation) -> Result<Self, Self::Error> {
+        match operation.operation {
+            Some(proto::node::batch_operation::Operation::Write(write)) => {
+                Ok(Self::Put { key: write.key, value: write.value })
+            },
+            Some(proto::node::batch_operation::Operation::DeleteKey(key)) => Ok(Self::Delete { key }),
+            None => Err(common::error::DatabaseError::InvalidArgument("Batch operation is empty".to_string())),
+        }
+    }
+}
+
+impl From<common::types::GroupHeartbeat> for proto::raft::GroupHeartbeat {
+    fn from(heartbeat: common::types::GroupHeartbeat) -> Self {
+        Self {
+            group_id: heartbeat.group_id,
+            term: heartbeat.term,
+            leader_id: heartbeat.leader.0,
+            leader_commit: heartbeat.leader_commit,
+            leader_ms: heartbeat.leader_ms,
+        }
+    }
+}
+
+impl From<proto::raft::GroupHeartbeat> for common::types::GroupHeartbeat {
+    fn from(heartbeat: proto::raft::GroupHeartbeat) -> Self {
+        Self {
+            group_id: heartbeat.group_id,
+            term: heartbeat.term,
+            leader: common::types::NodeId(heartbeat.leader_id),
+            leader_commit: heartbeat.leader_commit,
+            leader_ms: heartbeat.leader_ms,
+        }
+    }
+}
+
+impl From<common::types::GroupHeartbeatAck> for proto::raft::GroupHeartbeatAck {
+    fn from(ack: common::types::GroupHeartbeatAck) -> Self {
+        Self { group_id: ack.group_id, term: ack.term, success: ack.success }
+    }
+}
+
+impl From<proto::raft::GroupHeartbeatAck> for common::types::GroupHeartbeatAck {
+    fn from(ack: proto::raft::GroupHeartbeatAck) -> Self {
+        Self { group_id: ack.group_id, term: ack.term, success: ack.success }
+    }
+}
+
+impl From<&common::types::PartitionInfo> for proto::admin::Membership {
+    fn from(partition: &common::types::PartitionInfo) -> Self {
+        Self {
+            partition_id: partition.id,
+            leader: partition.leader.0.clone(),
+            voters: partition.voters().into_iter().map(|node| node.0).collect(),
+            learners: partition.learners.iter().map(|node| node.0.clone()).collect(),
+        }
+    }
+}
+
+impl From<common::types::NodeStatus> for proto::admin::NodeStatus {
+    fn from(status: common::types::NodeStatus) -> Self {
+        match status {
+            common::types::NodeStatus::Active => Self::Active,
+            common::types::NodeStatus::Inactive => Self::Inactive,
+            common::types::NodeStatus::Joining => Self::Joining,
+            common::types::NodeStatus::Leaving => Self::Leaving,
+            common::types::NodeStatus::Suspect => Self::Suspect,
+            common::types::NodeStatus::Dead => Self::Dead,
+            common::types::NodeStatus::Removed => Self::Removed,
+        }
+    }
+}
+
+impl From<proto::admin::NodeStatus> for common::types::NodeStatus {
+    fn from(status: proto::admin::NodeStatus) -> Self {
+        match status {
+            proto::admin::NodeStatus::Active => Self::Active,
+            proto::admin::NodeStatus::Inactive => Self::Inactive,
+            proto::admin::NodeStatus::Joining => Self::Joining,
+            proto::admin::NodeStatus::Leaving => Self::Leaving,
+            proto::admin::NodeStatus::Suspect => Self::Suspect,
+            proto::admin::NodeStatus::Dead => Self::Dead,
+            proto::admin::NodeStatus::Removed => Self::Removed,
+        }
+    }
+}
+
+impl From<common::types::NodeInfo> for proto::admin::Node {
+    fn from(info: common::types::NodeInfo) -> Self {
+        Self {
+            node_id: info.id.0,
+            address: info.address,
+            status: proto::admin::NodeStatus::from(info.status) as i32,
+            locality: info.locality.to_string(),
+            last_heartbeat_ms: info.last_heartbeat.map_or(0, |heartbeat| heartbeat.timestamp_millis().max(0) as u64),
+            disk_used_bytes: info.load.disk_used_bytes,
+            disk_capacity_bytes: info.load.disk_capacity_bytes,
+            qps: info.load.qps,
+        }
+    }
+}
+
+impl From<common::types::PlacementConstraints> for proto::admin::PlacementConstraints {
+    fn from(constraints: common::types::PlacementConstraints) -> Self {
+        Self {
+            start_key: constraints.range.start,
+            end_key: constraints.range.end,
+            num_replicas: constraints.num_replicas.unwrap_or(0) as u64,
+            spread_over: constraints.spread_over.unwrap_or_default(),
+            required: constraints.required.to_string(),
+            leader_locality: constraints.leader_locality.to_string(),
+            table: constraints.table.unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<proto::admin::PlacementConstraints> for common::types::PlacementConstraints {
+    type Error = common::error::DatabaseError;
+
+    fn try_from(constraints: proto::admin::PlacementConstraints) -> common::error::Result<Self> {
+        Ok(Self {
+            range: common::types::KeyRange::new(constraints.start_key, constraints.end_key),
+            table: (!constraints.table.is_empty()).then_some(constraints.table),
+            num_replicas: (constraints.num_replicas > 0).then_some(constraints.num_replicas as usize),
+            spread_over: (!constraints.spread_over.is_empty()).then_some(constraints.spread_over),
+            required: common::types::Locality::parse(&constraints.required)?,
+            leader_locality: common::types::Locality::parse(&constraints.leader_locality)?,
+        })
+    }
+}
+
+impl From<common::types::PartitionRoute> for proto::database::PartitionRoute {
+    fn from(route: common::types::PartitionRoute) -> Self {
+        Self {
+            partition_id: route.partition_id,
+            start_key: route.range.start,
+            end_key: route.range.end,
+            leader_id: route.leader.0,
+            leader_address: route.leader_address,
+        }
+    }
+}
+
+impl From<proto::database::PartitionRoute> for common::types::PartitionRoute {
+    fn from(route: proto::database::PartitionRoute) -> Self {
+        Self {
+            partition_id: route.partition_id,
+            range: common::types::KeyRange::new(route.start_key, route.end_key),
+            leader: common::types::NodeId(route.leader_id),
+            leader_address: route.leader_address,
+        }
+    }
+}
+
+impl From<proto::node::RouteError> for routing::RouteError {
+    fn from(error: proto::node::RouteError) -> Self {
+        match error.kind() {
+            proto::node::route_error::Kind::NotLeader => Self::NotLeader {
+                leader: (!error.leader_id.is_empty())
+                    .then_some((common::types::NodeId(error.leader_id), error.leader_address)),
+            },
+            proto::node::route_error::Kind::KeyOutOfRange => Self::KeyOutOfRange,
+            proto::node::route_error::Kind::StaleVersion => Self::StaleVersion { metadata_version: error.metadata_version },
+        }
+    }
+}
+
+impl From<routing::RouteError> for proto::node::RouteError {
+    fn from(error: routing::RouteError) -> Self {
+        match error {
+            routing::RouteError::NotLeader { leader } => {
+                let (leader_id, leader_address) = leader.map(|(id, address)| (id.0, address)).unwrap_or_default();
+                Self {
+                    kind: proto::node::route_error::Kind::NotLeader.into(),
+                    leader_id,
+                    leader_address,
+                    ..Default::default()
+                }
+            },
+            routing::RouteError::KeyOutOfRange => Self {
+                kind: proto::node::route_error::Kind::KeyOutOfRange.into(),
+                ..Default::default()
+            },
+            routing::RouteError::StaleVersion { metadata_version } => Self {
+                kind: proto::node::route_error::Kind::StaleVersion.into(),
+                metadata_version,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl From<routing::RouteError> for common::error::DatabaseError {
+    fn from(error: routing::RouteError) -> Self {
+        match error {
+            routing::RouteError::NotLeader { leader } => Self::NotLeader { partition: None, leader },
+            routing::RouteError::KeyOutOfRange => Self::Partition {
+                partition: None,
+                message: "Key is outside the ranges the node serves".to_string(),
+            },
+            routing::RouteError::StaleVersion { metadata_version } => Self::Partition {
+                partition: None,
+                message: format!("Routed by a partition map older than metadata version {}", metadata_version),
+            },
+        }
+    }
 }
 
 // pub mod database_service;
 // pub mod node_service;
 // pub mod raft_service;
-// pub mod client;
+pub mod client;
+pub mod pool;
+pub mod routing;
+pub mod smart_client;
+pub mod status;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,60 @@
+//! Pooled connections to coordinators and nodes.
+//!
+//! A gRPC channel multiplexes any number of concurrent requests, so one
+//! connection per server is all a client needs. The pool opens it on first
+//! use and keeps it until a request through it fails at the transport
+//! level, when the caller evicts it and the next request reconnects.
+
+use crate::client::{DatabaseClient, NodeClient, Timeouts};
+use common::error::Result;
+use common::hlc::HybridClock;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Open connections, by server address
+pub struct ConnectionPool {
+    timeouts: Timeouts,
+    clock: Arc<HybridClock>,
+    coordinators: HashMap<String, DatabaseClient>,
+    nodes: HashMap<String, NodeClient>,
+}
+
+impl ConnectionPool {
+    /// A pool whose node connections stamp requests with `clock`
+    pub fn new(timeouts: Timeouts, clock: Arc<HybridClock>) -> Self {
+        Self { timeouts, clock, coordinators: HashMap::new(), nodes: HashMap::new() }
+    }
+
+    /// Connection to the coordinator at `addr`
+    pub async fn coordinator(&mut self, addr: &str) -> Result<&mut DatabaseClient> {
+        if !self.coordinators.contains_key(addr) {
+            let client = DatabaseClient::connect_with_timeouts(addr, self.timeouts).await?;
+            self.coordinators.insert(addr.to_string(), client);
+        }
+        Ok(self.coordinators.get_mut(addr).expect("connection was just opened"))
+    }
+
+    /// Connection to the node at `addr`
+    pub async fn node(&mut self, addr: &str) -> Result<&mut NodeClient> {
+        if !self.nodes.contains_key(addr) {
+            let client = NodeClient::connect_with_timeouts(addr, self.clock.clone(), self.timeouts).await?;
+            self.nodes.insert(addr.to_string(), client);
+        }
+        Ok(self.nodes.get_mut(addr).expect("connection was just opened"))
+    }
+
+    /// Close the connections to `addr`, so the next request reconnects
+    pub fn evict(&mut self, addr: &str) {
+        self.coordinators.remove(addr);
+        self.nodes.remove(addr);
+    }
+
+    /// Number of open connections
+    pub fn len(&self) -> usize {
+        self.coordinators.len() + self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
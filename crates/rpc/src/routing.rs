@@ -0,0 +1,290 @@
+//! Routing key requests straight to partition leaders.
+//!
+//! Going through the coordinator costs every request an extra hop. A routing
+//! client instead caches the partition map the coordinator publishes and
+//! sends each request to the leader of the key's partition. The cached map
+//! goes stale as partitions split, merge and change leaders, and a node that
+//! receives a request it does not serve turns it down with a route error.
+//! A wrong leader that names the new one is fixed in place; otherwise only
+//! the route of the key is looked up again, so a change to one range never
+//! costs a reload of the whole map.
+//!
+//! Every cached route carries the metadata version it was fetched at. A
+//! route replaces the cached routes its range overlaps unless they are
+//! newer, so a slow lookup cannot bring back a route a faster one replaced.
+//! Requests carry the version of the route they were sent along, and a node
+//! that does not serve the key and has seen a newer version says so, which
+//! has the route looked up again rather than patched.
+
+use crate::client::Timeouts;
+use crate::pool::ConnectionPool;
+use common::error::{DatabaseError, Result};
+use common::hlc::HybridClock;
+use common::types::{NodeId, PartitionRoute};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Times a request is routed again after route errors before giving up
+const MAX_ROUTE_ATTEMPTS: usize = 3;
+
+/// Outcome of a request sent straight to a node
+#[derive(Debug, Clone, PartialEq)]
+pub enum Routed<T> {
+    Served(T),
+    /// The node does not serve the key
+    Misrouted(RouteError),
+}
+
+impl<T> Routed<T> {
+    /// Map the value of a served request
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Routed<U> {
+        match self {
+            Routed::Served(value) => Routed::Served(f(value)),
+            Routed::Misrouted(error) => Routed::Misrouted(error),
+        }
+    }
+}
+
+/// Why a node turned down a request for a key
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteError {
+    /// The node does not lead the partition of the key; the leader and its
+    /// address, if the node knows them
+    NotLeader { leader: Option<(NodeId, String)> },
+    /// The key is outside the ranges the node serves
+    KeyOutOfRange,
+    /// The node does not serve the key, and the request was routed by a
+    /// partition map older than `metadata_version`, which the node has seen
+    StaleVersion { metadata_version: u64 },
+}
+
+impl RouteError {
+    /// The error to turn down a request routed at metadata version
+    /// `routed_at` with, by a node that has seen version `seen`. A named
+    /// leader is the better hint, so it stays; 0 stands for an unknown
+    /// version.
+    pub fn at_version(self, routed_at: u64, seen: u64) -> Self {
+        if routed_at == 0 || routed_at >= seen || matches!(self, RouteError::NotLeader { leader: Some(_) }) {
+            return self;
+        }
+        RouteError::StaleVersion { metadata_version: seen }
+    }
+}
+
+/// Cached routes of partitions, by range start
+#[derive(Debug, Default)]
+pub struct RoutingCache {
+    routes: BTreeMap<String, (u64, PartitionRoute)>,
+    version: u64,
+}
+
+impl RoutingCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Newest metadata version a cached route was fetched at
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// The cached route of the partition owning `key`
+    pub fn lookup(&self, key: &str) -> Option<&PartitionRoute> {
+        self.lookup_versioned(key).map(|(_, route)| route)
+    }
+
+    /// The cached route of the partition owning `key` and the metadata
+    /// version it was fetched at
+    pub fn lookup_versioned(&self, key: &str) -> Option<(u64, &PartitionRoute)> {
+        self.routes.range::<str, _>((Bound::Unbounded, Bound::Included(key)))
+            .next_back()
+            .map(|(_, (version, route))| (*version, route))
+            .filter(|(_, route)| route.range.contains(key))
+    }
+
+    /// Cache routes fetched at metadata `version`, replacing the cached
+    /// routes they overlap unless those are newer
+    pub fn update(&mut self, version: u64, routes: Vec<PartitionRoute>) {
+        for route in routes {
+            let overlapping: Vec<&(u64, PartitionRoute)> = self.routes.values()
+                .filter(|(_, cached)| cached.range.start < route.range.end && route.range.start < cached.range.end)
+                .collect();
+            if overlapping.iter().any(|(cached_version, _)| *cached_version > version) {
+                continue;
+            }
+            self.routes.retain(|_, (_, cached)| !(cached.range.start < route.range.end && route.range.start < cached.range.end));
+            self.routes.insert(route.range.start.clone(), (version, route));
+        }
+        self.version = self.version.max(version);
+    }
+
+    /// Drop the cached route of the partition owning `key`
+    pub fn invalidate(&mut self, key: &str) {
+        if let Some(start) = self.lookup(key).map(|route| route.range.start.clone()) {
+            self.routes.remove(&start);
+        }
+    }
+
+    /// Handle a route error for `key`. Returns whether the route of the key
+    /// must be looked up again.
+    pub fn misrouted(&mut self, key: &str, error: &RouteError) -> bool {
+        if let RouteError::NotLeader { leader: Some((leader, address)) } = error {
+            let start = self.lookup(key).map(|route| route.range.start.clone());
+            if let Some((_, route)) = start.and_then(|start| self.routes.get_mut(&start))
+                && route.leader != *leader
+            {
+                route.leader = leader.clone();
+                route.leader_address = address.clone();
+                return false;
+            }
+        }
+        self.invalidate(key);
+        true
+    }
+}
+
+/// A request for a single key
+pub(crate) enum KeyRequest {
+    Read,
+    Write { value: Vec<u8>, expires_at_ms: u64 },
+    Delete,
+}
+
+/// Sends key requests along cached routes, looking routes up at a
+/// coordinator when they are missing or turn out stale.
+#[derive(Default)]
+pub(crate) struct Router {
+    pub(crate) cache: RoutingCache,
+}
+
+impl Router {
+    /// Fetch the route of the partition owning `key`, or of every partition
+    pub(crate) async fn refresh(&mut self, pool: &mut ConnectionPool, coordinator: &str, key: Option<&str>) -> Result<()> {
+        let response = pool.coordinator(coordinator).await?.partition_map(key.map(str::to_string)).await?;
+        let routes = response.routes.into_iter().map(Into::into).collect();
+        self.cache.update(response.metadata_version, routes);
+        Ok(())
+    }
+
+    /// Send a request to the leader of the key's partition, routing it again
+    /// after route errors. A leader that cannot be reached loses its cached
+    /// route and connection.
+    pub(crate) async fn send(
+        &mut self,
+        pool: &mut ConnectionPool,
+        coordinator: &str,
+        key: String,
+        request: &KeyRequest,
+    ) -> Result<Option<(Vec<u8>, u64)>> {
+        for _ in 0..MAX_ROUTE_ATTEMPTS {
+            let route = self.route(pool, coordinator, &key).await?;
+            let (version, route) = route;
+            let routed = match Self::send_to(pool, &route.leader_address, &key, request, version).await {
+                Ok(routed) => routed,
+                // A conflict comes from a leader that served the key
+                Err(e) if e.is_conflict() => return Err(e),
+                Err(e) => {
+                    pool.evict(&route.leader_address);
+                    self.cache.invalidate(&key);
+                    return Err(e);
+                },
+            };
+            match routed {
+                Routed::Served(value) => return Ok(value),
+                Routed::Misrouted(error) => {
+                    self.cache.misrouted(&key, &error);
+                },
+            }
+        }
+        Err(DatabaseError::Unavailable(format!(
+            "Key {} was misrouted {} times in a row", key, MAX_ROUTE_ATTEMPTS
+        )))
+    }
+
+    async fn send_to(
+        pool: &mut ConnectionPool,
+        address: &str,
+        key: &str,
+        request: &KeyRequest,
+        metadata_version: u64,
+    ) -> Result<Routed<Option<(Vec<u8>, u64)>>> {
+        let node = pool.node(address).await?;
+        Ok(match request {
+            KeyRequest::Read => node.read_routed(key.to_string(), metadata_version).await?,
+            KeyRequest::Write { value, expires_at_ms } => {
+                node.write(key.to_string(), value.clone(), *expires_at_ms, metadata_version).await?.map(|_| None)
+            },
+            KeyRequest::Delete => node.delete(key.to_string(), metadata_version).await?.map(|_| None),
+        })
+    }
+
+    // The route of the key's partition and the metadata version it was
+    // fetched at, looked up if it is not cached
+    async fn route(&mut self, pool: &mut ConnectionPool, coordinator: &str, key: &str) -> Result<(u64, PartitionRoute)> {
+        if self.cache.lookup(key).is_none_or(|route| route.leader_address.is_empty()) {
+            self.refresh(pool, coordinator, Some(key)).await?;
+        }
+        match self.cache.lookup_versioned(key) {
+            Some((version, route)) if !route.leader_address.is_empty() => Ok((version, route.clone())),
+            Some((_, route)) => Err(DatabaseError::NotLeader { partition: Some(route.partition_id), leader: None }),
+            None => Err(DatabaseError::Partition { partition: None, message: format!("No partition owns key {}", key) }),
+        }
+    }
+}
+
+/// Client that sends key requests straight to the partition leaders, with
+/// the coordinator as the source of the partition map.
+pub struct RoutingClient {
+    coordinator: String,
+    pool: ConnectionPool,
+    router: Router,
+}
+
+impl RoutingClient {
+    /// Connect to the coordinator at `addr` and load the partition map.
+    pub async fn connect(addr: &str, clock: Arc<HybridClock>) -> Result<Self> {
+        let mut client = Self {
+            coordinator: addr.to_string(),
+            pool: ConnectionPool::new(Timeouts::default(), clock),
+            router: Router::default(),
+        };
+        client.refresh(None).await?;
+        Ok(client)
+    }
+
+    /// Newest metadata version the routes were fetched at
+    pub fn metadata_version(&self) -> u64 {
+        self.router.cache.version()
+    }
+
+    /// Fetch the route of the partition owning `key`, or of every partition
+    pub async fn refresh(&mut self, key: Option<&str>) -> Result<()> {
+        self.router.refresh(&mut self.pool, &self.coordinator, key).await
+    }
+
+    /// Latest value and version of a key; `None` if the key does not exist
+    pub async fn get(&mut self, key: String) -> Result<Option<(Vec<u8>, u64)>> {
+        self.router.send(&mut self.pool, &self.coordinator, key, &KeyRequest::Read).await
+    }
+
+    /// Write a key. `expires_at_ms` is the absolute expiry; 0 means the key
+    /// never expires.
+    pub async fn put(&mut self, key: String, value: Vec<u8>, expires_at_ms: u64) -> Result<()> {
+        let request = KeyRequest::Write { value, expires_at_ms };
+        self.router.send(&mut self.pool, &self.coordinator, key, &request).await.map(|_| ())
+    }
+
+    /// Delete a key
+    pub async fn delete(&mut self, key: String) -> Result<()> {
+        self.router.send(&mut self.pool, &self.coordinator, key, &KeyRequest::Delete).await.map(|_| ())
+    }
+}
@@ -0,0 +1,343 @@
+//! Client SDK that survives coordinator failures and leader changes.
+//!
+//! `SmartClient` knows several coordinators, the seeds, and fails over to
+//! the next one when the one in use cannot be reached. Requests that failed
+//! on a transient error, such as a lost connection or a timeout, are retried
+//! with exponential backoff and jitter, but only if they are idempotent:
+//! gets, scans, blind puts and deletes apply the same way however often they
+//! are sent, while an increment or a compare-and-swap whose response was
+//! lost may already have been applied. Optionally, key requests skip the
+//! coordinator and go straight to the partition leaders along a cached
+//! partition map.
+//!
+//! An open transaction lives in the memory of the coordinator that began
+//! it, so a transaction sticks to that coordinator: its requests never fail
+//! over, and if the coordinator goes away the transaction is lost and must
+//! be run again from the start.
+
+use crate::client::{Timeouts, Transaction};
+use crate::pool::ConnectionPool;
+use crate::proto::database::{ConditionalWriteResponse, IsolationLevel, QueryResponse, ScanResponse};
+use crate::routing::{KeyRequest, Router};
+use common::error::{DatabaseError, Result};
+use common::hlc::{HybridClock, DEFAULT_MAX_CLOCK_OFFSET_MS};
+use common::util::random_delay;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// When and how often to retry a request that failed on a transient error
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first
+    pub max_attempts: usize,
+    /// Backoff before the first retry
+    pub initial_backoff: Duration,
+    /// Cap on the backoff
+    pub max_backoff: Duration,
+    /// Factor the backoff grows by with every retry
+    pub multiplier: f64,
+    /// Fraction of the backoff taken off at random, so clients that failed
+    /// together do not retry together
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self { max_attempts: 1, ..Self::default() }
+    }
+
+    /// Backoff before retry number `retry`, counting from 1
+    pub fn backoff(&self, retry: usize) -> Duration {
+        let exponent = retry.saturating_sub(1).min(32) as i32;
+        let backoff = self.initial_backoff.mul_f64(self.multiplier.max(1.0).powi(exponent)).min(self.max_backoff);
+        let max_ms = backoff.as_millis() as u64;
+        let min_ms = (max_ms as f64 * (1.0 - self.jitter.clamp(0.0, 1.0))) as u64;
+        random_delay(min_ms, max_ms)
+    }
+}
+
+/// Builder for a `SmartClient`
+#[derive(Clone, Default)]
+pub struct SmartClientBuilder {
+    seeds: Vec<String>,
+    timeouts: Timeouts,
+    retry_policy: RetryPolicy,
+    route_to_leaders: bool,
+    clock: Option<Arc<HybridClock>>,
+}
+
+impl SmartClientBuilder {
+    /// Add a coordinator address to fail over between
+    pub fn seed(mut self, addr: impl Into<String>) -> Self {
+        self.seeds.push(addr.into());
+        self
+    }
+
+    /// Add several coordinator addresses, tried in the order given
+    pub fn seeds<I, S>(mut self, addrs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.seeds.extend(addrs.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = timeout;
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.request = timeout;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Send gets, puts and deletes straight to the partition leaders
+    pub fn route_to_leaders(mut self, enabled: bool) -> Self {
+        self.route_to_leaders = enabled;
+        self
+    }
+
+    /// Clock to stamp requests to the nodes with; by default a new one that
+    /// tolerates `DEFAULT_MAX_CLOCK_OFFSET_MS` of clock offset
+    pub fn clock(mut self, clock: Arc<HybridClock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Build the client and connect to the first reachable seed
+    pub async fn connect(self) -> Result<SmartClient> {
+        if self.seeds.is_empty() {
+            return Err(DatabaseError::Config("No coordinator addresses given".to_string()));
+        }
+        if self.retry_policy.max_attempts == 0 {
+            return Err(DatabaseError::Config("Retry policy must allow at least one attempt".to_string()));
+        }
+        let clock = self.clock.unwrap_or_else(|| Arc::new(HybridClock::new(DEFAULT_MAX_CLOCK_OFFSET_MS)));
+        let mut client = SmartClient {
+            seeds: self.seeds,
+            current: 0,
+            pool: ConnectionPool::new(self.timeouts, clock),
+            retry_policy: self.retry_policy,
+            router: self.route_to_leaders.then(Router::default),
+        };
+        client.coordinator().await?;
+        Ok(client)
+    }
+}
+
+/// Client that fails over between coordinators and retries idempotent
+/// requests on transient errors.
+pub struct SmartClient {
+    seeds: Vec<String>,
+    /// Index of the seed in use
+    current: usize,
+    pool: ConnectionPool,
+    retry_policy: RetryPolicy,
+    /// Routes key requests to the leaders, if enabled
+    router: Option<Router>,
+}
+
+impl SmartClient {
+    pub fn builder() -> SmartClientBuilder {
+        SmartClientBuilder::default()
+    }
+
+    /// Address of the coordinator in use
+    pub fn current_coordinator(&self) -> &str {
+        &self.seeds[self.current]
+    }
+
+    /// Latest value and version of a key; `None` if the key does not exist
+    pub async fn get(&mut self, key: String) -> Result<Option<(Vec<u8>, u64)>> {
+        let mut attempt = 1;
+        loop {
+            match self.get_once(&key).await {
+                Err(e) if self.should_retry(&e, attempt).await => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Write a key
+    pub async fn put(&mut self, key: String, value: Vec<u8>) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            match self.put_once(&key, &value).await {
+                Err(e) if self.should_retry(&e, attempt).await => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Delete a key
+    pub async fn delete(&mut self, key: String) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            match self.delete_once(&key).await {
+                Err(e) if self.should_retry(&e, attempt).await => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Scan a range of keys. Pass the `continuation_token` of the previous
+    /// response to fetch the next page.
+    pub async fn scan(
+        &mut self,
+        start_key: String,
+        end_key: String,
+        limit: i32,
+        continuation_token: Option<String>,
+    ) -> Result<ScanResponse> {
+        let mut attempt = 1;
+        loop {
+            let addr = self.coordinator().await?;
+            let result = self.pool.coordinator(&addr).await?
+                .scan(start_key.clone(), end_key.clone(), limit, continuation_token.clone())
+                .await;
+            match result {
+                Err(e) if self.should_retry(&e, attempt).await => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Atomically add `delta` to an integer value and return the sum. Not
+    /// retried: a lost response does not tell whether the add was applied.
+    pub async fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        let addr = self.coordinator().await?;
+        let result = self.pool.coordinator(&addr).await?.increment(key, delta).await;
+        Ok(self.failed_over(result)?.value)
+    }
+
+    /// Store a value only if the key is still at `expected_version`. Not
+    /// retried, like `increment`.
+    pub async fn compare_and_swap(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        expected_version: u64,
+    ) -> Result<ConditionalWriteResponse> {
+        let addr = self.coordinator().await?;
+        let result = self.pool.coordinator(&addr).await?.compare_and_swap(key, value, expected_version).await;
+        self.failed_over(result)
+    }
+
+    /// Begin a transaction on the coordinator in use. Every request of the
+    /// transaction goes to that coordinator, which alone knows it; none is
+    /// retried or failed over, so a transaction whose coordinator cannot be
+    /// reached fails and must be run again from the start.
+    pub async fn begin_transaction(&mut self, isolation_level: IsolationLevel) -> Result<Transaction<'_>> {
+        let addr = self.coordinator().await?;
+        self.pool.coordinator(&addr).await?.begin_transaction_with_isolation(isolation_level).await
+    }
+
+    /// Execute a SQL query. Not retried, since the query may write.
+    pub async fn execute_query(&mut self, query: String, parameters: HashMap<String, String>) -> Result<QueryResponse> {
+        let addr = self.coordinator().await?;
+        let result = self.pool.coordinator(&addr).await?.execute_query(query, parameters).await;
+        self.failed_over(result)
+    }
+
+    async fn get_once(&mut self, key: &str) -> Result<Option<(Vec<u8>, u64)>> {
+        let addr = self.coordinator().await?;
+        if let Some(router) = self.router.as_mut() {
+            return router.send(&mut self.pool, &addr, key.to_string(), &KeyRequest::Read).await;
+        }
+        let response = self.pool.coordinator(&addr).await?.get(key.to_string()).await?;
+        Ok(response.found.then_some((response.value, response.version)))
+    }
+
+    async fn put_once(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        let addr = self.coordinator().await?;
+        if let Some(router) = self.router.as_mut() {
+            let request = KeyRequest::Write { value: value.to_vec(), expires_at_ms: 0 };
+            return router.send(&mut self.pool, &addr, key.to_string(), &request).await.map(|_| ());
+        }
+        self.pool.coordinator(&addr).await?.put(key.to_string(), value.to_vec()).await.map(|_| ())
+    }
+
+    async fn delete_once(&mut self, key: &str) -> Result<()> {
+        let addr = self.coordinator().await?;
+        if let Some(router) = self.router.as_mut() {
+            return router.send(&mut self.pool, &addr, key.to_string(), &KeyRequest::Delete).await.map(|_| ());
+        }
+        self.pool.coordinator(&addr).await?.delete(key.to_string()).await.map(|_| ())
+    }
+
+    // Address of a reachable coordinator, starting with the one in use and
+    // trying every seed once
+    async fn coordinator(&mut self) -> Result<String> {
+        let mut last_error = None;
+        for _ in 0..self.seeds.len() {
+            let addr = self.seeds[self.current].clone();
+            match self.pool.coordinator(&addr).await {
+                Ok(_) => return Ok(addr),
+                Err(e) => {
+                    last_error = Some(e);
+                    self.current = (self.current + 1) % self.seeds.len();
+                },
+            }
+        }
+        Err(last_error.expect("there is at least one seed"))
+    }
+
+    // Move on to the next coordinator if the one in use failed
+    fn fail_over(&mut self) {
+        let addr = self.seeds[self.current].clone();
+        self.pool.evict(&addr);
+        self.current = (self.current + 1) % self.seeds.len();
+    }
+
+    // Fail over if a request that is not retried found the coordinator down
+    fn failed_over<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result
+            && is_unreachable(e)
+        {
+            self.fail_over();
+        }
+        result
+    }
+
+    // Whether to send an idempotent request again after attempt `attempt` failed
+    // with `error`. Fails over and waits for the backoff first if so.
+    async fn should_retry(&mut self, error: &DatabaseError, attempt: usize) -> bool {
+        if !error.is_retryable() {
+            return false;
+        }
+        if is_unreachable(error) {
+            self.fail_over();
+        }
+        if attempt >= self.retry_policy.max_attempts {
+            return false;
+        }
+        tokio::time::sleep(self.retry_policy.backoff(attempt)).await;
+        true
+    }
+}
+
+// Whether the server the request went to may be down
+fn is_unreachable(error: &DatabaseError) -> bool {
+    matches!(error, DatabaseError::Unavailable(_) | DatabaseError::Timeout(_))
+}
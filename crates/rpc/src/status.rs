@@ -0,0 +1,136 @@
+//! Failing requests with structured gRPC statuses.
+//!
+//! A failed request carries a status whose code matches the kind of error,
+//! with an `ErrorDetail` in its details naming the exact kind. Every
+//! `DatabaseError` variant has its own kind, so an error survives the trip
+//! from server to client unchanged and clients can branch on it. Statuses
+//! without a detail did not come from a server of ours, e.g. because the
+//! connection failed, and become RPC errors unless their code says more.
+
+use crate::proto::error::{ErrorDetail, ErrorKind};
+use common::error::DatabaseError;
+use common::types::NodeId;
+use prost::Message;
+use tonic::{Code, Status};
+
+/// Status to fail a request with
+pub fn to_status(error: DatabaseError) -> Status {
+    to_status_at_version(error, 0)
+}
+
+/// Status to fail a request with, telling the client the metadata version
+/// the server is at
+pub fn to_status_at_version(error: DatabaseError, metadata_version: u64) -> Status {
+    let kind = kind(&error);
+    let (leader_id, leader_address) = error.leader_hint()
+        .map(|(leader, address)| (leader.0.clone(), address.clone()))
+        .unwrap_or_default();
+    let detail = ErrorDetail {
+        kind: kind as i32,
+        retryable: error.is_retryable(),
+        leader_id,
+        leader_address,
+        metadata_version,
+        partition_id: error.partition(),
+        node_id: error.node().map(|node| node.0.clone()),
+        key: error.key().map(str::to_string),
+    };
+    Status::with_details(code(kind), error.message(), detail.encode_to_vec().into())
+}
+
+/// Error a request failed with
+pub fn from_status(status: Status) -> DatabaseError {
+    let message = status.message().to_string();
+    let Some(detail) = error_detail(&status) else {
+        return match status.code() {
+            Code::Unavailable => DatabaseError::Unavailable(message),
+            Code::DeadlineExceeded => DatabaseError::Timeout(message),
+            Code::ResourceExhausted => DatabaseError::ResourceExhausted(message),
+            Code::InvalidArgument => DatabaseError::InvalidArgument(message),
+            code => DatabaseError::Rpc(format!("{}: {}", code, message)),
+        };
+    };
+    match detail.kind() {
+        ErrorKind::Unspecified | ErrorKind::Unknown => DatabaseError::Unknown(message),
+        ErrorKind::Io => DatabaseError::Io(std::io::Error::other(message)),
+        ErrorKind::Serialization => DatabaseError::Serialization(message),
+        ErrorKind::Deserialization => DatabaseError::Deserialization(serde::de::Error::custom(message)),
+        ErrorKind::Rpc => DatabaseError::Rpc(message),
+        ErrorKind::Unavailable => DatabaseError::Unavailable(message),
+        ErrorKind::Timeout => DatabaseError::Timeout(message),
+        ErrorKind::ResourceExhausted => DatabaseError::ResourceExhausted(message),
+        ErrorKind::Storage => DatabaseError::Storage { key: detail.key, message },
+        ErrorKind::Raft => DatabaseError::Raft {
+            partition: detail.partition_id,
+            node: detail.node_id.map(NodeId),
+            message,
+        },
+        ErrorKind::NotLeader => DatabaseError::NotLeader {
+            partition: detail.partition_id,
+            leader: (!detail.leader_id.is_empty())
+                .then(|| (NodeId(detail.leader_id.clone()), detail.leader_address.clone())),
+        },
+        ErrorKind::SqlParse => DatabaseError::SqlParse(message),
+        ErrorKind::Schema => DatabaseError::Schema(message),
+        ErrorKind::Config => DatabaseError::Config(message),
+        ErrorKind::NodeNotFound => DatabaseError::NodeNotFound(NodeId(detail.node_id.unwrap_or(message))),
+        ErrorKind::KeyNotFound => DatabaseError::KeyNotFound { key: message },
+        ErrorKind::Partition => DatabaseError::Partition { partition: detail.partition_id, message },
+        ErrorKind::InvalidArgument => DatabaseError::InvalidArgument(message),
+        ErrorKind::ClockOffset => DatabaseError::ClockOffset(message),
+        ErrorKind::Transaction => DatabaseError::Transaction(message),
+        ErrorKind::Conflict => DatabaseError::Conflict { key: detail.key, message },
+    }
+}
+
+/// The detail a server attached to a status, if any
+pub fn error_detail(status: &Status) -> Option<ErrorDetail> {
+    if status.details().is_empty() {
+        return None;
+    }
+    ErrorDetail::decode(status.details()).ok()
+}
+
+fn kind(error: &DatabaseError) -> ErrorKind {
+    match error {
+        DatabaseError::Io(_) => ErrorKind::Io,
+        DatabaseError::Serialization(_) => ErrorKind::Serialization,
+        DatabaseError::Deserialization(_) => ErrorKind::Deserialization,
+        DatabaseError::Rpc(_) => ErrorKind::Rpc,
+        DatabaseError::Unavailable(_) => ErrorKind::Unavailable,
+        DatabaseError::Timeout(_) => ErrorKind::Timeout,
+        DatabaseError::ResourceExhausted(_) => ErrorKind::ResourceExhausted,
+        DatabaseError::Storage { .. } => ErrorKind::Storage,
+        DatabaseError::Raft { .. } => ErrorKind::Raft,
+        DatabaseError::NotLeader { .. } => ErrorKind::NotLeader,
+        DatabaseError::SqlParse(_) => ErrorKind::SqlParse,
+        DatabaseError::Schema(_) => ErrorKind::Schema,
+        DatabaseError::Config(_) => ErrorKind::Config,
+        DatabaseError::NodeNotFound(_) => ErrorKind::NodeNotFound,
+        DatabaseError::KeyNotFound { .. } => ErrorKind::KeyNotFound,
+        DatabaseError::Partition { .. } => ErrorKind::Partition,
+        DatabaseError::InvalidArgument(_) => ErrorKind::InvalidArgument,
+        DatabaseError::ClockOffset(_) => ErrorKind::ClockOffset,
+        DatabaseError::Transaction(_) => ErrorKind::Transaction,
+        DatabaseError::Conflict { .. } => ErrorKind::Conflict,
+        DatabaseError::Unknown(_) => ErrorKind::Unknown,
+    }
+}
+
+fn code(kind: ErrorKind) -> Code {
+    match kind {
+        ErrorKind::Unspecified | ErrorKind::Unknown => Code::Unknown,
+        ErrorKind::Io | ErrorKind::Serialization | ErrorKind::Deserialization | ErrorKind::Storage => Code::Internal,
+        ErrorKind::Rpc | ErrorKind::Raft | ErrorKind::Unavailable | ErrorKind::NotLeader => Code::Unavailable,
+        ErrorKind::Timeout => Code::DeadlineExceeded,
+        ErrorKind::ResourceExhausted => Code::ResourceExhausted,
+        ErrorKind::SqlParse | ErrorKind::InvalidArgument => Code::InvalidArgument,
+        ErrorKind::Schema
+        | ErrorKind::Config
+        | ErrorKind::Partition
+        | ErrorKind::ClockOffset
+        | ErrorKind::Transaction => Code::FailedPrecondition,
+        ErrorKind::NodeNotFound | ErrorKind::KeyNotFound => Code::NotFound,
+        ErrorKind::Conflict => Code::Aborted,
+    }
+}
@@ -9,6 +9,10 @@ pub enum SqlStatement {
         table: String,
         where_clause: Option<WhereClause>,
         limit: Option<usize>,
+        /// `AS OF SYSTEM TIME` value: read the table as it was at that time
+        as_of: Option<SqlValue>,
+        /// `FOR UPDATE`: lock the rows read until the transaction ends
+        for_update: bool,
     },
     Insert {
         table: String,
@@ -28,6 +32,20 @@ pub enum SqlStatement {
         name: String,
         columns: Vec<ColumnDef>,
     },
+    Begin,
+    Commit,
+    Rollback,
+    /// `SET TRANSACTION ISOLATION LEVEL`, for the current transaction
+    SetTransaction {
+        isolation: IsolationLevel,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IsolationLevel {
+    /// `SNAPSHOT`, or its alias `REPEATABLE READ`
+    Snapshot,
+    Serializable,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -94,18 +112,26 @@ peg::parser! {
             / update_stmt()
             / delete_stmt()
             / create_table_stmt()
+            / begin_stmt()
+            / commit_stmt()
+            / rollback_stmt()
+            / set_transaction_stmt()
 
         rule select_stmt() -> SqlStatement
             = whitespace()* "SELECT" whitespace()+ columns:column_list() whitespace()+ 
               "FROM" whitespace()+ table:identifier() 
+              as_of:as_of_clause()?
               where_clause:where_clause()? 
               limit:limit_clause()?
+              for_update:for_update_clause()?
               whitespace()* ";"? whitespace()* {
                 SqlStatement::Select {
                     columns,
                     table,
                     where_clause,
                     limit,
+                    as_of,
+                    for_update: for_update.unwrap_or(false),
                 }
             }
 
@@ -152,6 +178,32 @@ peg::parser! {
                 }
             }
 
+        rule begin_stmt() -> SqlStatement
+            = whitespace()* ("BEGIN" / "START") (whitespace()+ "TRANSACTION")? whitespace()* ";"? whitespace()* {
+                SqlStatement::Begin
+            }
+
+        rule commit_stmt() -> SqlStatement
+            = whitespace()* "COMMIT" (whitespace()+ "TRANSACTION")? whitespace()* ";"? whitespace()* {
+                SqlStatement::Commit
+            }
+
+        rule set_transaction_stmt() -> SqlStatement
+            = whitespace()* "SET" whitespace()+ "TRANSACTION" whitespace()+ "ISOLATION" whitespace()+ "LEVEL" whitespace()+
+              isolation:isolation_level() whitespace()* ";"? whitespace()* {
+                SqlStatement::SetTransaction { isolation }
+            }
+
+        rule isolation_level() -> IsolationLevel
+            = "SERIALIZABLE" { IsolationLevel::Serializable }
+            / "SNAPSHOT" { IsolationLevel::Snapshot }
+            / "REPEATABLE" whitespace()+ "READ" { IsolationLevel::Snapshot }
+
+        rule rollback_stmt() -> SqlStatement
+            = whitespace()* "ROLLBACK" (whitespace()+ "TRANSACTION")? whitespace()* ";"? whitespace()* {
+                SqlStatement::Rollback
+            }
+
         rule column_def() -> ColumnDef
             = whitespace()* name:identifier() whitespace()+ data_type:data_type()
               nullable:nullable_def()? primary_key:primary_key_def()? whitespace()* {
@@ -186,8 +238,9 @@ peg::parser! {
                 (col, val)
             }
 
+        // Assignments consume their trailing whitespace, so none is required here
         rule where_clause() -> WhereClause
-            = whitespace()+ "WHERE" whitespace()+ condition:condition() {
+            = whitespace()* "WHERE" whitespace()+ condition:condition() {
                 WhereClause { condition }
             }
 
@@ -218,6 +271,15 @@ peg::parser! {
             / ">" { ComparisonOp::Gt }
             / "LIKE" { ComparisonOp::Like }
 
+        // Conditions consume their trailing whitespace, so none is required here
+        rule for_update_clause() -> bool
+            = whitespace()* "FOR" whitespace()+ "UPDATE" { true }
+
+        rule as_of_clause() -> SqlValue
+            = whitespace()+ "AS" whitespace()+ "OF" whitespace()+ "SYSTEM" whitespace()+ "TIME" whitespace()+ v:value() {
+                v
+            }
+
         rule limit_clause() -> usize
             = whitespace()+ "LIMIT" whitespace()+ n:number() {
                 n.parse().unwrap()
@@ -310,6 +372,8 @@ mod tests {
                 table: "users".to_string(),
                 where_clause: None,
                 limit: None,
+                as_of: None,
+                for_update: false,
             }
         );
     }
@@ -343,4 +407,80 @@ mod tests {
             _ => panic!("Expected INSERT statement"),
         }
     }
+
+    #[test]
+    fn test_update_with_where() {
+        let parsed = parse_sql("UPDATE users SET name = 'Bob', age = 31 WHERE id = 1;").unwrap();
+        match parsed {
+            SqlStatement::Update { table, assignments, where_clause } => {
+                assert_eq!(table, "users");
+                assert_eq!(assignments.len(), 2);
+                assert!(where_clause.is_some());
+            },
+            _ => panic!("Expected UPDATE statement"),
+        }
+    }
+
+    #[test]
+    fn test_select_as_of_system_time() {
+        let sql = "SELECT * FROM users AS OF SYSTEM TIME '-10s' WHERE id = 1;";
+        let parsed = parse_sql(sql).unwrap();
+
+        match parsed {
+            SqlStatement::Select { table, where_clause, as_of, .. } => {
+                assert_eq!(table, "users");
+                assert_eq!(as_of, Some(SqlValue::String("-10s".to_string())));
+                assert!(where_clause.is_some());
+            },
+            _ => panic!("Expected SELECT statement"),
+        }
+
+        let parsed = parse_sql("SELECT id FROM users AS OF SYSTEM TIME 1700000000000").unwrap();
+        assert!(matches!(parsed, SqlStatement::Select { as_of: Some(SqlValue::Integer(1_700_000_000_000)), .. }));
+    }
+
+    #[test]
+    fn test_transaction_control() {
+        assert_eq!(parse_sql("BEGIN;").unwrap(), SqlStatement::Begin);
+        assert_eq!(parse_sql("BEGIN TRANSACTION").unwrap(), SqlStatement::Begin);
+        assert_eq!(parse_sql("START TRANSACTION;").unwrap(), SqlStatement::Begin);
+        assert_eq!(parse_sql("COMMIT;").unwrap(), SqlStatement::Commit);
+        assert_eq!(parse_sql("ROLLBACK TRANSACTION;").unwrap(), SqlStatement::Rollback);
+        assert!(parse_sql("BEGINNING;").is_err());
+    }
+
+    #[test]
+    fn test_select_for_update() {
+        let parsed = parse_sql("SELECT * FROM items WHERE id = 7 FOR UPDATE;").unwrap();
+        match parsed {
+            SqlStatement::Select { table, where_clause, for_update, .. } => {
+                assert_eq!(table, "items");
+                assert!(where_clause.is_some());
+                assert!(for_update);
+            },
+            _ => panic!("Expected SELECT statement"),
+        }
+
+        let parsed = parse_sql("SELECT * FROM items FOR UPDATE").unwrap();
+        assert!(matches!(parsed, SqlStatement::Select { for_update: true, .. }));
+        let parsed = parse_sql("SELECT * FROM items").unwrap();
+        assert!(matches!(parsed, SqlStatement::Select { for_update: false, .. }));
+    }
+
+    #[test]
+    fn test_set_transaction_isolation_level() {
+        assert_eq!(
+            parse_sql("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE;").unwrap(),
+            SqlStatement::SetTransaction { isolation: IsolationLevel::Serializable }
+        );
+        assert_eq!(
+            parse_sql("SET TRANSACTION ISOLATION LEVEL SNAPSHOT").unwrap(),
+            SqlStatement::SetTransaction { isolation: IsolationLevel::Snapshot }
+        );
+        assert_eq!(
+            parse_sql("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ").unwrap(),
+            SqlStatement::SetTransaction { isolation: IsolationLevel::Snapshot }
+        );
+        assert!(parse_sql("SET TRANSACTION ISOLATION LEVEL READ COMMITTED").is_err());
+    }
 }
@@ -0,0 +1,679 @@
+//! RocksDB-backed multi-version key-value engine.
+
+use crate::mvcc::{self, WriteVersion};
+use crate::ttl;
+use crate::value::StoredValue;
+use common::error::{DatabaseError, Result};
+use common::hlc::HlcTimestamp;
+use common::types::{BatchOperation, Intent, TransactionId, TransactionMeta, TransactionRecord, VersionedValue};
+use common::util::timestamp_ms;
+use rocksdb::{Direction, IteratorMode, Options, WriteBatch, DB};
+use std::path::Path;
+
+/// Tuning options for the storage engine.
+#[derive(Debug, Clone)]
+pub struct StorageOptions {
+    /// How long old versions are kept for reads "as of" a past timestamp.
+    pub gc_window_ms: u64,
+}
+
+impl Default for StorageOptions {
+    fn default() -> Self {
+        Self {
+            gc_window_ms: 4 * 60 * 60 * 1000,
+        }
+    }
+}
+
+/// Size of the live data in a key range.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RangeStats {
+    pub size_bytes: u64,
+    pub key_count: u64,
+    /// First key with at least half of the range's bytes before it; `None`
+    /// for ranges of fewer than two keys.
+    pub midpoint_key: Option<String>,
+}
+
+/// Key-value storage engine for a single node.
+///
+/// Every write is kept as a separate version until it falls out of the GC
+/// window, so reads can be served as of any timestamp inside the window.
+pub struct Storage {
+    db: DB,
+    options: StorageOptions,
+}
+
+impl Storage {
+    /// Open the storage engine at `path` with default options, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_options(path, StorageOptions::default())
+    }
+
+    /// Open the storage engine at `path`, creating it if needed.
+    pub fn open_with_options(path: impl AsRef<Path>, options: StorageOptions) -> Result<Self> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.set_compaction_filter(ttl::FILTER_NAME, ttl::expiry_filter(options.gc_window_ms));
+
+        let db = DB::open(&opts, path).map_err(storage_error)?;
+        Ok(Self { db, options })
+    }
+
+    /// Get the latest value stored under `key` along with its version.
+    pub fn get(&self, key: &str) -> Result<Option<VersionedValue>> {
+        self.get_at(key, timestamp_ms())
+    }
+
+    /// Get the latest value stored under `key`, treating values that expired
+    /// by `now_ms` as absent.
+    ///
+    /// The state machine passes the timestamp of the log entry being applied
+    /// so every replica sees the same keys as expired.
+    pub fn get_at(&self, key: &str, now_ms: u64) -> Result<Option<VersionedValue>> {
+        self.read_version(key, HlcTimestamp::MAX, now_ms)
+    }
+
+    /// Get the value `key` had at `read_ts`.
+    pub fn get_as_of(&self, key: &str, read_ts: HlcTimestamp) -> Result<Option<VersionedValue>> {
+        self.check_read_timestamp(read_ts)?;
+        self.read_version(key, read_ts, read_ts.wall_ms)
+    }
+
+    fn read_version(&self, key: &str, read_ts: HlcTimestamp, now_ms: u64) -> Result<Option<VersionedValue>> {
+        let seek = mvcc::seek_key(key, read_ts);
+        let Some(item) = self.db.iterator(IteratorMode::From(&seek, Direction::Forward)).next() else {
+            return Ok(None);
+        };
+
+        let (raw_key, raw_value) = item.map_err(storage_error)?;
+        match mvcc::decode_key(&raw_key) {
+            Some((user_key, _)) if user_key == key => {
+                let stored = StoredValue::decode(&raw_value)?;
+                Ok(Some(stored).filter(|s| s.is_live(now_ms)).map(Into::into))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Start collecting writes to commit together.
+    pub fn batch(&self) -> Batch<'_> {
+        Batch { storage: self, batch: WriteBatch::default() }
+    }
+
+    /// Store `value` under `key` as a new version, expiring at `expires_at` (ms).
+    pub fn put(&self, key: &str, value: &[u8], version: WriteVersion, expires_at: Option<u64>) -> Result<()> {
+        let mut batch = self.batch();
+        batch.put(key, value, version, expires_at);
+        batch.commit()
+    }
+
+    /// Delete `key` by writing a tombstone version. Deleting a missing key is not an error.
+    pub fn delete(&self, key: &str, version: WriteVersion) -> Result<()> {
+        let mut batch = self.batch();
+        batch.delete(key, version);
+        batch.commit()
+    }
+
+    /// Return up to `limit` live pairs in `[start_key, end_key)`, in key order.
+    ///
+    /// An empty `end_key` scans to the end of the keyspace and a `limit` of
+    /// zero returns every pair in the range.
+    pub fn scan(&self, start_key: &str, end_key: &str, limit: usize) -> Result<Vec<(String, Vec<u8>)>> {
+        self.scan_versions(start_key, end_key, limit, HlcTimestamp::MAX, timestamp_ms())
+    }
+
+    /// Like `scan`, but returns the pairs as they were at `read_ts`.
+    pub fn scan_as_of(&self, start_key: &str, end_key: &str, limit: usize, read_ts: HlcTimestamp) -> Result<Vec<(String, Vec<u8>)>> {
+        self.check_read_timestamp(read_ts)?;
+        self.scan_versions(start_key, end_key, limit, read_ts, read_ts.wall_ms)
+    }
+
+    /// Measure the live data in `[start_key, end_key)`.
+    ///
+    /// Walks the whole range twice, once to size it and once to find the
+    /// midpoint, without holding it in memory. Callers should still not do
+    /// this on every request.
+    pub fn range_stats(&self, start_key: &str, end_key: &str) -> Result<RangeStats> {
+        let now_ms = timestamp_ms();
+        let mut size_bytes = 0;
+        let mut key_count = 0;
+        self.visit_versions(start_key, end_key, HlcTimestamp::MAX, now_ms, |key, value| {
+            size_bytes += (key.len() + value.len()) as u64;
+            key_count += 1;
+            true
+        })?;
+
+        // Never the first key, so both halves of a split hold data
+        let mut before = 0;
+        let mut seen = 0;
+        let mut midpoint_key = None;
+        self.visit_versions(start_key, end_key, HlcTimestamp::MAX, now_ms, |key, value| {
+            if seen > 0 && before * 2 >= size_bytes {
+                midpoint_key = Some(key.to_string());
+                return false;
+            }
+            before += (key.len() + value.len()) as u64;
+            seen += 1;
+            true
+        })?;
+        Ok(RangeStats { size_bytes, key_count, midpoint_key })
+    }
+
+    fn scan_versions(
+        &self,
+        start_key: &str,
+        end_key: &str,
+        limit: usize,
+        read_ts: HlcTimestamp,
+        now_ms: u64,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut items = Vec::new();
+        self.visit_versions(start_key, end_key, read_ts, now_ms, |key, value| {
+            items.push((key.to_string(), value.to_vec()));
+            limit == 0 || items.len() < limit
+        })?;
+        Ok(items)
+    }
+
+    // Call `visit` with every pair live at `read_ts` in order, until it
+    // returns false
+    fn visit_versions(
+        &self,
+        start_key: &str,
+        end_key: &str,
+        read_ts: HlcTimestamp,
+        now_ms: u64,
+        mut visit: impl FnMut(&str, &[u8]) -> bool,
+    ) -> Result<()> {
+        // The newest visible version of a key decides it; older ones are skipped
+        let mut decided: Option<String> = None;
+        let start = mvcc::key_prefix(start_key);
+
+        for item in self.db.iterator(IteratorMode::From(&start, Direction::Forward)) {
+            let (raw_key, raw_value) = item.map_err(storage_error)?;
+            if raw_key.first() == Some(&mvcc::RESERVED_PREFIX) {
+                break;
+            }
+            let (user_key, version) = mvcc::decode_key(&raw_key)
+                .ok_or_else(|| DatabaseError::Storage { key: None, message: "Invalid key encoding".to_string() })?;
+            if !end_key.is_empty() && user_key.as_str() >= end_key {
+                break;
+            }
+            if decided.as_deref() == Some(user_key.as_str()) || version.timestamp > read_ts {
+                continue;
+            }
+
+            let stored = StoredValue::decode(&raw_value)?;
+            if stored.is_live(now_ms) && !visit(&user_key, &stored.value) {
+                break;
+            }
+            decided = Some(user_key);
+        }
+
+        Ok(())
+    }
+
+    /// Apply all `operations` atomically as `version`: either every operation
+    /// is persisted or none is.
+    pub fn write_batch(&self, operations: &[BatchOperation], version: WriteVersion) -> Result<()> {
+        let mut batch = self.batch();
+        batch.write_operations(operations, version);
+        batch.commit()
+    }
+
+    /// The newest committed version of `key`, including deletes and expired writes.
+    pub fn last_write(&self, key: &str) -> Result<Option<WriteVersion>> {
+        let seek = mvcc::seek_key(key, HlcTimestamp::MAX);
+        let Some(item) = self.db.iterator(IteratorMode::From(&seek, Direction::Forward)).next() else {
+            return Ok(None);
+        };
+
+        let (raw_key, _) = item.map_err(storage_error)?;
+        Ok(mvcc::decode_key(&raw_key)
+            .filter(|(user_key, _)| user_key == key)
+            .map(|(_, version)| version))
+    }
+
+    /// The first key in `[start_key, end_key)` with a version committed after
+    /// `since`, including deletes and expired writes.
+    pub fn first_write_since(&self, start_key: &str, end_key: &str, since: HlcTimestamp) -> Result<Option<String>> {
+        let start = mvcc::key_prefix(start_key);
+        for item in self.db.iterator(IteratorMode::From(&start, Direction::Forward)) {
+            let (raw_key, _) = item.map_err(storage_error)?;
+            if raw_key.first() == Some(&mvcc::RESERVED_PREFIX) {
+                break;
+            }
+            let (user_key, version) = mvcc::decode_key(&raw_key)
+                .ok_or_else(|| DatabaseError::Storage { key: None, message: "Invalid key encoding".to_string() })?;
+            if !end_key.is_empty() && user_key.as_str() >= end_key {
+                break;
+            }
+            if version.timestamp > since {
+                return Ok(Some(user_key));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The unresolved write intent on `key`, if any.
+    ///
+    /// Reads do not consult intents; callers serving transactional reads must
+    /// check for an intent before trusting the committed value.
+    pub fn get_intent(&self, key: &str) -> Result<Option<Intent>> {
+        match self.db.get(mvcc::intent_key(key)).map_err(storage_error)? {
+            Some(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every unresolved write intent on keys in `[start_key, end_key)`, in key order.
+    pub fn intents_in_range(&self, start_key: &str, end_key: &str) -> Result<Vec<(String, Intent)>> {
+        let mut intents = Vec::new();
+        let start = mvcc::intent_key(start_key);
+
+        for item in self.db.iterator(IteratorMode::From(&start, Direction::Forward)) {
+            let (raw_key, raw_value) = item.map_err(storage_error)?;
+            let Some(user_key) = mvcc::decode_intent_key(&raw_key) else {
+                break;
+            };
+            if !end_key.is_empty() && user_key.as_str() >= end_key {
+                break;
+            }
+            intents.push((user_key, serde_json::from_slice(&raw_value)?));
+        }
+
+        Ok(intents)
+    }
+
+    /// Atomically lay down intents of `txn` for `operations`, and write the
+    /// transaction record when given.
+    pub fn write_intents(
+        &self,
+        txn: &TransactionMeta,
+        operations: &[BatchOperation],
+        record: Option<&TransactionRecord>,
+    ) -> Result<()> {
+        let mut batch = self.batch();
+        batch.write_intents(txn, operations, record)?;
+        batch.commit()
+    }
+
+    /// Atomically resolve the intents `txn_id` holds on `keys`: turn them into
+    /// committed versions at `commit`, or discard them when `commit` is `None`.
+    ///
+    /// Keys without an intent of `txn_id` are skipped, so resolving twice is
+    /// harmless. Returns the number of intents resolved.
+    pub fn resolve_intents(&self, txn_id: &TransactionId, keys: &[String], commit: Option<WriteVersion>) -> Result<usize> {
+        let mut batch = self.batch();
+        let resolved = batch.resolve_intents(txn_id, keys, commit)?;
+        batch.commit()?;
+        Ok(resolved)
+    }
+
+    /// The record of transaction `txn_id`, if this node holds it.
+    pub fn get_transaction(&self, txn_id: &TransactionId) -> Result<Option<TransactionRecord>> {
+        match self.db.get(mvcc::txn_record_key(&txn_id.0)).map_err(storage_error)? {
+            Some(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store a transaction record, replacing any previous one.
+    pub fn put_transaction(&self, record: &TransactionRecord) -> Result<()> {
+        let mut batch = self.batch();
+        batch.put_transaction(record)?;
+        batch.commit()
+    }
+
+    /// Every transaction record on this node, for sweeping abandoned transactions.
+    pub fn transaction_records(&self) -> Result<Vec<TransactionRecord>> {
+        let prefix = mvcc::txn_record_prefix();
+        let mut records = Vec::new();
+
+        for item in self.db.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+            let (raw_key, raw_value) = item.map_err(storage_error)?;
+            if !raw_key.starts_with(&prefix) {
+                break;
+            }
+            records.push(serde_json::from_slice(&raw_value)?);
+        }
+
+        Ok(records)
+    }
+
+    /// The state `name` that Raft group `group_id` stored, if any.
+    pub fn group_state(&self, group_id: u64, name: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.db.get(mvcc::group_state_key(group_id, name)).map_err(storage_error)
+    }
+
+    /// Every state of Raft group `group_id` whose name starts with `prefix`,
+    /// as (name, value) pairs in name order.
+    pub fn group_states(&self, group_id: u64, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let start = mvcc::group_state_key(group_id, prefix);
+        let mut states = Vec::new();
+
+        for item in self.db.iterator(IteratorMode::From(&start, Direction::Forward)) {
+            let (raw_key, raw_value) = item.map_err(storage_error)?;
+            if !raw_key.starts_with(&start) {
+                break;
+            }
+            states.push((raw_key[start.len() - prefix.len()..].to_vec(), raw_value.to_vec()));
+        }
+
+        Ok(states)
+    }
+
+    /// Ids of the Raft groups that stored a state called `name`, in order.
+    pub fn groups_with_state(&self, name: &[u8]) -> Result<Vec<u64>> {
+        let mut groups = Vec::new();
+        let mut seek = mvcc::group_state_prefix().to_vec();
+
+        // Jump from group to group instead of walking every group's log
+        loop {
+            let Some(item) = self.db.iterator(IteratorMode::From(&seek, Direction::Forward)).next() else {
+                break;
+            };
+            let (raw_key, _) = item.map_err(storage_error)?;
+            let Some((group_id, _)) = mvcc::decode_group_state_key(&raw_key) else {
+                break;
+            };
+            if self.group_state(group_id, name)?.is_some() {
+                groups.push(group_id);
+            }
+            match group_id.checked_add(1) {
+                Some(next) => seek = mvcc::group_state_key(next, b""),
+                None => break,
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Copy every version and intent in `[start_key, end_key)`, and the
+    /// records of transactions whose primary key is in it, to hand the range
+    /// to a replica that fell too far behind to catch up from the log.
+    pub fn range_snapshot(&self, start_key: &str, end_key: &str) -> Result<RangeSnapshot> {
+        let mut pairs = Vec::new();
+        self.visit_range(start_key, end_key, |raw_key, raw_value| pairs.push((raw_key.to_vec(), raw_value.to_vec())))?;
+        Ok(RangeSnapshot { pairs })
+    }
+
+    // Call `visit` with the raw pairs `range_snapshot` copies
+    fn visit_range(&self, start_key: &str, end_key: &str, mut visit: impl FnMut(&[u8], &[u8])) -> Result<()> {
+        let in_range = |key: &str| end_key.is_empty() || key < end_key;
+
+        for item in self.db.iterator(IteratorMode::From(&mvcc::key_prefix(start_key), Direction::Forward)) {
+            let (raw_key, raw_value) = item.map_err(storage_error)?;
+            match mvcc::decode_key(&raw_key) {
+                Some((user_key, _)) if in_range(&user_key) => visit(&raw_key, &raw_value),
+                _ => break,
+            }
+        }
+        for item in self.db.iterator(IteratorMode::From(&mvcc::intent_key(start_key), Direction::Forward)) {
+            let (raw_key, raw_value) = item.map_err(storage_error)?;
+            match mvcc::decode_intent_key(&raw_key) {
+                Some(user_key) if in_range(&user_key) => visit(&raw_key, &raw_value),
+                _ => break,
+            }
+        }
+        let prefix = mvcc::txn_record_prefix();
+        for item in self.db.iterator(IteratorMode::From(&prefix, Direction::Forward)) {
+            let (raw_key, raw_value) = item.map_err(storage_error)?;
+            if !raw_key.starts_with(&prefix) {
+                break;
+            }
+            let record: TransactionRecord = serde_json::from_slice(&raw_value)?;
+            let primary = &record.meta.primary_key;
+            if primary.as_str() >= start_key && in_range(primary) {
+                visit(&raw_key, &raw_value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove versions that no read inside the GC window can observe.
+    ///
+    /// For each key, the newest version at or below the GC horizon is the
+    /// oldest one still needed; everything older is deleted. That version is
+    /// deleted too when it is a tombstone or had expired by the horizon.
+    /// Returns the number of versions removed.
+    pub fn collect_garbage(&self) -> Result<usize> {
+        let horizon = self.gc_horizon();
+        let mut batch = WriteBatch::default();
+        let mut removed = 0;
+        let mut anchored: Option<String> = None;
+
+        for item in self.db.iterator(IteratorMode::Start) {
+            let (raw_key, raw_value) = item.map_err(storage_error)?;
+            if raw_key.first() == Some(&mvcc::RESERVED_PREFIX) {
+                break;
+            }
+            let Some((user_key, version)) = mvcc::decode_key(&raw_key) else {
+                continue;
+            };
+
+            if anchored.as_deref() == Some(user_key.as_str()) {
+                batch.delete(&raw_key);
+                removed += 1;
+            } else if version.timestamp.wall_ms <= horizon {
+                if !StoredValue::decode(&raw_value)?.is_live(horizon) {
+                    batch.delete(&raw_key);
+                    removed += 1;
+                }
+                anchored = Some(user_key);
+            }
+        }
+
+        self.db.write(batch).map_err(storage_error)?;
+        Ok(removed)
+    }
+
+    /// Oldest timestamp that reads may still use.
+    fn gc_horizon(&self) -> u64 {
+        timestamp_ms().saturating_sub(self.options.gc_window_ms)
+    }
+
+    fn check_read_timestamp(&self, read_ts: HlcTimestamp) -> Result<()> {
+        if read_ts.wall_ms < self.gc_horizon() {
+            return Err(DatabaseError::InvalidArgument(format!(
+                "Read timestamp {} is older than the GC window of {} ms",
+                read_ts, self.options.gc_window_ms
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Writes collected to be committed to storage together, atomically.
+///
+/// Reads do not see the writes until they are committed; dropping the batch
+/// discards them.
+pub struct Batch<'a> {
+    storage: &'a Storage,
+    batch: WriteBatch,
+}
+
+impl Batch<'_> {
+    /// Store `value` under `key` as a new version, expiring at `expires_at` (ms).
+    pub fn put(&mut self, key: &str, value: &[u8], version: WriteVersion, expires_at: Option<u64>) {
+        let stored = StoredValue::new(value.to_vec(), version.index, expires_at);
+        self.batch.put(mvcc::encode_key(key, version), stored.encode());
+    }
+
+    /// Delete `key` by writing a tombstone version.
+    pub fn delete(&mut self, key: &str, version: WriteVersion) {
+        let stored = StoredValue::tombstone(version.index);
+        self.batch.put(mvcc::encode_key(key, version), stored.encode());
+    }
+
+    /// Write every put and delete in `operations` as `version`.
+    pub fn write_operations(&mut self, operations: &[BatchOperation], version: WriteVersion) {
+        for operation in operations {
+            match operation {
+                BatchOperation::Put { key, value } => self.put(key, value, version, None),
+                BatchOperation::Delete { key } => self.delete(key, version),
+            }
+        }
+    }
+
+    /// Lay down intents of `txn` for `operations`, and write the transaction
+    /// record when given.
+    pub fn write_intents(
+        &mut self,
+        txn: &TransactionMeta,
+        operations: &[BatchOperation],
+        record: Option<&TransactionRecord>,
+    ) -> Result<()> {
+        for operation in operations {
+            let value = match operation {
+                BatchOperation::Put { value, .. } => Some(value.clone()),
+                BatchOperation::Delete { .. } => None,
+            };
+            let intent = Intent { txn: txn.clone(), value };
+            self.batch.put(mvcc::intent_key(operation.key()), encode_json(&intent)?);
+        }
+        if let Some(record) = record {
+            self.put_transaction(record)?;
+        }
+        Ok(())
+    }
+
+    /// Resolve the intents `txn_id` holds on `keys`, as
+    /// `Storage::resolve_intents` does. Returns the number of intents resolved.
+    pub fn resolve_intents(&mut self, txn_id: &TransactionId, keys: &[String], commit: Option<WriteVersion>) -> Result<usize> {
+        let mut resolved = 0;
+        for key in keys {
+            match self.storage.get_intent(key)? {
+                Some(intent) if intent.txn.id == *txn_id => {
+                    if let Some(version) = commit {
+                        let stored = match intent.value {
+                            Some(value) => StoredValue::new(value, version.index, None),
+                            None => StoredValue::tombstone(version.index),
+                        };
+                        self.batch.put(mvcc::encode_key(key, version), stored.encode());
+                    }
+                    self.batch.delete(mvcc::intent_key(key));
+                    resolved += 1;
+                },
+                _ => {},
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Store a transaction record, replacing any previous one.
+    pub fn put_transaction(&mut self, record: &TransactionRecord) -> Result<()> {
+        self.batch.put(mvcc::txn_record_key(&record.meta.id.0), encode_json(record)?);
+        Ok(())
+    }
+
+    /// Store the state `name` of Raft group `group_id`.
+    pub fn put_group_state(&mut self, group_id: u64, name: &[u8], value: &[u8]) {
+        self.batch.put(mvcc::group_state_key(group_id, name), value);
+    }
+
+    /// Remove the state `name` of Raft group `group_id`.
+    pub fn delete_group_state(&mut self, group_id: u64, name: &[u8]) {
+        self.batch.delete(mvcc::group_state_key(group_id, name));
+    }
+
+    /// Replace everything `Storage::range_snapshot` would copy from
+    /// `[start_key, end_key)` with the contents of `snapshot`.
+    pub fn install_range_snapshot(&mut self, start_key: &str, end_key: &str, snapshot: &RangeSnapshot) -> Result<()> {
+        let batch = &mut self.batch;
+        self.storage.visit_range(start_key, end_key, |raw_key, _| batch.delete(raw_key))?;
+        for (raw_key, raw_value) in &snapshot.pairs {
+            self.batch.put(raw_key, raw_value);
+        }
+        Ok(())
+    }
+
+    /// Persist every write in the batch, or none of them.
+    pub fn commit(self) -> Result<()> {
+        self.storage.db.write(self.batch).map_err(storage_error)
+    }
+}
+
+/// The data of a key range as `Storage::range_snapshot` copied it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RangeSnapshot {
+    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl RangeSnapshot {
+    /// Number of versions, intents and transaction records held.
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Encode as length-prefixed keys and values.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut raw = Vec::new();
+        for (key, value) in &self.pairs {
+            for part in [key, value] {
+                raw.extend_from_slice(&(part.len() as u32).to_be_bytes());
+                raw.extend_from_slice(part);
+            }
+        }
+        raw
+    }
+
+    /// Decode a snapshot produced by `encode`.
+    pub fn decode(mut raw: &[u8]) -> Result<Self> {
+        fn part<'a>(raw: &mut &'a [u8]) -> Option<&'a [u8]> {
+            let len = u32::from_be_bytes(raw.get(..4)?.try_into().ok()?) as usize;
+            let part = raw.get(4..4 + len)?;
+            *raw = &raw[4 + len..];
+            Some(part)
+        }
+
+        let mut pairs = Vec::new();
+        while !raw.is_empty() {
+            let (Some(key), Some(value)) = (part(&mut raw), part(&mut raw)) else {
+                return Err(DatabaseError::Storage { key: None, message: "Truncated range snapshot".to_string() });
+            };
+            pairs.push((key.to_vec(), value.to_vec()));
+        }
+        Ok(Self { pairs })
+    }
+}
+
+fn encode_json<T: serde::Serialize>(value: &T) -> Result<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|e| DatabaseError::Serialization(e.to_string()))
+}
+
+fn storage_error(e: rocksdb::Error) -> DatabaseError {
+    DatabaseError::Storage { key: None, message: e.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired_values_are_removed_after_the_gc_window() {
+        let path = std::env::temp_dir().join(format!("storage_ttl_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let storage = Storage::open_with_options(&path, StorageOptions { gc_window_ms: 50 }).unwrap();
+        let now = timestamp_ms();
+        let at = |index, wall_ms| WriteVersion { index, timestamp: HlcTimestamp::new(wall_ms, 0) };
+
+        storage.put("k", b"old", at(1, now - 1_000), None).unwrap();
+        storage.put("k", b"new", at(2, now - 900), Some(now - 800)).unwrap();
+        assert_eq!(storage.get("k").unwrap(), None);
+
+        // Compaction leaves a tombstone, so the older version stays hidden
+        storage.db.compact_range::<&[u8], &[u8]>(None, None);
+        let (_, newest) = storage.db.iterator(IteratorMode::Start).next().unwrap().unwrap();
+        assert!(StoredValue::decode(&newest).unwrap().tombstone);
+        assert_eq!(storage.get("k").unwrap(), None);
+
+        assert_eq!(storage.collect_garbage().unwrap(), 2);
+        assert_eq!(storage.db.iterator(IteratorMode::Start).count(), 0, "Nothing of the key is left");
+    }
+}
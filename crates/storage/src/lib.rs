@@ -1,3 +1,15 @@
+//! Storage layer for data nodes.
+
+pub mod engine;
+pub mod mvcc;
+pub mod value;
+mod ttl;
+
+// Re-export commonly used items
+pub use engine::{Batch, RangeSnapshot, RangeStats, Storage, StorageOptions};
+pub use mvcc::WriteVersion;
+pub use value::StoredValue;
+
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
@@ -0,0 +1,194 @@
+//! Multi-version key encoding.
+//!
+//! Each write is stored under its own RocksDB key: the escaped user key, a
+//! terminator, then the bitwise-inverted HLC commit timestamp (wall time and
+//! logical counter) and Raft index in big-endian. Inverting makes the newest
+//! version of a key sort first, so a seek to `(key, read_ts)` lands on the
+//! newest version visible at `read_ts`.
+//!
+//! Zero bytes inside the user key are escaped as `00 FF` and the key is
+//! terminated by `00 01`, which preserves the byte order of user keys.
+//!
+//! User keys are UTF-8, so they never start with `FF`. Keys with that prefix
+//! hold transaction state instead and sort after all user data: write intents
+//! under `FF 'i'` plus the encoded user key, and transaction records under
+//! `FF 't'` plus the transaction id. State a Raft group keeps for itself, such
+//! as how far it has applied its log, lives under `FF 'r'` plus the group id
+//! in big-endian and a name.
+
+use common::hlc::HlcTimestamp;
+
+/// Identifies one committed write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteVersion {
+    /// Raft log index of the write; exposed to clients as the key version.
+    pub index: u64,
+    /// Commit timestamp, used for reads "as of" a time.
+    pub timestamp: HlcTimestamp,
+}
+
+const ESCAPE: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+const SUFFIX_LEN: usize = 20;
+
+/// First byte of every key that does not hold a user value version.
+pub(crate) const RESERVED_PREFIX: u8 = 0xFF;
+const INTENT_TAG: u8 = b'i';
+const TXN_RECORD_TAG: u8 = b't';
+const GROUP_STATE_TAG: u8 = b'r';
+
+/// Encoded prefix shared by every version of `user_key`.
+pub(crate) fn key_prefix(user_key: &str) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(user_key.len() + 2 + SUFFIX_LEN);
+    for &b in user_key.as_bytes() {
+        raw.push(b);
+        if b == 0 {
+            raw.push(ESCAPE);
+        }
+    }
+    raw.push(0);
+    raw.push(TERMINATOR);
+    raw
+}
+
+/// Encode the RocksDB key of one version of `user_key`.
+pub(crate) fn encode_key(user_key: &str, version: WriteVersion) -> Vec<u8> {
+    let mut raw = key_prefix(user_key);
+    raw.extend_from_slice(&(!version.timestamp.wall_ms).to_be_bytes());
+    raw.extend_from_slice(&(!version.timestamp.logical).to_be_bytes());
+    raw.extend_from_slice(&(!version.index).to_be_bytes());
+    raw
+}
+
+/// The key to seek to for the newest version of `user_key` at or before `read_ts`.
+pub(crate) fn seek_key(user_key: &str, read_ts: HlcTimestamp) -> Vec<u8> {
+    encode_key(user_key, WriteVersion { index: u64::MAX, timestamp: read_ts })
+}
+
+/// The key of the write intent on `user_key`.
+pub(crate) fn intent_key(user_key: &str) -> Vec<u8> {
+    let mut raw = vec![RESERVED_PREFIX, INTENT_TAG];
+    raw.extend_from_slice(&key_prefix(user_key));
+    raw
+}
+
+/// The key of the record of transaction `txn_id`.
+pub(crate) fn txn_record_key(txn_id: &str) -> Vec<u8> {
+    let mut raw = vec![RESERVED_PREFIX, TXN_RECORD_TAG];
+    raw.extend_from_slice(txn_id.as_bytes());
+    raw
+}
+
+/// The key of the state `name` of Raft group `group_id`.
+pub(crate) fn group_state_key(group_id: u64, name: &[u8]) -> Vec<u8> {
+    let mut raw = vec![RESERVED_PREFIX, GROUP_STATE_TAG];
+    raw.extend_from_slice(&group_id.to_be_bytes());
+    raw.extend_from_slice(name);
+    raw
+}
+
+/// Prefix shared by the state of every Raft group.
+pub(crate) fn group_state_prefix() -> [u8; 2] {
+    [RESERVED_PREFIX, GROUP_STATE_TAG]
+}
+
+/// The group id and name of an encoded group state key.
+pub(crate) fn decode_group_state_key(raw: &[u8]) -> Option<(u64, &[u8])> {
+    match raw {
+        [RESERVED_PREFIX, GROUP_STATE_TAG, rest @ ..] if rest.len() >= 8 => {
+            let (id, name) = rest.split_at(8);
+            Some((u64::from_be_bytes(id.try_into().ok()?), name))
+        },
+        _ => None,
+    }
+}
+
+/// Prefix shared by every transaction record.
+pub(crate) fn txn_record_prefix() -> [u8; 2] {
+    [RESERVED_PREFIX, TXN_RECORD_TAG]
+}
+
+/// The user key of an encoded intent key.
+pub(crate) fn decode_intent_key(raw: &[u8]) -> Option<String> {
+    match raw {
+        [RESERVED_PREFIX, INTENT_TAG, rest @ ..] => {
+            let (user_key, len) = decode_user_key(rest)?;
+            (len == rest.len()).then_some(user_key)
+        },
+        _ => None,
+    }
+}
+
+/// Split an encoded key into the user key and the version.
+pub(crate) fn decode_key(raw: &[u8]) -> Option<(String, WriteVersion)> {
+    let (user_key, i) = decode_user_key(raw)?;
+    let suffix = raw.get(i..i + SUFFIX_LEN)?;
+    let wall_ms = !u64::from_be_bytes(suffix[..8].try_into().ok()?);
+    let logical = !u32::from_be_bytes(suffix[8..12].try_into().ok()?);
+    let index = !u64::from_be_bytes(suffix[12..].try_into().ok()?);
+    let timestamp = HlcTimestamp::new(wall_ms, logical);
+    Some((user_key, WriteVersion { index, timestamp }))
+}
+
+// Decode an escaped, terminated user key; returns it with the encoded length
+fn decode_user_key(raw: &[u8]) -> Option<(String, usize)> {
+    let mut user_key = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    loop {
+        match (raw.get(i)?, raw.get(i + 1)) {
+            (0, Some(&ESCAPE)) => {
+                user_key.push(0);
+                i += 2;
+            },
+            (0, Some(&TERMINATOR)) => {
+                i += 2;
+                break;
+            },
+            (0, _) => return None,
+            (&b, _) => {
+                user_key.push(b);
+                i += 1;
+            },
+        }
+    }
+
+    Some((String::from_utf8(user_key).ok()?, i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(index: u64, wall_ms: u64) -> WriteVersion {
+        WriteVersion { index, timestamp: HlcTimestamp::new(wall_ms, 0) }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for key in ["", "a", "a\0b", "\0", "user:42"] {
+            let version = WriteVersion { index: 9, timestamp: HlcTimestamp::new(1_700_000_000_000, 4) };
+            assert_eq!(decode_key(&encode_key(key, version)), Some((key.to_string(), version)));
+        }
+    }
+
+    #[test]
+    fn test_ordering() {
+        // User keys keep their order regardless of versions
+        assert!(encode_key("a", at(1, 1)) < encode_key("a\0", at(9, 9)));
+        assert!(encode_key("a\0", at(1, 1)) < encode_key("ab", at(9, 9)));
+        // Newer versions of the same key sort first
+        assert!(encode_key("a", at(2, 20)) < encode_key("a", at(1, 10)));
+        assert!(encode_key("a", at(3, 10)) < encode_key("a", at(2, 10)));
+        let logical = WriteVersion { index: 1, timestamp: HlcTimestamp::new(10, 1) };
+        assert!(encode_key("a", logical) < encode_key("a", at(2, 10)));
+        // A seek at read_ts lands before every version at or below read_ts
+        let read_ts = HlcTimestamp::new(10, 0);
+        assert!(seek_key("a", read_ts) <= encode_key("a", at(3, 10)));
+        assert!(seek_key("a", read_ts) > encode_key("a", logical));
+        assert!(seek_key("a", read_ts) > encode_key("a", at(4, 11)));
+        // Transaction state sorts after every user key
+        assert!(encode_key("\u{10FFFF}", at(1, 1)) < intent_key("a"));
+        assert_eq!(decode_key(&intent_key("a")), None);
+        assert_eq!(decode_intent_key(&intent_key("a\0b")), Some("a\0b".to_string()));
+    }
+}
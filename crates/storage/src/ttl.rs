@@ -0,0 +1,37 @@
+//! Background removal of expired keys.
+//!
+//! Reads already hide expired values; this compaction filter reclaims the
+//! space of their payload when RocksDB next compacts the files holding them.
+//! An expired version is turned into a tombstone rather than dropped, because
+//! the filter sees one version at a time and dropping it would let an older
+//! version of the key become visible again. The tombstone is kept on purpose:
+//! [`Storage::collect_garbage`](crate::Storage::collect_garbage), which data
+//! nodes run periodically, deletes it together with the versions it hides.
+//! Values are only rewritten once they expired longer ago than the GC window,
+//! so historical reads inside the window still see them.
+
+use crate::mvcc::RESERVED_PREFIX;
+use crate::value::{decode_header, TOMBSTONE};
+use common::util::timestamp_ms;
+use rocksdb::compaction_filter::Decision;
+
+pub(crate) const FILTER_NAME: &str = "ttl_expiry";
+
+/// Build a compaction filter that replaces values expired for longer than
+/// `gc_window_ms` with tombstones.
+pub(crate) fn expiry_filter(gc_window_ms: u64) -> impl FnMut(u32, &[u8], &[u8]) -> Decision + Send + 'static {
+    move |_level, key, value| {
+        // Intents and transaction records are not versioned values
+        if key.first() == Some(&RESERVED_PREFIX) {
+            return Decision::Keep;
+        }
+
+        let horizon = timestamp_ms().saturating_sub(gc_window_ms);
+        match decode_header(value) {
+            Some(header) if !header.tombstone && header.expires_at.is_some_and(|t| t <= horizon) => {
+                Decision::Change(&TOMBSTONE)
+            },
+            _ => Decision::Keep,
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! On-disk encoding of stored values.
+//!
+//! Every value is prefixed with a fixed header: a flags byte, the 8-byte
+//! big-endian version of the write, then the 8-byte big-endian expiry
+//! timestamp in milliseconds (0 when the key never expires), followed by the
+//! user value.
+
+use common::error::{DatabaseError, Result};
+use common::types::VersionedValue;
+
+const HEADER_LEN: usize = 17;
+const FLAG_TOMBSTONE: u8 = 1;
+
+/// Encoded tombstone with no version or expiry, used where the original
+/// header is not at hand (the compaction filter can only return static bytes).
+pub(crate) static TOMBSTONE: [u8; HEADER_LEN] = [FLAG_TOMBSTONE, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// A value as stored in RocksDB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredValue {
+    pub value: Vec<u8>,
+    pub version: u64,
+    pub expires_at: Option<u64>,
+    /// Marks a deleted key; an MVCC delete is a write of a tombstone.
+    pub tombstone: bool,
+}
+
+impl StoredValue {
+    pub fn new(value: Vec<u8>, version: u64, expires_at: Option<u64>) -> Self {
+        Self {
+            value,
+            version,
+            expires_at,
+            tombstone: false,
+        }
+    }
+
+    pub fn tombstone(version: u64) -> Self {
+        Self {
+            value: Vec::new(),
+            version,
+            expires_at: None,
+            tombstone: true,
+        }
+    }
+
+    /// Whether the value has expired at `now_ms`.
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now_ms)
+    }
+
+    /// Whether a reader at `now_ms` sees this version as an existing key.
+    pub fn is_live(&self, now_ms: u64) -> bool {
+        !self.tombstone && !self.is_expired(now_ms)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let flags = if self.tombstone { FLAG_TOMBSTONE } else { 0 };
+        let mut raw = Vec::with_capacity(HEADER_LEN + self.value.len());
+        raw.push(flags);
+        raw.extend_from_slice(&self.version.to_be_bytes());
+        raw.extend_from_slice(&self.expires_at.unwrap_or(0).to_be_bytes());
+        raw.extend_from_slice(&self.value);
+        raw
+    }
+
+    pub fn decode(raw: &[u8]) -> Result<Self> {
+        let header = decode_header(raw)
+            .ok_or_else(|| DatabaseError::Storage { key: None, message: "Stored value is truncated".to_string() })?;
+
+        Ok(Self {
+            value: raw[HEADER_LEN..].to_vec(),
+            version: header.version,
+            expires_at: header.expires_at,
+            tombstone: header.tombstone,
+        })
+    }
+}
+
+impl From<StoredValue> for VersionedValue {
+    fn from(stored: StoredValue) -> Self {
+        VersionedValue {
+            value: stored.value,
+            version: stored.version,
+        }
+    }
+}
+
+/// The fixed-size prefix of a stored value.
+pub(crate) struct Header {
+    pub version: u64,
+    pub expires_at: Option<u64>,
+    pub tombstone: bool,
+}
+
+/// Read the header without copying the value.
+pub(crate) fn decode_header(raw: &[u8]) -> Option<Header> {
+    if raw.len() < HEADER_LEN {
+        return None;
+    }
+    let version = u64::from_be_bytes(raw[1..9].try_into().ok()?);
+    let expires_at = u64::from_be_bytes(raw[9..17].try_into().ok()?);
+    Some(Header {
+        version,
+        expires_at: Some(expires_at).filter(|&t| t != 0),
+        tombstone: raw[0] & FLAG_TOMBSTONE != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let stored = StoredValue::new(b"hello".to_vec(), 7, Some(1_000));
+        assert_eq!(StoredValue::decode(&stored.encode()).unwrap(), stored);
+
+        let deleted = StoredValue::tombstone(3);
+        assert_eq!(StoredValue::decode(&deleted.encode()).unwrap(), deleted);
+        assert!(StoredValue::decode(&TOMBSTONE).unwrap().tombstone);
+        assert!(StoredValue::decode(&[0; 4]).is_err());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let stored = StoredValue::new(vec![], 1, Some(1_000));
+        assert!(stored.is_live(999));
+        assert!(stored.is_expired(1_000));
+        assert!(!stored.is_live(1_000));
+    }
+}